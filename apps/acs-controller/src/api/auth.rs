@@ -0,0 +1,122 @@
+//! Login and session introspection.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::state::ApiState;
+use crate::auth::{password, Principal, Role};
+
+// ── Request / response types ──────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    pub email:    String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginResponse {
+    pub token:      String,
+    pub token_type: &'static str,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub user_id:    Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MembershipView {
+    pub domain_id: Uuid,
+    pub role:      Role,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MeResponse {
+    pub user_id:        Uuid,
+    pub email:          String,
+    pub is_super_admin: bool,
+    pub memberships:    Vec<MembershipView>,
+}
+
+// ── Handlers ──────────────────────────────────────────────────────────────────
+
+/// `POST /api/v1/auth/login`
+///
+/// Verifies the password against `users.password_hash` (Argon2id) and
+/// returns a signed bearer token. Unknown email and wrong password produce
+/// the same `401` so the endpoint cannot be used to enumerate accounts.
+pub async fn login(
+    State(state): State<ApiState>,
+    Json(body): Json<LoginRequest>,
+) -> impl IntoResponse {
+    let row: Result<Option<(Uuid, Option<String>)>, sqlx::Error> =
+        sqlx::query_as("SELECT id, password_hash FROM users WHERE email = $1")
+            .bind(&body.email)
+            .fetch_optional(&state.pool)
+            .await;
+
+    let row = match row {
+        Ok(r) => r,
+        Err(e) => {
+            tracing::error!(?e, "login: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    // Argon2 is deliberately slow; keep it off the async executor.
+    let password_input = body.password;
+    let stored_hash = row.as_ref().and_then(|(_, h)| h.clone());
+    let verified = tokio::task::spawn_blocking(move || {
+        password::verify_password(&password_input, stored_hash.as_deref())
+    })
+    .await
+    .unwrap_or(false);
+
+    let (user_id, _) = match row {
+        Some(r) if verified => r,
+        _ => {
+            tracing::info!(email = %body.email, "Login failed");
+            return (StatusCode::UNAUTHORIZED, "Invalid email or password").into_response();
+        }
+    };
+
+    if let Err(e) = sqlx::query("UPDATE users SET last_login_at = now() WHERE id = $1")
+        .bind(user_id)
+        .execute(&state.pool)
+        .await
+    {
+        tracing::warn!(?e, %user_id, "login: failed to record last_login_at");
+    }
+
+    let (token, claims) = state.auth.issue(user_id, chrono::Utc::now().timestamp());
+    tracing::info!(%user_id, "Login succeeded");
+
+    let response = LoginResponse {
+        token,
+        token_type: "Bearer",
+        expires_at: chrono::DateTime::from_timestamp(claims.exp, 0).unwrap_or_default(),
+        user_id,
+    };
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+/// `GET /api/v1/auth/me`
+pub async fn me(Extension(principal): Extension<Principal>) -> impl IntoResponse {
+    let mut memberships: Vec<MembershipView> = principal
+        .memberships
+        .iter()
+        .map(|(domain_id, role)| MembershipView { domain_id: *domain_id, role: *role })
+        .collect();
+    memberships.sort_by_key(|m| m.domain_id);
+
+    Json(MeResponse {
+        user_id:        principal.user_id,
+        email:          principal.email,
+        is_super_admin: principal.is_super_admin,
+        memberships,
+    })
+}
@@ -0,0 +1,475 @@
+//! Configuration backup API.
+//!
+//! Backups are taken and kept per device (see [`crate::backups`]). Reading
+//! a device's backups requires `domain_viewer`; taking and restoring one
+//! require `domain_editor`, deleting one `domain_admin`. A domain's backup
+//! policy is visible to its viewers and set by its admins.
+//!
+//! Devices reach `/backups/files/:id/:token` without credentials to upload
+//! the file of an upload backup and, when it is restored, download it back.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::inventory::{is_check_violation, visible_device};
+use crate::api::state::ApiState;
+use crate::api::tasks::deliver_or_wake;
+use crate::auth::{forbidden, Principal, Role};
+use crate::backups::{self, Backup, Method, Plan, Policy, PolicyUpdate, StartError, BACKUP_COLUMNS, POLICY_COLUMNS};
+use crate::db;
+use crate::tasks::{self, NewTask, Task};
+
+// ── Request / response types ──────────────────────────────────────────────────
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateBackupRequest {
+    /// Omitted: the method of the domain's policy, else `parameters`.
+    pub method: Option<Method>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    /// Version compared against. Defaults to the previous complete one.
+    pub against: Option<i32>,
+}
+
+/// A backup with its parameters, for `parameters` backups.
+#[derive(Debug, Serialize)]
+pub struct BackupDetail {
+    #[serde(flatten)]
+    pub backup:     Backup,
+    pub parameters: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffResponse {
+    pub from: i32,
+    pub to:   i32,
+    #[serde(flatten)]
+    pub diff: backups::Diff,
+}
+
+// ── Device backups ────────────────────────────────────────────────────────────
+
+/// `GET /api/v1/device/:uid/backups` — newest first, without content.
+pub async fn list_backups(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(uid): Path<String>,
+) -> impl IntoResponse {
+    let device_id = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, _))) => device_id,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "list_backups: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let result = sqlx::query_as::<_, Backup>(&format!(
+        "SELECT {BACKUP_COLUMNS} FROM config_backups WHERE device_id = $1 ORDER BY version DESC"
+    ))
+    .bind(device_id)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(backups) => (StatusCode::OK, Json(backups)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "list_backups: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/device/:uid/backups` — take a backup now. Requires
+/// `domain_editor`.
+///
+/// Returns the `pending` backup; its task is delivered at once if the
+/// device is in a session, otherwise the device is woken (best effort).
+pub async fn create_backup(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(uid): Path<String>,
+    body: Option<Json<CreateBackupRequest>>,
+) -> impl IntoResponse {
+    let (device_id, domain_id) = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, domain_id))) if principal.has_role(domain_id, Role::Editor) => (device_id, domain_id),
+        Ok(Some(_)) => return forbidden(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "create_backup: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+    let Json(body) = body.unwrap_or_default();
+
+    let plan = match Plan::for_device(&state.pool, device_id, body.method).await {
+        Ok(plan) => plan,
+        Err(e) => {
+            tracing::error!(?e, %uid, "create_backup: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    match backups::start(&state.pool, &state.backups, device_id, &plan, "manual", Some(principal.user_id)).await {
+        Ok(backup) => {
+            deliver_or_wake(&state, device_id, domain_id, &uid).await;
+            (StatusCode::CREATED, Json(backup)).into_response()
+        }
+        Err(e @ StartError::InProgress) => (StatusCode::CONFLICT, e.to_string()).into_response(),
+        Err(e @ StartError::UploadsDisabled) => (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response(),
+        Err(StartError::Db(e)) => {
+            tracing::error!(?e, %uid, "create_backup: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/device/:uid/backups/:version` — with `parameters` for
+/// parameters backups. The file of an upload backup is at `…/file`.
+pub async fn get_backup(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path((uid, version)): Path<(String, i32)>,
+) -> impl IntoResponse {
+    let device_id = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, _))) => device_id,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "get_backup: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let backup = sqlx::query_as::<_, Backup>(&format!(
+        "SELECT {BACKUP_COLUMNS} FROM config_backups WHERE device_id = $1 AND version = $2"
+    ))
+    .bind(device_id)
+    .bind(version)
+    .fetch_optional(&state.pool)
+    .await;
+    let parameters = sqlx::query_scalar::<_, Option<serde_json::Value>>(
+        "SELECT parameters FROM config_backups WHERE device_id = $1 AND version = $2",
+    )
+    .bind(device_id)
+    .bind(version)
+    .fetch_optional(&state.pool)
+    .await;
+
+    match (backup, parameters) {
+        (Ok(Some(backup)), Ok(parameters)) => {
+            (StatusCode::OK, Json(BackupDetail { backup, parameters: parameters.flatten() })).into_response()
+        }
+        (Ok(None), _) => (StatusCode::NOT_FOUND, "Backup not found").into_response(),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!(?e, %uid, version, "get_backup: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/device/:uid/backups/:version/file` — the uploaded file.
+pub async fn get_backup_file(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path((uid, version)): Path<(String, i32)>,
+) -> impl IntoResponse {
+    let device_id = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, _))) => device_id,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "get_backup_file: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    match backups::content(&state.pool, device_id, version).await {
+        Ok(Some(backups::Content { file: Some(file), .. })) => file_response(file),
+        Ok(_) => (StatusCode::NOT_FOUND, "Backup file not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, version, "get_backup_file: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/device/:uid/backups/:version/diff[?against=<version>]`
+///
+/// What changed from `against` (by default the previous complete backup)
+/// to `version`. Both must be complete and taken with the same method.
+pub async fn diff_backup(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path((uid, version)): Path<(String, i32)>,
+    Query(query): Query<DiffQuery>,
+) -> impl IntoResponse {
+    let device_id = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, _))) => device_id,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "diff_backup: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let against = match query.against {
+        Some(against) => Ok(Some(against)),
+        None => sqlx::query_scalar::<_, Option<i32>>(
+            "SELECT max(version) FROM config_backups WHERE device_id = $1 AND status = 'complete' AND version < $2",
+        )
+        .bind(device_id)
+        .bind(version)
+        .fetch_one(&state.pool)
+        .await,
+    };
+    let against = match against {
+        Ok(Some(against)) => against,
+        Ok(None) => return (StatusCode::NOT_FOUND, "No earlier complete backup").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, version, "diff_backup: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let from = backups::content(&state.pool, device_id, against).await;
+    let to = backups::content(&state.pool, device_id, version).await;
+    match (from, to) {
+        (Ok(Some(from)), Ok(Some(to))) => match backups::diff(&from, &to) {
+            Ok(diff) => (StatusCode::OK, Json(DiffResponse { from: against, to: version, diff })).into_response(),
+            Err(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg).into_response(),
+        },
+        (Ok(_), Ok(_)) => (StatusCode::NOT_FOUND, "Complete backup not found").into_response(),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!(?e, %uid, version, "diff_backup: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/device/:uid/backups/:version/restore` — requires
+/// `domain_editor`.
+///
+/// Queues one SetParameterValues of every backed-up parameter, or a
+/// Download of the backed-up file, and returns the task.
+pub async fn restore_backup(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path((uid, version)): Path<(String, i32)>,
+) -> impl IntoResponse {
+    let (device_id, domain_id) = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, domain_id))) if principal.has_role(domain_id, Role::Editor) => (device_id, domain_id),
+        Ok(Some(_)) => return forbidden(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "restore_backup: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let content = match backups::content(&state.pool, device_id, version).await {
+        Ok(Some(content)) => content,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Complete backup not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, version, "restore_backup: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+    let action = match backups::restore_action(&content, &state.backups) {
+        Ok(action) => action,
+        Err(msg) => return (StatusCode::UNPROCESSABLE_ENTITY, msg).into_response(),
+    };
+
+    let task = NewTask { action, priority: None, expires_in_secs: None, max_attempts: Some(1) };
+    let task: Task = match tasks::enqueue(&state.pool, device_id, &task, Some(principal.user_id)).await {
+        Ok(t) => t,
+        Err(e) => {
+            tracing::error!(?e, %uid, "restore_backup: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    tracing::info!(%uid, version, task_id = %task.id, user = %principal.email, "Backup restore queued");
+    deliver_or_wake(&state, device_id, domain_id, &uid).await;
+    (StatusCode::CREATED, Json(task)).into_response()
+}
+
+/// `DELETE /api/v1/device/:uid/backups/:version` — requires `domain_admin`.
+pub async fn delete_backup(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path((uid, version)): Path<(String, i32)>,
+) -> impl IntoResponse {
+    let device_id = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, domain_id))) if principal.has_role(domain_id, Role::Admin) => device_id,
+        Ok(Some(_)) => return forbidden(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "delete_backup: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let result = sqlx::query("DELETE FROM config_backups WHERE device_id = $1 AND version = $2")
+        .bind(device_id)
+        .bind(version)
+        .execute(&state.pool)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Backup not found").into_response(),
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, version, "delete_backup: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+// ── Domain policy ─────────────────────────────────────────────────────────────
+
+/// `GET /api/v1/inventory/domains/:slug/backup-policy`
+pub async fn get_policy(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(slug): Path<String>,
+) -> impl IntoResponse {
+    let domain_id = match db::get_domain_id(&state.pool, &slug).await {
+        Ok(Some(id)) if principal.can_view(id) => id,
+        Ok(_) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, "get_policy: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let result = sqlx::query_as::<_, Policy>(&format!(
+        "SELECT {POLICY_COLUMNS} FROM backup_policies WHERE domain_id = $1"
+    ))
+    .bind(domain_id)
+    .fetch_optional(&state.pool)
+    .await;
+
+    match result {
+        Ok(Some(policy)) => (StatusCode::OK, Json(policy)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Domain has no backup policy").into_response(),
+        Err(e) => {
+            tracing::error!(?e, "get_policy: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `PUT /api/v1/inventory/domains/:slug/backup-policy` — requires
+/// `domain_admin`. Replaces the whole policy.
+pub async fn set_policy(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(slug): Path<String>,
+    Json(body): Json<PolicyUpdate>,
+) -> impl IntoResponse {
+    let domain_id = match db::get_domain_id(&state.pool, &slug).await {
+        Ok(Some(id)) if principal.has_role(id, Role::Admin) => id,
+        Ok(Some(id)) if principal.can_view(id) => return forbidden(),
+        Ok(_) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, "set_policy: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    if body.method == Some(Method::Upload) && state.backups.base_url.is_none() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "Upload backups are disabled (BACKUP_BASE_URL is unset)")
+            .into_response();
+    }
+
+    match backups::set_policy(&state.pool, domain_id, &body, principal.user_id).await {
+        Ok(policy) => {
+            tracing::info!(domain = %slug, user = %principal.email, "Backup policy set");
+            (StatusCode::OK, Json(policy)).into_response()
+        }
+        Err(e) if is_check_violation(&e) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "interval_hours, retain and retain_days must be positive",
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!(?e, "set_policy: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `DELETE /api/v1/inventory/domains/:slug/backup-policy` — requires
+/// `domain_admin`. Stops scheduled backups; retention falls back to the
+/// default.
+pub async fn delete_policy(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(slug): Path<String>,
+) -> impl IntoResponse {
+    let domain_id = match db::get_domain_id(&state.pool, &slug).await {
+        Ok(Some(id)) if principal.has_role(id, Role::Admin) => id,
+        Ok(Some(id)) if principal.can_view(id) => return forbidden(),
+        Ok(_) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, "delete_policy: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    match sqlx::query("DELETE FROM backup_policies WHERE domain_id = $1").bind(domain_id).execute(&state.pool).await {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Domain has no backup policy").into_response(),
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!(?e, "delete_policy: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+// ── Device file transfer ──────────────────────────────────────────────────────
+
+/// `PUT|POST /backups/files/:id/:token` — the device uploading the file of
+/// an upload backup. Unauthenticated: the token is the credential.
+pub async fn receive_file(
+    State(state): State<ApiState>,
+    Path((id, token)): Path<(Uuid, String)>,
+    body: Bytes,
+) -> impl IntoResponse {
+    match backups::receive_file(&state.pool, id, &token, &body).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "receive_file: db error");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// `GET /backups/files/:id/:token` — the device downloading a backup being
+/// restored.
+pub async fn send_file(
+    State(state): State<ApiState>,
+    Path((id, token)): Path<(Uuid, String)>,
+) -> impl IntoResponse {
+    match backups::stored_file(&state.pool, id, &token).await {
+        Ok(Some(file)) => file_response(file),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "send_file: db error");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+fn file_response(file: Vec<u8>) -> Response {
+    ([(header::CONTENT_TYPE, "application/octet-stream")], file).into_response()
+}
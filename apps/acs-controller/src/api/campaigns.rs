@@ -0,0 +1,472 @@
+//! Firmware campaign API.
+//!
+//! Campaigns belong to a domain. Viewing one requires `domain_viewer`;
+//! creating, starting, pausing, resuming, cancelling and deleting require
+//! `domain_editor`. The rollout itself is driven by
+//! [`crate::campaigns::run_scheduler`].
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::NaiveTime;
+use nats_common::Action;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::inventory::{is_check_violation, is_unique_violation};
+use crate::api::state::ApiState;
+use crate::auth::{forbidden, Principal, Role};
+use crate::campaigns::{self, Campaign, TargetFilter, WaveCounts, CAMPAIGN_COLUMNS};
+use crate::db;
+use crate::groups::{self, EvaluateError};
+
+// ── Request / response types ──────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct CampaignListQuery {
+    /// Filter by domain slug.
+    pub domain: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateCampaignRequest {
+    /// Domain slug.
+    pub domain:              String,
+    pub name:                String,
+    #[serde(default)]
+    pub target_filter:       TargetFilter,
+    /// Must be a `Download`. Omitted: each device gets the firmware catalog
+    /// image of `target_version` compatible with it.
+    pub action:              Option<Action>,
+    pub target_version:      String,
+    pub batch_size:          Option<i32>,
+    pub max_concurrent:      Option<i32>,
+    pub window_start:        Option<NaiveTime>,
+    pub window_end:          Option<NaiveTime>,
+    pub failure_threshold:   Option<f32>,
+    pub device_timeout_secs: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PauseRequest {
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CampaignDevicesQuery {
+    pub state: Option<String>,
+    pub wave:  Option<i32>,
+}
+
+/// A campaign with its progress.
+#[derive(Debug, Serialize)]
+pub struct CampaignDetail {
+    #[serde(flatten)]
+    pub campaign: Campaign,
+    pub waves:    Vec<WaveCounts>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct CampaignDevice {
+    pub device_uid:   String,
+    pub wave:         i32,
+    pub state:        String,
+    pub task_id:      Option<Uuid>,
+    pub error:        Option<String>,
+    pub started_at:   Option<chrono::DateTime<chrono::Utc>>,
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub updated_at:   chrono::DateTime<chrono::Utc>,
+}
+
+// ── Handlers ──────────────────────────────────────────────────────────────────
+
+/// `GET /api/v1/campaigns[?domain=<slug>]` — newest first.
+pub async fn list_campaigns(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<CampaignListQuery>,
+) -> impl IntoResponse {
+    let result = sqlx::query_as::<_, Campaign>(&format!(
+        r#"
+        SELECT {CAMPAIGN_COLUMNS} FROM campaigns
+        WHERE ($1::UUID[] IS NULL OR domain_id = ANY($1))
+          AND ($2::TEXT IS NULL OR domain_id = (SELECT id FROM domains WHERE slug = $2))
+        ORDER BY created_at DESC
+        "#
+    ))
+    .bind(principal.visible_domains())
+    .bind(&query.domain)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(campaigns) => (StatusCode::OK, Json(campaigns)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, "list_campaigns: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/campaigns` — creates a campaign in `draft`.
+pub async fn create_campaign(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Json(body): Json<CreateCampaignRequest>,
+) -> impl IntoResponse {
+    let domain_id = match db::get_domain_id(&state.pool, &body.domain).await {
+        Ok(Some(id)) if principal.has_role(id, Role::Editor) => id,
+        Ok(Some(id)) if principal.can_view(id) => return forbidden(),
+        Ok(_) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, "create_campaign: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    if !matches!(body.action, None | Some(Action::Download { .. })) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "action must be a Download").into_response();
+    }
+    if body.window_start.is_some() != body.window_end.is_some() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "window_start and window_end must be set together")
+            .into_response();
+    }
+    if let Some(ref name) = body.target_filter.group {
+        match groups::find_by_name(&state.pool, domain_id, name).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return (StatusCode::UNPROCESSABLE_ENTITY, "target_filter.group does not exist in this domain")
+                    .into_response()
+            }
+            Err(e) => {
+                tracing::error!(?e, "create_campaign: db error");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+            }
+        }
+    }
+
+    // Serialising an Action or filter cannot fail.
+    let action = body.action.as_ref().map(|a| serde_json::to_value(a).expect("Action serialises"));
+    let filter = serde_json::to_value(&body.target_filter).expect("TargetFilter serialises");
+
+    let result = sqlx::query_as::<_, Campaign>(&format!(
+        r#"
+        INSERT INTO campaigns (
+            domain_id, name, target_filter, action, target_version, batch_size,
+            max_concurrent, window_start, window_end, failure_threshold,
+            device_timeout_secs, created_by
+        )
+        VALUES ($1, $2, $3, $4, $5, COALESCE($6, 100), COALESCE($7, 10), $8, $9,
+                COALESCE($10, 0.1), COALESCE($11, 3600), $12)
+        RETURNING {CAMPAIGN_COLUMNS}
+        "#
+    ))
+    .bind(domain_id)
+    .bind(&body.name)
+    .bind(filter)
+    .bind(action)
+    .bind(&body.target_version)
+    .bind(body.batch_size)
+    .bind(body.max_concurrent)
+    .bind(body.window_start)
+    .bind(body.window_end)
+    .bind(body.failure_threshold)
+    .bind(body.device_timeout_secs)
+    .bind(principal.user_id)
+    .fetch_one(&state.pool)
+    .await;
+
+    match result {
+        Ok(campaign) => (StatusCode::CREATED, Json(campaign)).into_response(),
+        Err(e) if is_unique_violation(&e) => {
+            (StatusCode::CONFLICT, "Campaign name already exists in this domain").into_response()
+        }
+        Err(e) if is_check_violation(&e) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "batch_size, max_concurrent and device_timeout_secs must be positive; failure_threshold between 0 and 1",
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!(?e, "create_campaign: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/campaigns/:id` — the campaign with per-wave progress.
+pub async fn get_campaign(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let campaign = match authorize(&state, &principal, id, Role::Viewer).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+
+    match campaigns::wave_counts(&state.pool, id).await {
+        Ok(waves) => (StatusCode::OK, Json(CampaignDetail { campaign, waves })).into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "get_campaign: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/campaigns/:id/devices[?state=failed&wave=0]`
+pub async fn list_campaign_devices(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<CampaignDevicesQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id, Role::Viewer).await {
+        return resp;
+    }
+
+    let result = sqlx::query_as::<_, CampaignDevice>(
+        r#"
+        SELECT d.device_uid, cd.wave, cd.state, cd.task_id, cd.error,
+               cd.started_at, cd.completed_at, cd.updated_at
+        FROM campaign_devices cd
+        JOIN devices d ON d.id = cd.device_id
+        WHERE cd.campaign_id = $1
+          AND ($2::TEXT IS NULL OR cd.state = $2)
+          AND ($3::INT  IS NULL OR cd.wave = $3)
+        ORDER BY cd.wave, d.device_uid
+        "#,
+    )
+    .bind(id)
+    .bind(&query.state)
+    .bind(query.wave)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(devices) => (StatusCode::OK, Json(devices)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "list_campaign_devices: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/campaigns/:id/start` — `draft` → `running`.
+///
+/// Target devices are resolved now; devices that match the filter later
+/// are not added. A dynamic target group is re-evaluated first; if its
+/// stored filter no longer compiles the campaign is not started (`409`).
+pub async fn start_campaign(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let campaign = match authorize(&state, &principal, id, Role::Editor).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+
+    let group = campaign.target_filter.get("group").and_then(|g| g.as_str());
+    if let Some(name) = group.filter(|_| campaign.status == "draft") {
+        let refreshed = match groups::find_by_name(&state.pool, campaign.domain_id, name).await {
+            Ok(Some(group)) => groups::evaluate(&state.pool, &state.nats, &group).await.map(drop),
+            // Deleted since the campaign was created: nothing to target.
+            Ok(None) => Ok(()),
+            Err(e) => Err(e.into()),
+        };
+        match refreshed {
+            Ok(()) => {}
+            Err(e @ EvaluateError::Search(_)) => return (StatusCode::CONFLICT, e.to_string()).into_response(),
+            Err(EvaluateError::Db(e)) => {
+                tracing::error!(?e, %id, "start_campaign: target group evaluation failed");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+            }
+        }
+    }
+
+    match campaigns::start(&state.pool, id).await {
+        Ok(Some(targets)) => {
+            (StatusCode::OK, Json(serde_json::json!({ "targets": targets }))).into_response()
+        }
+        Ok(None) => (StatusCode::CONFLICT, "Only draft campaigns can be started").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "start_campaign: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/campaigns/:id/pause` — `running` → `paused`.
+///
+/// Devices already in flight carry on; no new devices are started.
+pub async fn pause_campaign(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+    body: Option<Json<PauseRequest>>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id, Role::Editor).await {
+        return resp;
+    }
+
+    let reason = body
+        .and_then(|Json(b)| b.reason)
+        .unwrap_or_else(|| "Paused by operator".to_string());
+    transition(
+        &state,
+        id,
+        "UPDATE campaigns SET status = 'paused', pause_reason = $2, updated_at = now() \
+         WHERE id = $1 AND status = 'running' RETURNING id",
+        Some(reason),
+        "Only running campaigns can be paused",
+    )
+    .await
+}
+
+/// `POST /api/v1/campaigns/:id/resume` — `paused` → `running`.
+///
+/// Failures so far in the current wave are accepted: only further failures
+/// count towards the threshold.
+pub async fn resume_campaign(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id, Role::Editor).await {
+        return resp;
+    }
+
+    transition(
+        &state,
+        id,
+        r#"
+        UPDATE campaigns c
+        SET status = 'running', pause_reason = NULL, updated_at = now(),
+            failures_accepted = (
+                SELECT count(*) FROM campaign_devices
+                WHERE campaign_id = c.id AND wave = c.current_wave AND state = 'failed'
+            )
+        WHERE id = $1 AND status = 'paused'
+        RETURNING id
+        "#,
+        None,
+        "Only paused campaigns can be resumed",
+    )
+    .await
+}
+
+/// `POST /api/v1/campaigns/:id/cancel`
+///
+/// Stops the campaign for good. Download tasks not yet delivered are
+/// withdrawn; devices already downloading cannot be recalled.
+pub async fn cancel_campaign(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id, Role::Editor).await {
+        return resp;
+    }
+
+    let resp = transition(
+        &state,
+        id,
+        "UPDATE campaigns SET status = 'cancelled', completed_at = now(), updated_at = now() \
+         WHERE id = $1 AND status IN ('draft', 'running', 'paused') RETURNING id",
+        None,
+        "Campaign has already finished",
+    )
+    .await;
+
+    if resp.status().is_success() {
+        let withdrawn = sqlx::query(
+            r#"
+            DELETE FROM tasks t
+            USING campaign_devices cd
+            WHERE cd.task_id = t.id AND cd.campaign_id = $1 AND t.status = 'pending'
+            "#,
+        )
+        .bind(id)
+        .execute(&state.pool)
+        .await;
+        if let Err(e) = withdrawn {
+            tracing::error!(?e, %id, "cancel_campaign: failed to withdraw pending downloads");
+        }
+    }
+    resp
+}
+
+/// `DELETE /api/v1/campaigns/:id` — only `draft`, `completed` or `cancelled`.
+pub async fn delete_campaign(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id, Role::Editor).await {
+        return resp;
+    }
+
+    let result: Result<Option<Uuid>, sqlx::Error> = sqlx::query_scalar(
+        "DELETE FROM campaigns WHERE id = $1 AND status IN ('draft', 'completed', 'cancelled') RETURNING id",
+    )
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await;
+
+    match result {
+        Ok(Some(_)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(None) => (StatusCode::CONFLICT, "Running or paused campaigns must be cancelled first").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "delete_campaign: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+// ── Helpers ───────────────────────────────────────────────────────────────────
+
+/// Load a campaign the caller holds at least `min` on. Campaigns in
+/// domains the caller cannot see are reported as `404`.
+async fn authorize(state: &ApiState, principal: &Principal, id: Uuid, min: Role) -> Result<Campaign, Response> {
+    let result = sqlx::query_as::<_, Campaign>(&format!("SELECT {CAMPAIGN_COLUMNS} FROM campaigns WHERE id = $1"))
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await;
+
+    match result {
+        Ok(Some(c)) if principal.has_role(c.domain_id, min) => Ok(c),
+        Ok(Some(c)) if principal.can_view(c.domain_id) => Err(forbidden()),
+        Ok(_) => Err((StatusCode::NOT_FOUND, "Campaign not found").into_response()),
+        Err(e) => {
+            tracing::error!(?e, %id, "campaign lookup: db error");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response())
+        }
+    }
+}
+
+/// Run a guarded status `UPDATE … RETURNING id`; no row means the campaign
+/// was not in a state the transition applies to.
+async fn transition(
+    state: &ApiState,
+    id: Uuid,
+    sql: &str,
+    reason: Option<String>,
+    conflict: &'static str,
+) -> Response {
+    let mut query = sqlx::query_scalar::<_, Uuid>(sql).bind(id);
+    if let Some(reason) = reason {
+        query = query.bind(reason);
+    }
+
+    match query.fetch_optional(&state.pool).await {
+        Ok(Some(_)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(None) => (StatusCode::CONFLICT, conflict).into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "campaign transition: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Asynchronous command API.
+//!
+//! `POST /device/:uid/commands` stores one or more actions as a batch of
+//! tasks (see [`crate::tasks`]) and answers `202` straight away with the
+//! batch id. Clients then poll `GET /commands/:id` or follow
+//! `GET /commands/:id/events`, a Server-Sent-Events stream that pushes each
+//! `DeviceResponse` as it arrives.
+
+use std::convert::Infallible;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    Extension, Json,
+};
+use nats_common::Action;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+use crate::api::datamodel::rejected;
+use crate::api::inventory::visible_device;
+use crate::api::state::ApiState;
+use crate::api::tasks::deliver_or_wake;
+use crate::auth::{forbidden, Principal, Role};
+use crate::datamodel;
+use crate::tasks;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateCommandRequest {
+    /// Executed in order, within a single session.
+    pub actions:         Vec<Action>,
+    /// Seconds from now after which undelivered actions expire.
+    pub expires_in_secs: Option<i64>,
+}
+
+/// Status of an async command and each of its actions.
+#[derive(Debug, Serialize)]
+pub struct CommandInfo {
+    pub id:         Uuid,
+    pub device_uid: String,
+    /// `queued` | `running` | `succeeded` | `failed`
+    pub status:     &'static str,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub actions:    Vec<CommandAction>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct CommandAction {
+    pub seq:          i32,
+    pub task_id:      Uuid,
+    pub action:       JsonValue,
+    pub status:       String,
+    pub last_error:   Option<String>,
+    pub sent_at:      Option<chrono::DateTime<chrono::Utc>>,
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Latest `ActionResult` received for this action.
+    pub result:       Option<JsonValue>,
+    #[serde(skip)]
+    pub device_uid:   String,
+    #[serde(skip)]
+    pub domain_id:    Uuid,
+    #[serde(skip)]
+    pub created_at:   chrono::DateTime<chrono::Utc>,
+}
+
+impl CommandInfo {
+    fn is_final(&self) -> bool {
+        matches!(self.status, "succeeded" | "failed")
+    }
+}
+
+/// `POST /api/v1/device/:uid/commands` — requires `domain_editor`.
+///
+/// Returns `202` with the command id as soon as the actions are stored.
+/// A device in a session receives them immediately; otherwise a connection
+/// request is sent and they are delivered on the next Inform.
+pub async fn create_command(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(uid): Path<String>,
+    Json(mut body): Json<CreateCommandRequest>,
+) -> impl IntoResponse {
+    let (device_id, domain_id) = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, domain_id))) if principal.has_role(domain_id, Role::Editor) => (device_id, domain_id),
+        Ok(Some(_)) => return forbidden(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "create_command: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    if body.actions.is_empty() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "actions must not be empty").into_response();
+    }
+    if body.expires_in_secs.is_some_and(|s| s < 1) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "expires_in_secs must be positive").into_response();
+    }
+    let mut problems = Vec::new();
+    for action in &mut body.actions {
+        match datamodel::check(&state.pool, device_id, action).await {
+            Ok(p) => problems.extend(p),
+            Err(e) => {
+                tracing::error!(?e, %uid, "create_command: db error");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+            }
+        }
+    }
+    if !problems.is_empty() {
+        return rejected(&problems);
+    }
+
+    let id = match tasks::enqueue_batch(
+        &state.pool,
+        device_id,
+        &body.actions,
+        body.expires_in_secs,
+        Some(principal.user_id),
+    )
+    .await
+    {
+        Ok(id) => id,
+        Err(e) => {
+            tracing::error!(?e, %uid, "create_command: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    tracing::info!(%uid, command_id = %id, actions = body.actions.len(), "Async command queued");
+    deliver_or_wake(&state, device_id, domain_id, &uid).await;
+
+    (
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({
+            "id": id,
+            "status_url": format!("/api/v1/commands/{id}"),
+            "events_url": format!("/api/v1/commands/{id}/events"),
+        })),
+    )
+        .into_response()
+}
+
+/// `GET /api/v1/commands/:id`
+pub async fn get_command(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match load_command(&state, id).await {
+        Ok(Some((domain_id, info))) if principal.can_view(domain_id) => (StatusCode::OK, Json(info)).into_response(),
+        Ok(_) => (StatusCode::NOT_FOUND, "Command not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "get_command: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/commands/:id/events` — Server-Sent Events.
+///
+/// Sends a `status` event with the current [`CommandInfo`], then a
+/// `response` event per device response, and finally a `done` event with
+/// the final status before closing. Browsers' `EventSource` cannot set
+/// headers, so authenticate with `?access_token=`.
+pub async fn command_events(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    // Subscribe before the snapshot so no response falls in between.
+    let mut updates = state.task_updates.subscribe();
+
+    let info = match load_command(&state, id).await {
+        Ok(Some((domain_id, info))) if principal.can_view(domain_id) => info,
+        Ok(_) => return (StatusCode::NOT_FOUND, "Command not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "command_events: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let (tx, rx) = mpsc::channel::<Result<Event, Infallible>>(16);
+
+    tokio::spawn(async move {
+        if info.is_final() {
+            let _ = tx.send(Ok(json_event("done", &info))).await;
+            return;
+        }
+        if tx.send(Ok(json_event("status", &info))).await.is_err() {
+            return;
+        }
+
+        loop {
+            let refresh = tokio::select! {
+                _ = tx.closed() => return,
+                update = updates.recv() => match update {
+                    Ok(update) if update.batch_id == Some(id) => {
+                        if tx.send(Ok(json_event("response", &update))).await.is_err() {
+                            return;
+                        }
+                        true
+                    }
+                    Ok(_) => false,
+                    // Missed updates: resynchronise from the database.
+                    Err(broadcast::error::RecvError::Lagged(_)) => true,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+            };
+            if !refresh {
+                continue;
+            }
+
+            match load_command(&state, id).await {
+                Ok(Some((_, info))) if info.is_final() => {
+                    let _ = tx.send(Ok(json_event("done", &info))).await;
+                    return;
+                }
+                Ok(Some(_)) => {}
+                // Deleted with its device.
+                Ok(None) => return,
+                Err(e) => {
+                    tracing::error!(?e, %id, "command_events: db error");
+                    return;
+                }
+            }
+        }
+    });
+
+    Sse::new(ReceiverStream::new(rx))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+// ── Helpers ───────────────────────────────────────────────────────────────────
+
+/// The command's device domain and current status, or `None` if no task
+/// carries this batch id.
+async fn load_command(state: &ApiState, id: Uuid) -> Result<Option<(Uuid, CommandInfo)>, sqlx::Error> {
+    let actions = sqlx::query_as::<_, CommandAction>(
+        r#"
+        SELECT t.batch_seq AS seq, t.id AS task_id, t.action, t.status, t.last_error,
+               t.sent_at, t.completed_at, r.result, d.device_uid, d.domain_id, t.created_at
+        FROM tasks t
+        JOIN devices d ON d.id = t.device_id
+        LEFT JOIN LATERAL (
+            SELECT result FROM task_results
+            WHERE task_id = t.id
+            ORDER BY received_at DESC
+            LIMIT 1
+        ) r ON true
+        WHERE t.batch_id = $1
+        ORDER BY t.batch_seq
+        "#,
+    )
+    .bind(id)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let Some(first) = actions.first() else {
+        return Ok(None);
+    };
+    let domain_id = first.domain_id;
+    let info = CommandInfo {
+        id,
+        device_uid: first.device_uid.clone(),
+        status:     tasks::batch_status(actions.iter().map(|a| a.status.as_str())),
+        created_at: first.created_at,
+        actions,
+    };
+    Ok(Some((domain_id, info)))
+}
+
+fn json_event<T: Serialize>(name: &str, data: &T) -> Event {
+    Event::default()
+        .event(name)
+        .json_data(data)
+        // Serialising our own response types cannot fail.
+        .expect("event data serialises")
+}
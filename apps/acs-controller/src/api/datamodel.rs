@@ -0,0 +1,228 @@
+//! Data model catalog API.
+//!
+//! The catalog (see [`crate::datamodel`]) is shared by all domains: every
+//! user can browse it, super admins import and delete models. Documents are
+//! posted as raw XML; a device type can be bound to the devices it
+//! describes with `?oui=` and `?product_class=`.
+//!
+//! `GET /api/v1/device/:uid/datamodel?path=` looks a path up in the model
+//! that applies to the device, for the GUI's object browser.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::api::inventory::visible_device;
+use crate::api::state::ApiState;
+use crate::auth::{forbidden, Principal};
+use crate::datamodel::{self, import, DataModel, Entry, Kind, ENTRY_COLUMNS, MODEL_COLUMNS};
+
+/// Largest document accepted; TR-181 "-full" documents are around 10 MiB.
+pub const MAX_DOCUMENT_SIZE: usize = 64 * 1024 * 1024;
+
+// ── Request types ─────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    /// Device types only: the devices the type applies to.
+    pub oui:           Option<String>,
+    pub product_class: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EntriesQuery {
+    /// Only entries under this path, e.g. `Device.WiFi.`.
+    pub prefix: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NodeQuery {
+    /// A device path; instance numbers are allowed.
+    pub path: String,
+}
+
+// ── Handlers ──────────────────────────────────────────────────────────────────
+
+/// `GET /api/v1/datamodel/models`
+pub async fn list_models(State(state): State<ApiState>) -> impl IntoResponse {
+    let result = sqlx::query_as::<_, DataModel>(&format!(
+        "SELECT {MODEL_COLUMNS} FROM data_models m ORDER BY m.root, m.kind, m.name"
+    ))
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(models) => (StatusCode::OK, Json(models)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, "list_models: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/datamodel/models` — super admins only. The body is a
+/// cwmp-datamodel or device type XML document; each model in it is stored,
+/// replacing an earlier import of the same model.
+///
+/// A device type needs the data model it refers to imported first, for the
+/// types it does not restate.
+pub async fn import_models(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ImportQuery>,
+    body: String,
+) -> impl IntoResponse {
+    if !principal.is_super_admin {
+        return forbidden();
+    }
+
+    // Large documents take a while to read; keep that off the runtime.
+    let models = match tokio::task::spawn_blocking(move || import::parse(&body)).await {
+        Ok(Ok(models)) => models,
+        Ok(Err(e)) => return (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response(),
+        Err(e) => {
+            tracing::error!(?e, "import_models: parser panicked");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read the document").into_response();
+        }
+    };
+
+    let bound = query.oui.is_some() || query.product_class.is_some();
+    let mut stored = Vec::with_capacity(models.len());
+    for mut model in models {
+        if model.kind == Kind::Datamodel && bound {
+            return (StatusCode::UNPROCESSABLE_ENTITY, "oui and product_class apply to device types only").into_response();
+        }
+        if model.kind == Kind::DeviceType {
+            let base = model.base.clone().unwrap_or_default();
+            match datamodel::base_entries(&state.pool, &base).await {
+                Ok(Some(entries)) => model.inherit(&entries),
+                Ok(None) => {
+                    return (StatusCode::UNPROCESSABLE_ENTITY, format!("Import the {base} data model before device types of it"))
+                        .into_response()
+                }
+                Err(e) => {
+                    tracing::error!(?e, "import_models: db error");
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+                }
+            }
+        }
+
+        match datamodel::store(&state.pool, &model, query.oui.as_deref(), query.product_class.as_deref(), principal.user_id).await {
+            Ok(m) => {
+                tracing::info!(model_id = %m.id, name = %m.name, kind = %m.kind, entries = m.entries, user = %principal.email, "Data model imported");
+                stored.push(m);
+            }
+            Err(e) => {
+                tracing::error!(?e, name = %model.name, "import_models: db error");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+            }
+        }
+    }
+
+    (StatusCode::CREATED, Json(stored)).into_response()
+}
+
+/// `GET /api/v1/datamodel/models/:id`
+pub async fn get_model(State(state): State<ApiState>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    let result = sqlx::query_as::<_, DataModel>(&format!("SELECT {MODEL_COLUMNS} FROM data_models m WHERE m.id = $1"))
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await;
+
+    match result {
+        Ok(Some(model)) => (StatusCode::OK, Json(model)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Data model not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "get_model: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/datamodel/models/:id/entries?prefix=` — in path order.
+pub async fn list_entries(
+    State(state): State<ApiState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<EntriesQuery>,
+) -> impl IntoResponse {
+    let prefix = query.prefix.as_deref().map(datamodel::normalize).unwrap_or_default();
+    let result = sqlx::query_as::<_, Entry>(&format!(
+        r#"
+        SELECT {ENTRY_COLUMNS} FROM data_model_entries
+        WHERE model_id = $1 AND starts_with(path, $2)
+        ORDER BY path
+        "#
+    ))
+    .bind(id)
+    .bind(&prefix)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(entries) if entries.is_empty() => (StatusCode::NOT_FOUND, "No such entries").into_response(),
+        Ok(entries) => (StatusCode::OK, Json(entries)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "list_entries: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `DELETE /api/v1/datamodel/models/:id` — super admins only.
+pub async fn delete_model(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !principal.is_super_admin {
+        return forbidden();
+    }
+    match sqlx::query("DELETE FROM data_models WHERE id = $1").bind(id).execute(&state.pool).await {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Data model not found").into_response(),
+        Ok(_) => {
+            tracing::info!(model_id = %id, user = %principal.email, "Data model deleted");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, %id, "delete_model: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/device/:uid/datamodel?path=` — the definition of `path`
+/// in the device's model and, for objects, of what is directly below it.
+pub async fn device_node(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(uid): Path<String>,
+    Query(query): Query<NodeQuery>,
+) -> impl IntoResponse {
+    let device_id = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, _))) => device_id,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "device_node: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    match datamodel::node(&state.pool, device_id, &query.path).await {
+        Ok(Some(node)) => (StatusCode::OK, Json(node)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "No data model covers the path").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "device_node: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// The `422` for an action the device's model refuses.
+pub fn rejected(problems: &[String]) -> axum::response::Response {
+    (StatusCode::UNPROCESSABLE_ENTITY, format!("Rejected by the data model:\n{}", problems.join("\n"))).into_response()
+}
@@ -1,78 +1,101 @@
 use std::time::Duration;
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
-    Json,
+    Extension, Json,
 };
 use nats_common::{Action, DeviceCommand, DeviceResponse};
-use tokio::sync::oneshot;
+use serde::Deserialize;
+use tokio_stream::StreamExt;
 use uuid::Uuid;
 
+use crate::api::datamodel::rejected;
+use crate::api::inventory::visible_device;
 use crate::api::state::ApiState;
+use crate::auth::{forbidden, Principal, Role};
+use crate::datamodel;
+use crate::health;
+use crate::sessions;
+use crate::tasks::{self, NewTask};
 
+#[derive(Debug, Deserialize)]
+pub struct SendCommandQuery {
+    /// Queue the command as a task instead of failing when the device
+    /// cannot be reached.
+    #[serde(default)]
+    pub queue: bool,
+}
+
+/// `POST /api/v1/device/:uid/command[?queue=true]` — requires `domain_editor`.
+///
+/// With `queue=true`, a device that cannot be woken gets the command as a
+/// persistent task (`202` with the task) rather than a `504`. A session
+/// that ends before the device responds is a `502`.
 pub async fn send_command(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Path(uid): Path<String>,
-    Json(action): Json<Action>,
+    Query(query): Query<SendCommandQuery>,
+    Json(mut action): Json<Action>,
 ) -> impl IntoResponse {
+    let device_id = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, domain_id))) if principal.has_role(domain_id, Role::Editor) => device_id,
+        Ok(Some(_)) => return forbidden(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "send_command: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+    match datamodel::check(&state.pool, device_id, &mut action).await {
+        Ok(problems) if problems.is_empty() => {}
+        Ok(problems) => return rejected(&problems),
+        Err(e) => {
+            tracing::error!(?e, %uid, "send_command: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    }
+
     // 1. Check if the device is currently online
-    let mut session_id_opt = state.active_sessions.get(&uid).map(|s| s.clone());
-
-    if session_id_opt.is_none() {
-        tracing::info!(%uid, "Device offline. Querying connection request details...");
-        
-        let row: Result<(Option<String>, Option<String>), sqlx::Error> = sqlx::query_as(
-            r#"
-            SELECT dp.connection_request_url, dp.username
-            FROM device_protocols dp
-            JOIN devices d ON dp.device_id = d.id
-            WHERE d.device_uid = $1 AND dp.protocol = 'cwmp'
-            "#,
-        )
-        .bind(&uid)
-        .fetch_one(&state.pool)
-        .await;
-
-        match row {
-            Ok((Some(url), username)) => {
-                let payload = serde_json::json!({
-                    "device_id": uid,
-                    "connection_request_url": url,
-                    "username": username,
-                    "password": Option::<String>::None // To be implemented if we store passwords
-                });
-                
-                if let Ok(bytes) = serde_json::to_vec(&payload) {
-                    if let Err(e) = state.nats.publish_connection_request(bytes).await {
-                        tracing::error!(?e, "Failed to publish connection request");
-                    } else {
-                        tracing::info!(%uid, "Published connection request, waiting for device...");
-                        
-                        // Poll for up to 15 seconds
-                        let timeout = tokio::time::Instant::now() + Duration::from_secs(15);
-                        while tokio::time::Instant::now() < timeout {
-                            if let Some(s) = state.active_sessions.get(&uid) {
-                                session_id_opt = Some(s.clone());
-                                break;
-                            }
-                            tokio::time::sleep(Duration::from_millis(250)).await;
-                        }
-                    }
-                }
-            }
-            Ok((None, _)) => {
-                tracing::warn!(%uid, "Device found but no connection request URL recorded.");
-            }
-            Err(e) => {
-                tracing::warn!(?e, %uid, "Failed to fetch connection request details.");
+    let mut session_id_opt = match state.sessions.get(&uid).await {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!(?e, %uid, "send_command: session lookup failed");
+            return (StatusCode::SERVICE_UNAVAILABLE, "Session routing unavailable").into_response();
+        }
+    };
+
+    if session_id_opt.is_none() && request_connection(&state, device_id, &uid).await {
+        tracing::info!(%uid, "Published connection request, waiting for device...");
+
+        // Poll for up to 15 seconds; the Inform may land on any replica.
+        let timeout = tokio::time::Instant::now() + Duration::from_secs(15);
+        while tokio::time::Instant::now() < timeout {
+            if let Ok(Some(s)) = state.sessions.get(&uid).await {
+                session_id_opt = Some(s);
+                break;
             }
+            tokio::time::sleep(Duration::from_millis(500)).await;
         }
     }
 
     let session_id = match session_id_opt {
         Some(s) => s,
+        None if query.queue => {
+            let task = NewTask { action, priority: None, expires_in_secs: None, max_attempts: None };
+            return match tasks::enqueue(&state.pool, device_id, &task, Some(principal.user_id)).await {
+                Ok(task) => {
+                    tracing::info!(%uid, task_id = %task.id, "Device unreachable, command queued as task");
+                    (StatusCode::ACCEPTED, Json(task)).into_response()
+                }
+                Err(e) => {
+                    tracing::error!(?e, %uid, "send_command: failed to queue task");
+                    (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+                }
+            };
+        }
         None => {
             return (
                 StatusCode::GATEWAY_TIMEOUT,
@@ -83,7 +106,8 @@ pub async fn send_command(
     };
 
 
-    // 2. Prepare the command and a oneshot channel to await the response
+    // 2. Prepare the command and subscribe to its reply. Whichever replica
+    //    receives the device's response forwards it there.
     let command_id = Uuid::new_v4();
     let command = DeviceCommand {
         command_id,
@@ -91,15 +115,23 @@ pub async fn send_command(
         action,
     };
 
-    let (tx, rx) = oneshot::channel::<DeviceResponse>();
-    state.pending_commands.insert(command_id, tx);
+    let subscriptions = tokio::try_join!(
+        state.nats.subscribe_command_reply(command_id),
+        state.nats.subscribe_session_ended(&session_id),
+    );
+    let (mut replies, mut session_ended) = match subscriptions {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!(?e, %command_id, "Failed to subscribe to command reply");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to subscribe to NATS").into_response();
+        }
+    };
 
     // 3. Serialize and publish the command to NATS
     let payload = match serde_json::to_vec(&command) {
         Ok(p) => p,
         Err(e) => {
             tracing::error!(?e, "Failed to serialize DeviceCommand");
-            state.pending_commands.remove(&command_id);
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Failed to serialize command",
@@ -110,7 +142,6 @@ pub async fn send_command(
 
     if let Err(e) = state.nats.publish_command(&session_id, payload).await {
         tracing::error!(?e, %session_id, "Failed to publish command to NATS");
-        state.pending_commands.remove(&command_id);
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
             "Failed to publish command to NATS",
@@ -119,25 +150,91 @@ pub async fn send_command(
     }
 
     tracing::info!(%uid, %session_id, %command_id, "Command published, awaiting response");
+    if let Err(e) = sessions::count_commands(&state.pool, &session_id, 1).await {
+        tracing::error!(?e, %session_id, "Failed to count session command");
+    }
 
-    // 4. Await the response with a timeout
-    match tokio::time::timeout(Duration::from_secs(30), rx).await {
-        Ok(Ok(response)) => {
-            // Received response from device
-            (StatusCode::OK, Json(response)).into_response()
+    // 4. Await the response with a timeout. A response is always relayed
+    //    before the end of its session, so check for it first.
+    let outcome = tokio::time::timeout(Duration::from_secs(30), async {
+        tokio::select! {
+            biased;
+            reply = replies.next() => reply,
+            _ = session_ended.next() => None,
         }
-        Ok(Err(_)) => {
-            // The sender was dropped (e.g. session ended unexpectedly)
+    })
+    .await;
+    match outcome {
+        Ok(Some(msg)) => match serde_json::from_slice::<DeviceResponse>(&msg.payload) {
+            // Received response from device
+            Ok(response) => {
+                health::learn_from_answer(&state.pool, device_id, &command.action, &response.result).await;
+                (StatusCode::OK, Json(response)).into_response()
+            }
+            Err(e) => {
+                tracing::error!(?e, %command_id, "Malformed command reply");
+                (StatusCode::BAD_GATEWAY, "Malformed device response").into_response()
+            }
+        },
+        Ok(None) => {
+            // The session ended (or the NATS connection was lost) first
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Session ended before response was received",
+                StatusCode::BAD_GATEWAY,
+                "Device session ended before a response was received",
             )
                 .into_response()
         }
         Err(_) => {
             // Timeout
-            state.pending_commands.remove(&command_id);
             (StatusCode::GATEWAY_TIMEOUT, "Device response timeout").into_response()
         }
     }
 }
+
+/// Ask the connection requester to wake the device `device_id` (`uid`).
+///
+/// Returns `true` once the request is published; the device still has to
+/// open a session on its own. `false` if no connection request URL is known
+/// or publishing failed (both are logged).
+pub(crate) async fn request_connection(state: &ApiState, device_id: Uuid, uid: &str) -> bool {
+    tracing::info!(%uid, "Device offline. Querying connection request details...");
+
+    let row: Result<(Option<String>, Option<String>), sqlx::Error> = sqlx::query_as(
+        r#"
+        SELECT dp.connection_request_url, dp.username
+        FROM device_protocols dp
+        WHERE dp.device_id = $1 AND dp.protocol = 'cwmp'
+        "#,
+    )
+    .bind(device_id)
+    .fetch_one(&state.pool)
+    .await;
+
+    match row {
+        Ok((Some(url), username)) => {
+            let payload = serde_json::json!({
+                "device_id": uid,
+                "connection_request_url": url,
+                "username": username,
+                "password": Option::<String>::None // To be implemented if we store passwords
+            });
+
+            let Ok(bytes) = serde_json::to_vec(&payload) else {
+                return false;
+            };
+            if let Err(e) = state.nats.publish_connection_request(bytes).await {
+                tracing::error!(?e, "Failed to publish connection request");
+                return false;
+            }
+            true
+        }
+        Ok((None, _)) => {
+            tracing::warn!(%uid, "Device found but no connection request URL recorded.");
+            false
+        }
+        Err(e) => {
+            tracing::warn!(?e, %uid, "Failed to fetch connection request details.");
+            false
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Real-time event stream API.
+//!
+//! `GET /events` streams [`crate::events`] as Server-Sent Events;
+//! `GET /events/ws` streams the same events over a WebSocket. Both accept
+//! the same filters and resume tokens and only carry events of domains the
+//! caller can view. Memberships are checked when the stream opens; a
+//! revoked membership takes effect when the client reconnects.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+    Extension,
+};
+use serde::Deserialize;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+
+use crate::api::inventory::visible_device;
+use crate::api::state::ApiState;
+use crate::auth::Principal;
+use crate::db;
+use crate::events::{EventFilter, EventHub, EventKind, Start, StreamEvent};
+
+#[derive(Debug, Deserialize)]
+pub struct EventStreamQuery {
+    /// Comma-separated domain slugs.
+    pub domain: Option<String>,
+    /// Device UID.
+    pub device: Option<String>,
+    /// Comma-separated event types, e.g. `inform,task_status`.
+    pub types:  Option<String>,
+    /// Resume token; SSE clients may send `Last-Event-ID` instead.
+    pub resume: Option<String>,
+}
+
+/// What the stream sends, in order.
+enum Outgoing {
+    Event(Arc<StreamEvent>),
+    /// Nothing new; carries the token to resume from.
+    Heartbeat(String),
+    /// Events were missed and cannot be replayed; the client should re-read
+    /// state from the REST API. Carries the token to resume from.
+    Reset(String),
+}
+
+impl Outgoing {
+    fn name(&self) -> &'static str {
+        match self {
+            Outgoing::Event(e)     => e.kind.as_str(),
+            Outgoing::Heartbeat(_) => "heartbeat",
+            Outgoing::Reset(_)     => "reset",
+        }
+    }
+
+    fn id(&self) -> &str {
+        match self {
+            Outgoing::Event(e) => &e.id,
+            Outgoing::Heartbeat(id) | Outgoing::Reset(id) => id,
+        }
+    }
+
+    fn json(&self) -> String {
+        // Our own event types always serialise.
+        match self {
+            Outgoing::Event(e) => serde_json::to_string(&**e).expect("StreamEvent serialises"),
+            other => serde_json::json!({ "type": other.name(), "id": other.id() }).to_string(),
+        }
+    }
+}
+
+// ── Handlers ──────────────────────────────────────────────────────────────────
+
+/// `GET /api/v1/events[?domain=&device=&types=&resume=]` — Server-Sent Events.
+///
+/// Each event's SSE `id` is its resume token, so a reconnecting
+/// `EventSource` resumes automatically via `Last-Event-ID`.
+pub async fn event_stream(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<EventStreamQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let filter = match build_filter(&state, &principal, &query).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+    let resume = query
+        .resume
+        .or_else(|| headers.get("last-event-id").and_then(|v| v.to_str().ok()).map(str::to_string));
+
+    let rx = spawn_stream(state.events.clone(), filter, resume.as_deref());
+    let stream = ReceiverStream::new(rx).map(|out| {
+        Ok::<_, Infallible>(Event::default().id(out.id()).event(out.name()).data(out.json()))
+    });
+    Sse::new(stream).into_response()
+}
+
+/// `GET /api/v1/events/ws[?domain=&device=&types=&resume=]` — WebSocket.
+///
+/// Every message is a JSON text frame with a `type` and an `id` (the resume
+/// token). Messages sent by the client are ignored.
+pub async fn event_socket(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<EventStreamQuery>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let filter = match build_filter(&state, &principal, &query).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+    let rx = spawn_stream(state.events.clone(), filter, query.resume.as_deref());
+    ws.on_upgrade(move |socket| forward_to_socket(socket, rx))
+}
+
+async fn forward_to_socket(mut socket: WebSocket, mut rx: mpsc::Receiver<Outgoing>) {
+    loop {
+        tokio::select! {
+            out = rx.recv() => {
+                let Some(out) = out else { break };
+                if socket.send(Message::Text(out.json())).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                // Pings are answered by axum; anything else is ignored.
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+// ── Helpers ───────────────────────────────────────────────────────────────────
+
+/// Turn the query into a filter limited to the caller's domains. Unknown or
+/// invisible domains and devices are `404`, unknown types `422`.
+async fn build_filter(
+    state: &ApiState,
+    principal: &Principal,
+    query: &EventStreamQuery,
+) -> Result<EventFilter, Response> {
+    let db_error = |e: sqlx::Error| {
+        tracing::error!(?e, "event stream: db error");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+    };
+
+    let domains = match query.domain.as_deref() {
+        Some(slugs) => {
+            let mut ids = Vec::new();
+            for slug in slugs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                match db::get_domain_id(&state.pool, slug).await.map_err(db_error)? {
+                    Some(id) if principal.can_view(id) => ids.push(id),
+                    _ => return Err((StatusCode::NOT_FOUND, format!("Domain '{slug}' not found")).into_response()),
+                }
+            }
+            Some(ids)
+        }
+        None => principal.visible_domains(),
+    };
+
+    if let Some(ref uid) = query.device {
+        if visible_device(state, principal, uid).await.map_err(db_error)?.is_none() {
+            return Err((StatusCode::NOT_FOUND, "Device not found").into_response());
+        }
+    }
+
+    let kinds = match query.types.as_deref() {
+        Some(types) => {
+            let mut kinds = Vec::new();
+            for t in types.split(',').map(str::trim) {
+                match EventKind::parse(t) {
+                    Some(kind) => kinds.push(kind),
+                    None => {
+                        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("unknown event type '{t}'")).into_response())
+                    }
+                }
+            }
+            Some(kinds)
+        }
+        None => None,
+    };
+
+    Ok(EventFilter { domains, device: query.device.clone(), kinds })
+}
+
+/// Feed a client's stream: replayed events first, then live ones, with a
+/// heartbeat whenever the stream has been idle for the hub's interval.
+///
+/// A client that falls behind the live channel is caught up from the
+/// hub's buffer, or sent a reset if the buffer has moved on too.
+fn spawn_stream(hub: EventHub, filter: EventFilter, resume: Option<&str>) -> mpsc::Receiver<Outgoing> {
+    let (tx, rx) = mpsc::channel::<Outgoing>(64);
+    let (start, head, mut live) = hub.subscribe(resume);
+
+    tokio::spawn(async move {
+        // Highest sequence number handled, matching or not.
+        let mut last = head;
+        // Events filtered out don't count as activity.
+        let mut idle_since = tokio::time::Instant::now();
+
+        let replay = match start {
+            Start::Resume(events) => events,
+            Start::Live { reset } => {
+                if reset && tx.send(Outgoing::Reset(hub.token(head))).await.is_err() {
+                    return;
+                }
+                Vec::new()
+            }
+        };
+        for event in replay {
+            if filter.matches(&event) && tx.send(Outgoing::Event(event)).await.is_err() {
+                return;
+            }
+        }
+
+        loop {
+            let batch = tokio::select! {
+                _ = tx.closed() => return,
+                _ = tokio::time::sleep_until(idle_since + hub.heartbeat()) => {
+                    if tx.send(Outgoing::Heartbeat(hub.token(last))).await.is_err() {
+                        return;
+                    }
+                    idle_since = tokio::time::Instant::now();
+                    continue;
+                }
+                received = live.recv() => match received {
+                    Ok(event) => vec![event],
+                    Err(broadcast::error::RecvError::Lagged(_)) => match hub.replay_after(last) {
+                        Some(events) => events,
+                        None => {
+                            last = hub.head();
+                            if tx.send(Outgoing::Reset(hub.token(last))).await.is_err() {
+                                return;
+                            }
+                            continue;
+                        }
+                    },
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+            };
+
+            for event in batch {
+                // Replays after a lag overlap with what the channel still holds.
+                if event.seq <= last {
+                    continue;
+                }
+                last = event.seq;
+                if filter.matches(&event) {
+                    if tx.send(Outgoing::Event(event)).await.is_err() {
+                        return;
+                    }
+                    idle_since = tokio::time::Instant::now();
+                }
+            }
+        }
+    });
+    rx
+}
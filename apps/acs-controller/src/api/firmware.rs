@@ -0,0 +1,390 @@
+//! Firmware catalog API.
+//!
+//! Images belong to a domain or are shared by all (see [`crate::firmware`]).
+//! Shared images are visible to every user and managed by super admins; a
+//! domain's images are visible to its viewers and managed by its admins.
+//! Upgrading a device to a version requires `domain_editor`.
+//!
+//! An image registered without a URL awaits its file, uploaded with
+//! `PUT /api/v1/firmware/images/:id/file` and then served to devices from
+//! `/firmware/files/:id`.
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::inventory::{is_check_violation, visible_device};
+use crate::api::state::ApiState;
+use crate::api::tasks::deliver_or_wake;
+use crate::auth::{forbidden, Principal, Role};
+use crate::db;
+use crate::firmware::{self, Choice, Image, UploadError, IMAGE_COLUMNS};
+use crate::tasks::{self, NewTask, Task};
+
+// ── Request / response types ──────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct ImageListQuery {
+    /// Filter by domain slug; shared images are always included.
+    pub domain:  Option<String>,
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateImageRequest {
+    /// Domain slug. Omitted: a shared image (super admins only).
+    pub domain:            Option<String>,
+    pub version:           String,
+    pub file_type:         Option<String>,
+    /// Omitted: the file is uploaded afterwards.
+    pub url:               Option<String>,
+    pub file_size:         Option<i64>,
+    pub sha256:            Option<String>,
+    pub target_filename:   Option<String>,
+    #[serde(default)]
+    pub ouis:              Vec<String>,
+    #[serde(default)]
+    pub product_classes:   Vec<String>,
+    #[serde(default)]
+    pub hardware_versions: Vec<String>,
+    pub release_notes:     Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpgradeRequest {
+    pub version:         String,
+    pub priority:        Option<i32>,
+    pub expires_in_secs: Option<i64>,
+    pub max_attempts:    Option<i32>,
+}
+
+/// The image chosen for an upgrade and the Download task installing it.
+#[derive(Debug, Serialize)]
+pub struct UpgradeResponse {
+    pub image: Image,
+    pub task:  Task,
+}
+
+// ── Handlers ──────────────────────────────────────────────────────────────────
+
+/// `GET /api/v1/firmware/images[?domain=<slug>][&version=<v>]` — by version,
+/// newest first.
+pub async fn list_images(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ImageListQuery>,
+) -> impl IntoResponse {
+    let result = sqlx::query_as::<_, Image>(&format!(
+        r#"
+        SELECT {IMAGE_COLUMNS} FROM firmware_images
+        WHERE ($1::UUID[] IS NULL OR domain_id IS NULL OR domain_id = ANY($1))
+          AND ($2::TEXT IS NULL OR domain_id IS NULL OR domain_id = (SELECT id FROM domains WHERE slug = $2))
+          AND ($3::TEXT IS NULL OR version = $3)
+        ORDER BY version, created_at DESC
+        "#
+    ))
+    .bind(principal.visible_domains())
+    .bind(&query.domain)
+    .bind(&query.version)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(images) => (StatusCode::OK, Json(images)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, "list_images: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/firmware/images` — register an image.
+///
+/// Without a `url` the image awaits its upload and is not installed until
+/// it arrives; a `sha256` given then must match the uploaded file.
+pub async fn create_image(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Json(body): Json<CreateImageRequest>,
+) -> impl IntoResponse {
+    let domain_id = match body.domain {
+        None if principal.is_super_admin => None,
+        None => return forbidden(),
+        Some(ref slug) => match db::get_domain_id(&state.pool, slug).await {
+            Ok(Some(id)) if principal.has_role(id, Role::Admin) => Some(id),
+            Ok(Some(id)) if principal.can_view(id) => return forbidden(),
+            Ok(_) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+            Err(e) => {
+                tracing::error!(?e, "create_image: db error");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+            }
+        },
+    };
+
+    if body.version.trim().is_empty() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "version must not be empty").into_response();
+    }
+    if body.url.as_deref().is_some_and(|u| u.trim().is_empty()) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "url must not be empty").into_response();
+    }
+    if body.url.is_none() && state.firmware.base_url.is_none() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "url is required: uploads are disabled (FIRMWARE_BASE_URL is unset)")
+            .into_response();
+    }
+    let sha256 = body.sha256.as_deref().map(str::to_ascii_lowercase);
+
+    let result = sqlx::query_as::<_, Image>(&format!(
+        r#"
+        INSERT INTO firmware_images (
+            domain_id, version, file_type, url, file_size, sha256, target_filename,
+            ouis, product_classes, hardware_versions, release_notes, created_by
+        )
+        VALUES ($1, $2, COALESCE($3, '1 Firmware Upgrade Image'), $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        RETURNING {IMAGE_COLUMNS}
+        "#
+    ))
+    .bind(domain_id)
+    .bind(body.version.trim())
+    .bind(&body.file_type)
+    .bind(&body.url)
+    .bind(body.file_size)
+    .bind(&sha256)
+    .bind(&body.target_filename)
+    .bind(&body.ouis)
+    .bind(&body.product_classes)
+    .bind(&body.hardware_versions)
+    .bind(&body.release_notes)
+    .bind(principal.user_id)
+    .fetch_one(&state.pool)
+    .await;
+
+    match result {
+        Ok(image) => {
+            tracing::info!(image_id = %image.id, version = %image.version, user = %principal.email, "Firmware image registered");
+            (StatusCode::CREATED, Json(image)).into_response()
+        }
+        Err(e) if is_check_violation(&e) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "file_size must be between 0 and 4294967295; sha256 must be 64 hex digits",
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!(?e, "create_image: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/firmware/images/:id`
+pub async fn get_image(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match fetch_image(&state, id).await {
+        Ok(Some(image)) if image.domain_id.is_none_or(|d| principal.can_view(d)) => {
+            (StatusCode::OK, Json(image)).into_response()
+        }
+        Ok(_) => (StatusCode::NOT_FOUND, "Image not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "get_image: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `DELETE /api/v1/firmware/images/:id` — also removes an uploaded file.
+///
+/// Campaigns already running keep the Downloads they queued.
+pub async fn delete_image(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let image = match managed_image(&state, &principal, id).await {
+        Ok(image) => image,
+        Err(response) => return response,
+    };
+
+    if let Err(e) = sqlx::query("DELETE FROM firmware_images WHERE id = $1").bind(id).execute(&state.pool).await {
+        tracing::error!(?e, %id, "delete_image: db error");
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+    }
+    if image.stored {
+        if let Err(e) = state.firmware.remove(id).await {
+            tracing::warn!(?e, %id, "delete_image: failed to remove the image file");
+        }
+    }
+
+    tracing::info!(image_id = %id, version = %image.version, user = %principal.email, "Firmware image deleted");
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// `PUT /api/v1/firmware/images/:id/file` — upload the file of an image
+/// registered without a URL. The body is the raw file.
+pub async fn upload_image_file(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+    body: Body,
+) -> impl IntoResponse {
+    let image = match managed_image(&state, &principal, id).await {
+        Ok(image) => image,
+        Err(response) => return response,
+    };
+    if image.url.is_some() {
+        return (StatusCode::CONFLICT, "Image already has a file").into_response();
+    }
+    let Some(url) = state.firmware.url(id) else {
+        return (StatusCode::CONFLICT, "Uploads are disabled (FIRMWARE_BASE_URL is unset)").into_response();
+    };
+
+    let (size, sha256) = match state.firmware.save(id, body.into_data_stream()).await {
+        Ok(saved) => saved,
+        Err(e @ UploadError::TooLarge(_)) => return (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()).into_response(),
+        Err(e @ UploadError::Body(_)) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        Err(UploadError::Io(e)) => {
+            tracing::error!(?e, %id, "upload_image_file: failed to store the file");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store the file").into_response();
+        }
+    };
+
+    if image.sha256.as_ref().is_some_and(|expected| *expected != sha256) {
+        if let Err(e) = state.firmware.remove(id).await {
+            tracing::warn!(?e, %id, "upload_image_file: failed to remove the rejected file");
+        }
+        return (StatusCode::UNPROCESSABLE_ENTITY, format!("sha256 of the upload is {sha256}, expected {}", image.sha256.unwrap_or_default()))
+            .into_response();
+    }
+
+    let result = sqlx::query_as::<_, Image>(&format!(
+        r#"
+        UPDATE firmware_images
+        SET url = $2, file_size = $3, sha256 = $4, stored = true
+        WHERE id = $1 AND url IS NULL
+        RETURNING {IMAGE_COLUMNS}
+        "#
+    ))
+    .bind(id)
+    .bind(url)
+    .bind(size as i64)
+    .bind(&sha256)
+    .fetch_optional(&state.pool)
+    .await;
+
+    match result {
+        Ok(Some(image)) => {
+            tracing::info!(image_id = %id, size, %sha256, user = %principal.email, "Firmware image uploaded");
+            (StatusCode::OK, Json(image)).into_response()
+        }
+        // Deleted, or given a file by a concurrent upload, meanwhile.
+        Ok(None) => (StatusCode::CONFLICT, "Image already has a file").into_response(),
+        Err(e) if is_check_violation(&e) => {
+            let _ = state.firmware.remove(id).await;
+            (StatusCode::PAYLOAD_TOO_LARGE, "Image is larger than 4294967295 bytes").into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, %id, "upload_image_file: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/device/:uid/firmware` — requires `domain_editor`.
+///
+/// Queues the Download of the image of `version` compatible with the device
+/// (see [`firmware::choose`]) like any other task. `404` if the catalog has
+/// no image of the version for the device's domain, `422` if none is
+/// compatible with the device.
+pub async fn upgrade_device(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(uid): Path<String>,
+    Json(body): Json<UpgradeRequest>,
+) -> impl IntoResponse {
+    let (device_id, domain_id) = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, domain_id))) if principal.has_role(domain_id, Role::Editor) => (device_id, domain_id),
+        Ok(Some(_)) => return forbidden(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "upgrade_device: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    if body.max_attempts.is_some_and(|n| n < 1) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "max_attempts must be at least 1").into_response();
+    }
+    if body.expires_in_secs.is_some_and(|s| s < 1) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "expires_in_secs must be positive").into_response();
+    }
+
+    let image = match firmware::choose(&state.pool, device_id, &body.version).await {
+        Ok(Choice::Image(image)) => image,
+        Ok(Choice::Unknown) => {
+            return (StatusCode::NOT_FOUND, format!("No firmware image of {}", body.version)).into_response()
+        }
+        Ok(Choice::Incompatible(why)) => {
+            let msg = format!("No firmware image of {} is compatible with the device: {why}", body.version);
+            return (StatusCode::UNPROCESSABLE_ENTITY, msg).into_response();
+        }
+        Err(e) => {
+            tracing::error!(?e, %uid, "upgrade_device: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+    let Some(action) = image.download() else {
+        return (StatusCode::NOT_FOUND, format!("No firmware image of {}", body.version)).into_response();
+    };
+
+    let task = NewTask {
+        action,
+        priority:        body.priority,
+        expires_in_secs: body.expires_in_secs,
+        max_attempts:    body.max_attempts,
+    };
+    let task = match tasks::enqueue(&state.pool, device_id, &task, Some(principal.user_id)).await {
+        Ok(t) => t,
+        Err(e) => {
+            tracing::error!(?e, %uid, "upgrade_device: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    tracing::info!(%uid, version = %image.version, image_id = %image.id, task_id = %task.id, "Firmware upgrade queued");
+    deliver_or_wake(&state, device_id, domain_id, &uid).await;
+    (StatusCode::CREATED, Json(UpgradeResponse { image: *image, task })).into_response()
+}
+
+// ── Helpers ───────────────────────────────────────────────────────────────────
+
+async fn fetch_image(state: &ApiState, id: Uuid) -> Result<Option<Image>, sqlx::Error> {
+    sqlx::query_as::<_, Image>(&format!("SELECT {IMAGE_COLUMNS} FROM firmware_images WHERE id = $1"))
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await
+}
+
+/// Image `id`, if the caller may manage it; otherwise the response to send.
+async fn managed_image(state: &ApiState, principal: &Principal, id: Uuid) -> Result<Image, Response> {
+    match fetch_image(state, id).await {
+        Ok(Some(image)) => match image.domain_id {
+            None if principal.is_super_admin => Ok(image),
+            Some(d) if principal.has_role(d, Role::Admin) => Ok(image),
+            None => Err(forbidden()),
+            Some(d) if principal.can_view(d) => Err(forbidden()),
+            Some(_) => Err((StatusCode::NOT_FOUND, "Image not found").into_response()),
+        },
+        Ok(None) => Err((StatusCode::NOT_FOUND, "Image not found").into_response()),
+        Err(e) => {
+            tracing::error!(?e, %id, "firmware image lookup: db error");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response())
+        }
+    }
+}
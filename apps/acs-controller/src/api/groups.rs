@@ -0,0 +1,438 @@
+//! Device group API.
+//!
+//! Groups belong to a domain. Viewing a group and its members requires
+//! `domain_viewer`; creating, editing, deleting, changing static membership
+//! and re-evaluating dynamic groups require `domain_editor`. Membership
+//! bookkeeping and NATS events live in [`crate::groups`].
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::inventory::{is_check_violation, is_unique_violation};
+use crate::api::state::ApiState;
+use crate::auth::{forbidden, Principal, Role};
+use crate::db;
+use crate::groups::{self, DeviceGroup, EvaluateError, GROUP_COLUMNS};
+
+// ── Request / response types ──────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct GroupListQuery {
+    /// Filter by domain slug.
+    pub domain: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateGroupRequest {
+    /// Domain slug.
+    pub domain:      String,
+    pub name:        String,
+    pub description: Option<String>,
+    /// `static` | `dynamic`
+    pub kind:        String,
+    /// Search expression; required for dynamic groups, rejected for static ones.
+    pub filter:      Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchGroupRequest {
+    pub name:        Option<String>,
+    pub description: Option<String>,
+    /// Dynamic groups only; the group is re-evaluated straight away.
+    pub filter:      Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddMembersRequest {
+    /// Device UIDs, e.g. `["AABB00-1234567"]`.
+    pub devices: Vec<String>,
+}
+
+/// A group with its current size.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct GroupInfo {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub group:        DeviceGroup,
+    pub member_count: i64,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct GroupMember {
+    pub device_uid: String,
+    pub added_at:   chrono::DateTime<chrono::Utc>,
+}
+
+const GROUP_INFO_SELECT: &str = "(SELECT count(*) FROM device_group_members m WHERE m.group_id = g.id) AS member_count";
+
+// ── Handlers ──────────────────────────────────────────────────────────────────
+
+/// `GET /api/v1/groups[?domain=<slug>]` — ordered by name.
+pub async fn list_groups(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<GroupListQuery>,
+) -> impl IntoResponse {
+    let result = sqlx::query_as::<_, GroupInfo>(&format!(
+        r#"
+        SELECT {GROUP_COLUMNS}, {GROUP_INFO_SELECT} FROM device_groups g
+        WHERE ($1::UUID[] IS NULL OR domain_id = ANY($1))
+          AND ($2::TEXT IS NULL OR domain_id = (SELECT id FROM domains WHERE slug = $2))
+        ORDER BY name
+        "#
+    ))
+    .bind(principal.visible_domains())
+    .bind(&query.domain)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(groups) => (StatusCode::OK, Json(groups)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, "list_groups: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/groups` — a dynamic group is evaluated before responding.
+pub async fn create_group(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Json(body): Json<CreateGroupRequest>,
+) -> impl IntoResponse {
+    let domain_id = match db::get_domain_id(&state.pool, &body.domain).await {
+        Ok(Some(id)) if principal.has_role(id, Role::Editor) => id,
+        Ok(Some(id)) if principal.can_view(id) => return forbidden(),
+        Ok(_) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, "create_group: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    match (body.kind.as_str(), &body.filter) {
+        ("static", None) => {}
+        ("static", Some(_)) => {
+            return (StatusCode::UNPROCESSABLE_ENTITY, "static groups take no filter").into_response()
+        }
+        ("dynamic", Some(filter)) => {
+            if let Err(e) = groups::compile_filter(filter, 1) {
+                return (StatusCode::UNPROCESSABLE_ENTITY, format!("invalid filter: {e}")).into_response();
+            }
+        }
+        ("dynamic", None) => {
+            return (StatusCode::UNPROCESSABLE_ENTITY, "dynamic groups require a filter").into_response()
+        }
+        _ => return (StatusCode::UNPROCESSABLE_ENTITY, "kind must be 'static' or 'dynamic'").into_response(),
+    }
+
+    let result = sqlx::query_as::<_, DeviceGroup>(&format!(
+        r#"
+        INSERT INTO device_groups (domain_id, name, description, kind, filter, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING {GROUP_COLUMNS}
+        "#
+    ))
+    .bind(domain_id)
+    .bind(&body.name)
+    .bind(&body.description)
+    .bind(&body.kind)
+    .bind(&body.filter)
+    .bind(principal.user_id)
+    .fetch_one(&state.pool)
+    .await;
+
+    let group = match result {
+        Ok(group) => group,
+        Err(e) if is_unique_violation(&e) => {
+            return (StatusCode::CONFLICT, "Group name already exists in this domain").into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, "create_group: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    match groups::evaluate(&state.pool, &state.nats, &group).await {
+        Ok(_) => {}
+        Err(e @ EvaluateError::Search(_)) => return (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response(),
+        Err(EvaluateError::Db(e)) => {
+            tracing::error!(?e, group_id = %group.id, "create_group: evaluation failed");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    }
+    group_info(&state, group.id, StatusCode::CREATED).await
+}
+
+/// `GET /api/v1/groups/:id`
+pub async fn get_group(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id, Role::Viewer).await {
+        return resp;
+    }
+    group_info(&state, id, StatusCode::OK).await
+}
+
+/// `PATCH /api/v1/groups/:id`
+///
+/// A group's kind and domain cannot change. Changing a dynamic group's
+/// filter re-evaluates it before responding.
+pub async fn patch_group(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<PatchGroupRequest>,
+) -> impl IntoResponse {
+    let group = match authorize(&state, &principal, id, Role::Editor).await {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+
+    if let Some(ref filter) = body.filter {
+        if !group.is_dynamic() {
+            return (StatusCode::UNPROCESSABLE_ENTITY, "static groups take no filter").into_response();
+        }
+        if let Err(e) = groups::compile_filter(filter, 1) {
+            return (StatusCode::UNPROCESSABLE_ENTITY, format!("invalid filter: {e}")).into_response();
+        }
+    }
+
+    let result = sqlx::query_as::<_, DeviceGroup>(&format!(
+        r#"
+        UPDATE device_groups
+        SET name        = COALESCE($2, name),
+            description = COALESCE($3, description),
+            filter      = COALESCE($4, filter),
+            updated_at  = now()
+        WHERE id = $1
+        RETURNING {GROUP_COLUMNS}
+        "#
+    ))
+    .bind(id)
+    .bind(&body.name)
+    .bind(&body.description)
+    .bind(&body.filter)
+    .fetch_one(&state.pool)
+    .await;
+
+    let group = match result {
+        Ok(group) => group,
+        Err(e) if is_unique_violation(&e) => {
+            return (StatusCode::CONFLICT, "Group name already exists in this domain").into_response()
+        }
+        Err(e) if is_check_violation(&e) => {
+            return (StatusCode::UNPROCESSABLE_ENTITY, "Invalid group").into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, %id, "patch_group: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    if body.filter.is_some() {
+        match groups::evaluate(&state.pool, &state.nats, &group).await {
+            Ok(_) => {}
+            Err(e @ EvaluateError::Search(_)) => {
+                return (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response()
+            }
+            Err(EvaluateError::Db(e)) => {
+                tracing::error!(?e, %id, "patch_group: evaluation failed");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+            }
+        }
+    }
+    group_info(&state, id, StatusCode::OK).await
+}
+
+/// `DELETE /api/v1/groups/:id` — every member is announced as leaving.
+pub async fn delete_group(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let group = match authorize(&state, &principal, id, Role::Editor).await {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+
+    match groups::delete(&state.pool, &state.nats, &group).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "delete_group: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/groups/:id/members` — ordered by device UID.
+pub async fn list_group_members(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id, Role::Viewer).await {
+        return resp;
+    }
+
+    let result = sqlx::query_as::<_, GroupMember>(
+        r#"
+        SELECT d.device_uid, m.added_at
+        FROM device_group_members m
+        JOIN devices d ON d.id = m.device_id
+        WHERE m.group_id = $1
+        ORDER BY d.device_uid
+        "#,
+    )
+    .bind(id)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(members) => (StatusCode::OK, Json(members)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "list_group_members: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/groups/:id/members` — static groups only.
+///
+/// Only devices of the group's domain can be added. Responds with the
+/// number of devices added and the UIDs that matched no such device.
+pub async fn add_group_members(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<AddMembersRequest>,
+) -> impl IntoResponse {
+    let group = match authorize(&state, &principal, id, Role::Editor).await {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+    if group.is_dynamic() {
+        return (StatusCode::CONFLICT, "Membership of a dynamic group follows its filter").into_response();
+    }
+
+    match groups::add_members(&state.pool, &state.nats, &group, &body.devices).await {
+        Ok((added, not_found)) => (
+            StatusCode::OK,
+            Json(serde_json::json!({ "added": added.len(), "not_found": not_found })),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "add_group_members: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `DELETE /api/v1/groups/:id/members/:uid` — static groups only.
+pub async fn remove_group_member(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path((id, uid)): Path<(Uuid, String)>,
+) -> impl IntoResponse {
+    let group = match authorize(&state, &principal, id, Role::Editor).await {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+    if group.is_dynamic() {
+        return (StatusCode::CONFLICT, "Membership of a dynamic group follows its filter").into_response();
+    }
+
+    match groups::remove_member(&state.pool, &state.nats, &group, &uid).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "Device is not a member of this group").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, %uid, "remove_group_member: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/groups/:id/evaluate` — dynamic groups only.
+///
+/// Re-runs the filter over the whole domain and responds with how many
+/// devices joined and left. A stored filter that no longer compiles is a
+/// `422`.
+pub async fn evaluate_group(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let group = match authorize(&state, &principal, id, Role::Editor).await {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+    if !group.is_dynamic() {
+        return (StatusCode::CONFLICT, "Only dynamic groups can be evaluated").into_response();
+    }
+
+    match groups::evaluate(&state.pool, &state.nats, &group).await {
+        Ok(changes) => {
+            let joined = changes.iter().filter(|c| c.change == nats_common::MembershipChange::Joined).count();
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({ "joined": joined, "left": changes.len() - joined })),
+            )
+                .into_response()
+        }
+        Err(e @ EvaluateError::Search(_)) => (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response(),
+        Err(EvaluateError::Db(e)) => {
+            tracing::error!(?e, %id, "evaluate_group: failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+// ── Helpers ───────────────────────────────────────────────────────────────────
+
+/// Load a group the caller holds at least `min` on. Groups in domains the
+/// caller cannot see are reported as `404`.
+async fn authorize(state: &ApiState, principal: &Principal, id: Uuid, min: Role) -> Result<DeviceGroup, Response> {
+    let result = sqlx::query_as::<_, DeviceGroup>(&format!("SELECT {GROUP_COLUMNS} FROM device_groups WHERE id = $1"))
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await;
+
+    match result {
+        Ok(Some(g)) if principal.has_role(g.domain_id, min) => Ok(g),
+        Ok(Some(g)) if principal.can_view(g.domain_id) => Err(forbidden()),
+        Ok(_) => Err((StatusCode::NOT_FOUND, "Group not found").into_response()),
+        Err(e) => {
+            tracing::error!(?e, %id, "group lookup: db error");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response())
+        }
+    }
+}
+
+/// Respond with the group and its member count.
+async fn group_info(state: &ApiState, id: Uuid, status: StatusCode) -> Response {
+    let result = sqlx::query_as::<_, GroupInfo>(&format!(
+        "SELECT {GROUP_COLUMNS}, {GROUP_INFO_SELECT} FROM device_groups g WHERE id = $1"
+    ))
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await;
+
+    match result {
+        Ok(Some(info)) => (status, Json(info)).into_response(),
+        // Deleted concurrently.
+        Ok(None) => (StatusCode::NOT_FOUND, "Group not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "group lookup: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
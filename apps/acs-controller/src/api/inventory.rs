@@ -4,19 +4,29 @@
 //! device protocols, and domains. All writes are scoped to what the
 //! Inform flow creates; `POST /devices` is intentionally absent —
 //! devices are registered automatically on first contact.
+//!
+//! Every handler is scoped by the caller's domain memberships. Devices and
+//! domains the caller cannot see are reported as `404`, not `403`, so their
+//! existence is not disclosed.
 
 use axum::{
     extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
-    Json,
+    Extension, Json,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::types::JsonValue;
+use sqlx::{FromRow, Row};
 use uuid::Uuid;
 
 use crate::api::state::ApiState;
+use crate::auth::{forbidden, Principal, Role};
+use crate::db;
+use crate::events::EventKind;
+use crate::handlers;
+use crate::search::{self, Cursor, SearchError, Sort};
 
 // ── Response types ────────────────────────────────────────────────────────────
 
@@ -37,6 +47,11 @@ pub struct DeviceInfo {
     pub metadata:         JsonValue,
     pub first_seen:       chrono::DateTime<chrono::Utc>,
     pub last_seen:        chrono::DateTime<chrono::Utc>,
+    /// `online`, `in_session`, `late`, `offline` or `faulting`.
+    pub health:           String,
+    pub health_changed_at: chrono::DateTime<chrono::Utc>,
+    /// Learned `PeriodicInformInterval`, in seconds.
+    pub periodic_inform_interval: Option<i32>,
 }
 
 #[derive(Debug, Serialize, sqlx::FromRow)]
@@ -69,12 +84,43 @@ pub struct DeviceProtocol {
     pub metadata:              JsonValue,
 }
 
+/// One page of `GET /inventory/devices`.
+#[derive(Debug, Serialize)]
+pub struct DevicePage {
+    pub items:       Vec<Value>,
+    /// Pass as `cursor` to fetch the next page; `null` on the last page.
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total:       Option<i64>,
+}
+
+/// Field names accepted by `?fields=`.
+const DEVICE_FIELDS: &[&str] = &[
+    "id", "device_uid", "domain_id", "manufacturer", "oui", "product_class",
+    "serial_number", "current_protocol", "software_version", "hardware_version",
+    "tags", "metadata", "first_seen", "last_seen", "health", "health_changed_at",
+    "periodic_inform_interval",
+];
+
 // ── Request types ─────────────────────────────────────────────────────────────
 
 #[derive(Debug, Deserialize)]
 pub struct DeviceListQuery {
     /// Filter by domain slug, e.g. `?domain=acme`
     pub domain: Option<String>,
+    /// Filter expression, see [`crate::search`].
+    pub q:      Option<String>,
+    /// Sort field, `-` prefix for descending. Default `-last_seen`.
+    pub sort:   Option<String>,
+    /// Page size, 1–1000. Default 100.
+    pub limit:  Option<i64>,
+    /// `next_cursor` from the previous page.
+    pub cursor: Option<String>,
+    /// Comma-separated device fields to return.
+    pub fields: Option<String>,
+    /// Include the total number of matching devices.
+    #[serde(default)]
+    pub count:  bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -106,59 +152,160 @@ pub struct SetPropertyRequest {
 
 // ── Devices ───────────────────────────────────────────────────────────────────
 
-const DEVICE_SELECT: &str = r#"
-    SELECT
-        d.id, d.device_uid, d.domain_id, d.manufacturer, d.oui,
-        d.product_class, d.serial_number, d.current_protocol,
-        d.software_version, d.hardware_version, d.tags, d.metadata,
-        d.first_seen, d.last_seen
-    FROM devices d
-"#;
+const DEVICE_COLUMNS: &str = "d.id, d.device_uid, d.domain_id, d.manufacturer, d.oui, \
+                              d.product_class, d.serial_number, d.current_protocol, \
+                              d.software_version, d.hardware_version, d.tags, d.metadata, \
+                              d.first_seen, d.last_seen, d.health, d.health_changed_at, \
+                              d.periodic_inform_interval";
 
-/// `GET /api/v1/inventory/devices[?domain=<slug>]`
+/// `GET /api/v1/inventory/devices[?domain=&q=&sort=&limit=&cursor=&fields=&count=]`
+///
+/// Only devices in domains the caller is a member of are returned. Results
+/// are paginated by keyset: each page's `next_cursor` continues after its
+/// last row, so deep pages cost the same as the first.
 pub async fn list_devices(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Query(params): Query<DeviceListQuery>,
 ) -> impl IntoResponse {
-    let result = match params.domain {
-        None => {
-            sqlx::query_as::<_, DeviceInfo>(&format!("{DEVICE_SELECT} ORDER BY d.last_seen DESC"))
-                .fetch_all(&state.pool)
-                .await
-        }
-        Some(slug) => {
-            sqlx::query_as::<_, DeviceInfo>(&format!(
-                "{DEVICE_SELECT} JOIN domains dom ON dom.id = d.domain_id \
-                 WHERE dom.slug = $1 ORDER BY d.last_seen DESC"
-            ))
-            .bind(slug)
-            .fetch_all(&state.pool)
-            .await
+    let unprocessable = |e: SearchError| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response();
+
+    let sort_spec = params.sort.as_deref().unwrap_or("-last_seen");
+    let sort = match Sort::parse(sort_spec) {
+        Ok(s) => s,
+        Err(e) => return unprocessable(e),
+    };
+    let filter = match params.q.as_deref().filter(|q| !q.trim().is_empty()).map(search::parse).transpose() {
+        Ok(f) => f,
+        Err(e) => return unprocessable(e),
+    };
+    let cursor = match params.cursor.as_deref().map(Cursor::decode).transpose() {
+        Ok(Some(c)) if c.sort != sort_spec => return unprocessable(SearchError::BadCursor),
+        Ok(c) => c,
+        Err(e) => return unprocessable(e),
+    };
+    let fields: Option<Vec<&str>> = params.fields.as_deref().map(|f| f.split(',').map(str::trim).collect());
+    if let Some(unknown) = fields.iter().flatten().find(|f| !DEVICE_FIELDS.contains(f)) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, format!("unknown field '{unknown}'")).into_response();
+    }
+    let limit = params.limit.unwrap_or(100).clamp(1, 1000);
+
+    let visible = principal.visible_domains();
+
+    let mut clauses: Vec<String> = Vec::new();
+    let mut idx: usize = 1;
+    if params.domain.is_some() { clauses.push(format!("dom.slug = ${idx}"));        idx += 1; }
+    if visible.is_some()       { clauses.push(format!("d.domain_id = ANY(${idx})")); idx += 1; }
+    let compiled = filter.map(|expr| search::compile(&expr, idx));
+    if let Some(ref f) = compiled {
+        clauses.push(f.sql.clone());
+        idx += f.binds.len();
+    }
+    let filter_binds: &[String] = compiled.as_ref().map_or(&[], |f| &f.binds);
+
+    let where_sql = |clauses: &[String]| {
+        if clauses.is_empty() { String::new() } else { format!("WHERE {}", clauses.join(" AND ")) }
+    };
+    let from = "FROM devices d JOIN domains dom ON dom.id = d.domain_id";
+
+    // ── Total (ignores the cursor) ───────────────────────────────────────────
+    let total = if params.count {
+        let sql = format!("SELECT count(*) {from} {}", where_sql(&clauses));
+        let q = bind_device_filters(sqlx::query(&sql), &params.domain, &visible, filter_binds);
+        match q.fetch_one(&state.pool).await.and_then(|row| row.try_get::<i64, _>(0)) {
+            Ok(n) => Some(n),
+            Err(e) => {
+                tracing::error!(?e, "list_devices: count failed");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+            }
         }
+    } else {
+        None
     };
 
-    match result {
-        Ok(devices) => (StatusCode::OK, Json(devices)).into_response(),
+    // ── Page ─────────────────────────────────────────────────────────────────
+    if cursor.is_some() {
+        clauses.push(sort.after(idx, idx + 1));
+        idx += 2;
+    }
+    let sql = format!(
+        "SELECT {DEVICE_COLUMNS}, ({})::text AS sort_key {from} {} {} LIMIT ${idx}",
+        sort.expr,
+        where_sql(&clauses),
+        sort.order_by(),
+    );
+
+    let mut q = bind_device_filters(sqlx::query(&sql), &params.domain, &visible, filter_binds);
+    if let Some(ref c) = cursor {
+        q = q.bind(&c.key).bind(c.id);
+    }
+    // One extra row tells us whether another page exists.
+    let rows = match q.bind(limit + 1).fetch_all(&state.pool).await {
+        Ok(rows) => rows,
         Err(e) => {
             tracing::error!(?e, "list_devices: db error");
-            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let has_more = rows.len() as i64 > limit;
+    let mut items = Vec::with_capacity(rows.len().min(limit as usize));
+    let mut next_cursor = None;
+    for row in rows.iter().take(limit as usize) {
+        let device = match DeviceInfo::from_row(row) {
+            Ok(d) => d,
+            Err(e) => {
+                tracing::error!(?e, "list_devices: decode error");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+            }
+        };
+        if has_more {
+            let key: String = row.try_get("sort_key").unwrap_or_default();
+            next_cursor = Some(Cursor { sort: sort_spec.to_string(), key, id: device.id }.encode());
         }
+        // Serialising a DeviceInfo cannot fail.
+        let mut value = serde_json::to_value(&device).expect("DeviceInfo serialises");
+        if let (Some(fields), Value::Object(map)) = (&fields, &mut value) {
+            map.retain(|k, _| fields.contains(&k.as_str()));
+        }
+        items.push(value);
     }
+
+    (StatusCode::OK, Json(DevicePage { items, next_cursor, total })).into_response()
+}
+
+/// Bind the domain, visibility and filter-expression parameters shared by
+/// the count and page queries of [`list_devices`], in placeholder order.
+fn bind_device_filters<'q>(
+    mut q: sqlx::query::Query<'q, sqlx::Postgres, sqlx::postgres::PgArguments>,
+    domain: &'q Option<String>,
+    visible: &'q Option<Vec<Uuid>>,
+    filter_binds: &'q [String],
+) -> sqlx::query::Query<'q, sqlx::Postgres, sqlx::postgres::PgArguments> {
+    if let Some(slug)    = domain  { q = q.bind(slug); }
+    if let Some(domains) = visible { q = q.bind(domains); }
+    for value in filter_binds {
+        q = q.bind(value);
+    }
+    q
 }
 
 /// `GET /api/v1/inventory/devices/:uid`
 pub async fn get_device(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Path(uid): Path<String>,
 ) -> impl IntoResponse {
-    let result = sqlx::query_as::<_, DeviceInfo>(&format!("{DEVICE_SELECT} WHERE d.device_uid = $1"))
+    let result = sqlx::query_as::<_, DeviceInfo>(&format!("SELECT {DEVICE_COLUMNS} FROM devices d WHERE d.device_uid = $1"))
         .bind(&uid)
         .fetch_optional(&state.pool)
         .await;
 
     match result {
-        Ok(Some(device)) => (StatusCode::OK, Json(device)).into_response(),
-        Ok(None)         => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Ok(Some(device)) if principal.can_view(device.domain_id) => {
+            (StatusCode::OK, Json(device)).into_response()
+        }
+        Ok(_) => (StatusCode::NOT_FOUND, "Device not found").into_response(),
         Err(e) => {
             tracing::error!(?e, uid, "get_device: db error");
             (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
@@ -169,22 +316,32 @@ pub async fn get_device(
 /// `PATCH /api/v1/inventory/devices/:uid`
 ///
 /// Updates `tags`, `metadata`, and/or `domain_id`. Only supplied fields are changed.
+/// Requires `domain_editor`; moving a device requires it in both domains.
 pub async fn patch_device(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Path(uid): Path<String>,
     Json(body): Json<PatchDeviceRequest>,
 ) -> impl IntoResponse {
     // Resolve device UUID from uid first
-    let device_uuid: Option<Uuid> = sqlx::query_scalar("SELECT id FROM devices WHERE device_uid = $1")
-        .bind(&uid)
-        .fetch_optional(&state.pool)
-        .await
-        .unwrap_or(None);
-
-    let Some(device_uuid) = device_uuid else {
-        return (StatusCode::NOT_FOUND, "Device not found").into_response();
+    let (device_uuid, domain_id) = match visible_device(&state, &principal, &uid).await {
+        Ok(Some(ids)) => ids,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, uid, "patch_device: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
     };
 
+    if !principal.has_role(domain_id, Role::Editor) {
+        return forbidden();
+    }
+    if let Some(target) = body.domain_id {
+        if !principal.has_role(target, Role::Editor) {
+            return forbidden();
+        }
+    }
+
     // Build update dynamically: only touch columns the caller provided
     if body.tags.is_none() && body.metadata.is_none() && body.domain_id.is_none() {
         return (StatusCode::BAD_REQUEST, "Nothing to update").into_response();
@@ -221,19 +378,49 @@ pub async fn patch_device(
 
 /// `DELETE /api/v1/inventory/devices/:uid`
 ///
-/// Hard-deletes the device. Cascade FK rules remove protocols and properties.
+/// Runs the device's decommission scripts, then hard-deletes it. Cascade FK
+/// rules remove protocols and properties. A failing script does not stop the
+/// deletion. Requires `domain_admin`.
 pub async fn delete_device(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Path(uid): Path<String>,
 ) -> impl IntoResponse {
+    let (device_id, domain_id) = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, domain_id))) if principal.has_role(domain_id, Role::Admin) => (device_id, domain_id),
+        Ok(Some(_)) => return forbidden(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, uid, "delete_device: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let commands = match handlers::decommission::handle_decommission(&state, device_id, &uid).await {
+        Ok(n) => n,
+        Err(e) => {
+            tracing::error!(?e, uid, "delete_device: decommission failed");
+            0
+        }
+    };
+
     let result: Result<Option<Uuid>, sqlx::Error> =
-        sqlx::query_scalar("DELETE FROM devices WHERE device_uid = $1 RETURNING id")
-            .bind(&uid)
+        sqlx::query_scalar("DELETE FROM devices WHERE id = $1 AND domain_id = $2 RETURNING id")
+            .bind(device_id)
+            .bind(domain_id)
             .fetch_optional(&state.pool)
             .await;
 
     match result {
-        Ok(Some(_)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(Some(_)) => {
+            state.events.publish(
+                EventKind::Decommissioned,
+                &uid,
+                domain_id,
+                serde_json::json!({ "deleted_by": principal.user_id, "commands": commands }),
+            );
+            StatusCode::NO_CONTENT.into_response()
+        }
         Ok(None)    => (StatusCode::NOT_FOUND, "Device not found").into_response(),
         Err(e) => {
             tracing::error!(?e, uid, "delete_device: db error");
@@ -247,8 +434,18 @@ pub async fn delete_device(
 /// `GET /api/v1/inventory/devices/:uid/properties`
 pub async fn list_device_properties(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Path(uid): Path<String>,
 ) -> impl IntoResponse {
+    match visible_device(&state, &principal, &uid).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, uid, "list_device_properties: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    }
+
     let result = sqlx::query_as::<_, DeviceProperty>(
         r#"
         SELECT
@@ -274,19 +471,21 @@ pub async fn list_device_properties(
 }
 
 /// `PUT /api/v1/inventory/devices/:uid/properties/:name`
+/// Requires `domain_editor`.
 pub async fn set_device_property(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Path((uid, name)): Path<(String, String)>,
     Json(body): Json<SetPropertyRequest>,
 ) -> impl IntoResponse {
-    let device_uuid: Option<Uuid> = sqlx::query_scalar("SELECT id FROM devices WHERE device_uid = $1")
-        .bind(&uid)
-        .fetch_optional(&state.pool)
-        .await
-        .unwrap_or(None);
-
-    let Some(device_uuid) = device_uuid else {
-        return (StatusCode::NOT_FOUND, "Device not found").into_response();
+    let device_uuid = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((id, domain_id))) if principal.has_role(domain_id, Role::Editor) => id,
+        Ok(Some(_)) => return forbidden(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, uid, "set_device_property: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
     };
 
     let source   = body.source.unwrap_or_else(|| "api".to_string());
@@ -321,10 +520,23 @@ pub async fn set_device_property(
 }
 
 /// `DELETE /api/v1/inventory/devices/:uid/properties/:name`
+///
+/// Requires `domain_editor`.
 pub async fn delete_device_property(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Path((uid, name)): Path<(String, String)>,
 ) -> impl IntoResponse {
+    match visible_device(&state, &principal, &uid).await {
+        Ok(Some((_, domain_id))) if principal.has_role(domain_id, Role::Editor) => {}
+        Ok(Some(_)) => return forbidden(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, uid, name, "delete_device_property: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    }
+
     let result: Result<Option<String>, sqlx::Error> = sqlx::query_scalar(
         r#"
         DELETE FROM device_properties dp
@@ -355,8 +567,18 @@ pub async fn delete_device_property(
 /// `GET /api/v1/inventory/devices/:uid/protocols`
 pub async fn list_device_protocols(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Path(uid): Path<String>,
 ) -> impl IntoResponse {
+    match visible_device(&state, &principal, &uid).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, uid, "list_device_protocols: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    }
+
     let result = sqlx::query_as::<_, DeviceProtocol>(
         r#"
         SELECT
@@ -384,12 +606,30 @@ pub async fn list_device_protocols(
 // ── Domains ───────────────────────────────────────────────────────────────────
 
 /// `GET /api/v1/inventory/domains`
-pub async fn list_domains(State(state): State<ApiState>) -> impl IntoResponse {
-    let result = sqlx::query_as::<_, DomainInfo>(
-        "SELECT id, name, slug, description, created_at, updated_at FROM domains ORDER BY name",
-    )
-    .fetch_all(&state.pool)
-    .await;
+///
+/// Lists the domains the caller is a member of (all domains for super admins).
+pub async fn list_domains(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+) -> impl IntoResponse {
+    let result = match principal.visible_domains() {
+        None => {
+            sqlx::query_as::<_, DomainInfo>(
+                "SELECT id, name, slug, description, created_at, updated_at FROM domains ORDER BY name",
+            )
+            .fetch_all(&state.pool)
+            .await
+        }
+        Some(ids) => {
+            sqlx::query_as::<_, DomainInfo>(
+                "SELECT id, name, slug, description, created_at, updated_at FROM domains \
+                 WHERE id = ANY($1) ORDER BY name",
+            )
+            .bind(ids)
+            .fetch_all(&state.pool)
+            .await
+        }
+    };
 
     match result {
         Ok(domains) => (StatusCode::OK, Json(domains)).into_response(),
@@ -403,6 +643,7 @@ pub async fn list_domains(State(state): State<ApiState>) -> impl IntoResponse {
 /// `GET /api/v1/inventory/domains/:slug`
 pub async fn get_domain(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Path(slug): Path<String>,
 ) -> impl IntoResponse {
     let result = sqlx::query_as::<_, DomainInfo>(
@@ -413,8 +654,10 @@ pub async fn get_domain(
     .await;
 
     match result {
-        Ok(Some(domain)) => (StatusCode::OK, Json(domain)).into_response(),
-        Ok(None)         => (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+        Ok(Some(domain)) if principal.can_view(domain.id) => {
+            (StatusCode::OK, Json(domain)).into_response()
+        }
+        Ok(_) => (StatusCode::NOT_FOUND, "Domain not found").into_response(),
         Err(e) => {
             tracing::error!(?e, slug, "get_domain: db error");
             (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
@@ -422,11 +665,16 @@ pub async fn get_domain(
     }
 }
 
-/// `POST /api/v1/inventory/domains`
+/// `POST /api/v1/inventory/domains` — super admin only.
 pub async fn create_domain(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Json(body): Json<CreateDomainRequest>,
 ) -> impl IntoResponse {
+    if !principal.is_super_admin {
+        return forbidden();
+    }
+
     let result = sqlx::query_as::<_, DomainInfo>(
         r#"
         INSERT INTO domains (name, slug, description)
@@ -455,12 +703,23 @@ pub async fn create_domain(
     }
 }
 
-/// `PATCH /api/v1/inventory/domains/:slug`
+/// `PATCH /api/v1/inventory/domains/:slug` — domain admin.
 pub async fn patch_domain(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Path(slug): Path<String>,
     Json(body): Json<PatchDomainRequest>,
 ) -> impl IntoResponse {
+    match db::get_domain_id(&state.pool, &slug).await {
+        Ok(Some(id)) if principal.has_role(id, Role::Admin) => {}
+        Ok(Some(id)) if principal.can_view(id) => return forbidden(),
+        Ok(_) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, slug, "patch_domain: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    }
+
     if body.name.is_none() && body.description.is_none() {
         return (StatusCode::BAD_REQUEST, "Nothing to update").into_response();
     }
@@ -499,10 +758,16 @@ pub async fn patch_domain(
 ///
 /// Fails with 409 if any devices are still assigned to this domain
 /// (enforced by `ON DELETE RESTRICT` on the `devices.domain_id` FK).
+/// Super admin only.
 pub async fn delete_domain(
     State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
     Path(slug): Path<String>,
 ) -> impl IntoResponse {
+    if !principal.is_super_admin {
+        return forbidden();
+    }
+
     let result: Result<Option<Uuid>, sqlx::Error> =
         sqlx::query_scalar("DELETE FROM domains WHERE slug = $1 RETURNING id")
             .bind(&slug)
@@ -522,6 +787,22 @@ pub async fn delete_domain(
     }
 }
 
+// ── Access helpers ────────────────────────────────────────────────────────────
+
+/// Resolve `uid` to `(device id, domain id)` if the caller may see it.
+///
+/// Devices outside the caller's domains resolve to `None` so handlers
+/// answer `404` rather than confirming the device exists.
+pub(crate) async fn visible_device(
+    state: &ApiState,
+    principal: &Principal,
+    uid: &str,
+) -> Result<Option<(Uuid, Uuid)>, sqlx::Error> {
+    Ok(db::get_device_ids(&state.pool, uid)
+        .await?
+        .filter(|(_, domain_id)| principal.can_view(*domain_id)))
+}
+
 // ── DB error helpers ──────────────────────────────────────────────────────────
 
 fn pg_code(e: &sqlx::Error) -> Option<String> {
@@ -531,6 +812,6 @@ fn pg_code(e: &sqlx::Error) -> Option<String> {
     None
 }
 
-fn is_unique_violation(e: &sqlx::Error) -> bool       { pg_code(e).as_deref() == Some("23505") }
-fn is_foreign_key_violation(e: &sqlx::Error) -> bool  { pg_code(e).as_deref() == Some("23503") }
-fn is_check_violation(e: &sqlx::Error) -> bool        { pg_code(e).as_deref() == Some("23514") }
+pub(crate) fn is_unique_violation(e: &sqlx::Error) -> bool       { pg_code(e).as_deref() == Some("23505") }
+pub(crate) fn is_foreign_key_violation(e: &sqlx::Error) -> bool  { pg_code(e).as_deref() == Some("23503") }
+pub(crate) fn is_check_violation(e: &sqlx::Error) -> bool        { pg_code(e).as_deref() == Some("23514") }
@@ -0,0 +1,14 @@
+//! Prometheus metrics.
+
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::api::state::ApiState;
+
+/// `GET /metrics` — Prometheus text format. Unauthenticated, like most
+/// scrape targets; it exposes counts and timings only.
+pub async fn metrics(State(state): State<ApiState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.event_metrics.render(),
+    )
+}
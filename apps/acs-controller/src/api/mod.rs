@@ -1,23 +1,51 @@
 use axum::{
-    routing::{get, post, put},
+    extract::DefaultBodyLimit,
+    http::{header, HeaderValue, Method},
+    middleware,
+    routing::{delete, get, post, put},
     Router,
 };
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
+    services::ServeDir,
+};
 
+pub mod auth;
+pub mod backups;
+pub mod campaigns;
+pub mod commands;
+pub mod datamodel;
 pub mod device;
+pub mod events;
+pub mod firmware;
+pub mod groups;
 pub mod inventory;
+pub mod metrics;
+pub mod onboarding;
+pub mod parameters;
+pub mod provisioning;
 pub mod state;
+pub mod tasks;
+pub mod users;
+pub mod webhooks;
 
 pub use state::ApiState;
 
-pub async fn start_server(state: ApiState, port: u16) -> Result<(), std::io::Error> {
-    // Enable CORS for GUI development
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
-
-    let app = Router::new()
+pub async fn start_server(state: ApiState, port: u16, cors_origins: Vec<String>) -> Result<(), std::io::Error> {
+    // Everything except login requires a valid session token. `route_layer`
+    // only wraps matched routes, so unknown paths still get a plain 404.
+    let protected = Router::new()
+        // ── Session ─────────────────────────────────────────────────────────
+        .route("/api/v1/auth/me",
+            get(auth::me))
+        // ── Users ───────────────────────────────────────────────────────────
+        .route("/api/v1/users",
+            get(users::list_users)
+            .post(users::create_user))
+        .route("/api/v1/users/:id",
+            get(users::get_user)
+            .patch(users::patch_user)
+            .delete(users::delete_user))
         // ── Devices ─────────────────────────────────────────────────────────
         .route("/api/v1/inventory/devices",
             get(inventory::list_devices))
@@ -42,10 +70,175 @@ pub async fn start_server(state: ApiState, port: u16) -> Result<(), std::io::Err
             get(inventory::get_domain)
             .patch(inventory::patch_domain)
             .delete(inventory::delete_domain))
+        // ── Domain memberships ───────────────────────────────────────────────
+        .route("/api/v1/inventory/domains/:slug/members",
+            get(users::list_members)
+            .post(users::add_member))
+        .route("/api/v1/inventory/domains/:slug/members/:user_id",
+            delete(users::remove_member))
+        // ── Backup policies ──────────────────────────────────────────────────
+        .route("/api/v1/inventory/domains/:slug/backup-policy",
+            get(backups::get_policy)
+            .put(backups::set_policy)
+            .delete(backups::delete_policy))
+        // ── Onboarding rules ─────────────────────────────────────────────────
+        .route("/api/v1/onboarding/rules",
+            get(onboarding::list_rules)
+            .post(onboarding::create_rule))
+        .route("/api/v1/onboarding/rules/:id",
+            delete(onboarding::delete_rule))
         // ── Device commands ──────────────────────────────────────────────────
         .route("/api/v1/device/:uid/command",
             post(device::send_command))
-        .layer(cors)
+        .route("/api/v1/device/:uid/commands",
+            post(commands::create_command))
+        .route("/api/v1/commands/:id",
+            get(commands::get_command))
+        .route("/api/v1/commands/:id/events",
+            get(commands::command_events))
+        // ── Event stream ─────────────────────────────────────────────────────
+        .route("/api/v1/events",
+            get(events::event_stream))
+        .route("/api/v1/events/ws",
+            get(events::event_socket))
+        // ── Device tasks ─────────────────────────────────────────────────────
+        .route("/api/v1/device/:uid/tasks",
+            get(tasks::list_device_tasks)
+            .post(tasks::create_device_task))
+        .route("/api/v1/tasks/:id",
+            get(tasks::get_task)
+            .delete(tasks::delete_task))
+        // ── Firmware campaigns ───────────────────────────────────────────────
+        .route("/api/v1/campaigns",
+            get(campaigns::list_campaigns)
+            .post(campaigns::create_campaign))
+        .route("/api/v1/campaigns/:id",
+            get(campaigns::get_campaign)
+            .delete(campaigns::delete_campaign))
+        .route("/api/v1/campaigns/:id/devices",
+            get(campaigns::list_campaign_devices))
+        .route("/api/v1/campaigns/:id/start",
+            post(campaigns::start_campaign))
+        .route("/api/v1/campaigns/:id/pause",
+            post(campaigns::pause_campaign))
+        .route("/api/v1/campaigns/:id/resume",
+            post(campaigns::resume_campaign))
+        .route("/api/v1/campaigns/:id/cancel",
+            post(campaigns::cancel_campaign))
+        // ── Firmware catalog ─────────────────────────────────────────────────
+        .route("/api/v1/firmware/images",
+            get(firmware::list_images)
+            .post(firmware::create_image))
+        .route("/api/v1/firmware/images/:id",
+            get(firmware::get_image)
+            .delete(firmware::delete_image))
+        .route("/api/v1/firmware/images/:id/file",
+            put(firmware::upload_image_file))
+        .route("/api/v1/device/:uid/firmware",
+            post(firmware::upgrade_device))
+        // ── Configuration backups ────────────────────────────────────────────
+        .route("/api/v1/device/:uid/backups",
+            get(backups::list_backups)
+            .post(backups::create_backup))
+        .route("/api/v1/device/:uid/backups/:version",
+            get(backups::get_backup)
+            .delete(backups::delete_backup))
+        .route("/api/v1/device/:uid/backups/:version/file",
+            get(backups::get_backup_file))
+        .route("/api/v1/device/:uid/backups/:version/diff",
+            get(backups::diff_backup))
+        .route("/api/v1/device/:uid/backups/:version/restore",
+            post(backups::restore_backup))
+        // ── Data model catalog ───────────────────────────────────────────────
+        .route("/api/v1/datamodel/models",
+            get(datamodel::list_models)
+            .post(datamodel::import_models)
+            .layer(DefaultBodyLimit::max(datamodel::MAX_DOCUMENT_SIZE)))
+        .route("/api/v1/datamodel/models/:id",
+            get(datamodel::get_model)
+            .delete(datamodel::delete_model))
+        .route("/api/v1/datamodel/models/:id/entries",
+            get(datamodel::list_entries))
+        .route("/api/v1/device/:uid/datamodel",
+            get(datamodel::device_node))
+        // ── Device parameters ────────────────────────────────────────────────
+        .route("/api/v1/device/:uid/parameters",
+            get(parameters::get_tree))
+        .route("/api/v1/device/:uid/parameters/refresh",
+            post(parameters::start_refresh))
+        .route("/api/v1/device/:uid/parameters/refreshes",
+            get(parameters::list_refreshes))
+        .route("/api/v1/device/:uid/parameters/refreshes/:id",
+            get(parameters::get_refresh))
+        // ── Webhooks ─────────────────────────────────────────────────────────
+        .route("/api/v1/webhooks",
+            get(webhooks::list_webhooks)
+            .post(webhooks::create_webhook))
+        .route("/api/v1/webhooks/:id",
+            get(webhooks::get_webhook)
+            .patch(webhooks::patch_webhook)
+            .delete(webhooks::delete_webhook))
+        .route("/api/v1/webhooks/:id/ping",
+            post(webhooks::ping_webhook))
+        .route("/api/v1/webhooks/:id/deliveries",
+            get(webhooks::list_deliveries))
+        .route("/api/v1/webhooks/:id/redeliver",
+            post(webhooks::redeliver))
+        // ── Device groups ────────────────────────────────────────────────────
+        .route("/api/v1/groups",
+            get(groups::list_groups)
+            .post(groups::create_group))
+        .route("/api/v1/groups/:id",
+            get(groups::get_group)
+            .patch(groups::patch_group)
+            .delete(groups::delete_group))
+        .route("/api/v1/groups/:id/members",
+            get(groups::list_group_members)
+            .post(groups::add_group_members))
+        .route("/api/v1/groups/:id/members/:uid",
+            delete(groups::remove_group_member))
+        .route("/api/v1/groups/:id/evaluate",
+            post(groups::evaluate_group))
+        // ── Provisioning ─────────────────────────────────────────────────────
+        .route("/api/v1/provisioning/scripts",
+            get(provisioning::list_scripts)
+            .post(provisioning::upload_script))
+        .route("/api/v1/provisioning/scripts/:id",
+            get(provisioning::get_script))
+        .route("/api/v1/provisioning/scripts/:id/versions/:version",
+            get(provisioning::get_script_version))
+        .route("/api/v1/provisioning/scripts/:id/activate",
+            post(provisioning::activate_script))
+        .route("/api/v1/provisioning/scripts/:id/deactivate",
+            post(provisioning::deactivate_script))
+        .route("/api/v1/provisioning/scripts/:id/rollback",
+            post(provisioning::rollback_script))
+        .route("/api/v1/provisioning/scripts/:id/stage",
+            put(provisioning::stage_script)
+            .delete(provisioning::unstage_script))
+        .route("/api/v1/provisioning/dry-run",
+            post(provisioning::dry_run))
+        .route("/api/v1/device/:uid/provisioning-runs",
+            get(provisioning::list_device_runs))
+        .route_layer(middleware::from_fn_with_state(state.clone(), crate::auth::require_auth));
+
+    // Uploaded firmware images, fetched by devices without credentials.
+    let firmware_files = ServeDir::new(&state.firmware.dir);
+
+    let app = Router::new()
+        .route("/api/v1/auth/login",
+            post(auth::login))
+        .route("/metrics",
+            get(metrics::metrics))
+        .nest_service("/firmware/files", firmware_files)
+        // Backup files, transferred by devices with the token in the URL.
+        .route("/backups/files/:id/:token",
+            get(backups::send_file)
+            .put(backups::receive_file)
+            .post(backups::receive_file)
+            .layer(DefaultBodyLimit::max(state.backups.max_file_size)))
+        .merge(protected)
+        .layer(cors_layer(&cors_origins))
         .with_state(state);
 
     let addr = format!("0.0.0.0:{}", port);
@@ -54,3 +247,26 @@ pub async fn start_server(state: ApiState, port: u16) -> Result<(), std::io::Err
     let listener = tokio::net::TcpListener::bind(addr).await?;
     axum::serve(listener, app).await
 }
+
+/// CORS for browser clients such as `acs-gui`.
+///
+/// Only the configured origins are allowed; with none configured the
+/// browser's same-origin policy applies unchanged.
+fn cors_layer(origins: &[String]) -> CorsLayer {
+    let origins: Vec<HeaderValue> = origins
+        .iter()
+        .filter(|o| !o.is_empty())
+        .filter_map(|o| match HeaderValue::from_str(o) {
+            Ok(v) => Some(v),
+            Err(_) => {
+                tracing::warn!(origin = %o, "Ignoring invalid CORS origin");
+                None
+            }
+        })
+        .collect();
+
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+}
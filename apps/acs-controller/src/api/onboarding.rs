@@ -0,0 +1,141 @@
+//! Onboarding rule management.
+//!
+//! Rules decide which domain a never-seen device lands in, so they span
+//! domains and are managed by super admins only.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use ipnet::IpNet;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::api::inventory::{is_foreign_key_violation, is_unique_violation};
+use crate::api::state::ApiState;
+use crate::auth::{forbidden, Principal};
+use crate::onboarding::OnboardingRule;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRuleRequest {
+    pub name:           String,
+    pub domain_id:      Uuid,
+    pub priority:       Option<i32>,
+    pub enabled:        Option<bool>,
+    pub oui:            Option<String>,
+    pub product_class:  Option<String>,
+    pub serial_pattern: Option<String>,
+    pub source_cidr:    Option<IpNet>,
+    pub acs_path:       Option<String>,
+}
+
+const RULE_COLUMNS: &str = "id, domain_id, name, priority, enabled, oui, product_class, \
+                            serial_pattern, source_cidr, acs_path, created_at";
+
+/// `GET /api/v1/onboarding/rules` — in evaluation order.
+pub async fn list_rules(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+) -> impl IntoResponse {
+    if !principal.is_super_admin {
+        return forbidden();
+    }
+
+    let result = sqlx::query_as::<_, OnboardingRule>(&format!(
+        "SELECT {RULE_COLUMNS} FROM domain_assignment_rules ORDER BY priority, created_at"
+    ))
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, "list_rules: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/onboarding/rules`
+///
+/// `serial_pattern` is compiled before the rule is stored so a typo is
+/// reported here rather than silently disabling the rule at Inform time.
+pub async fn create_rule(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Json(body): Json<CreateRuleRequest>,
+) -> impl IntoResponse {
+    if !principal.is_super_admin {
+        return forbidden();
+    }
+
+    if let Some(pattern) = &body.serial_pattern {
+        if let Err(e) = regex::Regex::new(pattern) {
+            return (StatusCode::UNPROCESSABLE_ENTITY, format!("Invalid serial_pattern: {e}")).into_response();
+        }
+    }
+
+    let result = sqlx::query_as::<_, OnboardingRule>(&format!(
+        r#"
+        INSERT INTO domain_assignment_rules (
+            name, domain_id, priority, enabled, oui, product_class,
+            serial_pattern, source_cidr, acs_path
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING {RULE_COLUMNS}
+        "#
+    ))
+    .bind(&body.name)
+    .bind(body.domain_id)
+    .bind(body.priority.unwrap_or(100))
+    .bind(body.enabled.unwrap_or(true))
+    .bind(&body.oui)
+    .bind(&body.product_class)
+    .bind(&body.serial_pattern)
+    .bind(body.source_cidr)
+    .bind(&body.acs_path)
+    .fetch_one(&state.pool)
+    .await;
+
+    match result {
+        Ok(rule) => (StatusCode::CREATED, Json(rule)).into_response(),
+        Err(e) if is_unique_violation(&e) => {
+            (StatusCode::CONFLICT, "Rule name already exists").into_response()
+        }
+        Err(e) if is_foreign_key_violation(&e) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, "Domain does not exist").into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, "create_rule: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `DELETE /api/v1/onboarding/rules/:id`
+pub async fn delete_rule(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !principal.is_super_admin {
+        return forbidden();
+    }
+
+    let result: Result<Option<Uuid>, sqlx::Error> =
+        sqlx::query_scalar("DELETE FROM domain_assignment_rules WHERE id = $1 RETURNING id")
+            .bind(id)
+            .fetch_optional(&state.pool)
+            .await;
+
+    match result {
+        Ok(Some(_)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(None)    => (StatusCode::NOT_FOUND, "Rule not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "delete_rule: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Device parameter tree API.
+//!
+//! `GET /api/v1/device/:uid/parameters` returns the parameters stored for a
+//! device as its object tree. A refresh (see [`crate::refresh`]) re-reads a
+//! subtree from the device: reading requires `domain_viewer`, starting a
+//! refresh `domain_editor`.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::api::inventory::visible_device;
+use crate::api::state::ApiState;
+use crate::api::tasks::deliver_or_wake;
+use crate::auth::{forbidden, Principal, Role};
+use crate::refresh::{self, Refresh, StartError, DEFAULT_CHUNK_SIZE, MAX_CHUNK_SIZE, REFRESH_COLUMNS};
+
+/// Refreshes listed by `GET …/parameters/refreshes`.
+const LIST_LIMIT: i64 = 20;
+
+// ── Request types ─────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct TreeQuery {
+    /// Object path of the subtree, e.g. `Device.WiFi.`. Omitted: everything.
+    #[serde(default)]
+    pub path: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RefreshRequest {
+    /// Object path of the subtree to refresh. Omitted: the whole data model.
+    #[serde(default)]
+    pub path:       String,
+    /// Parameters per GetParameterValues; lower it for devices that cannot
+    /// answer large requests.
+    pub chunk_size: Option<i32>,
+}
+
+// ── Handlers ──────────────────────────────────────────────────────────────────
+
+/// `GET /api/v1/device/:uid/parameters?path=` — the stored tree under
+/// `path`, with values, writable flags and per-node timestamps.
+pub async fn get_tree(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(uid): Path<String>,
+    Query(query): Query<TreeQuery>,
+) -> impl IntoResponse {
+    if let Err(msg) = check_path(&query.path) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, msg).into_response();
+    }
+    let device_id = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, _))) => device_id,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "get_tree: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    match refresh::stored_tree(&state.pool, device_id, &query.path).await {
+        Ok(Some(tree)) => (StatusCode::OK, Json(tree)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "No parameters stored under the path").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "get_tree: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/device/:uid/parameters/refresh` — re-read a subtree from
+/// the device. Requires `domain_editor`.
+///
+/// Returns `202` with the `pending` refresh; its first task is delivered at
+/// once if the device is in a session, otherwise the device is woken (best
+/// effort) and the refresh runs at its next session.
+pub async fn start_refresh(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(uid): Path<String>,
+    body: Option<Json<RefreshRequest>>,
+) -> impl IntoResponse {
+    let (device_id, domain_id) = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, domain_id))) if principal.has_role(domain_id, Role::Editor) => (device_id, domain_id),
+        Ok(Some(_)) => return forbidden(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "start_refresh: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+    let Json(body) = body.unwrap_or_default();
+
+    if let Err(msg) = check_path(&body.path) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, msg).into_response();
+    }
+    let chunk_size = body.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
+    if !(1..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, format!("chunk_size must be between 1 and {MAX_CHUNK_SIZE}"))
+            .into_response();
+    }
+
+    match refresh::start(&state.pool, device_id, &body.path, chunk_size, Some(principal.user_id)).await {
+        Ok(refresh) => {
+            deliver_or_wake(&state, device_id, domain_id, &uid).await;
+            (StatusCode::ACCEPTED, Json(refresh)).into_response()
+        }
+        Err(e @ StartError::InProgress) => (StatusCode::CONFLICT, e.to_string()).into_response(),
+        Err(StartError::Db(e)) => {
+            tracing::error!(?e, %uid, "start_refresh: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/device/:uid/parameters/refreshes` — the latest refreshes,
+/// newest first.
+pub async fn list_refreshes(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(uid): Path<String>,
+) -> impl IntoResponse {
+    let device_id = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, _))) => device_id,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "list_refreshes: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let result = sqlx::query_as::<_, Refresh>(&format!(
+        "SELECT {REFRESH_COLUMNS} FROM parameter_refreshes WHERE device_id = $1 ORDER BY created_at DESC LIMIT $2"
+    ))
+    .bind(device_id)
+    .bind(LIST_LIMIT)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(refreshes) => (StatusCode::OK, Json(refreshes)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "list_refreshes: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/device/:uid/parameters/refreshes/:id`
+pub async fn get_refresh(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path((uid, id)): Path<(String, Uuid)>,
+) -> impl IntoResponse {
+    let device_id = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, _))) => device_id,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "get_refresh: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let result = sqlx::query_as::<_, Refresh>(&format!(
+        "SELECT {REFRESH_COLUMNS} FROM parameter_refreshes WHERE id = $1 AND device_id = $2"
+    ))
+    .bind(id)
+    .bind(device_id)
+    .fetch_optional(&state.pool)
+    .await;
+
+    match result {
+        Ok(Some(refresh)) => (StatusCode::OK, Json(refresh)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Refresh not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, %id, "get_refresh: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// Subtrees are addressed by object path, as GetParameterNames takes them.
+fn check_path(path: &str) -> Result<(), &'static str> {
+    if path.is_empty() || (path.ends_with('.') && !path.starts_with('.') && !path.contains("..")) {
+        Ok(())
+    } else {
+        Err("path must be an object path ending in '.', or empty")
+    }
+}
@@ -0,0 +1,664 @@
+//! Provisioning API: the script repository, dry runs and the run log.
+//!
+//! Scripts stored through the API live in [`crate::provisioning::repository`].
+//! Reading a domain's scripts requires `domain_editor`; uploading and
+//! changing which version runs require `domain_admin`. A dry run runs the
+//! scripts an event would run for a device and reports what they returned,
+//! without sending anything to the device. A device's recorded runs (see
+//! [`crate::provisioning::runs`]) are visible to its domain's viewers.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use nats_common::Action;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+use crate::api::inventory::visible_device;
+use crate::api::state::ApiState;
+use crate::auth::{forbidden, Principal, Role};
+use crate::db::{self, InformPayload};
+use crate::onboarding;
+use crate::provisioning::repository::{self, Script, ScriptVersion, Upload, EVENT_TYPES, SCRIPT_COLUMNS};
+use crate::provisioning::runs;
+use crate::provisioning::updates::InventoryUpdate;
+use crate::provisioning::{Lifecycle, Target};
+
+// ── Request / response types ──────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct ScriptListQuery {
+    /// Filter by domain slug.
+    pub domain:     Option<String>,
+    pub event_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunListQuery {
+    /// Runs returned, newest first; 20 by default, at most 100.
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadScriptRequest {
+    /// Domain slug.
+    pub domain:     String,
+    pub event_type: String,
+    /// Below the domain's directory, e.g. `HW1/2.0.0/update.rhai`.
+    pub path:       String,
+    pub source:     String,
+    pub comment:    Option<String>,
+    /// Make the new version the active one straight away.
+    #[serde(default)]
+    pub activate:   bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadScriptResponse {
+    #[serde(flatten)]
+    pub script:  Script,
+    /// The version just uploaded.
+    pub version: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScriptDetail {
+    #[serde(flatten)]
+    pub script:   Script,
+    /// Newest first.
+    pub versions: Vec<ScriptVersion>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ScriptVersionSource {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub version: ScriptVersion,
+    pub source:  String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActivateRequest {
+    pub version: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StageRequest {
+    pub version:  i32,
+    /// Devices of this group, in the script's domain, run `version`.
+    pub group_id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DryRunRequest {
+    /// `inform` (default), `session_ended` or `decommission`.
+    #[serde(default = "default_event_type")]
+    pub event_type: String,
+    /// Device to run the scripts for. Defaults to `payload.device_id`.
+    pub device_id:  Option<String>,
+    /// Payload the scripts receive, e.g. a synthetic Inform. Built from the
+    /// stored device when absent.
+    pub payload:    Option<JsonValue>,
+    /// Defaults to the stage the event would have.
+    pub lifecycle:  Option<Lifecycle>,
+    /// Domain slug whose scripts run. Defaults to the device's domain, or
+    /// for a new device the one onboarding would place it in.
+    pub domain:     Option<String>,
+}
+
+fn default_event_type() -> String {
+    "inform".to_string()
+}
+
+#[derive(Debug, Serialize)]
+pub struct DryRunResponse {
+    pub event_type:       String,
+    pub device_id:        String,
+    pub domain:           String,
+    pub lifecycle:        Lifecycle,
+    pub hardware_version: Option<String>,
+    pub software_version: Option<String>,
+    /// Directories searched, general to specific, relative to the
+    /// provisioning root.
+    pub directories:      Vec<String>,
+    /// Every action, in the order it would be sent.
+    pub actions:          Vec<DryRunAction>,
+    /// Every script that ran, in order.
+    pub scripts:          Vec<DryRunScript>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DryRunAction {
+    /// Script that returned the action.
+    pub script: String,
+    pub action: Action,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DryRunScript {
+    pub script:  String,
+    /// Version of a stored script; `null` for a file.
+    pub version: Option<i32>,
+    /// Number of actions it returned.
+    pub actions: usize,
+    /// Inventory updates it returned; a dry run does not apply them.
+    pub updates: Vec<InventoryUpdate>,
+    /// Rhai `print` / `debug` output, or Python stderr.
+    pub output:  String,
+    pub error:   Option<String>,
+}
+
+// ── Script repository ─────────────────────────────────────────────────────────
+
+/// `GET /api/v1/provisioning/scripts[?domain=<slug>&event_type=<type>]` —
+/// the scripts of the domains the caller edits, ordered by place.
+pub async fn list_scripts(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ScriptListQuery>,
+) -> impl IntoResponse {
+    let result = sqlx::query_as::<_, Script>(&format!(
+        r#"
+        SELECT {SCRIPT_COLUMNS} FROM provisioning_scripts
+        WHERE ($1::UUID[] IS NULL OR domain_id = ANY($1))
+          AND ($2::TEXT IS NULL OR domain_id = (SELECT id FROM domains WHERE slug = $2))
+          AND ($3::TEXT IS NULL OR event_type = $3)
+        ORDER BY event_type, domain_id, path
+        "#
+    ))
+    .bind(principal.visible_domains())
+    .bind(&query.domain)
+    .bind(&query.event_type)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(mut scripts) => {
+            scripts.retain(|s| principal.has_role(s.domain_id, Role::Editor));
+            (StatusCode::OK, Json(scripts)).into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, "list_scripts: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/provisioning/scripts` — adds a version of the script at
+/// a place of the tree, creating the script on first upload.
+///
+/// Stored scripts are Rhai and must compile. The new version only runs
+/// once activated or staged, unless `activate` is set.
+pub async fn upload_script(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Json(body): Json<UploadScriptRequest>,
+) -> impl IntoResponse {
+    let domain_id = match db::get_domain_id(&state.pool, &body.domain).await {
+        Ok(Some(id)) if principal.has_role(id, Role::Admin) => id,
+        Ok(Some(id)) if principal.can_view(id) => return forbidden(),
+        Ok(_) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, "upload_script: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    if !EVENT_TYPES.contains(&body.event_type.as_str()) {
+        let msg = format!("event_type must be one of {}", EVENT_TYPES.join(", "));
+        return (StatusCode::UNPROCESSABLE_ENTITY, msg).into_response();
+    }
+    if let Err(e) = repository::validate_path(&body.path) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, format!("invalid path: {e}")).into_response();
+    }
+    if let Err(e) = state.provisioner.check_rhai(&body.source) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, format!("script does not compile: {e}")).into_response();
+    }
+
+    let upload = Upload {
+        event_type: &body.event_type,
+        domain_id,
+        path: &body.path,
+        source: &body.source,
+        comment: body.comment.as_deref(),
+        activate: body.activate,
+    };
+    match repository::upload(&state.pool, &upload, principal.user_id).await {
+        Ok((script, version)) => {
+            tracing::info!(script_id = %script.id, version, user = %principal.email, "Provisioning script uploaded");
+            (StatusCode::CREATED, Json(UploadScriptResponse { script, version })).into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, "upload_script: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/provisioning/scripts/:id` — the script and its versions,
+/// without their sources.
+pub async fn get_script(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let script = match authorize(&state, &principal, id, Role::Editor).await {
+        Ok(s) => s,
+        Err(resp) => return resp,
+    };
+
+    let result = sqlx::query_as::<_, ScriptVersion>(
+        r#"
+        SELECT version, comment, created_by, created_at
+        FROM provisioning_script_versions
+        WHERE script_id = $1
+        ORDER BY version DESC
+        "#,
+    )
+    .bind(id)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(versions) => (StatusCode::OK, Json(ScriptDetail { script, versions })).into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "get_script: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/provisioning/scripts/:id/versions/:version` — with its source.
+pub async fn get_script_version(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path((id, version)): Path<(Uuid, i32)>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id, Role::Editor).await {
+        return resp;
+    }
+
+    let result = sqlx::query_as::<_, ScriptVersionSource>(
+        r#"
+        SELECT version, comment, created_by, created_at, source
+        FROM provisioning_script_versions
+        WHERE script_id = $1 AND version = $2
+        "#,
+    )
+    .bind(id)
+    .bind(version)
+    .fetch_optional(&state.pool)
+    .await;
+
+    match result {
+        Ok(Some(v)) => (StatusCode::OK, Json(v)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Version not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, version, "get_script_version: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/provisioning/scripts/:id/activate` — runs `version` for
+/// every device from the next event on. Ends staging of that version.
+pub async fn activate_script(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<ActivateRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id, Role::Admin).await {
+        return resp;
+    }
+    if let Err(resp) = require_version(&state, id, body.version).await {
+        return resp;
+    }
+    script_response(repository::activate(&state.pool, id, Some(body.version)).await, id, "activate_script")
+}
+
+/// `POST /api/v1/provisioning/scripts/:id/deactivate` — no version runs,
+/// except a staged one; a file at the same place runs again.
+pub async fn deactivate_script(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id, Role::Admin).await {
+        return resp;
+    }
+    script_response(repository::activate(&state.pool, id, None).await, id, "deactivate_script")
+}
+
+/// `POST /api/v1/provisioning/scripts/:id/rollback` — back to the version
+/// active before the last activation or rollback.
+pub async fn rollback_script(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id, Role::Admin).await {
+        return resp;
+    }
+    match repository::rollback(&state.pool, id).await {
+        Ok(Some(script)) => (StatusCode::OK, Json(script)).into_response(),
+        Ok(None) => (StatusCode::CONFLICT, "No previous version to roll back to").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "rollback_script: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `PUT /api/v1/provisioning/scripts/:id/stage` — runs `version` instead
+/// of the active one for the members of a group of the script's domain.
+pub async fn stage_script(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<StageRequest>,
+) -> impl IntoResponse {
+    let script = match authorize(&state, &principal, id, Role::Admin).await {
+        Ok(s) => s,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_version(&state, id, body.version).await {
+        return resp;
+    }
+
+    let group_domain: Result<Option<Uuid>, _> = sqlx::query_scalar("SELECT domain_id FROM device_groups WHERE id = $1")
+        .bind(body.group_id)
+        .fetch_optional(&state.pool)
+        .await;
+    match group_domain {
+        Ok(Some(domain_id)) if domain_id == script.domain_id => {}
+        Ok(_) => {
+            return (StatusCode::UNPROCESSABLE_ENTITY, "group_id is not a group of the script's domain").into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, %id, "stage_script: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    }
+
+    let result = repository::stage(&state.pool, id, Some((body.version, body.group_id))).await;
+    script_response(result, id, "stage_script")
+}
+
+/// `DELETE /api/v1/provisioning/scripts/:id/stage`
+pub async fn unstage_script(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id, Role::Admin).await {
+        return resp;
+    }
+    script_response(repository::stage(&state.pool, id, None).await, id, "unstage_script")
+}
+
+// ── Run log ───────────────────────────────────────────────────────────────────
+
+/// `GET /api/v1/device/:uid/provisioning-runs[?limit=20]` — the device's
+/// latest runs, newest first, each with its scripts and actions.
+pub async fn list_device_runs(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(uid): Path<String>,
+    Query(query): Query<RunListQuery>,
+) -> impl IntoResponse {
+    let device_id = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, _))) => device_id,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, uid, "list_device_runs: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    match runs::for_device(&state.pool, device_id, limit).await {
+        Ok(runs) => (StatusCode::OK, Json(runs)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, uid, "list_device_runs: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+// ── Dry run ───────────────────────────────────────────────────────────────────
+
+/// `POST /api/v1/provisioning/dry-run` — requires `domain_editor` in the
+/// domain whose scripts run.
+///
+/// Scripts run as they would for the event, except that nothing reaches the
+/// device: returned actions are only reported, and `send()` fails.
+pub async fn dry_run(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Json(req): Json<DryRunRequest>,
+) -> impl IntoResponse {
+    if !EVENT_TYPES.contains(&req.event_type.as_str()) {
+        let msg = format!("event_type must be one of {}", EVENT_TYPES.join(", "));
+        return (StatusCode::UNPROCESSABLE_ENTITY, msg).into_response();
+    }
+    let payload_uid = req.payload.as_ref().and_then(|p| p.get("device_id")).and_then(JsonValue::as_str);
+    let Some(uid) = req.device_id.as_deref().or(payload_uid).map(str::to_string) else {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "device_id or payload.device_id is required").into_response();
+    };
+
+    let known = match visible_device(&state, &principal, &uid).await {
+        Ok(known) => known,
+        Err(e) => {
+            tracing::error!(?e, uid, "dry_run: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let payload = match (req.payload, known) {
+        (Some(payload), _) => payload,
+        (None, Some((device_id, _))) => match stored_payload(&state.pool, device_id, &req.event_type).await {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::error!(?e, uid, "dry_run: db error");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+            }
+        },
+        (None, None) => return (StatusCode::NOT_FOUND, "Device not found; pass a payload for a new device").into_response(),
+    };
+
+    let domain_id = match (&req.domain, known) {
+        (Some(slug), _) => match db::get_domain_id(&state.pool, slug).await {
+            Ok(Some(id)) => id,
+            Ok(None) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+            Err(e) => {
+                tracing::error!(?e, slug, "dry_run: db error");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+            }
+        },
+        (None, Some((_, domain_id))) => domain_id,
+        (None, None) => {
+            // A new device lands where onboarding puts it.
+            let inform: InformPayload = match serde_json::from_value(payload.clone()) {
+                Ok(inform) => inform,
+                Err(e) => {
+                    let msg = format!("payload is not an Inform: {e}; pass domain instead");
+                    return (StatusCode::UNPROCESSABLE_ENTITY, msg).into_response();
+                }
+            };
+            match onboarding::assign_domain(&state.pool, &inform).await {
+                Ok(domain_id) => domain_id.unwrap_or(state.default_domain_id),
+                Err(e) => {
+                    tracing::error!(?e, uid, "dry_run: db error");
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+                }
+            }
+        }
+    };
+    if !principal.has_role(domain_id, Role::Editor) {
+        return forbidden();
+    }
+    let domain = match db::get_domain_slug(&state.pool, domain_id).await {
+        Ok(slug) => slug,
+        Err(e) => {
+            tracing::error!(?e, %domain_id, "dry_run: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let lifecycle = req.lifecycle.unwrap_or(match req.event_type.as_str() {
+        "decommission" => Lifecycle::Delete,
+        "session_ended" => Lifecycle::Update,
+        _ if known.is_some() => Lifecycle::Update,
+        _ => Lifecycle::Add,
+    });
+    let hardware_version = version(&payload, "hardware_version", "HardwareVersion");
+    let software_version = version(&payload, "software_version", "SoftwareVersion");
+    let target = Target {
+        event_type:  &req.event_type,
+        domain_slug: &domain,
+        hw_version:  hardware_version.as_deref(),
+        sw_version:  software_version.as_deref(),
+        device_id:   &uid,
+        lifecycle,
+    };
+
+    // A JSON value always serialises.
+    let payload = serde_json::to_vec(&payload).expect("payload serialises");
+    let runs = match state.provisioner.run_scripts_traced(&target, &payload, None).await {
+        Ok(runs) => runs,
+        Err(e) => {
+            tracing::error!(?e, uid, "dry_run: provisioning failed");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Provisioning engine failed").into_response();
+        }
+    };
+    let directories = state.provisioner.directories(&target);
+
+    let mut actions = Vec::new();
+    let mut scripts = Vec::new();
+    for run in runs {
+        scripts.push(DryRunScript {
+            script:  run.script.clone(),
+            version: run.version,
+            actions: run.actions.len(),
+            updates: run.updates,
+            output:  run.output,
+            error:   run.error,
+        });
+        actions.extend(run.actions.into_iter().map(|action| DryRunAction { script: run.script.clone(), action }));
+    }
+
+    let response = DryRunResponse {
+        event_type: req.event_type,
+        device_id: uid,
+        domain,
+        lifecycle,
+        hardware_version,
+        software_version,
+        directories,
+        actions,
+        scripts,
+    };
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+// ── Helpers ───────────────────────────────────────────────────────────────────
+
+/// Load a script the caller holds at least `min` on. Scripts of domains the
+/// caller cannot see are reported as `404`.
+async fn authorize(state: &ApiState, principal: &Principal, id: Uuid, min: Role) -> Result<Script, Response> {
+    let result = sqlx::query_as::<_, Script>(&format!("SELECT {SCRIPT_COLUMNS} FROM provisioning_scripts WHERE id = $1"))
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await;
+
+    match result {
+        Ok(Some(s)) if principal.has_role(s.domain_id, min) => Ok(s),
+        Ok(Some(s)) if principal.can_view(s.domain_id) => Err(forbidden()),
+        Ok(_) => Err((StatusCode::NOT_FOUND, "Script not found").into_response()),
+        Err(e) => {
+            tracing::error!(?e, %id, "provisioning script lookup: db error");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response())
+        }
+    }
+}
+
+/// `404` unless the script has `version`.
+async fn require_version(state: &ApiState, id: Uuid, version: i32) -> Result<(), Response> {
+    match repository::has_version(&state.pool, id, version).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((StatusCode::NOT_FOUND, "Version not found").into_response()),
+        Err(e) => {
+            tracing::error!(?e, %id, version, "provisioning script version lookup: db error");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response())
+        }
+    }
+}
+
+fn script_response(result: Result<Script, sqlx::Error>, id: Uuid, handler: &str) -> Response {
+    match result {
+        Ok(script) => (StatusCode::OK, Json(script)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "{handler}: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// A payload for `event_type` built from what is stored about the device:
+/// its identity, versions and last known parameters. It has no Inform
+/// events.
+async fn stored_payload(pool: &sqlx::PgPool, device_id: Uuid, event_type: &str) -> Result<JsonValue, sqlx::Error> {
+    let mut payload: JsonValue = sqlx::query_scalar(
+        r#"
+        SELECT jsonb_build_object(
+            'event',            $2::text,
+            'session_id',       'dry-run',
+            'device_id',        d.device_uid,
+            'oui',              d.oui,
+            'serial_number',    d.serial_number,
+            'manufacturer',     d.manufacturer,
+            'product_class',    d.product_class,
+            'protocol',         d.current_protocol,
+            'hardware_version', d.hardware_version,
+            'software_version', d.software_version,
+            'events',           '[]'::jsonb,
+            'parameter_list',   COALESCE(
+                (SELECT jsonb_object_agg(p.parameter_name, p.parameter_value)
+                 FROM device_parameters p
+                 WHERE p.device_id = d.id AND p.parameter_value IS NOT NULL),
+                '{}'::jsonb)
+        )
+        FROM devices d
+        WHERE d.id = $1
+        "#,
+    )
+    .bind(device_id)
+    .bind(event_type)
+    .fetch_one(pool)
+    .await?;
+
+    if event_type == "session_ended" {
+        payload["reason"] = "dry_run".into();
+        payload["session"] = JsonValue::Null;
+    }
+    Ok(payload)
+}
+
+/// `field` of the payload, else `DeviceInfo.{param}` from its parameter
+/// list (TR-181, then TR-098).
+fn version(payload: &JsonValue, field: &str, param: &str) -> Option<String> {
+    let params = &payload["parameter_list"];
+    [
+        &payload[field],
+        &params[format!("Device.DeviceInfo.{param}").as_str()],
+        &params[format!("InternetGatewayDevice.DeviceInfo.{param}").as_str()],
+    ]
+    .into_iter()
+    .find_map(JsonValue::as_str)
+    .map(str::to_string)
+}
@@ -1,29 +1,77 @@
 use std::sync::Arc;
 
-use dashmap::DashMap;
-use nats_common::DeviceResponse;
-use tokio::sync::oneshot;
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
+use crate::auth::TokenSigner;
+use crate::backups;
+use crate::dispatch::EventMetrics;
+use crate::events::EventHub;
+use crate::firmware;
 use crate::nats::NatsClient;
+use crate::provisioning::Provisioner;
+use crate::sessions::SessionRegistry;
+use crate::tasks::TaskUpdate;
 
 #[derive(Clone)]
 pub struct ApiState {
     pub pool: sqlx::PgPool,
     pub nats: NatsClient,
-    /// Maps `device_uid` -> `session_id`
-    pub active_sessions: Arc<DashMap<String, String>>,
-    /// Maps `command_id` -> oneshot sender for waiting on command response
-    pub pending_commands: Arc<DashMap<Uuid, oneshot::Sender<DeviceResponse>>>,
+    /// Issues and verifies API session tokens.
+    pub auth: TokenSigner,
+    /// Maps `device_uid` -> `session_id`, shared by all replicas.
+    pub sessions: SessionRegistry,
+    /// Responses to queued tasks, fanned out to API clients streaming them.
+    /// Fed from NATS so updates handled by any replica arrive here.
+    pub task_updates: broadcast::Sender<TaskUpdate>,
+    /// Device events streamed to API clients (`/api/v1/events`).
+    pub events: EventHub,
+    /// Runs provisioning scripts.
+    pub provisioner: Provisioner,
+    /// Event loop metrics, served at `/metrics`.
+    pub event_metrics: Arc<EventMetrics>,
+    /// Domain of new devices no onboarding rule matches.
+    pub default_domain_id: Uuid,
+    /// Where uploaded firmware images are kept.
+    pub firmware: firmware::Storage,
+    /// How devices upload and download backup files.
+    pub backups: backups::Settings,
 }
 
 impl ApiState {
-    pub fn new(pool: sqlx::PgPool, nats: NatsClient) -> Self {
+    pub fn new(
+        pool: sqlx::PgPool,
+        nats: NatsClient,
+        auth: TokenSigner,
+        events: EventHub,
+        sessions: SessionRegistry,
+        provisioner: Provisioner,
+        default_domain_id: Uuid,
+    ) -> Self {
         Self {
             pool,
             nats,
-            active_sessions: Arc::new(DashMap::new()),
-            pending_commands: Arc::new(DashMap::new()),
+            auth,
+            sessions,
+            task_updates: broadcast::channel(256).0,
+            events,
+            provisioner,
+            event_metrics: Arc::default(),
+            default_domain_id,
+            firmware: firmware::Storage::default(),
+            backups: backups::Settings::default(),
         }
     }
+
+    /// Keep uploaded firmware images in `firmware`.
+    pub fn with_firmware(mut self, firmware: firmware::Storage) -> Self {
+        self.firmware = firmware;
+        self
+    }
+
+    /// Let devices transfer backup files as `backups` says.
+    pub fn with_backups(mut self, backups: backups::Settings) -> Self {
+        self.backups = backups;
+        self
+    }
 }
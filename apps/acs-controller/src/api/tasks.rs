@@ -0,0 +1,248 @@
+//! Device task queue API.
+//!
+//! Tasks are commands stored until the device next connects (see
+//! [`crate::tasks`]). Reading tasks requires `domain_viewer` on the
+//! device's domain; queueing and cancelling require `domain_editor`.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::datamodel::rejected;
+use crate::api::device::request_connection;
+use crate::api::inventory::visible_device;
+use crate::api::state::ApiState;
+use crate::auth::{forbidden, Principal, Role};
+use crate::datamodel;
+use crate::tasks::{self, NewTask, Task, TaskResult, TaskStatus, TASK_COLUMNS};
+
+#[derive(Debug, Deserialize)]
+pub struct TaskListQuery {
+    pub status: Option<TaskStatus>,
+}
+
+/// A task with every response received for it, oldest first.
+#[derive(Debug, Serialize)]
+pub struct TaskDetail {
+    #[serde(flatten)]
+    pub task:    Task,
+    pub results: Vec<TaskResult>,
+}
+
+/// `GET /api/v1/device/:uid/tasks[?status=pending]`
+///
+/// Newest first.
+pub async fn list_device_tasks(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(uid): Path<String>,
+    Query(query): Query<TaskListQuery>,
+) -> impl IntoResponse {
+    let device_id = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, _))) => device_id,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "list_device_tasks: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let result = sqlx::query_as::<_, Task>(&format!(
+        r#"
+        SELECT {TASK_COLUMNS} FROM tasks
+        WHERE device_id = $1 AND ($2::TEXT IS NULL OR status = $2)
+        ORDER BY created_at DESC
+        "#
+    ))
+    .bind(device_id)
+    .bind(query.status.map(TaskStatus::as_str))
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(tasks) => (StatusCode::OK, Json(tasks)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "list_device_tasks: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/device/:uid/tasks` — requires `domain_editor`.
+///
+/// Stores the task and returns `201` immediately. If the device is in a
+/// session the task is delivered to it now; otherwise a connection request
+/// is sent on a best-effort basis and the task waits for the next Inform.
+pub async fn create_device_task(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(uid): Path<String>,
+    Json(mut body): Json<NewTask>,
+) -> impl IntoResponse {
+    let (device_id, domain_id) = match visible_device(&state, &principal, &uid).await {
+        Ok(Some((device_id, domain_id))) if principal.has_role(domain_id, Role::Editor) => (device_id, domain_id),
+        Ok(Some(_)) => return forbidden(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %uid, "create_device_task: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    if body.max_attempts.is_some_and(|n| n < 1) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "max_attempts must be at least 1").into_response();
+    }
+    if body.expires_in_secs.is_some_and(|s| s < 1) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "expires_in_secs must be positive").into_response();
+    }
+    match datamodel::check(&state.pool, device_id, &mut body.action).await {
+        Ok(problems) if problems.is_empty() => {}
+        Ok(problems) => return rejected(&problems),
+        Err(e) => {
+            tracing::error!(?e, %uid, "create_device_task: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    }
+
+    let task = match tasks::enqueue(&state.pool, device_id, &body, Some(principal.user_id)).await {
+        Ok(t) => t,
+        Err(e) => {
+            tracing::error!(?e, %uid, "create_device_task: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    if deliver_or_wake(&state, device_id, domain_id, &uid).await {
+        // Re-read so the response reflects the delivery.
+        match fetch_task(&state, task.id).await {
+            Ok(Some(t)) => return (StatusCode::CREATED, Json(t)).into_response(),
+            Ok(None) => {}
+            Err(e) => tracing::error!(?e, task_id = %task.id, "create_device_task: db error"),
+        }
+    }
+
+    (StatusCode::CREATED, Json(task)).into_response()
+}
+
+/// `GET /api/v1/tasks/:id`
+pub async fn get_task(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match task_domain(&state, id).await {
+        Ok(Some(domain_id)) if principal.can_view(domain_id) => {}
+        Ok(_) => return (StatusCode::NOT_FOUND, "Task not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "get_task: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    }
+
+    let task = fetch_task(&state, id).await;
+    let results = sqlx::query_as::<_, TaskResult>(
+        r#"
+        SELECT command_id, attempt, result, received_at
+        FROM task_results WHERE task_id = $1
+        ORDER BY received_at
+        "#,
+    )
+    .bind(id)
+    .fetch_all(&state.pool)
+    .await;
+
+    match (task, results) {
+        (Ok(Some(task)), Ok(results)) => (StatusCode::OK, Json(TaskDetail { task, results })).into_response(),
+        (Ok(None), _) => (StatusCode::NOT_FOUND, "Task not found").into_response(),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!(?e, %id, "get_task: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `DELETE /api/v1/tasks/:id` — requires `domain_editor`.
+///
+/// Only `pending` tasks can be cancelled; a task already sent to the device
+/// cannot be recalled and answers `409`.
+pub async fn delete_task(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match task_domain(&state, id).await {
+        Ok(Some(domain_id)) if principal.has_role(domain_id, Role::Editor) => {}
+        Ok(Some(domain_id)) if principal.can_view(domain_id) => return forbidden(),
+        Ok(_) => return (StatusCode::NOT_FOUND, "Task not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "delete_task: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    }
+
+    let result: Result<Option<Uuid>, sqlx::Error> =
+        sqlx::query_scalar("DELETE FROM tasks WHERE id = $1 AND status = 'pending' RETURNING id")
+            .bind(id)
+            .fetch_optional(&state.pool)
+            .await;
+
+    match result {
+        Ok(Some(_)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(None) => (StatusCode::CONFLICT, "Only pending tasks can be cancelled").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "delete_task: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+// ── Helpers ───────────────────────────────────────────────────────────────────
+
+/// Hand newly queued tasks to the device's open session, or wake it.
+///
+/// Returns `true` if the device was in a session and delivery was
+/// attempted; `false` if a connection request was sent (best effort).
+pub(crate) async fn deliver_or_wake(state: &ApiState, device_id: Uuid, domain_id: Uuid, uid: &str) -> bool {
+    let session_id = match state.sessions.get(uid).await {
+        Ok(session_id) => session_id,
+        Err(e) => {
+            tracing::error!(?e, %uid, "Session lookup failed");
+            None
+        }
+    };
+    match session_id {
+        Some(session_id) => {
+            match tasks::deliver_pending(&state.pool, &state.nats, device_id, uid, &session_id).await {
+                Ok(changes) => tasks::announce(&state.events, uid, domain_id, &changes),
+                Err(e) => tracing::error!(?e, %uid, "Immediate task delivery failed"),
+            }
+            true
+        }
+        None => {
+            request_connection(state, device_id, uid).await;
+            false
+        }
+    }
+}
+
+async fn fetch_task(state: &ApiState, id: Uuid) -> Result<Option<Task>, sqlx::Error> {
+    sqlx::query_as::<_, Task>(&format!("SELECT {TASK_COLUMNS} FROM tasks WHERE id = $1"))
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await
+}
+
+/// Domain of the device a task belongs to.
+async fn task_domain(state: &ApiState, id: Uuid) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT d.domain_id FROM tasks t JOIN devices d ON d.id = t.device_id WHERE t.id = $1",
+    )
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await
+}
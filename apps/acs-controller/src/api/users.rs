@@ -0,0 +1,391 @@
+//! User and domain membership management.
+//!
+//! Platform users are managed by super admins; each user may read and update
+//! their own record. Domain memberships are managed by the domain's admins.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::inventory::{is_check_violation, is_unique_violation};
+use crate::api::state::ApiState;
+use crate::auth::{forbidden, password, Principal, Role};
+use crate::db;
+
+// ── Response types ────────────────────────────────────────────────────────────
+
+/// User record returned by the API. Never includes the password hash.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct UserInfo {
+    pub id:             Uuid,
+    pub email:          String,
+    pub display_name:   Option<String>,
+    pub is_super_admin: bool,
+    pub created_at:     chrono::DateTime<chrono::Utc>,
+    pub updated_at:     chrono::DateTime<chrono::Utc>,
+    pub last_login_at:  Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct MemberInfo {
+    pub user_id:      Uuid,
+    pub email:        String,
+    pub display_name: Option<String>,
+    pub role:         String,
+    pub granted_at:   chrono::DateTime<chrono::Utc>,
+    pub granted_by:   Option<Uuid>,
+}
+
+// ── Request types ─────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUserRequest {
+    pub email:          String,
+    pub password:       Option<String>,
+    pub display_name:   Option<String>,
+    #[serde(default)]
+    pub is_super_admin: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchUserRequest {
+    pub display_name:   Option<String>,
+    pub password:       Option<String>,
+    pub is_super_admin: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddMemberRequest {
+    pub email: String,
+    pub role:  Role,
+}
+
+const USER_SELECT: &str =
+    "SELECT id, email, display_name, is_super_admin, created_at, updated_at, last_login_at FROM users";
+
+// ── Users ─────────────────────────────────────────────────────────────────────
+
+/// `GET /api/v1/users` — super admin only.
+pub async fn list_users(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+) -> impl IntoResponse {
+    if !principal.is_super_admin {
+        return forbidden();
+    }
+
+    match sqlx::query_as::<_, UserInfo>(&format!("{USER_SELECT} ORDER BY email"))
+        .fetch_all(&state.pool)
+        .await
+    {
+        Ok(users) => (StatusCode::OK, Json(users)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, "list_users: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/users` — super admin only.
+///
+/// `password` may be omitted for accounts that will authenticate through an
+/// external identity provider.
+pub async fn create_user(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Json(body): Json<CreateUserRequest>,
+) -> impl IntoResponse {
+    if !principal.is_super_admin {
+        return forbidden();
+    }
+
+    let password_hash = match body.password {
+        Some(pw) => match hash_blocking(pw).await {
+            Some(h) => Some(h),
+            None => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password").into_response(),
+        },
+        None => None,
+    };
+
+    let result = sqlx::query_as::<_, UserInfo>(
+        r#"
+        INSERT INTO users (email, password_hash, display_name, is_super_admin)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, email, display_name, is_super_admin, created_at, updated_at, last_login_at
+        "#,
+    )
+    .bind(&body.email)
+    .bind(&password_hash)
+    .bind(&body.display_name)
+    .bind(body.is_super_admin)
+    .fetch_one(&state.pool)
+    .await;
+
+    match result {
+        Ok(user) => (StatusCode::CREATED, Json(user)).into_response(),
+        Err(e) if is_unique_violation(&e) => {
+            (StatusCode::CONFLICT, "Email already registered").into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, "create_user: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/users/:id` — super admin, or the user themself.
+pub async fn get_user(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !principal.is_super_admin && principal.user_id != id {
+        return forbidden();
+    }
+
+    match sqlx::query_as::<_, UserInfo>(&format!("{USER_SELECT} WHERE id = $1"))
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await
+    {
+        Ok(Some(user)) => (StatusCode::OK, Json(user)).into_response(),
+        Ok(None)       => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "get_user: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `PATCH /api/v1/users/:id`
+///
+/// Users may change their own `display_name` and `password`. Only super
+/// admins may edit other users or change `is_super_admin`.
+pub async fn patch_user(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<PatchUserRequest>,
+) -> impl IntoResponse {
+    if !principal.is_super_admin && (principal.user_id != id || body.is_super_admin.is_some()) {
+        return forbidden();
+    }
+
+    if body.display_name.is_none() && body.password.is_none() && body.is_super_admin.is_none() {
+        return (StatusCode::BAD_REQUEST, "Nothing to update").into_response();
+    }
+
+    let password_hash = match body.password {
+        Some(pw) => match hash_blocking(pw).await {
+            Some(h) => Some(h),
+            None => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password").into_response(),
+        },
+        None => None,
+    };
+
+    let mut sets: Vec<String> = vec!["updated_at = now()".to_string()];
+    let mut idx: i32 = 1;
+
+    if body.display_name.is_some()   { sets.push(format!("display_name = ${idx}"));   idx += 1; }
+    if password_hash.is_some()       { sets.push(format!("password_hash = ${idx}"));  idx += 1; }
+    if body.is_super_admin.is_some() { sets.push(format!("is_super_admin = ${idx}")); idx += 1; }
+
+    let sql = format!("UPDATE users SET {} WHERE id = ${} RETURNING id", sets.join(", "), idx);
+
+    let mut q = sqlx::query_scalar::<_, Uuid>(&sql);
+    if let Some(ref name) = body.display_name   { q = q.bind(name); }
+    if let Some(ref hash) = password_hash       { q = q.bind(hash); }
+    if let Some(flag)     = body.is_super_admin { q = q.bind(flag); }
+    q = q.bind(id);
+
+    match q.fetch_optional(&state.pool).await {
+        Ok(Some(_)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(None)    => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "patch_user: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `DELETE /api/v1/users/:id` — super admin only; cannot delete yourself.
+pub async fn delete_user(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !principal.is_super_admin {
+        return forbidden();
+    }
+    if principal.user_id == id {
+        return (StatusCode::CONFLICT, "Cannot delete your own account").into_response();
+    }
+
+    let result: Result<Option<Uuid>, sqlx::Error> =
+        sqlx::query_scalar("DELETE FROM users WHERE id = $1 RETURNING id")
+            .bind(id)
+            .fetch_optional(&state.pool)
+            .await;
+
+    match result {
+        Ok(Some(_)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(None)    => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "delete_user: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+// ── Domain memberships ────────────────────────────────────────────────────────
+
+/// `GET /api/v1/inventory/domains/:slug/members` — domain admin.
+pub async fn list_members(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(slug): Path<String>,
+) -> impl IntoResponse {
+    let domain_id = match db::get_domain_id(&state.pool, &slug).await {
+        Ok(Some(id)) if principal.can_view(id) => id,
+        Ok(_) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, slug, "list_members: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+    if !principal.has_role(domain_id, Role::Admin) {
+        return forbidden();
+    }
+
+    let result = sqlx::query_as::<_, MemberInfo>(
+        r#"
+        SELECT m.user_id, u.email, u.display_name, m.role, m.granted_at, m.granted_by
+        FROM domain_memberships m
+        JOIN users u ON u.id = m.user_id
+        WHERE m.domain_id = $1
+        ORDER BY u.email
+        "#,
+    )
+    .bind(domain_id)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(members) => (StatusCode::OK, Json(members)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, slug, "list_members: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/inventory/domains/:slug/members` — domain admin.
+///
+/// Grants `role` to the user with `email`. Re-posting for an existing member
+/// changes their role.
+pub async fn add_member(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(slug): Path<String>,
+    Json(body): Json<AddMemberRequest>,
+) -> impl IntoResponse {
+    let domain_id = match db::get_domain_id(&state.pool, &slug).await {
+        Ok(Some(id)) if principal.can_view(id) => id,
+        Ok(_) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, slug, "add_member: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+    if !principal.has_role(domain_id, Role::Admin) {
+        return forbidden();
+    }
+
+    let result: Result<Option<Uuid>, sqlx::Error> = sqlx::query_scalar(
+        r#"
+        INSERT INTO domain_memberships (user_id, domain_id, role, granted_by)
+        SELECT u.id, $2, $3, $4 FROM users u WHERE u.email = $1
+        ON CONFLICT (user_id, domain_id) DO UPDATE SET
+            role       = EXCLUDED.role,
+            granted_at = now(),
+            granted_by = EXCLUDED.granted_by
+        RETURNING user_id
+        "#,
+    )
+    .bind(&body.email)
+    .bind(domain_id)
+    .bind(body.role.as_str())
+    .bind(principal.user_id)
+    .fetch_optional(&state.pool)
+    .await;
+
+    match result {
+        Ok(Some(_)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(None)    => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) if is_check_violation(&e) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, "Invalid role").into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, slug, "add_member: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `DELETE /api/v1/inventory/domains/:slug/members/:user_id` — domain admin.
+pub async fn remove_member(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path((slug, user_id)): Path<(String, Uuid)>,
+) -> impl IntoResponse {
+    let domain_id = match db::get_domain_id(&state.pool, &slug).await {
+        Ok(Some(id)) if principal.can_view(id) => id,
+        Ok(_) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, slug, "remove_member: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+    if !principal.has_role(domain_id, Role::Admin) {
+        return forbidden();
+    }
+
+    let result: Result<Option<Uuid>, sqlx::Error> = sqlx::query_scalar(
+        "DELETE FROM domain_memberships WHERE domain_id = $1 AND user_id = $2 RETURNING user_id",
+    )
+    .bind(domain_id)
+    .bind(user_id)
+    .fetch_optional(&state.pool)
+    .await;
+
+    match result {
+        Ok(Some(_)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(None)    => (StatusCode::NOT_FOUND, "Membership not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, slug, %user_id, "remove_member: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+// ── Helpers ───────────────────────────────────────────────────────────────────
+
+/// Hash a password on the blocking pool. `None` on hashing failure.
+async fn hash_blocking(password: String) -> Option<String> {
+    match tokio::task::spawn_blocking(move || password::hash_password(&password)).await {
+        Ok(Ok(hash)) => Some(hash),
+        Ok(Err(e)) => {
+            tracing::error!(error = %e, "Password hashing failed");
+            None
+        }
+        Err(e) => {
+            tracing::error!(?e, "Password hashing task panicked");
+            None
+        }
+    }
+}
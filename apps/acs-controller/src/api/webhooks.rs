@@ -0,0 +1,437 @@
+//! Webhook subscription API.
+//!
+//! Subscriptions belong to a domain and are managed by its `domain_admin`s,
+//! who alone may see them and their delivery history. A subscription's
+//! secret is returned only when it is set: on creation, or when replaced
+//! through `PATCH`. Delivery lives in [`crate::webhooks`].
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::inventory::{is_check_violation, is_unique_violation};
+use crate::api::state::ApiState;
+use crate::auth::{forbidden, Principal, Role};
+use crate::db;
+use crate::webhooks::{self, Delivery, Subscription, WebhookEvent, DELIVERY_COLUMNS, SUBSCRIPTION_COLUMNS};
+
+/// Deliveries listed per page unless `limit` says otherwise.
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+
+// ── Request / response types ──────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookListQuery {
+    /// Filter by domain slug.
+    pub domain: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+    /// Domain slug.
+    pub domain:       String,
+    pub name:         String,
+    pub url:          String,
+    /// Event types delivered; omitted or empty: all of them.
+    #[serde(default)]
+    pub events:       Vec<String>,
+    /// Generated when omitted.
+    pub secret:       Option<String>,
+    pub enabled:      Option<bool>,
+    pub max_attempts: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchWebhookRequest {
+    pub name:         Option<String>,
+    pub url:          Option<String>,
+    pub events:       Option<Vec<String>>,
+    /// Replaces the secret; `""` generates a new one.
+    pub secret:       Option<String>,
+    pub enabled:      Option<bool>,
+    pub max_attempts: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveryListQuery {
+    /// `pending` | `delivered` | `dead`
+    pub status: Option<String>,
+    pub limit:  Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RedeliverRequest {
+    /// Deliveries to send again; omitted: every dead one.
+    pub deliveries: Option<Vec<Uuid>>,
+}
+
+/// A subscription with its queue.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct WebhookInfo {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub subscription: Subscription,
+    pub pending:      i64,
+    pub dead:         i64,
+}
+
+/// A subscription with the secret just set.
+#[derive(Debug, Serialize)]
+pub struct WebhookWithSecret {
+    #[serde(flatten)]
+    pub webhook: WebhookInfo,
+    pub secret:  String,
+}
+
+const WEBHOOK_INFO_SELECT: &str = "\
+    (SELECT count(*) FROM webhook_deliveries d WHERE d.subscription_id = w.id AND d.status = 'pending') AS pending, \
+    (SELECT count(*) FROM webhook_deliveries d WHERE d.subscription_id = w.id AND d.status = 'dead') AS dead";
+
+// ── Handlers ──────────────────────────────────────────────────────────────────
+
+/// `GET /api/v1/webhooks[?domain=<slug>]` — the subscriptions of the domains
+/// the caller administers, ordered by name.
+pub async fn list_webhooks(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<WebhookListQuery>,
+) -> impl IntoResponse {
+    let result = sqlx::query_as::<_, WebhookInfo>(&format!(
+        r#"
+        SELECT {SUBSCRIPTION_COLUMNS}, {WEBHOOK_INFO_SELECT} FROM webhook_subscriptions w
+        WHERE ($1::UUID[] IS NULL OR domain_id = ANY($1))
+          AND ($2::TEXT IS NULL OR domain_id = (SELECT id FROM domains WHERE slug = $2))
+        ORDER BY name
+        "#
+    ))
+    .bind(principal.visible_domains())
+    .bind(&query.domain)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(webhooks) => {
+            let webhooks: Vec<_> =
+                webhooks.into_iter().filter(|w| principal.has_role(w.subscription.domain_id, Role::Admin)).collect();
+            (StatusCode::OK, Json(webhooks)).into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, "list_webhooks: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/webhooks` — requires `domain_admin`. The response carries
+/// the secret.
+pub async fn create_webhook(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Json(body): Json<CreateWebhookRequest>,
+) -> impl IntoResponse {
+    let domain_id = match db::get_domain_id(&state.pool, &body.domain).await {
+        Ok(Some(id)) if principal.has_role(id, Role::Admin) => id,
+        Ok(Some(id)) if principal.can_view(id) => return forbidden(),
+        Ok(_) => return (StatusCode::NOT_FOUND, "Domain not found").into_response(),
+        Err(e) => {
+            tracing::error!(?e, "create_webhook: db error");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+    if let Err(msg) = check_url(&body.url).and(check_events(&body.events)) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, msg).into_response();
+    }
+    let secret = match body.secret {
+        Some(s) if !s.is_empty() => s,
+        _ => webhooks::generate_secret(),
+    };
+
+    let result = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        INSERT INTO webhook_subscriptions (domain_id, name, url, secret, event_types, enabled, max_attempts, created_by)
+        VALUES ($1, $2, $3, $4, $5, COALESCE($6, true), COALESCE($7, 10), $8)
+        RETURNING id
+        "#,
+    )
+    .bind(domain_id)
+    .bind(&body.name)
+    .bind(&body.url)
+    .bind(&secret)
+    .bind(&body.events)
+    .bind(body.enabled)
+    .bind(body.max_attempts)
+    .bind(principal.user_id)
+    .fetch_one(&state.pool)
+    .await;
+
+    match result {
+        Ok(id) => {
+            tracing::info!(webhook_id = %id, url = %body.url, user = %principal.email, "Webhook created");
+            webhook_info(&state, id, StatusCode::CREATED, Some(secret)).await
+        }
+        Err(e) if is_unique_violation(&e) => {
+            (StatusCode::CONFLICT, "Webhook name already exists in this domain").into_response()
+        }
+        Err(e) if is_check_violation(&e) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, "max_attempts must be between 1 and 20").into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, "create_webhook: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/webhooks/:id`
+pub async fn get_webhook(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id).await {
+        return resp;
+    }
+    webhook_info(&state, id, StatusCode::OK, None).await
+}
+
+/// `PATCH /api/v1/webhooks/:id` — the response carries the secret if it was
+/// replaced.
+pub async fn patch_webhook(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<PatchWebhookRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id).await {
+        return resp;
+    }
+    let checked = body.url.as_deref().map_or(Ok(()), check_url).and(body.events.as_deref().map_or(Ok(()), check_events));
+    if let Err(msg) = checked {
+        return (StatusCode::UNPROCESSABLE_ENTITY, msg).into_response();
+    }
+    let secret = body.secret.map(|s| if s.is_empty() { webhooks::generate_secret() } else { s });
+
+    let result = sqlx::query(
+        r#"
+        UPDATE webhook_subscriptions
+        SET name         = COALESCE($2, name),
+            url          = COALESCE($3, url),
+            event_types  = COALESCE($4, event_types),
+            secret       = COALESCE($5, secret),
+            enabled      = COALESCE($6, enabled),
+            max_attempts = COALESCE($7, max_attempts),
+            updated_at   = now()
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .bind(&body.name)
+    .bind(&body.url)
+    .bind(&body.events)
+    .bind(&secret)
+    .bind(body.enabled)
+    .bind(body.max_attempts)
+    .execute(&state.pool)
+    .await;
+
+    match result {
+        Ok(_) => webhook_info(&state, id, StatusCode::OK, secret).await,
+        Err(e) if is_unique_violation(&e) => {
+            (StatusCode::CONFLICT, "Webhook name already exists in this domain").into_response()
+        }
+        Err(e) if is_check_violation(&e) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, "max_attempts must be between 1 and 20").into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, %id, "patch_webhook: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `DELETE /api/v1/webhooks/:id` — with its delivery history.
+pub async fn delete_webhook(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id).await {
+        return resp;
+    }
+    match sqlx::query("DELETE FROM webhook_subscriptions WHERE id = $1").bind(id).execute(&state.pool).await {
+        Ok(_) => {
+            tracing::info!(webhook_id = %id, user = %principal.email, "Webhook deleted");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, %id, "delete_webhook: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/webhooks/:id/ping` — queue a test delivery.
+pub async fn ping_webhook(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let subscription = match authorize(&state, &principal, id).await {
+        Ok(s) => s,
+        Err(resp) => return resp,
+    };
+    match webhooks::ping(&state.pool, &subscription).await {
+        Ok(delivery) => (StatusCode::ACCEPTED, Json(delivery)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "ping_webhook: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `GET /api/v1/webhooks/:id/deliveries[?status=dead&limit=50]` — newest
+/// first.
+pub async fn list_deliveries(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DeliveryListQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id).await {
+        return resp;
+    }
+    if let Some(status) = query.status.as_deref() {
+        if !matches!(status, "pending" | "delivered" | "dead") {
+            return (StatusCode::UNPROCESSABLE_ENTITY, "status must be 'pending', 'delivered' or 'dead'").into_response();
+        }
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let result = sqlx::query_as::<_, Delivery>(&format!(
+        r#"
+        SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries
+        WHERE subscription_id = $1 AND ($2::TEXT IS NULL OR status = $2)
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#
+    ))
+    .bind(id)
+    .bind(&query.status)
+    .bind(limit)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(deliveries) => (StatusCode::OK, Json(deliveries)).into_response(),
+        Err(e) => {
+            tracing::error!(?e, %id, "list_deliveries: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// `POST /api/v1/webhooks/:id/redeliver` — send dead deliveries again, with
+/// a fresh set of attempts. Returns the deliveries requeued.
+pub async fn redeliver(
+    State(state): State<ApiState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<Uuid>,
+    body: Option<Json<RedeliverRequest>>,
+) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &principal, id).await {
+        return resp;
+    }
+    let Json(body) = body.unwrap_or_default();
+
+    let result = sqlx::query_as::<_, Delivery>(&format!(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = 'pending', attempts = 0, next_attempt_at = now()
+        WHERE subscription_id = $1 AND status = 'dead' AND ($2::UUID[] IS NULL OR id = ANY($2))
+        RETURNING {DELIVERY_COLUMNS}
+        "#
+    ))
+    .bind(id)
+    .bind(&body.deliveries)
+    .fetch_all(&state.pool)
+    .await;
+
+    match result {
+        Ok(deliveries) => {
+            tracing::info!(webhook_id = %id, count = deliveries.len(), user = %principal.email, "Webhook deliveries requeued");
+            (StatusCode::OK, Json(deliveries)).into_response()
+        }
+        Err(e) => {
+            tracing::error!(?e, %id, "redeliver: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+// ── Helpers ───────────────────────────────────────────────────────────────────
+
+/// Load a subscription the caller administers. Subscriptions in domains the
+/// caller cannot see are reported as `404`.
+async fn authorize(state: &ApiState, principal: &Principal, id: Uuid) -> Result<Subscription, Response> {
+    let result = sqlx::query_as::<_, Subscription>(&format!(
+        "SELECT {SUBSCRIPTION_COLUMNS} FROM webhook_subscriptions WHERE id = $1"
+    ))
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await;
+
+    match result {
+        Ok(Some(s)) if principal.has_role(s.domain_id, Role::Admin) => Ok(s),
+        Ok(Some(s)) if principal.can_view(s.domain_id) => Err(forbidden()),
+        Ok(_) => Err((StatusCode::NOT_FOUND, "Webhook not found").into_response()),
+        Err(e) => {
+            tracing::error!(?e, %id, "webhook lookup: db error");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response())
+        }
+    }
+}
+
+/// Respond with the subscription and its queue, and `secret` if one was
+/// just set.
+async fn webhook_info(state: &ApiState, id: Uuid, status: StatusCode, secret: Option<String>) -> Response {
+    let result = sqlx::query_as::<_, WebhookInfo>(&format!(
+        "SELECT {SUBSCRIPTION_COLUMNS}, {WEBHOOK_INFO_SELECT} FROM webhook_subscriptions w WHERE id = $1"
+    ))
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await;
+
+    match (result, secret) {
+        (Ok(Some(webhook)), Some(secret)) => (status, Json(WebhookWithSecret { webhook, secret })).into_response(),
+        (Ok(Some(webhook)), None) => (status, Json(webhook)).into_response(),
+        // Deleted concurrently.
+        (Ok(None), _) => (StatusCode::NOT_FOUND, "Webhook not found").into_response(),
+        (Err(e), _) => {
+            tracing::error!(?e, %id, "webhook lookup: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+fn check_url(url: &str) -> Result<(), String> {
+    match reqwest::Url::parse(url) {
+        Ok(u) if matches!(u.scheme(), "http" | "https") && u.host().is_some() => Ok(()),
+        _ => Err(format!("url must be an http(s) URL: {url}")),
+    }
+}
+
+fn check_events(events: &[String]) -> Result<(), String> {
+    match events.iter().find(|e| WebhookEvent::parse(e).is_none()) {
+        None => Ok(()),
+        Some(e) => {
+            let known: Vec<_> = WebhookEvent::ALL.iter().map(|e| e.as_str()).collect();
+            Err(format!("unknown event type '{e}'; known: {}", known.join(", ")))
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Authentication and domain role-based access control.
+//!
+//! Every API route except `POST /api/v1/auth/login` sits behind
+//! [`require_auth`], which verifies the bearer token, loads the caller's
+//! platform flag and domain memberships, and stores them as a [`Principal`]
+//! in the request extensions. Handlers then ask the principal whether it
+//! holds a sufficient [`Role`] in the domain the request touches.
+//!
+//! Role matrix (see `db/README.md`):
+//!
+//! | Role            | Scope  | Grants |
+//! |-----------------|--------|--------|
+//! | `super_admin`   | Global | everything, including domain create/delete and user management |
+//! | `domain_admin`  | Domain | editor rights + membership management, domain settings, device deletion |
+//! | `domain_editor` | Domain | viewer rights + commands, device/property updates, profile assignment |
+//! | `domain_viewer` | Domain | read-only access to devices, events and parameters |
+
+pub mod password;
+pub mod token;
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::state::ApiState;
+
+pub use token::{TokenSigner, MIN_SECRET_LEN};
+
+/// Domain-scoped role, ordered by ascending privilege so `>=` means
+/// "at least as privileged as".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Role {
+    #[serde(rename = "domain_viewer")]
+    Viewer,
+    #[serde(rename = "domain_editor")]
+    Editor,
+    #[serde(rename = "domain_admin")]
+    Admin,
+}
+
+impl Role {
+    /// The value stored in `domain_memberships.role`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "domain_viewer",
+            Role::Editor => "domain_editor",
+            Role::Admin  => "domain_admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "domain_viewer" => Some(Role::Viewer),
+            "domain_editor" => Some(Role::Editor),
+            "domain_admin"  => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// The authenticated caller, as loaded by [`require_auth`].
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub user_id:        Uuid,
+    pub email:          String,
+    pub is_super_admin: bool,
+    /// `domain_id` → role held in that domain.
+    pub memberships:    HashMap<Uuid, Role>,
+}
+
+impl Principal {
+    /// `true` if the caller holds at least `min` in `domain_id`.
+    /// Super admins pass every check.
+    pub fn has_role(&self, domain_id: Uuid, min: Role) -> bool {
+        self.is_super_admin || self.memberships.get(&domain_id).is_some_and(|r| *r >= min)
+    }
+
+    /// `true` if the caller may see anything in `domain_id`.
+    pub fn can_view(&self, domain_id: Uuid) -> bool {
+        self.has_role(domain_id, Role::Viewer)
+    }
+
+    /// Domains the caller may read, or `None` when unrestricted (super admin).
+    ///
+    /// Intended for `WHERE domain_id = ANY($n)` filters on list endpoints.
+    pub fn visible_domains(&self) -> Option<Vec<Uuid>> {
+        if self.is_super_admin {
+            None
+        } else {
+            Some(self.memberships.keys().copied().collect())
+        }
+    }
+}
+
+/// `403` response used by every handler when a role check fails.
+pub fn forbidden() -> Response {
+    (StatusCode::FORBIDDEN, "Insufficient permissions").into_response()
+}
+
+/// Middleware: reject requests without a valid bearer token and attach the
+/// caller's [`Principal`] to the request.
+pub async fn require_auth(
+    State(state): State<ApiState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = bearer_token(&req) else {
+        return (StatusCode::UNAUTHORIZED, "Missing bearer token").into_response();
+    };
+
+    let claims = match state.auth.verify(token, chrono::Utc::now().timestamp()) {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::debug!(error = %e, "Rejected session token");
+            return (StatusCode::UNAUTHORIZED, "Invalid or expired token").into_response();
+        }
+    };
+
+    match load_principal(&state.pool, claims.sub).await {
+        Ok(Some(principal)) => {
+            req.extensions_mut().insert(principal);
+            next.run(req).await
+        }
+        // The user was deleted after the token was issued.
+        Ok(None) => (StatusCode::UNAUTHORIZED, "Invalid or expired token").into_response(),
+        Err(e) => {
+            tracing::error!(?e, "require_auth: db error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// Routes that accept `?access_token=`: the streams browsers open with
+/// `EventSource` or a WebSocket, which cannot set headers.
+const QUERY_TOKEN_ROUTES: &[&str] = &["/api/v1/commands/:id/events", "/api/v1/events", "/api/v1/events/ws"];
+
+/// Extract a token from `Authorization: Bearer <token>`, or from
+/// `?access_token=` on the [`QUERY_TOKEN_ROUTES`]. Elsewhere the query
+/// parameter is ignored: URLs end up in access logs and proxies.
+fn bearer_token(req: &Request) -> Option<&str> {
+    if let Some(value) = req.headers().get(header::AUTHORIZATION) {
+        return value.to_str().ok()?.strip_prefix("Bearer ").map(str::trim);
+    }
+    let route = req.extensions().get::<MatchedPath>()?.as_str();
+    if !QUERY_TOKEN_ROUTES.contains(&route) {
+        return None;
+    }
+    req.uri()
+        .query()?
+        .split('&')
+        .find_map(|kv| kv.strip_prefix("access_token="))
+}
+
+/// Load a user's platform flag and domain memberships.
+pub async fn load_principal(pool: &sqlx::PgPool, user_id: Uuid) -> Result<Option<Principal>, sqlx::Error> {
+    let user: Option<(String, bool)> =
+        sqlx::query_as("SELECT email, is_super_admin FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+
+    let Some((email, is_super_admin)) = user else {
+        return Ok(None);
+    };
+
+    let rows: Vec<(Uuid, String)> =
+        sqlx::query_as("SELECT domain_id, role FROM domain_memberships WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+
+    let memberships = rows
+        .into_iter()
+        .filter_map(|(domain_id, role)| Role::parse(&role).map(|r| (domain_id, r)))
+        .collect();
+
+    Ok(Some(Principal { user_id, email, is_super_admin, memberships }))
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(memberships: &[(Uuid, Role)], is_super_admin: bool) -> Principal {
+        Principal {
+            user_id: Uuid::new_v4(),
+            email: "user@example.com".to_string(),
+            is_super_admin,
+            memberships: memberships.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn roles_are_ordered_by_privilege() {
+        assert!(Role::Admin > Role::Editor);
+        assert!(Role::Editor > Role::Viewer);
+    }
+
+    #[test]
+    fn role_round_trips_through_db_string() {
+        for role in [Role::Viewer, Role::Editor, Role::Admin] {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::parse("super_admin"), None);
+    }
+
+    #[test]
+    fn membership_grants_only_its_own_domain() {
+        let (acme, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let p = principal(&[(acme, Role::Editor)], false);
+        assert!(p.has_role(acme, Role::Viewer));
+        assert!(p.has_role(acme, Role::Editor));
+        assert!(!p.has_role(acme, Role::Admin));
+        assert!(!p.can_view(other));
+        assert_eq!(p.visible_domains(), Some(vec![acme]));
+    }
+
+    #[test]
+    fn super_admin_passes_every_check() {
+        let p = principal(&[], true);
+        assert!(p.has_role(Uuid::new_v4(), Role::Admin));
+        assert_eq!(p.visible_domains(), None);
+    }
+
+    #[tokio::test]
+    async fn query_token_is_only_read_on_stream_routes() {
+        async fn echo(req: Request, _next: Next) -> Response {
+            bearer_token(&req).unwrap_or("none").to_string().into_response()
+        }
+        let ok = || async { StatusCode::OK };
+        let app = axum::Router::new()
+            .route("/api/v1/events", axum::routing::get(ok))
+            .route("/api/v1/commands/:id/events", axum::routing::get(ok))
+            .route("/api/v1/inventory/devices", axum::routing::get(ok))
+            .route_layer(axum::middleware::from_fn(echo));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let token = |path: &str| {
+            let url = format!("http://{addr}{path}");
+            async move { reqwest::get(url).await.unwrap().text().await.unwrap() }
+        };
+        assert_eq!(token("/api/v1/events?access_token=t0k").await, "t0k");
+        assert_eq!(token("/api/v1/commands/42/events?types=x&access_token=t0k").await, "t0k");
+        assert_eq!(token("/api/v1/inventory/devices?access_token=t0k").await, "none");
+    }
+}
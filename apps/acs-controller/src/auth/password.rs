@@ -0,0 +1,54 @@
+//! Argon2id password hashing for `users.password_hash`.
+//!
+//! Hashes are stored in PHC string format
+//! (`$argon2id$v=19$m=...,t=...,p=...$salt$hash`), so parameters can be
+//! tightened later without invalidating existing hashes.
+
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+
+/// PHC hash verified when the looked-up user does not exist, so a login
+/// attempt for an unknown email costs the same as one with a wrong password.
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHRzb21lc2FsdA$Y0Wb6hlpAP0a1mhJhB2M6PNC9aEDEUmTtBgDzLQvJ1I";
+
+/// Hash `password` with Argon2id and a fresh random salt.
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+/// Check `password` against a stored PHC hash.
+///
+/// A missing or unparseable hash never matches.
+pub fn verify_password(password: &str, stored: Option<&str>) -> bool {
+    let Some(hash) = stored.and_then(|h| PasswordHash::new(h).ok()) else {
+        // Burn the same work as a real check before refusing.
+        if let Ok(dummy) = PasswordHash::new(DUMMY_HASH) {
+            let _ = Argon2::default().verify_password(password.as_bytes(), &dummy);
+        }
+        return false;
+    };
+    Argon2::default().verify_password(password.as_bytes(), &hash).is_ok()
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_round_trips() {
+        let hash = hash_password("hunter2").unwrap();
+        assert!(hash.starts_with("$argon2id$"), "hash={hash}");
+        assert!(verify_password("hunter2", Some(&hash)));
+        assert!(!verify_password("hunter3", Some(&hash)));
+    }
+
+    #[test]
+    fn missing_or_invalid_hash_never_matches() {
+        assert!(!verify_password("anything", None));
+        assert!(!verify_password("anything", Some("not-a-phc-string")));
+    }
+}
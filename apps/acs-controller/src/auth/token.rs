@@ -0,0 +1,139 @@
+//! Signed, stateless session tokens.
+//!
+//! A token is `{claims}.{signature}` where both halves are base64url (no
+//! padding). `claims` is the JSON-encoded [`Claims`] and `signature` is
+//! HMAC-SHA256 over the encoded claims, keyed with `AUTH_SECRET`.
+//!
+//! Tokens carry only the user ID and expiry. Roles are re-read from the
+//! database on every request, so revoking a membership takes effect
+//! immediately without a token blacklist.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Shortest `AUTH_SECRET` the controller starts with.
+pub const MIN_SECRET_LEN: usize = 32;
+
+/// Payload embedded in every session token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    /// `users.id` of the authenticated user.
+    pub sub: Uuid,
+    /// Issued-at, Unix seconds.
+    pub iat: i64,
+    /// Expiry, Unix seconds.
+    pub exp: i64,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TokenError {
+    #[error("token is malformed")]
+    Malformed,
+    #[error("token signature is invalid")]
+    BadSignature,
+    #[error("token has expired")]
+    Expired,
+}
+
+/// Issues and verifies session tokens with a shared HMAC key.
+#[derive(Clone)]
+pub struct TokenSigner {
+    key: std::sync::Arc<[u8]>,
+    ttl_secs: i64,
+}
+
+impl TokenSigner {
+    pub fn new(secret: &[u8], ttl_secs: i64) -> Self {
+        Self { key: secret.into(), ttl_secs }
+    }
+
+    /// Issue a token for `user_id`, valid for `ttl_secs` from `now`.
+    pub fn issue(&self, user_id: Uuid, now: i64) -> (String, Claims) {
+        let claims = Claims { sub: user_id, iat: now, exp: now + self.ttl_secs };
+        // Serialising a struct of a UUID and two integers cannot fail.
+        let body = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).expect("claims serialise"));
+        let sig = URL_SAFE_NO_PAD.encode(self.mac(body.as_bytes()).finalize().into_bytes());
+        (format!("{body}.{sig}"), claims)
+    }
+
+    /// Verify the signature and expiry of `token` as of `now`.
+    pub fn verify(&self, token: &str, now: i64) -> Result<Claims, TokenError> {
+        let (body, sig) = token.split_once('.').ok_or(TokenError::Malformed)?;
+        let sig = URL_SAFE_NO_PAD.decode(sig).map_err(|_| TokenError::Malformed)?;
+
+        // `verify_slice` compares in constant time.
+        self.mac(body.as_bytes())
+            .verify_slice(&sig)
+            .map_err(|_| TokenError::BadSignature)?;
+
+        let raw = URL_SAFE_NO_PAD.decode(body).map_err(|_| TokenError::Malformed)?;
+        let claims: Claims = serde_json::from_slice(&raw).map_err(|_| TokenError::Malformed)?;
+
+        if claims.exp <= now {
+            return Err(TokenError::Expired);
+        }
+        Ok(claims)
+    }
+
+    fn mac(&self, data: &[u8]) -> HmacSha256 {
+        // HMAC accepts keys of any length.
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC key of any size");
+        mac.update(data);
+        mac
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer() -> TokenSigner {
+        TokenSigner::new(b"test-secret", 3600)
+    }
+
+    #[test]
+    fn issued_token_verifies() {
+        let user = Uuid::new_v4();
+        let (token, claims) = signer().issue(user, 1_000);
+        assert_eq!(signer().verify(&token, 1_001), Ok(claims));
+    }
+
+    #[test]
+    fn expired_token_is_rejected() {
+        let (token, _) = signer().issue(Uuid::new_v4(), 1_000);
+        assert_eq!(signer().verify(&token, 1_000 + 3600), Err(TokenError::Expired));
+    }
+
+    #[test]
+    fn tampered_claims_are_rejected() {
+        let (token, _) = signer().issue(Uuid::new_v4(), 1_000);
+        let (_, sig) = token.split_once('.').unwrap();
+        let forged = Claims { sub: Uuid::new_v4(), iat: 1_000, exp: 9_999_999 };
+        let body = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+        assert_eq!(
+            signer().verify(&format!("{body}.{sig}"), 1_001),
+            Err(TokenError::BadSignature)
+        );
+    }
+
+    #[test]
+    fn other_key_is_rejected() {
+        let (token, _) = signer().issue(Uuid::new_v4(), 1_000);
+        let other = TokenSigner::new(b"another-secret", 3600);
+        assert_eq!(other.verify(&token, 1_001), Err(TokenError::BadSignature));
+    }
+
+    #[test]
+    fn garbage_is_malformed() {
+        assert_eq!(signer().verify("not-a-token", 0), Err(TokenError::Malformed));
+    }
+}
@@ -0,0 +1,808 @@
+//! Device configuration backups.
+//!
+//! A backup is taken in one of two ways (see `db/config_backups.sql`):
+//!
+//! - **parameters** — a GetParameterNames task over the backup's subtree;
+//!   when it succeeds, [`on_task_update`] reads every writable parameter
+//!   with GetParameterValues tasks of [`refresh::DEFAULT_CHUNK_SIZE`]
+//!   parameters each, and their values together become the backup.
+//! - **upload** — an Upload task pointing the device at
+//!   `/backups/files/:id/:token`, where [`receive_file`] stores the file.
+//!
+//! [`run_scheduler`] fails backups whose task did not succeed, takes the
+//! backups due under each domain's policy and applies retention. Restoring
+//! turns a backup back into an action with [`restore_action`]: one
+//! SetParameterValues, or a Download of the stored file.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use nats_common::{Action, ActionResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::api::ApiState;
+use crate::refresh;
+use crate::tasks::{self, NewTask, TaskStatus, TaskUpdate};
+
+/// A pending backup still incomplete after this long fails; its task
+/// expires then too.
+const PENDING_TIMEOUT_SECS: i64 = 24 * 3600;
+
+/// Complete backups kept per device in domains without a policy.
+pub const DEFAULT_RETAIN: i32 = 10;
+
+/// Scheduled backups started per tick, so a new policy does not queue a
+/// task for every device of a large domain at once.
+const SCHEDULE_BATCH: i64 = 500;
+
+// ── Types ─────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    Parameters,
+    Upload,
+}
+
+impl Method {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::Parameters => "parameters",
+            Method::Upload     => "upload",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "parameters" => Some(Method::Parameters),
+            "upload"     => Some(Method::Upload),
+            _            => None,
+        }
+    }
+}
+
+/// One row of `config_backups`, without its content.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Backup {
+    pub id:           Uuid,
+    pub device_id:    Uuid,
+    pub version:      i32,
+    pub method:       String,
+    pub trigger:      String,
+    pub status:       String,
+    pub task_id:      Option<Uuid>,
+    pub path_prefix:  Option<String>,
+    pub file_type:    Option<String>,
+    pub file_size:    Option<i64>,
+    pub sha256:       Option<String>,
+    pub error:        Option<String>,
+    pub created_by:   Option<Uuid>,
+    pub created_at:   chrono::DateTime<chrono::Utc>,
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub const BACKUP_COLUMNS: &str = "id, device_id, version, method, trigger, status, task_id, path_prefix, \
+                                  file_type, file_size, sha256, error, created_by, created_at, completed_at";
+
+/// One row of `backup_policies`.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Policy {
+    pub domain_id:      Uuid,
+    pub method:         String,
+    pub interval_hours: Option<i32>,
+    pub retain:         i32,
+    pub retain_days:    Option<i32>,
+    pub path_prefix:    String,
+    pub file_type:      String,
+    pub updated_by:     Option<Uuid>,
+    pub updated_at:     chrono::DateTime<chrono::Utc>,
+}
+
+pub const POLICY_COLUMNS: &str = "domain_id, method, interval_hours, retain, retain_days, path_prefix, \
+                                  file_type, updated_by, updated_at";
+
+/// How devices reach the controller for upload backups.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// Base URL devices reach the controller's `/backups/files/` at. Upload
+    /// backups are refused without one.
+    pub base_url:      Option<String>,
+    /// Largest file a device may upload, in bytes.
+    pub max_file_size: usize,
+}
+
+impl Settings {
+    /// URL the file of backup `id` is uploaded to and downloaded from.
+    pub fn file_url(&self, id: Uuid, token: &str) -> Option<String> {
+        self.base_url.as_ref().map(|base| format!("{}/backups/files/{id}/{token}", base.trim_end_matches('/')))
+    }
+}
+
+/// How one backup is taken.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub method:      Method,
+    pub path_prefix: String,
+    pub file_type:   String,
+}
+
+impl Plan {
+    /// The plan of `device_id`'s domain policy, or the defaults, with
+    /// `method` overriding the policy's.
+    pub async fn for_device(pool: &PgPool, device_id: Uuid, method: Option<Method>) -> Result<Self, sqlx::Error> {
+        let policy: Option<(String, String, String)> = sqlx::query_as(
+            r#"
+            SELECT p.method, p.path_prefix, p.file_type
+            FROM backup_policies p JOIN devices d ON d.domain_id = p.domain_id
+            WHERE d.id = $1
+            "#,
+        )
+        .bind(device_id)
+        .fetch_optional(pool)
+        .await?;
+
+        let (policy_method, path_prefix, file_type) = policy.unwrap_or_else(|| {
+            ("parameters".into(), String::new(), "1 Vendor Configuration File".into())
+        });
+        Ok(Plan {
+            method: method.or_else(|| Method::parse(&policy_method)).unwrap_or(Method::Parameters),
+            path_prefix,
+            file_type,
+        })
+    }
+}
+
+/// Why a backup could not be started.
+#[derive(Debug, thiserror::Error)]
+pub enum StartError {
+    #[error("A backup of the device is already in progress")]
+    InProgress,
+    #[error("Upload backups are disabled (BACKUP_BASE_URL is unset)")]
+    UploadsDisabled,
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+// ── Taking backups ────────────────────────────────────────────────────────────
+
+/// Start a backup of `device_id` and queue its first task. The caller
+/// decides whether to wake the device.
+pub async fn start(
+    pool: &PgPool,
+    settings: &Settings,
+    device_id: Uuid,
+    plan: &Plan,
+    trigger: &str,
+    created_by: Option<Uuid>,
+) -> Result<Backup, StartError> {
+    let in_progress: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM config_backups WHERE device_id = $1 AND status = 'pending')")
+            .bind(device_id)
+            .fetch_one(pool)
+            .await?;
+    if in_progress {
+        return Err(StartError::InProgress);
+    }
+
+    let id = Uuid::new_v4();
+    let (action, path_prefix, file_type, token) = match plan.method {
+        Method::Parameters => {
+            let action = Action::GetParameterNames { path_prefix: plan.path_prefix.clone(), next_level: false };
+            (action, Some(plan.path_prefix.as_str()), None, None)
+        }
+        Method::Upload => {
+            let token = Uuid::new_v4().simple().to_string();
+            let url = settings.file_url(id, &token).ok_or(StartError::UploadsDisabled)?;
+            let action = Action::Upload { url, file_type: plan.file_type.clone() };
+            (action, None, Some(plan.file_type.as_str()), Some(token))
+        }
+    };
+
+    let task = NewTask {
+        action,
+        priority:        None,
+        expires_in_secs: Some(PENDING_TIMEOUT_SECS),
+        max_attempts:    None,
+    };
+    let task = tasks::enqueue(pool, device_id, &task, created_by).await?;
+
+    let backup = sqlx::query_as::<_, Backup>(&format!(
+        r#"
+        INSERT INTO config_backups (id, device_id, version, method, trigger, task_id, path_prefix, file_type, file_token, created_by)
+        SELECT $1, $2, COALESCE(max(version), 0) + 1, $3, $4, $5, $6, $7, $8, $9
+        FROM config_backups WHERE device_id = $2
+        RETURNING {BACKUP_COLUMNS}
+        "#
+    ))
+    .bind(id)
+    .bind(device_id)
+    .bind(plan.method.as_str())
+    .bind(trigger)
+    .bind(task.id)
+    .bind(path_prefix)
+    .bind(file_type)
+    .bind(token)
+    .bind(created_by)
+    .fetch_one(pool)
+    .await;
+
+    match backup {
+        Ok(backup) => {
+            info!(backup_id = %backup.id, %device_id, version = backup.version, method = %backup.method, trigger, "Backup started");
+            Ok(backup)
+        }
+        Err(e) => {
+            // Another backup started meanwhile; withdraw this one's task.
+            sqlx::query("DELETE FROM tasks WHERE id = $1 AND status = 'pending'").bind(task.id).execute(pool).await?;
+            match e.as_database_error().and_then(|d| d.code()).as_deref() {
+                Some("23505") => Err(StartError::InProgress),
+                _ => Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Advance the backup a task response belongs to, if any.
+pub async fn on_task_update(state: &ApiState, update: &TaskUpdate) -> Result<(), sqlx::Error> {
+    let pool = &state.pool;
+    let row: Option<(Uuid, Uuid, Uuid, String, String, bool)> = sqlx::query_as(
+        r#"
+        SELECT b.id, b.device_id, d.domain_id, d.device_uid, b.method, t.action ? 'GetParameterNames'
+        FROM config_backups b
+        JOIN tasks t ON t.id = b.task_id
+        JOIN devices d ON d.id = b.device_id
+        WHERE b.task_id = $1 AND b.status = 'pending'
+        "#,
+    )
+    .bind(update.task_id)
+    .fetch_optional(pool)
+    .await?;
+
+    let Some((backup_id, device_id, domain_id, device_uid, method, names)) = row else {
+        return Ok(());
+    };
+
+    let values = match (&update.status, &update.response.result) {
+        (TaskStatus::Faulted, ActionResult::Fault { code, string }) => {
+            return fail(pool, backup_id, &format!("{code}: {string}")).await;
+        }
+        (TaskStatus::Succeeded, ActionResult::Success(values)) => values,
+        // Upload backups complete when the file arrives.
+        _ => return Ok(()),
+    };
+    if method != Method::Parameters.as_str() {
+        return Ok(());
+    }
+
+    if names {
+        let paths = writable_parameters(values);
+        if paths.is_empty() {
+            return fail(pool, backup_id, "Device reported no writable parameters").await;
+        }
+        sqlx::query("UPDATE config_backups SET remaining = $2 WHERE id = $1")
+            .bind(backup_id)
+            .bind(&paths)
+            .execute(pool)
+            .await?;
+    } else {
+        let parameters: BTreeMap<&String, &String> = values.iter().collect();
+        sqlx::query("UPDATE config_backups SET parameters = COALESCE(parameters, '{}') || $2 WHERE id = $1")
+            .bind(backup_id)
+            .bind(serde_json::to_value(parameters).expect("string map serialises"))
+            .execute(pool)
+            .await?;
+    }
+
+    let remaining: Vec<String> = sqlx::query_scalar("SELECT remaining FROM config_backups WHERE id = $1")
+        .bind(backup_id)
+        .fetch_one(pool)
+        .await?;
+    if remaining.is_empty() {
+        let stored: Option<i64> = sqlx::query_scalar(
+            r#"
+            UPDATE config_backups
+            SET status = 'complete', parameters = COALESCE(parameters, '{}'), completed_at = now()
+            WHERE id = $1 AND status = 'pending'
+            RETURNING (SELECT count(*) FROM jsonb_object_keys(parameters))
+            "#,
+        )
+        .bind(backup_id)
+        .fetch_optional(pool)
+        .await?;
+        info!(%backup_id, %device_uid, parameters = stored, "Backup complete");
+        return Ok(());
+    }
+
+    let split = remaining.len().min(refresh::DEFAULT_CHUNK_SIZE as usize);
+    let task = NewTask {
+        action:          Action::GetParameterValues { paths: remaining[..split].to_vec() },
+        priority:        None,
+        expires_in_secs: Some(PENDING_TIMEOUT_SECS),
+        max_attempts:    None,
+    };
+    let task = tasks::enqueue(pool, device_id, &task, None).await?;
+    sqlx::query("UPDATE config_backups SET task_id = $2, remaining = $3 WHERE id = $1 AND status = 'pending'")
+        .bind(backup_id)
+        .bind(task.id)
+        .bind(&remaining[split..])
+        .execute(pool)
+        .await?;
+    // The device is most likely still in the session that answered.
+    crate::api::tasks::deliver_or_wake(state, device_id, domain_id, &device_uid).await;
+    Ok(())
+}
+
+/// Writable leaf parameters of a GetParameterNames result, sorted, except
+/// [secrets](is_secret).
+fn writable_parameters(names: &HashMap<String, String>) -> Vec<String> {
+    let mut paths: Vec<String> = names
+        .iter()
+        .filter(|(name, writable)| !name.ends_with('.') && *writable == "true" && !is_secret(name))
+        .map(|(name, _)| name.clone())
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Whether `name` is a password, key or shared secret. TR-069 has these
+/// read back as empty strings, so a backup cannot hold them and restoring
+/// the empty value would lock the device out, e.g. of the ACS through
+/// `ManagementServer.Password`.
+fn is_secret(name: &str) -> bool {
+    let leaf = name.rsplit('.').next().unwrap_or(name);
+    ["Password", "Passphrase", "PreSharedKey", "WEPKey", "Secret"]
+        .iter()
+        .any(|suffix| leaf.ends_with(suffix))
+}
+
+async fn fail(pool: &PgPool, backup_id: Uuid, error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE config_backups SET status = 'failed', error = $2, completed_at = now() WHERE id = $1 AND status = 'pending'",
+    )
+    .bind(backup_id)
+    .bind(error)
+    .execute(pool)
+    .await?;
+    warn!(%backup_id, error, "Backup failed");
+    Ok(())
+}
+
+/// Store the file a device uploaded for backup `id`. `false` if no pending
+/// upload backup has that id and token.
+pub async fn receive_file(pool: &PgPool, id: Uuid, token: &str, file: &[u8]) -> Result<bool, sqlx::Error> {
+    let sha256: String = Sha256::digest(file).iter().map(|b| format!("{b:02x}")).collect();
+    let stored = sqlx::query(
+        r#"
+        UPDATE config_backups
+        SET status = 'complete', file = $3, file_size = $4, sha256 = $5, completed_at = now()
+        WHERE id = $1 AND file_token = $2 AND method = 'upload' AND status = 'pending'
+        "#,
+    )
+    .bind(id)
+    .bind(token)
+    .bind(file)
+    .bind(file.len() as i64)
+    .bind(&sha256)
+    .execute(pool)
+    .await?
+    .rows_affected()
+        > 0;
+    if stored {
+        info!(backup_id = %id, size = file.len(), %sha256, "Backup file received");
+    }
+    Ok(stored)
+}
+
+/// The file of complete upload backup `id`, for a device restoring it.
+pub async fn stored_file(pool: &PgPool, id: Uuid, token: &str) -> Result<Option<Vec<u8>>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT file FROM config_backups WHERE id = $1 AND file_token = $2 AND status = 'complete' AND file IS NOT NULL",
+    )
+    .bind(id)
+    .bind(token)
+    .fetch_optional(pool)
+    .await
+}
+
+// ── Restore and diff ──────────────────────────────────────────────────────────
+
+/// Content of a complete backup.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Content {
+    pub id:         Uuid,
+    pub parameters: Option<sqlx::types::Json<BTreeMap<String, String>>>,
+    pub file:       Option<Vec<u8>>,
+    pub file_type:  Option<String>,
+    pub file_token: Option<String>,
+}
+
+/// Content of complete backup `version` of `device_id`.
+pub async fn content(pool: &PgPool, device_id: Uuid, version: i32) -> Result<Option<Content>, sqlx::Error> {
+    sqlx::query_as::<_, Content>(
+        r#"
+        SELECT id, parameters, file, file_type, file_token
+        FROM config_backups
+        WHERE device_id = $1 AND version = $2 AND status = 'complete'
+        "#,
+    )
+    .bind(device_id)
+    .bind(version)
+    .fetch_optional(pool)
+    .await
+}
+
+/// The action putting `content` back on the device, or why there is none.
+pub fn restore_action(content: &Content, settings: &Settings) -> Result<Action, String> {
+    if let Some(ref parameters) = content.parameters {
+        // Backups taken before secrets were left out still hold them, empty.
+        let parameters: HashMap<String, String> = parameters
+            .0
+            .iter()
+            .filter(|(name, _)| !is_secret(name))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        if parameters.is_empty() {
+            return Err("Backup has no parameters to restore".into());
+        }
+        return Ok(Action::SetParameterValues { parameters, types: HashMap::new() });
+    }
+    let (Some(file), Some(token)) = (&content.file, &content.file_token) else {
+        return Err("Backup has no content".into());
+    };
+    let url = settings
+        .file_url(content.id, token)
+        .ok_or("Restoring upload backups is disabled (BACKUP_BASE_URL is unset)")?;
+    Ok(Action::Download {
+        url,
+        file_type:       restore_file_type(content.file_type.as_deref().unwrap_or_default()),
+        file_size:       file.len() as u32,
+        target_filename: String::new(),
+    })
+}
+
+/// Download FileType of a file uploaded as `upload_type`: configuration
+/// files go back as `3 Vendor Configuration File`, whatever the instance
+/// suffix; vendor-specific types are kept.
+fn restore_file_type(upload_type: &str) -> String {
+    if upload_type.starts_with("1 Vendor Configuration File") || upload_type.starts_with("3 Vendor Configuration File") {
+        "3 Vendor Configuration File".into()
+    } else {
+        upload_type.into()
+    }
+}
+
+/// Differences between two backups of one device, `from` → `to`.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Diff {
+    Parameters {
+        added:   BTreeMap<String, String>,
+        removed: BTreeMap<String, String>,
+        /// Path → `[from, to]`.
+        changed: BTreeMap<String, [String; 2]>,
+    },
+    Upload {
+        identical: bool,
+        /// Changed lines, `-` from and `+` to, in file order. `None` if
+        /// either file is not text or they are too large to compare.
+        lines:     Option<Vec<String>>,
+    },
+}
+
+/// Largest product of line counts compared line by line.
+const MAX_LINE_PAIRS: usize = 4_000_000;
+
+/// The difference between two backups, or why they cannot be compared.
+pub fn diff(from: &Content, to: &Content) -> Result<Diff, String> {
+    match (&from.parameters, &to.parameters, &from.file, &to.file) {
+        (Some(a), Some(b), _, _) => {
+            let (a, b) = (&a.0, &b.0);
+            let mut diff = (BTreeMap::new(), BTreeMap::new(), BTreeMap::new());
+            for (path, old) in a {
+                match b.get(path) {
+                    None => {
+                        diff.1.insert(path.clone(), old.clone());
+                    }
+                    Some(new) if new != old => {
+                        diff.2.insert(path.clone(), [old.clone(), new.clone()]);
+                    }
+                    Some(_) => {}
+                }
+            }
+            for (path, new) in b {
+                if !a.contains_key(path) {
+                    diff.0.insert(path.clone(), new.clone());
+                }
+            }
+            Ok(Diff::Parameters { added: diff.0, removed: diff.1, changed: diff.2 })
+        }
+        (_, _, Some(a), Some(b)) => {
+            let lines = match (std::str::from_utf8(a), std::str::from_utf8(b)) {
+                (Ok(a), Ok(b)) => line_diff(a, b),
+                _ => None,
+            };
+            Ok(Diff::Upload { identical: a == b, lines })
+        }
+        _ => Err("Backups taken with different methods cannot be compared".into()),
+    }
+}
+
+/// Lines removed from `a` (`-`) and added in `b` (`+`), by longest common
+/// subsequence.
+fn line_diff(a: &str, b: &str) -> Option<Vec<String>> {
+    let (a, b): (Vec<&str>, Vec<&str>) = (a.lines().collect(), b.lines().collect());
+    if a.len().saturating_mul(b.len()) > MAX_LINE_PAIRS {
+        return None;
+    }
+    // lcs[i][j]: common lines of a[i..] and b[j..].
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let (mut i, mut j, mut lines) = (0, 0, Vec::new());
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(format!("-{}", a[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+{}", b[j]));
+            j += 1;
+        }
+    }
+    Some(lines)
+}
+
+// ── Scheduler ─────────────────────────────────────────────────────────────────
+
+/// Postgres advisory lock key held while a replica runs the backup tick.
+const SCHEDULER_LOCK: i64 = 0x6163_735f_6261_636b; // "acs_back"
+
+/// Run the backup tick every `tick`. Runs until the process exits.
+///
+/// Every replica runs the scheduler, but each tick only proceeds on the one
+/// that takes the advisory lock; the others skip it.
+pub async fn run_scheduler(state: ApiState, tick: Duration) {
+    let mut interval = tokio::time::interval(tick);
+    loop {
+        interval.tick().await;
+        let tick = run_tick(&state);
+        if let Some(Err(e)) = crate::db::with_tick_lock(&state.pool, SCHEDULER_LOCK, "Backup scheduler", tick).await {
+            error!(?e, "Backup scheduler: tick failed");
+        }
+    }
+}
+
+async fn run_tick(state: &ApiState) -> Result<(), sqlx::Error> {
+    let pool = &state.pool;
+
+    // 1. Backups whose task did not succeed, or that never completed.
+    let failed = sqlx::query(
+        r#"
+        UPDATE config_backups b
+        SET status = 'failed', completed_at = now(),
+            error = CASE
+                WHEN t.id IS NULL                         THEN 'Task cancelled'
+                WHEN t.status IN ('faulted', 'expired')   THEN COALESCE(t.last_error, 'Task ' || t.status)
+                ELSE 'Timed out'
+            END
+        FROM config_backups b2
+        LEFT JOIN tasks t ON t.id = b2.task_id
+        WHERE b.id = b2.id
+          AND b.status = 'pending'
+          AND (t.id IS NULL
+               OR t.status IN ('faulted', 'expired')
+               OR b.created_at < now() - make_interval(secs => $1))
+        "#,
+    )
+    .bind(PENDING_TIMEOUT_SECS as f64)
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if failed > 0 {
+        warn!(failed, "Backups failed");
+    }
+
+    // 2. Scheduled backups that are due.
+    let due: Vec<(Uuid, String, String, String)> = sqlx::query_as(
+        r#"
+        SELECT d.id, p.method, p.path_prefix, p.file_type
+        FROM devices d
+        JOIN backup_policies p ON p.domain_id = d.domain_id
+        WHERE p.interval_hours IS NOT NULL
+          AND NOT EXISTS (
+              SELECT 1 FROM config_backups b
+              WHERE b.device_id = d.id
+                AND (b.status = 'pending' OR b.created_at > now() - make_interval(hours => p.interval_hours))
+          )
+        ORDER BY d.last_seen DESC
+        LIMIT $1
+        "#,
+    )
+    .bind(SCHEDULE_BATCH)
+    .fetch_all(pool)
+    .await?;
+
+    for (device_id, method, path_prefix, file_type) in due {
+        let plan = Plan {
+            method: Method::parse(&method).unwrap_or(Method::Parameters),
+            path_prefix,
+            file_type,
+        };
+        // Delivered on the device's next Inform; no connection request.
+        match start(pool, &state.backups, device_id, &plan, "scheduled", None).await {
+            Ok(_) | Err(StartError::InProgress) => {}
+            Err(StartError::UploadsDisabled) => {
+                warn!(%device_id, "Backup scheduler: upload backups are disabled — skipping");
+            }
+            Err(StartError::Db(e)) => return Err(e),
+        }
+    }
+
+    // 3. Retention.
+    let pruned = sqlx::query(
+        r#"
+        DELETE FROM config_backups b
+        USING (
+            SELECT b.id,
+                   b.completed_at,
+                   row_number() OVER (PARTITION BY b.device_id ORDER BY b.version DESC) AS rank,
+                   COALESCE(p.retain, $1) AS retain,
+                   p.retain_days
+            FROM config_backups b
+            JOIN devices d ON d.id = b.device_id
+            LEFT JOIN backup_policies p ON p.domain_id = d.domain_id
+            WHERE b.status = 'complete'
+        ) r
+        WHERE b.id = r.id
+          AND r.rank > 1
+          AND (r.rank > r.retain
+               OR (r.retain_days IS NOT NULL AND r.completed_at < now() - make_interval(days => r.retain_days)))
+        "#,
+    )
+    .bind(DEFAULT_RETAIN)
+    .execute(pool)
+    .await?
+    .rows_affected();
+    let superseded = sqlx::query(
+        r#"
+        DELETE FROM config_backups b
+        WHERE b.status = 'failed'
+          AND EXISTS (
+              SELECT 1 FROM config_backups c
+              WHERE c.device_id = b.device_id AND c.status = 'complete' AND c.version > b.version
+          )
+        "#,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if pruned + superseded > 0 {
+        info!(pruned, superseded, "Backups pruned");
+    }
+
+    Ok(())
+}
+
+/// Parameters of a policy, from the API.
+#[derive(Debug, Deserialize)]
+pub struct PolicyUpdate {
+    pub method:         Option<Method>,
+    pub interval_hours: Option<i32>,
+    pub retain:         Option<i32>,
+    pub retain_days:    Option<i32>,
+    pub path_prefix:    Option<String>,
+    pub file_type:      Option<String>,
+}
+
+/// Create or replace `domain_id`'s policy. Fields left out take the
+/// defaults.
+pub async fn set_policy(
+    pool: &PgPool,
+    domain_id: Uuid,
+    policy: &PolicyUpdate,
+    updated_by: Uuid,
+) -> Result<Policy, sqlx::Error> {
+    sqlx::query_as::<_, Policy>(&format!(
+        r#"
+        INSERT INTO backup_policies (domain_id, method, interval_hours, retain, retain_days, path_prefix, file_type, updated_by)
+        VALUES ($1, COALESCE($2, 'parameters'), $3, COALESCE($4, $8), $5, COALESCE($6, ''),
+                COALESCE($7, '1 Vendor Configuration File'), $9)
+        ON CONFLICT (domain_id) DO UPDATE
+        SET method = EXCLUDED.method, interval_hours = EXCLUDED.interval_hours, retain = EXCLUDED.retain,
+            retain_days = EXCLUDED.retain_days, path_prefix = EXCLUDED.path_prefix,
+            file_type = EXCLUDED.file_type, updated_by = EXCLUDED.updated_by, updated_at = now()
+        RETURNING {POLICY_COLUMNS}
+        "#
+    ))
+    .bind(domain_id)
+    .bind(policy.method.map(Method::as_str))
+    .bind(policy.interval_hours)
+    .bind(policy.retain)
+    .bind(policy.retain_days)
+    .bind(&policy.path_prefix)
+    .bind(&policy.file_type)
+    .bind(DEFAULT_RETAIN)
+    .bind(updated_by)
+    .fetch_one(pool)
+    .await
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters(pairs: &[(&str, &str)]) -> Content {
+        Content {
+            id:         Uuid::new_v4(),
+            parameters: Some(sqlx::types::Json(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())),
+            file:       None,
+            file_type:  None,
+            file_token: None,
+        }
+    }
+
+    #[test]
+    fn only_writable_leaves_are_backed_up() {
+        let names = HashMap::from([
+            ("Device.ManagementServer.".to_string(), "false".to_string()),
+            ("Device.ManagementServer.URL".to_string(), "true".to_string()),
+            ("Device.DeviceInfo.UpTime".to_string(), "false".to_string()),
+            ("Device.Time.NTPServer1".to_string(), "true".to_string()),
+        ]);
+        assert_eq!(writable_parameters(&names), ["Device.ManagementServer.URL", "Device.Time.NTPServer1"]);
+    }
+
+    #[test]
+    fn secrets_are_neither_backed_up_nor_restored() {
+        let names = HashMap::from([
+            ("Device.ManagementServer.Password".to_string(), "true".to_string()),
+            ("Device.ManagementServer.ConnectionRequestPassword".to_string(), "true".to_string()),
+            ("Device.WiFi.AccessPoint.1.Security.KeyPassphrase".to_string(), "true".to_string()),
+            ("InternetGatewayDevice.LANDevice.1.WLANConfiguration.1.PreSharedKey.1.PreSharedKey".to_string(), "true".to_string()),
+            ("Device.WiFi.SSID.1.SSID".to_string(), "true".to_string()),
+        ]);
+        assert_eq!(writable_parameters(&names), ["Device.WiFi.SSID.1.SSID"]);
+
+        let old = parameters(&[("Device.ManagementServer.Password", ""), ("Device.WiFi.SSID.1.SSID", "home")]);
+        let Ok(Action::SetParameterValues { parameters: set, .. }) = restore_action(&old, &Settings::default()) else { panic!() };
+        assert_eq!(set, HashMap::from([("Device.WiFi.SSID.1.SSID".to_string(), "home".to_string())]));
+
+        let only_secrets = parameters(&[("Device.ManagementServer.Password", "")]);
+        assert!(restore_action(&only_secrets, &Settings::default()).is_err());
+    }
+
+    #[test]
+    fn parameter_backups_diff_by_path() {
+        let from = parameters(&[("A", "1"), ("B", "2"), ("C", "3")]);
+        let to = parameters(&[("A", "1"), ("B", "20"), ("D", "4")]);
+        let Ok(Diff::Parameters { added, removed, changed }) = diff(&from, &to) else { panic!() };
+        assert_eq!(added, BTreeMap::from([("D".to_string(), "4".to_string())]));
+        assert_eq!(removed, BTreeMap::from([("C".to_string(), "3".to_string())]));
+        assert_eq!(changed, BTreeMap::from([("B".to_string(), ["2".to_string(), "20".to_string()])]));
+
+        let Ok(Action::SetParameterValues { parameters, .. }) = restore_action(&to, &Settings::default()) else { panic!() };
+        assert_eq!(parameters.len(), 3);
+    }
+
+    #[test]
+    fn file_backups_diff_by_line() {
+        assert_eq!(
+            line_diff("a\nb\nc\n", "a\nc\nd\n"),
+            Some(vec!["-b".to_string(), "+d".to_string()]),
+        );
+        assert_eq!(line_diff("same\n", "same\n"), Some(vec![]));
+        assert_eq!(restore_file_type("3 Vendor Configuration File 2"), "3 Vendor Configuration File");
+        assert_eq!(restore_file_type("X ACME Config"), "X ACME Config");
+    }
+}
@@ -0,0 +1,660 @@
+//! Firmware upgrade campaigns.
+//!
+//! Starting a campaign snapshots its target devices into `campaign_devices`
+//! and splits them into waves. [`run_scheduler`] then advances every running
+//! campaign on a fixed tick:
+//!
+//! 1. Download tasks that finished move their device to `downloading` (the
+//!    device accepted the RPC) or `failed`.
+//! 2. Devices in flight longer than `device_timeout_secs` fail.
+//! 3. If the current wave's failure rate exceeds `failure_threshold`, the
+//!    campaign pauses itself.
+//! 4. A finished wave advances the campaign to the next one, or completes it.
+//! 5. Inside the maintenance window, pending devices of the current wave are
+//!    queued as Download tasks until `max_concurrent` are in flight. A
+//!    campaign without an action downloads each device the catalog image of
+//!    `target_version` compatible with it; devices with none fail.
+//!
+//! The rest of the lifecycle comes from Informs, handled by [`on_inform`]:
+//! `7 TRANSFER COMPLETE` marks the device `transferred`, and the boot that
+//! follows is checked against `target_version`.
+//!
+//! Every step reads and writes the database only, so a restarted controller
+//! resumes running campaigns where they were.
+
+use std::time::Duration;
+
+use chrono::NaiveTime;
+use nats_common::Action;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::api::ApiState;
+use crate::db::InformPayload;
+use crate::firmware::{self, Choice};
+use crate::tasks::{self, NewTask};
+
+// ── Types ─────────────────────────────────────────────────────────────────────
+
+/// One row of `campaigns`.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Campaign {
+    pub id:                  Uuid,
+    pub domain_id:           Uuid,
+    pub name:                String,
+    pub status:              String,
+    pub target_filter:       JsonValue,
+    /// `None`: each device gets the catalog image of `target_version`
+    /// compatible with it.
+    pub action:              Option<JsonValue>,
+    pub target_version:      String,
+    pub batch_size:          i32,
+    pub max_concurrent:      i32,
+    pub window_start:        Option<NaiveTime>,
+    pub window_end:          Option<NaiveTime>,
+    pub failure_threshold:   f32,
+    pub device_timeout_secs: i32,
+    pub current_wave:        i32,
+    pub failures_accepted:   i32,
+    pub pause_reason:        Option<String>,
+    pub created_by:          Option<Uuid>,
+    pub created_at:          chrono::DateTime<chrono::Utc>,
+    pub updated_at:          chrono::DateTime<chrono::Utc>,
+    pub started_at:          Option<chrono::DateTime<chrono::Utc>>,
+    pub completed_at:        Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub const CAMPAIGN_COLUMNS: &str = "id, domain_id, name, status, target_filter, action, target_version, \
+                                    batch_size, max_concurrent, window_start, window_end, \
+                                    failure_threshold, device_timeout_secs, current_wave, \
+                                    failures_accepted, pause_reason, created_by, created_at, \
+                                    updated_at, started_at, completed_at";
+
+/// `campaigns.target_filter`. Every field set must match.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetFilter {
+    pub oui:              Option<String>,
+    pub product_class:    Option<String>,
+    pub hardware_version: Option<String>,
+    pub software_version: Option<String>,
+    /// Device must carry every one of these tags.
+    #[serde(default)]
+    pub tags:             Vec<String>,
+    /// Device must be a member of this group of the campaign's domain.
+    pub group:            Option<String>,
+}
+
+/// Device counts of one wave.
+#[derive(Debug, Default, Serialize, sqlx::FromRow)]
+pub struct WaveCounts {
+    pub wave:      i32,
+    pub total:     i64,
+    pub pending:   i64,
+    pub in_flight: i64,
+    pub succeeded: i64,
+    pub failed:    i64,
+}
+
+const WAVE_COUNTS_SQL: &str = r#"
+    SELECT wave,
+           count(*)                                                           AS total,
+           count(*) FILTER (WHERE state = 'pending')                          AS pending,
+           count(*) FILTER (WHERE state IN ('queued', 'downloading', 'transferred')) AS in_flight,
+           count(*) FILTER (WHERE state = 'succeeded')                        AS succeeded,
+           count(*) FILTER (WHERE state = 'failed')                           AS failed
+    FROM campaign_devices
+"#;
+
+/// Per-wave counts for a campaign, in wave order.
+pub async fn wave_counts(pool: &PgPool, campaign_id: Uuid) -> Result<Vec<WaveCounts>, sqlx::Error> {
+    sqlx::query_as::<_, WaveCounts>(&format!(
+        "{WAVE_COUNTS_SQL} WHERE campaign_id = $1 GROUP BY wave ORDER BY wave"
+    ))
+    .bind(campaign_id)
+    .fetch_all(pool)
+    .await
+}
+
+// ── Lifecycle ─────────────────────────────────────────────────────────────────
+
+/// Snapshot the targets of a `draft` campaign and set it running.
+///
+/// Returns the number of target devices, or `None` if the campaign is not
+/// in `draft`. Devices already on `target_version` are not targeted.
+pub async fn start(pool: &PgPool, campaign_id: Uuid) -> Result<Option<u64>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let row: Option<(Uuid, JsonValue, String, i32)> = sqlx::query_as(
+        r#"
+        UPDATE campaigns
+        SET status = 'running', started_at = now(), current_wave = 0, updated_at = now()
+        WHERE id = $1 AND status = 'draft'
+        RETURNING domain_id, target_filter, target_version, batch_size
+        "#,
+    )
+    .bind(campaign_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some((domain_id, filter, target_version, batch_size)) = row else {
+        return Ok(None);
+    };
+    // Validated on create. Never fall back to an empty filter here — that
+    // would target every device in the domain. Dropping `tx` rolls back.
+    let filter: TargetFilter =
+        serde_json::from_value(filter).map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
+
+    let targets = sqlx::query(
+        r#"
+        INSERT INTO campaign_devices (campaign_id, device_id, wave)
+        SELECT $1, d.id, (row_number() OVER (ORDER BY d.device_uid) - 1) / $2
+        FROM devices d
+        WHERE d.domain_id = $3
+          AND ($4::TEXT IS NULL OR upper(d.oui) = upper($4))
+          AND ($5::TEXT IS NULL OR d.product_class = $5)
+          AND ($6::TEXT IS NULL OR d.hardware_version = $6)
+          AND ($7::TEXT IS NULL OR d.software_version = $7)
+          AND d.tags @> $8
+          AND d.software_version IS DISTINCT FROM $9
+          AND ($10::TEXT IS NULL OR EXISTS (
+              SELECT 1 FROM device_group_members m
+              JOIN device_groups g ON g.id = m.group_id
+              WHERE m.device_id = d.id AND g.domain_id = $3 AND g.name = $10
+          ))
+        "#,
+    )
+    .bind(campaign_id)
+    .bind(batch_size)
+    .bind(domain_id)
+    .bind(&filter.oui)
+    .bind(&filter.product_class)
+    .bind(&filter.hardware_version)
+    .bind(&filter.software_version)
+    .bind(&filter.tags)
+    .bind(&target_version)
+    .bind(&filter.group)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+    info!(%campaign_id, targets, "Campaign started");
+    Ok(Some(targets))
+}
+
+/// Advance an in-flight campaign device from the events of an Inform.
+pub async fn on_inform(pool: &PgPool, device_id: Uuid, payload: &InformPayload) -> Result<(), sqlx::Error> {
+    let row: Option<(Uuid, String, String)> = sqlx::query_as(
+        r#"
+        SELECT cd.campaign_id, cd.state, c.target_version
+        FROM campaign_devices cd
+        JOIN campaigns c ON c.id = cd.campaign_id
+        WHERE cd.device_id = $1
+          AND cd.state IN ('queued', 'downloading', 'transferred')
+          AND c.status IN ('running', 'paused')
+        LIMIT 1
+        "#,
+    )
+    .bind(device_id)
+    .fetch_optional(pool)
+    .await?;
+
+    let Some((campaign_id, state, target_version)) = row else {
+        return Ok(());
+    };
+
+    let Some((next, error)) = inform_step(&state, payload, &target_version) else {
+        return Ok(());
+    };
+
+    sqlx::query(
+        r#"
+        UPDATE campaign_devices
+        SET state        = $3,
+            error        = $4,
+            completed_at = CASE WHEN $3 IN ('succeeded', 'failed') THEN now() END,
+            updated_at   = now()
+        WHERE campaign_id = $1 AND device_id = $2
+        "#,
+    )
+    .bind(campaign_id)
+    .bind(device_id)
+    .bind(next)
+    .bind(&error)
+    .execute(pool)
+    .await?;
+
+    info!(%campaign_id, device_id = %payload.device_id, from = %state, to = next, ?error, "Campaign device advanced");
+    Ok(())
+}
+
+/// [`device_step`] for an Inform. `M Download` comes with the
+/// TransferComplete and says nothing about a reboot, so only `1 BOOT` counts.
+fn inform_step(state: &str, payload: &InformPayload, target_version: &str) -> Option<(&'static str, Option<String>)> {
+    device_step(
+        state,
+        payload.has_event("7 TRANSFER COMPLETE"),
+        payload.has_event("1 BOOT"),
+        payload.software_version(),
+        target_version,
+    )
+}
+
+/// The state a campaign device moves to given the events of an Inform, and
+/// the error to record. `None` if the Inform does not change anything.
+///
+/// Devices commonly report `7 TRANSFER COMPLETE` and `1 BOOT` in the same
+/// Inform, so both transitions can happen at once.
+fn device_step(
+    state: &str,
+    transfer_complete: bool,
+    booted: bool,
+    software_version: Option<&str>,
+    target_version: &str,
+) -> Option<(&'static str, Option<String>)> {
+    let transferred = match state {
+        "queued" | "downloading" => transfer_complete,
+        "transferred" => true,
+        _ => return None,
+    };
+
+    if !transferred {
+        return None;
+    }
+    if !booted {
+        return (state != "transferred").then_some(("transferred", None));
+    }
+
+    match software_version {
+        Some(v) if v == target_version => Some(("succeeded", None)),
+        other => Some((
+            "failed",
+            Some(format!(
+                "Reported version {} after upgrade, expected {target_version}",
+                other.unwrap_or("(none)")
+            )),
+        )),
+    }
+}
+
+// ── Scheduler ─────────────────────────────────────────────────────────────────
+
+/// Postgres advisory lock key held while a replica advances campaigns.
+const SCHEDULER_LOCK: i64 = 0x6163_735f_6361_6d70; // "acs_camp"
+
+/// Advance all running campaigns every `tick`. Runs until the process exits.
+///
+/// Every replica runs the scheduler, but each tick only proceeds on the one
+/// that takes the advisory lock; the others skip it.
+pub async fn run_scheduler(state: ApiState, tick: Duration) {
+    let mut interval = tokio::time::interval(tick);
+    loop {
+        interval.tick().await;
+        crate::db::with_tick_lock(&state.pool, SCHEDULER_LOCK, "Campaign scheduler", advance_all(&state)).await;
+    }
+}
+
+async fn advance_all(state: &ApiState) {
+    let running = match sqlx::query_as::<_, Campaign>(&format!(
+        "SELECT {CAMPAIGN_COLUMNS} FROM campaigns WHERE status = 'running' ORDER BY started_at"
+    ))
+    .fetch_all(&state.pool)
+    .await
+    {
+        Ok(c) => c,
+        Err(e) => {
+            error!(?e, "Campaign scheduler: failed to load running campaigns");
+            return;
+        }
+    };
+
+    for campaign in running {
+        if let Err(e) = advance(state, &campaign).await {
+            error!(?e, campaign_id = %campaign.id, "Campaign scheduler: failed to advance campaign");
+        }
+    }
+}
+
+async fn advance(state: &ApiState, campaign: &Campaign) -> Result<(), sqlx::Error> {
+    let pool = &state.pool;
+
+    // 1. Download task outcomes.
+    sqlx::query(
+        r#"
+        UPDATE campaign_devices cd
+        SET state        = CASE WHEN t.status = 'succeeded' THEN 'downloading' ELSE 'failed' END,
+            error        = CASE WHEN t.status = 'succeeded' THEN NULL
+                                ELSE COALESCE(t.last_error, 'Download task ' || t.status) END,
+            completed_at = CASE WHEN t.status = 'succeeded' THEN NULL ELSE now() END,
+            updated_at   = now()
+        FROM tasks t
+        WHERE t.id = cd.task_id
+          AND cd.campaign_id = $1
+          AND cd.state = 'queued'
+          AND t.status IN ('succeeded', 'faulted', 'expired')
+        "#,
+    )
+    .bind(campaign.id)
+    .execute(pool)
+    .await?;
+
+    // A Download task cancelled through the task API takes its device with it.
+    sqlx::query(
+        r#"
+        UPDATE campaign_devices
+        SET state = 'failed', error = 'Download task cancelled', completed_at = now(), updated_at = now()
+        WHERE campaign_id = $1 AND state = 'queued' AND task_id IS NULL
+        "#,
+    )
+    .bind(campaign.id)
+    .execute(pool)
+    .await?;
+
+    // 2. Timeouts. An undelivered Download is withdrawn so the device does
+    //    not upgrade after it has been written off.
+    let timed_out: Vec<Option<Uuid>> = sqlx::query_scalar(
+        r#"
+        UPDATE campaign_devices
+        SET state = 'failed', error = 'Timed out', completed_at = now(), updated_at = now()
+        WHERE campaign_id = $1
+          AND state IN ('queued', 'downloading', 'transferred')
+          AND started_at < now() - make_interval(secs => $2)
+        RETURNING task_id
+        "#,
+    )
+    .bind(campaign.id)
+    .bind(campaign.device_timeout_secs)
+    .fetch_all(pool)
+    .await?;
+    let timed_out: Vec<Uuid> = timed_out.into_iter().flatten().collect();
+    if !timed_out.is_empty() {
+        sqlx::query("DELETE FROM tasks WHERE id = ANY($1) AND status = 'pending'")
+            .bind(&timed_out)
+            .execute(pool)
+            .await?;
+    }
+
+    // 3. Failure threshold for the current wave.
+    let wave = sqlx::query_as::<_, WaveCounts>(&format!(
+        "{WAVE_COUNTS_SQL} WHERE campaign_id = $1 AND wave = $2 GROUP BY wave"
+    ))
+    .bind(campaign.id)
+    .bind(campaign.current_wave)
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(WaveCounts { wave: campaign.current_wave, ..Default::default() });
+
+    let new_failures = (wave.failed - i64::from(campaign.failures_accepted)).max(0);
+    if threshold_exceeded(new_failures, wave.total, campaign.failure_threshold) {
+        let reason = format!(
+            "Wave {} failure rate {}/{} exceeds threshold {:.0}%",
+            wave.wave,
+            wave.failed,
+            wave.total,
+            campaign.failure_threshold * 100.0
+        );
+        warn!(campaign_id = %campaign.id, %reason, "Pausing campaign");
+        sqlx::query(
+            "UPDATE campaigns SET status = 'paused', pause_reason = $2, updated_at = now() WHERE id = $1 AND status = 'running'",
+        )
+        .bind(campaign.id)
+        .bind(reason)
+        .execute(pool)
+        .await?;
+        return Ok(());
+    }
+
+    // 4. Wave finished: next wave or done.
+    if wave.pending + wave.in_flight == 0 {
+        let more: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM campaign_devices WHERE campaign_id = $1 AND wave > $2)",
+        )
+        .bind(campaign.id)
+        .bind(campaign.current_wave)
+        .fetch_one(pool)
+        .await?;
+
+        if more {
+            info!(campaign_id = %campaign.id, wave = campaign.current_wave + 1, "Campaign advancing to next wave");
+            sqlx::query(
+                r#"
+                UPDATE campaigns
+                SET current_wave = current_wave + 1, failures_accepted = 0, updated_at = now()
+                WHERE id = $1 AND status = 'running'
+                "#,
+            )
+            .bind(campaign.id)
+            .execute(pool)
+            .await?;
+        } else {
+            info!(campaign_id = %campaign.id, "Campaign completed");
+            sqlx::query(
+                r#"
+                UPDATE campaigns
+                SET status = 'completed', completed_at = now(), updated_at = now()
+                WHERE id = $1 AND status = 'running'
+                "#,
+            )
+            .bind(campaign.id)
+            .execute(pool)
+            .await?;
+        }
+        return Ok(());
+    }
+
+    // 5. Start more devices inside the maintenance window.
+    let now = chrono::Utc::now().time();
+    if !in_window(now, campaign.window_start, campaign.window_end) {
+        return Ok(());
+    }
+
+    let slots = i64::from(campaign.max_concurrent) - wave.in_flight;
+    if slots <= 0 || wave.pending == 0 {
+        return Ok(());
+    }
+
+    let action: Option<Action> = match campaign.action.clone().map(serde_json::from_value).transpose() {
+        Ok(a) => a,
+        Err(e) => {
+            error!(campaign_id = %campaign.id, error = %e, "Campaign action is not a valid Action — pausing");
+            sqlx::query(
+                "UPDATE campaigns SET status = 'paused', pause_reason = 'Invalid action', updated_at = now() WHERE id = $1",
+            )
+            .bind(campaign.id)
+            .execute(pool)
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let next: Vec<(Uuid, String)> = sqlx::query_as(
+        r#"
+        SELECT cd.device_id, d.device_uid
+        FROM campaign_devices cd
+        JOIN devices d ON d.id = cd.device_id
+        WHERE cd.campaign_id = $1 AND cd.wave = $2 AND cd.state = 'pending'
+        ORDER BY d.device_uid
+        LIMIT $3
+        "#,
+    )
+    .bind(campaign.id)
+    .bind(campaign.current_wave)
+    .bind(slots)
+    .fetch_all(pool)
+    .await?;
+
+    for (device_id, device_uid) in next {
+        let action = match action {
+            Some(ref action) => action.clone(),
+            None => match firmware_download(pool, device_id, &campaign.target_version).await? {
+                Ok(action) => action,
+                Err(error) => {
+                    sqlx::query(
+                        r#"
+                        UPDATE campaign_devices
+                        SET state = 'failed', error = $3, started_at = now(), completed_at = now(), updated_at = now()
+                        WHERE campaign_id = $1 AND device_id = $2
+                        "#,
+                    )
+                    .bind(campaign.id)
+                    .bind(device_id)
+                    .bind(&error)
+                    .execute(pool)
+                    .await?;
+                    warn!(campaign_id = %campaign.id, %device_uid, %error, "Campaign device has no firmware image");
+                    continue;
+                }
+            },
+        };
+        let task = NewTask {
+            action,
+            priority:        None,
+            expires_in_secs: Some(i64::from(campaign.device_timeout_secs)),
+            max_attempts:    Some(1),
+        };
+        let task = tasks::enqueue(pool, device_id, &task, campaign.created_by).await?;
+
+        sqlx::query(
+            r#"
+            UPDATE campaign_devices
+            SET state = 'queued', task_id = $3, started_at = now(), updated_at = now()
+            WHERE campaign_id = $1 AND device_id = $2
+            "#,
+        )
+        .bind(campaign.id)
+        .bind(device_id)
+        .bind(task.id)
+        .execute(pool)
+        .await?;
+
+        info!(campaign_id = %campaign.id, %device_uid, task_id = %task.id, "Campaign Download queued");
+        crate::api::tasks::deliver_or_wake(state, device_id, campaign.domain_id, &device_uid).await;
+    }
+
+    Ok(())
+}
+
+/// The Download of the catalog image of `version` for `device_id`, or why
+/// there is none.
+async fn firmware_download(
+    pool: &PgPool,
+    device_id: Uuid,
+    version: &str,
+) -> Result<Result<Action, String>, sqlx::Error> {
+    Ok(match firmware::choose(pool, device_id, version).await? {
+        Choice::Image(image) => image.download().ok_or_else(|| format!("No firmware image of {version}")),
+        Choice::Unknown => Err(format!("No firmware image of {version}")),
+        Choice::Incompatible(why) => Err(format!("No firmware image of {version} is compatible: {why}")),
+    })
+}
+
+/// `true` if `now` falls inside the `[start, end)` window. The window may
+/// wrap midnight (`22:00`–`04:00`). No window means always.
+fn in_window(now: NaiveTime, start: Option<NaiveTime>, end: Option<NaiveTime>) -> bool {
+    let (Some(start), Some(end)) = (start, end) else {
+        return true;
+    };
+    if start <= end {
+        start <= now && now < end
+    } else {
+        now >= start || now < end
+    }
+}
+
+/// `true` if `failed` out of `total` devices is more than `threshold`.
+fn threshold_exceeded(failed: i64, total: i64, threshold: f32) -> bool {
+    total > 0 && failed > 0 && (failed as f64 / total as f64) > f64::from(threshold)
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn no_window_is_always_open() {
+        assert!(in_window(t(12, 0), None, None));
+    }
+
+    #[test]
+    fn daytime_window() {
+        assert!(in_window(t(10, 0), Some(t(9, 0)), Some(t(17, 0))));
+        assert!(!in_window(t(17, 0), Some(t(9, 0)), Some(t(17, 0))));
+        assert!(!in_window(t(8, 59), Some(t(9, 0)), Some(t(17, 0))));
+    }
+
+    #[test]
+    fn window_wrapping_midnight() {
+        assert!(in_window(t(23, 30), Some(t(22, 0)), Some(t(4, 0))));
+        assert!(in_window(t(1, 0), Some(t(22, 0)), Some(t(4, 0))));
+        assert!(!in_window(t(12, 0), Some(t(22, 0)), Some(t(4, 0))));
+    }
+
+    #[test]
+    fn threshold_is_exclusive() {
+        assert!(!threshold_exceeded(1, 10, 0.1));
+        assert!(threshold_exceeded(2, 10, 0.1));
+        assert!(!threshold_exceeded(0, 10, 0.0));
+        assert!(threshold_exceeded(1, 10, 0.0));
+    }
+
+    #[test]
+    fn transfer_and_boot_in_one_inform_completes() {
+        assert_eq!(
+            device_step("downloading", true, true, Some("2.0"), "2.0"),
+            Some(("succeeded", None))
+        );
+    }
+
+    #[test]
+    fn transfer_then_boot() {
+        assert_eq!(device_step("queued", true, false, Some("1.0"), "2.0"), Some(("transferred", None)));
+        assert_eq!(device_step("transferred", false, false, Some("1.0"), "2.0"), None);
+        assert_eq!(device_step("transferred", false, true, Some("2.0"), "2.0"), Some(("succeeded", None)));
+    }
+
+    #[test]
+    fn wrong_version_after_boot_fails() {
+        let (state, error) = device_step("transferred", false, true, Some("1.0"), "2.0").unwrap();
+        assert_eq!(state, "failed");
+        assert!(error.unwrap().contains("1.0"));
+    }
+
+    #[test]
+    fn boot_without_transfer_is_ignored() {
+        assert_eq!(device_step("downloading", false, true, Some("1.0"), "2.0"), None);
+    }
+
+    fn inform(events: &[&str], software_version: &str) -> InformPayload {
+        serde_json::from_value(serde_json::json!({
+            "session_id": "s1", "device_id": "AABB00-1", "oui": "AABB00", "serial_number": "1",
+            "manufacturer": "Example", "product_class": "Gateway", "events": events,
+            "parameter_list": { "Device.DeviceInfo.SoftwareVersion": software_version },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn inform_events_are_matched_by_code() {
+        // As acs-cwmp sends them: "{code} {command_key}".
+        let transfer = inform(&["7 TRANSFER COMPLETE ", "M Download fw-2.0"], "1.0");
+        assert_eq!(inform_step("downloading", &transfer, "2.0"), Some(("transferred", None)));
+
+        let both = inform(&["1 BOOT ", "7 TRANSFER COMPLETE fw-2.0", "M Download fw-2.0"], "2.0");
+        assert_eq!(inform_step("downloading", &both, "2.0"), Some(("succeeded", None)));
+
+        let boot = inform(&["1 BOOT "], "2.0");
+        assert_eq!(inform_step("transferred", &boot, "2.0"), Some(("succeeded", None)));
+        assert_eq!(inform_step("transferred", &inform(&["2 PERIODIC "], "1.0"), "2.0"), None);
+        assert_eq!(inform_step("transferred", &inform(&["1 BOOTSTRAP "], "1.0"), "2.0"), None);
+    }
+}
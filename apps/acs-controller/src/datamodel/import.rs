@@ -0,0 +1,407 @@
+//! Reading Broadband Forum data model documents.
+//!
+//! Two kinds of document are understood:
+//!
+//! - **cwmp-datamodel** (TR-106 DM schema), e.g. `tr-181-2-16-0-cwmp-full.xml`:
+//!   each `<model name="Device:2.16">` becomes a [`Model`] of every object
+//!   and parameter, with the syntax of `<dataType>` references resolved.
+//!   Only the "-full" variants are self-contained; documents built from
+//!   `<component>`s are refused.
+//! - **device type** (TR-106 DT schema): what one kind of device supports of
+//!   a standard model, `<model ref="Device:2.16">` with `<object ref>` and
+//!   `<parameter ref>`. Types not restated in the document are taken from
+//!   the referenced model with [`Model::inherit`].
+
+use std::collections::HashMap;
+
+use sqlx::types::Json;
+
+use super::xml::{self, Element};
+use super::{Entry, Kind, Range};
+
+/// One model read from a document.
+#[derive(Debug)]
+pub struct Model {
+    pub kind:    Kind,
+    /// `Device:2.16`, or the device type URN.
+    pub name:    String,
+    /// `Device.` or `InternetGatewayDevice.`.
+    pub root:    String,
+    /// Model this one extends or restricts.
+    pub base:    Option<String>,
+    pub spec:    Option<String>,
+    pub file:    Option<String>,
+    /// In document order.
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error(transparent)]
+    Xml(#[from] xml::Error),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+const PRIMITIVES: &[&str] =
+    &["string", "boolean", "unsignedInt", "int", "unsignedLong", "long", "dateTime", "base64", "hexBinary", "decimal"];
+
+/// TR-106 named types, for `<dataType ref>`s to types the document imports
+/// rather than defines.
+const TR106_TYPES: &[(&str, &str, Option<i32>)] = &[
+    ("Alias", "string", Some(64)),
+    ("Dbm1000", "int", None),
+    ("DiagnosticsState", "string", None),
+    ("IEEE_EUI64", "string", Some(23)),
+    ("IPAddress", "string", Some(45)),
+    ("IPPrefix", "string", Some(49)),
+    ("IPv4Address", "string", Some(15)),
+    ("IPv4Prefix", "string", Some(18)),
+    ("IPv6Address", "string", Some(45)),
+    ("IPv6Prefix", "string", Some(49)),
+    ("MACAddress", "string", Some(17)),
+    ("StatsCounter32", "unsignedInt", None),
+    ("StatsCounter64", "unsignedLong", None),
+    ("UUID", "string", Some(36)),
+    ("ZigBeeNetworkAddress", "string", Some(4)),
+];
+
+/// Read every model in `input`.
+pub fn parse(input: &str) -> Result<Vec<Model>, ImportError> {
+    let doc = xml::parse(input)?;
+    if doc.name != "document" {
+        return Err(ImportError::Invalid(format!("<{}> is not a data model or device type document", doc.name)));
+    }
+    let spec = doc.attr("spec").map(str::to_string);
+    let file = doc.attr("file").map(str::to_string);
+    let types = DataTypes { defs: doc.children_named("dataType").filter_map(|d| Some((d.attr("name")?, d))).collect() };
+
+    let mut models: Vec<Model> = Vec::new();
+    for el in doc.children_named("model") {
+        let model = if let Some(base) = el.attr("ref") {
+            let Some(name) = doc.attr("deviceType") else {
+                return Err(ImportError::Invalid("device type document has no deviceType".into()));
+            };
+            device_type(el, name, base, &types)?
+        } else {
+            let Some(name) = el.attr("name") else {
+                return Err(ImportError::Invalid("<model> has neither name nor ref".into()));
+            };
+            let base = el.attr("base").and_then(|b| models.iter().find(|m| m.name == b));
+            datamodel(el, name, base, &types)?
+        };
+        models.push(Model { spec: spec.clone(), file: file.clone(), ..model });
+    }
+    if models.is_empty() {
+        return Err(ImportError::Invalid("document defines no models".into()));
+    }
+    Ok(models)
+}
+
+fn datamodel(el: &Element, name: &str, base: Option<&Model>, types: &DataTypes) -> Result<Model, ImportError> {
+    if el.child("component").is_some() {
+        return Err(ImportError::Invalid(format!(
+            "model {name} is built from components; import the \"-full\" variant of the document"
+        )));
+    }
+    let mut entries = Entries::default();
+    if let Some(base) = base {
+        for e in &base.entries {
+            entries.put(e.clone());
+        }
+    }
+
+    for obj in el.children_named("object") {
+        let Some(path) = obj.attr("name").or_else(|| obj.attr("base")) else { continue };
+        let entry = entries.get_or_insert(path, || object(path));
+        if let Some(access) = obj.attr("access") {
+            entry.access = access.to_string();
+        }
+        set_description(entry, obj);
+
+        for param in obj.children_named("parameter") {
+            let Some(name) = param.attr("name").or_else(|| param.attr("base")) else { continue };
+            let entry = entries.get_or_insert(&format!("{path}{name}"), || parameter(path, name));
+            if let Some(access) = param.attr("access") {
+                entry.access = access.to_string();
+            }
+            if let Some(syntax) = param.child("syntax") {
+                types.apply(entry, syntax)?;
+            }
+            set_description(entry, param);
+        }
+    }
+    model(Kind::Datamodel, name, base.map(|b| b.name.clone()), entries)
+}
+
+fn device_type(el: &Element, name: &str, base: &str, types: &DataTypes) -> Result<Model, ImportError> {
+    let mut entries = Entries::default();
+    for obj in el.children_named("object") {
+        let Some(path) = obj.attr("ref") else { continue };
+        let entry = entries.get_or_insert(path, || Entry { access: String::new(), ..object(path) });
+        if let Some(access) = obj.attr("access") {
+            entry.access = access.to_string();
+        }
+        set_description(entry, obj);
+
+        for param in obj.children_named("parameter") {
+            let Some(name) = param.attr("ref") else { continue };
+            let entry = entries.get_or_insert(&format!("{path}{name}"), || Entry {
+                access: String::new(),
+                data_type: None,
+                ..parameter(path, name)
+            });
+            if let Some(access) = param.attr("access") {
+                entry.access = access.to_string();
+            }
+            if let Some(syntax) = param.child("syntax") {
+                types.apply(entry, syntax)?;
+            }
+            set_description(entry, param);
+        }
+    }
+    model(Kind::DeviceType, name, Some(base.to_string()), entries)
+}
+
+fn model(kind: Kind, name: &str, base: Option<String>, entries: Entries) -> Result<Model, ImportError> {
+    let Some(first) = entries.list.first() else {
+        return Err(ImportError::Invalid(format!("model {name} defines no objects")));
+    };
+    let root = match first.path.find('.') {
+        Some(i) => first.path[..=i].to_string(),
+        None => return Err(ImportError::Invalid(format!("{} is not an object path", first.path))),
+    };
+    if let Some(e) = entries.list.iter().find(|e| !e.path.starts_with(&root)) {
+        return Err(ImportError::Invalid(format!("model {name} mixes {root} and {}", e.path)));
+    }
+    Ok(Model { kind, name: name.to_string(), root, base, spec: None, file: None, entries: entries.list })
+}
+
+impl Model {
+    /// Fill in what a device type leaves out from the model it restricts:
+    /// parameter syntax, descriptions, and access where none is given.
+    pub fn inherit(&mut self, base: &HashMap<String, Entry>) {
+        for entry in &mut self.entries {
+            let Some(b) = base.get(&entry.path) else { continue };
+            if entry.access.is_empty() {
+                entry.access.clone_from(&b.access);
+            }
+            if entry.data_type.is_none() && !entry.is_object {
+                entry.data_type.clone_from(&b.data_type);
+                entry.list = b.list;
+                entry.max_length = b.max_length;
+                entry.ranges.clone_from(&b.ranges);
+                entry.patterns.clone_from(&b.patterns);
+                if entry.enumerations.is_empty() {
+                    entry.enumerations.clone_from(&b.enumerations);
+                }
+            }
+            if entry.description.is_none() {
+                entry.description.clone_from(&b.description);
+            }
+        }
+        for entry in &mut self.entries {
+            if entry.access.is_empty() {
+                entry.access = "readOnly".into();
+            }
+        }
+    }
+}
+
+fn object(path: &str) -> Entry {
+    Entry {
+        path:           path.to_string(),
+        is_object:      true,
+        data_type:      None,
+        access:         "readOnly".into(),
+        multi_instance: path.ends_with("{i}."),
+        list:           false,
+        enumerations:   Vec::new(),
+        patterns:       Vec::new(),
+        ranges:         Json(Vec::new()),
+        max_length:     None,
+        description:    None,
+    }
+}
+
+fn parameter(object_path: &str, name: &str) -> Entry {
+    Entry { is_object: false, multi_instance: false, data_type: Some("string".into()), ..object(&format!("{object_path}{name}")) }
+}
+
+fn set_description(entry: &mut Entry, el: &Element) {
+    let Some(text) = el.child("description").map(|d| d.text.trim()).filter(|t| !t.is_empty()) else { return };
+    entry.description = Some(text.to_string());
+}
+
+/// Entries by path, in the order first seen.
+#[derive(Default)]
+struct Entries {
+    list:  Vec<Entry>,
+    index: HashMap<String, usize>,
+}
+
+impl Entries {
+    fn put(&mut self, entry: Entry) {
+        self.index.insert(entry.path.clone(), self.list.len());
+        self.list.push(entry);
+    }
+
+    fn get_or_insert(&mut self, path: &str, new: impl FnOnce() -> Entry) -> &mut Entry {
+        let i = match self.index.get(path) {
+            Some(&i) => i,
+            None => {
+                self.put(new());
+                self.list.len() - 1
+            }
+        };
+        &mut self.list[i]
+    }
+}
+
+/// The document's `<dataType>` definitions.
+struct DataTypes<'a> {
+    defs: HashMap<&'a str, &'a Element>,
+}
+
+impl DataTypes<'_> {
+    /// Apply a parameter's `<syntax>` to `entry`.
+    fn apply(&self, entry: &mut Entry, syntax: &Element) -> Result<(), ImportError> {
+        let list = syntax.child("list");
+        for el in &syntax.children {
+            if PRIMITIVES.contains(&el.name.as_str()) {
+                entry.data_type = Some(el.name.clone());
+                facets(entry, el);
+            } else if el.name == "dataType" {
+                if let Some(name) = el.attr("ref") {
+                    self.resolve(entry, name, 0)?;
+                }
+                facets(entry, el);
+            }
+        }
+        if let Some(list) = list {
+            entry.list = true;
+            entry.max_length = list.child("size").and_then(|s| s.attr("maxLength")).and_then(|m| m.parse().ok());
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, entry: &mut Entry, name: &str, depth: usize) -> Result<(), ImportError> {
+        if depth > 16 {
+            return Err(ImportError::Invalid(format!("dataType {name} refers to itself")));
+        }
+        let Some(def) = self.defs.get(name) else {
+            let (ty, max_length) = TR106_TYPES
+                .iter()
+                .find(|(n, ..)| *n == name)
+                .map_or(("string", None), |(_, ty, max)| (*ty, *max));
+            entry.data_type = Some(ty.to_string());
+            entry.max_length = max_length;
+            return Ok(());
+        };
+        if let Some(base) = def.attr("base") {
+            self.resolve(entry, base, depth + 1)?;
+        }
+        for el in &def.children {
+            if PRIMITIVES.contains(&el.name.as_str()) {
+                entry.data_type = Some(el.name.clone());
+                facets(entry, el);
+            }
+        }
+        facets(entry, def);
+        Ok(())
+    }
+}
+
+/// Copy the size, enumeration, pattern and range restrictions among
+/// `el`'s children to `entry`; each kind given replaces what was there.
+fn facets(entry: &mut Entry, el: &Element) {
+    if let Some(max) = el.child("size").and_then(|s| s.attr("maxLength")).and_then(|m| m.parse().ok()) {
+        entry.max_length = Some(max);
+    }
+    let values = |name| el.children_named(name).filter_map(|e| e.attr("value")).map(str::to_string).collect::<Vec<_>>();
+    let enumerations = values("enumeration");
+    if !enumerations.is_empty() {
+        entry.enumerations = enumerations;
+    }
+    let patterns = values("pattern");
+    if !patterns.is_empty() {
+        entry.patterns = patterns;
+    }
+    let ranges: Vec<Range> = el
+        .children_named("range")
+        .map(|r| Range {
+            min: r.attr("minInclusive").and_then(|v| v.parse().ok()),
+            max: r.attr("maxInclusive").and_then(|v| v.parse().ok()),
+        })
+        .collect();
+    if !ranges.is_empty() {
+        entry.ranges = Json(ranges);
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATAMODEL: &str = r#"<?xml version="1.0"?>
+<dm:document xmlns:dm="urn:broadband-forum-org:cwmp:datamodel-1-8" spec="urn:broadband-forum-org:tr-181-2-16-0-cwmp" file="tr-181-2-16-0-cwmp-full.xml">
+  <dataType name="Port"><unsignedInt><range minInclusive="1" maxInclusive="65535"/></unsignedInt></dataType>
+  <model name="Device:2.16">
+    <object name="Device." access="readOnly" minEntries="1" maxEntries="1"/>
+    <object name="Device.ManagementServer." access="readOnly" minEntries="1" maxEntries="1">
+      <parameter name="PeriodicInformInterval" access="readWrite">
+        <description>Seconds between Informs.</description>
+        <syntax><unsignedInt><range minInclusive="1"/></unsignedInt></syntax>
+      </parameter>
+      <parameter name="ConnectionRequestPort" access="readWrite"><syntax><dataType ref="Port"/></syntax></parameter>
+      <parameter name="InstanceMode" access="readWrite">
+        <syntax><string><enumeration value="InstanceNumber"/><enumeration value="InstanceAlias"/></string></syntax>
+      </parameter>
+    </object>
+    <object name="Device.IP.Interface.{i}." access="readWrite" minEntries="0" maxEntries="unbounded">
+      <parameter name="Alias" access="readWrite"><syntax><dataType ref="Alias"/></syntax></parameter>
+      <parameter name="LowerLayers" access="readWrite"><syntax><list><size maxLength="1024"/></list><string/></syntax></parameter>
+    </object>
+  </model>
+</dm:document>"#;
+
+    #[test]
+    fn reads_datamodel_and_device_type_documents() {
+        let models = parse(DATAMODEL).unwrap();
+        let [m] = &models[..] else { panic!("{models:?}") };
+        assert_eq!((m.name.as_str(), m.root.as_str(), m.kind), ("Device:2.16", "Device.", Kind::Datamodel));
+        let by_path: HashMap<_, _> = m.entries.iter().map(|e| (e.path.clone(), e.clone())).collect();
+
+        let pii = &by_path["Device.ManagementServer.PeriodicInformInterval"];
+        assert_eq!((pii.data_type.as_deref(), pii.access.as_str()), (Some("unsignedInt"), "readWrite"));
+        assert_eq!(pii.ranges.0, [Range { min: Some(1), max: None }]);
+        assert_eq!(pii.description.as_deref(), Some("Seconds between Informs."));
+        let port = &by_path["Device.ManagementServer.ConnectionRequestPort"];
+        assert_eq!(port.ranges.0, [Range { min: Some(1), max: Some(65535) }]);
+        assert_eq!(by_path["Device.ManagementServer.InstanceMode"].enumerations, ["InstanceNumber", "InstanceAlias"]);
+        assert_eq!(by_path["Device.IP.Interface.{i}.Alias"].max_length, Some(64));
+        assert!(by_path["Device.IP.Interface.{i}.LowerLayers"].list);
+        assert!(by_path["Device.IP.Interface.{i}."].multi_instance);
+
+        let mut dt = parse(
+            r#"<dt:document xmlns:dt="urn:broadband-forum-org:cwmp:devicetype-1-4" deviceType="urn:example-com:router-1-0">
+              <model ref="Device:2.16">
+                <object ref="Device.ManagementServer.">
+                  <parameter ref="PeriodicInformInterval" access="readOnly"/>
+                  <parameter ref="InstanceMode"><syntax><string><enumeration value="InstanceNumber"/></string></syntax></parameter>
+                </object>
+              </model>
+            </dt:document>"#,
+        )
+        .unwrap()
+        .remove(0);
+        dt.inherit(&by_path);
+        assert_eq!((dt.kind, dt.base.as_deref()), (Kind::DeviceType, Some("Device:2.16")));
+        assert_eq!(dt.entries[1].access, "readOnly");
+        assert_eq!(dt.entries[1].data_type.as_deref(), Some("unsignedInt"));
+        assert_eq!(dt.entries[2].access, "readWrite");
+        assert_eq!(dt.entries[2].enumerations, ["InstanceNumber"]);
+    }
+}
@@ -0,0 +1,637 @@
+//! Data model catalog.
+//!
+//! Broadband Forum data model definitions (TR-181 `Device:2`, TR-098
+//! `InternetGatewayDevice:1`, ...) and device types are [`import`]ed into
+//! `data_models` / `data_model_entries`: every object and parameter with its
+//! type, access, enumerations and other restrictions, instance numbers
+//! written as `{i}`.
+//!
+//! Before an action is sent, [`check`] looks its paths up in the model that
+//! applies to the device — the newest device type matching its OUI and
+//! product class, else the newest data model with the path's root — and
+//! reports what the device would refuse: unknown paths, read-only or
+//! mistyped parameters, objects that cannot be added to. It also fills in
+//! the `xsd:` type of each SetParameterValues parameter. Paths under roots
+//! with no imported model, and vendor extensions (`X_...`), are not
+//! checked.
+
+pub mod import;
+pub mod xml;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use nats_common::Action;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use tracing::warn;
+use uuid::Uuid;
+
+/// Rows per INSERT when storing a model's entries.
+const INSERT_BATCH: usize = 2000;
+
+// ── Types ─────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Datamodel,
+    DeviceType,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Datamodel  => "datamodel",
+            Kind::DeviceType => "device_type",
+        }
+    }
+}
+
+/// One row of `data_models`.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct DataModel {
+    pub id:            Uuid,
+    pub kind:          String,
+    pub name:          String,
+    pub root:          String,
+    pub base:          Option<String>,
+    pub spec:          Option<String>,
+    pub file_name:     Option<String>,
+    /// Device types only: the devices they apply to.
+    pub oui:           Option<String>,
+    pub product_class: Option<String>,
+    pub entries:       i64,
+    pub imported_by:   Option<Uuid>,
+    pub imported_at:   chrono::DateTime<chrono::Utc>,
+}
+
+pub const MODEL_COLUMNS: &str = "m.id, m.kind, m.name, m.root, m.base, m.spec, m.file_name, m.oui, m.product_class, \
+                                 (SELECT count(*) FROM data_model_entries e WHERE e.model_id = m.id) AS entries, \
+                                 m.imported_by, m.imported_at";
+
+/// One object or parameter of a model.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Entry {
+    /// `Device.IP.Interface.{i}.Enable`; objects end in `.`.
+    pub path:           String,
+    pub is_object:      bool,
+    /// Parameters: `string`, `unsignedInt`, `boolean`, ... (without `xsd:`).
+    pub data_type:      Option<String>,
+    /// `readOnly` or `readWrite`. On a multi-instance object, whether
+    /// instances can be added and deleted.
+    pub access:         String,
+    pub multi_instance: bool,
+    /// A comma-separated list of `data_type` values.
+    pub list:           bool,
+    pub enumerations:   Vec<String>,
+    pub patterns:       Vec<String>,
+    pub ranges:         Json<Vec<Range>>,
+    /// Characters, of the whole value for lists.
+    pub max_length:     Option<i32>,
+    pub description:    Option<String>,
+}
+
+pub const ENTRY_COLUMNS: &str = "path, is_object, data_type, access, multi_instance, list, enumerations, patterns, \
+                                 ranges, max_length, description";
+
+/// Inclusive bounds of an integer parameter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Range {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+impl Range {
+    fn contains(&self, n: i128) -> bool {
+        self.min.is_none_or(|min| n >= min.into()) && self.max.is_none_or(|max| n <= max.into())
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) if min == max => write!(f, "{min}"),
+            (min, max) => {
+                if let Some(min) = min {
+                    write!(f, "{min}")?;
+                }
+                f.write_str("..")?;
+                if let Some(max) = max {
+                    write!(f, "{max}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Entry {
+    pub fn writable(&self) -> bool {
+        self.access != "readOnly"
+    }
+
+    /// The type SetParameterValues declares for the parameter.
+    pub fn xsd_type(&self) -> Option<String> {
+        if self.list {
+            return Some("xsd:string".into());
+        }
+        self.data_type.as_ref().map(|t| format!("xsd:{t}"))
+    }
+
+    /// Why the parameter cannot be set to `value`, or `Ok` if it can.
+    pub fn check_value(&self, value: &str) -> Result<(), String> {
+        if let Some(max) = self.max_length {
+            if value.chars().count() > max as usize {
+                return Err(format!("longer than {max} characters"));
+            }
+        }
+        if self.list {
+            return value.split(',').map(str::trim).filter(|v| !v.is_empty()).try_for_each(|v| self.check_item(v));
+        }
+        self.check_item(value)
+    }
+
+    fn check_item(&self, value: &str) -> Result<(), String> {
+        let ty = self.data_type.as_deref().unwrap_or("string");
+        let valid = match ty {
+            "boolean" => matches!(value, "true" | "false" | "0" | "1"),
+            "int" => value.parse::<i32>().is_ok(),
+            "unsignedInt" => value.parse::<u32>().is_ok(),
+            "long" => value.parse::<i64>().is_ok(),
+            "unsignedLong" => value.parse::<u64>().is_ok(),
+            "decimal" => value.parse::<f64>().is_ok(),
+            "dateTime" => {
+                chrono::DateTime::parse_from_rfc3339(value).is_ok()
+                    || chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f").is_ok()
+            }
+            "hexBinary" => value.len().is_multiple_of(2) && value.bytes().all(|b| b.is_ascii_hexdigit()),
+            "base64" => value.len().is_multiple_of(4) && value.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'=')),
+            _ => true,
+        };
+        if !valid {
+            return Err(format!("{value:?} is not a valid {ty}"));
+        }
+
+        let ranges = &self.ranges.0;
+        if let Ok(n) = value.parse::<i128>() {
+            if !ranges.is_empty() && !ranges.iter().any(|r| r.contains(n)) {
+                let ranges: Vec<_> = ranges.iter().map(Range::to_string).collect();
+                return Err(format!("{value} is outside {}", ranges.join(" or ")));
+            }
+        }
+        if !self.enumerations.is_empty() && !self.enumerations.iter().any(|e| e == value) {
+            return Err(format!("{value:?} is not one of {}", self.enumerations.join(", ")));
+        }
+        // Patterns are XML Schema regular expressions; the few the regex
+        // crate cannot compile are not enforced.
+        let patterns: Vec<_> =
+            self.patterns.iter().filter_map(|p| regex::Regex::new(&format!("^(?:{p})$")).ok()).collect();
+        if !patterns.is_empty() && !patterns.iter().any(|p| p.is_match(value)) {
+            return Err(format!("{value:?} does not match the required format"));
+        }
+        Ok(())
+    }
+}
+
+// ── Paths ─────────────────────────────────────────────────────────────────────
+
+/// `path` as the catalog writes it: instance numbers and `[alias]`
+/// instance references become `{i}`.
+pub fn normalize(path: &str) -> String {
+    path.split('.')
+        .map(|seg| {
+            let number = !seg.is_empty() && seg.bytes().all(|b| b.is_ascii_digit());
+            let alias = seg.len() > 1 && seg.starts_with('[') && seg.ends_with(']');
+            if number || alias { "{i}" } else { seg }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// `Device.` of `Device.DeviceInfo.UpTime`.
+fn root(path: &str) -> Option<&str> {
+    path.find('.').map(|i| &path[..=i])
+}
+
+fn is_vendor(path: &str) -> bool {
+    path.split('.').any(|seg| seg.starts_with("X_"))
+}
+
+// ── Checking actions ──────────────────────────────────────────────────────────
+
+/// The part of a device's model an action refers to.
+#[derive(Debug, Default)]
+pub struct Catalog {
+    /// Roots the device has a model for.
+    roots:   HashSet<String>,
+    /// By normalized path.
+    entries: HashMap<String, Entry>,
+}
+
+enum Lookup<'a> {
+    Unchecked,
+    Unknown,
+    Found(&'a Entry),
+}
+
+impl Catalog {
+    /// Object paths of tables, without an instance, find the table.
+    fn lookup(&self, path: &str) -> Lookup<'_> {
+        if is_vendor(path) || !root(path).is_some_and(|r| self.roots.contains(r)) {
+            return Lookup::Unchecked;
+        }
+        let path = normalize(path);
+        let table = || path.ends_with('.').then(|| self.entries.get(&format!("{path}{{i}}."))).flatten();
+        match self.entries.get(&path).or_else(table) {
+            Some(entry) => Lookup::Found(entry),
+            None => Lookup::Unknown,
+        }
+    }
+
+    /// Problems with `action` against the model, as `"<path>: <why>"`.
+    /// SetParameterValues parameters without a declared type get theirs.
+    pub fn check(&self, action: &mut Action) -> Vec<String> {
+        let mut problems = Vec::new();
+        match action {
+            Action::SetParameterValues { parameters, types } => {
+                let mut paths: Vec<_> = parameters.keys().collect();
+                paths.sort();
+                for path in paths {
+                    let entry = match self.lookup(path) {
+                        Lookup::Unchecked => continue,
+                        Lookup::Unknown => {
+                            problems.push(format!("{path}: not in the data model"));
+                            continue;
+                        }
+                        Lookup::Found(entry) => entry,
+                    };
+                    let problem = if entry.is_object {
+                        Some("an object, not a parameter".to_string())
+                    } else if !entry.writable() {
+                        Some("read-only".to_string())
+                    } else {
+                        entry.check_value(&parameters[path]).err()
+                    };
+                    match (problem, entry.xsd_type()) {
+                        (Some(why), _) => problems.push(format!("{path}: {why}")),
+                        (None, Some(ty)) => {
+                            types.entry(path.clone()).or_insert(ty);
+                        }
+                        (None, None) => {}
+                    }
+                }
+            }
+            Action::GetParameterValues { paths } => {
+                for path in paths.iter().filter(|p| !p.is_empty()) {
+                    if let Lookup::Unknown = self.lookup(path) {
+                        problems.push(format!("{path}: not in the data model"));
+                    }
+                }
+            }
+            Action::AddObject { path } => {
+                if !path.ends_with('.') {
+                    problems.push(format!("{path}: object paths end with '.'"));
+                } else {
+                    match (self.lookup(&format!("{path}{{i}}.")), self.lookup(path)) {
+                        (Lookup::Unchecked, _) => {}
+                        (Lookup::Found(table), _) if table.writable() => {}
+                        (Lookup::Found(_), _) => problems.push(format!("{path}: instances cannot be added")),
+                        (Lookup::Unknown, Lookup::Found(_)) => {
+                            problems.push(format!("{path}: not a multi-instance object"))
+                        }
+                        (Lookup::Unknown, _) => problems.push(format!("{path}: not in the data model")),
+                    }
+                }
+            }
+            _ => {}
+        }
+        problems
+    }
+}
+
+/// Paths [`Catalog::check`] may look up for `action`.
+fn lookup_paths(action: &Action) -> Vec<String> {
+    match action {
+        Action::SetParameterValues { parameters, .. } => parameters.keys().cloned().collect(),
+        Action::GetParameterValues { paths } => paths.clone(),
+        Action::AddObject { path } => vec![path.clone(), format!("{path}{{i}}.")],
+        _ => Vec::new(),
+    }
+}
+
+/// The model that applies to `device_id` for paths under `root`.
+pub async fn model_for(pool: &PgPool, device_id: Uuid, root: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT m.id FROM data_models m, devices d
+        WHERE d.id = $1 AND m.root = $2
+          AND (m.kind = 'datamodel'
+               OR ((m.oui IS NULL OR upper(m.oui) = upper(d.oui))
+                   AND (m.product_class IS NULL OR m.product_class = d.product_class)))
+        ORDER BY m.kind = 'device_type' DESC, m.imported_at DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(device_id)
+    .bind(root)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Load what [`Catalog::check`] needs to check `action` for `device_id`.
+pub async fn catalog_for(pool: &PgPool, device_id: Uuid, action: &Action) -> Result<Catalog, sqlx::Error> {
+    let mut by_root: HashMap<&str, Vec<String>> = HashMap::new();
+    let paths = lookup_paths(action);
+    for path in paths.iter().filter(|p| !is_vendor(p)) {
+        if let Some(root) = root(path) {
+            let paths = by_root.entry(root).or_default();
+            paths.push(normalize(path));
+            if path.ends_with('.') {
+                paths.push(format!("{}{{i}}.", normalize(path)));
+            }
+        }
+    }
+
+    let mut catalog = Catalog::default();
+    for (root, paths) in by_root {
+        let Some(model_id) = model_for(pool, device_id, root).await? else { continue };
+        let entries = sqlx::query_as::<_, Entry>(&format!(
+            "SELECT {ENTRY_COLUMNS} FROM data_model_entries WHERE model_id = $1 AND path = ANY($2)"
+        ))
+        .bind(model_id)
+        .bind(&paths)
+        .fetch_all(pool)
+        .await?;
+        catalog.roots.insert(root.to_string());
+        catalog.entries.extend(entries.into_iter().map(|e| (e.path.clone(), e)));
+    }
+    Ok(catalog)
+}
+
+/// Check `action` for `device_id` against its model and type its
+/// SetParameterValues parameters; see [`Catalog::check`].
+pub async fn check(pool: &PgPool, device_id: Uuid, action: &mut Action) -> Result<Vec<String>, sqlx::Error> {
+    if lookup_paths(action).is_empty() {
+        return Ok(Vec::new());
+    }
+    let catalog = catalog_for(pool, device_id, action).await?;
+    Ok(catalog.check(action))
+}
+
+/// Type a SetParameterValues about to be sent where it can no longer be
+/// refused, as for provisioning and queued tasks: problems are logged, and
+/// a failed lookup leaves the action untyped.
+pub async fn prepare(pool: &PgPool, device_id: Uuid, action: &mut Action) {
+    if !matches!(action, Action::SetParameterValues { .. }) {
+        return;
+    }
+    match check(pool, device_id, action).await {
+        Ok(problems) if !problems.is_empty() => {
+            warn!(%device_id, ?problems, "SetParameterValues does not fit the device's data model");
+        }
+        Ok(_) => {}
+        Err(e) => warn!(?e, %device_id, "Data model lookup failed — sending SetParameterValues untyped"),
+    }
+}
+
+// ── Browsing ──────────────────────────────────────────────────────────────────
+
+/// A path of a device's model, for browsing.
+#[derive(Debug, Serialize)]
+pub struct Node {
+    pub model_id: Uuid,
+    /// The path as the catalog writes it.
+    pub path:     String,
+    /// `None` if the model has no such path.
+    pub entry:    Option<Entry>,
+    /// The objects and parameters directly below an object path.
+    pub children: Vec<Child>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Child {
+    /// `Enable`, or `Interface` for `Interface.` and `Interface.{i}.`.
+    pub name:  String,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+/// Look `path` up in the model that applies to `device_id`. `None` if no
+/// model covers the path's root.
+pub async fn node(pool: &PgPool, device_id: Uuid, path: &str) -> Result<Option<Node>, sqlx::Error> {
+    let Some(model_id) = (match root(path) {
+        Some(root) => model_for(pool, device_id, root).await?,
+        None => None,
+    }) else {
+        return Ok(None);
+    };
+
+    let mut path = normalize(path);
+    let sql = format!("SELECT {ENTRY_COLUMNS} FROM data_model_entries WHERE model_id = $1 AND path = $2");
+    let fetch = |p: String| sqlx::query_as::<_, Entry>(&sql).bind(model_id).bind(p).fetch_optional(pool);
+    let mut entry = fetch(path.clone()).await?;
+    // A table's own path: describe it by its instances.
+    if entry.is_none() && path.ends_with('.') {
+        entry = fetch(format!("{path}{{i}}.")).await?;
+    }
+
+    let mut children = Vec::new();
+    if path.ends_with('.') {
+        if let Some(e) = entry.as_ref().filter(|e| e.path.ends_with("{i}.") && !path.ends_with("{i}.")) {
+            path.clone_from(&e.path);
+        }
+        // Entries one level down: `Name`, `Name.` or `Name.{i}.`.
+        let rows = sqlx::query_as::<_, Entry>(&format!(
+            r#"
+            SELECT {ENTRY_COLUMNS} FROM data_model_entries
+            WHERE model_id = $1 AND starts_with(path, $2) AND path <> $2
+              AND position('.' IN rtrim(replace(substr(path, length($2) + 1), '{{i}}.', ''), '.')) = 0
+            ORDER BY path
+            "#
+        ))
+        .bind(model_id)
+        .bind(&path)
+        .fetch_all(pool)
+        .await?;
+        children = rows
+            .into_iter()
+            .map(|entry| {
+                let rest = &entry.path[path.len()..];
+                let name = rest.trim_end_matches("{i}.").trim_end_matches('.').to_string();
+                Child { name, entry }
+            })
+            .collect();
+    }
+    Ok(Some(Node { model_id, path, entry, children }))
+}
+
+// ── Importing ─────────────────────────────────────────────────────────────────
+
+/// Entries of the newest data model called `name`, by path, for a device
+/// type to [`import::Model::inherit`] from. `None` if there is none.
+pub async fn base_entries(pool: &PgPool, name: &str) -> Result<Option<HashMap<String, Entry>>, sqlx::Error> {
+    let model_id: Option<Uuid> = sqlx::query_scalar(
+        "SELECT id FROM data_models WHERE kind = 'datamodel' AND name = $1 ORDER BY imported_at DESC LIMIT 1",
+    )
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+    let Some(model_id) = model_id else { return Ok(None) };
+
+    let entries = sqlx::query_as::<_, Entry>(&format!(
+        "SELECT {ENTRY_COLUMNS} FROM data_model_entries WHERE model_id = $1"
+    ))
+    .bind(model_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(Some(entries.into_iter().map(|e| (e.path.clone(), e)).collect()))
+}
+
+/// Store `model`, replacing any earlier import of the same model.
+pub async fn store(
+    pool: &PgPool,
+    model: &import::Model,
+    oui: Option<&str>,
+    product_class: Option<&str>,
+    imported_by: Uuid,
+) -> Result<DataModel, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let id: Uuid = sqlx::query_scalar(
+        r#"
+        INSERT INTO data_models (kind, name, root, base, spec, file_name, oui, product_class, imported_by)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (kind, name, root) DO UPDATE
+        SET base = EXCLUDED.base, spec = EXCLUDED.spec, file_name = EXCLUDED.file_name,
+            oui = EXCLUDED.oui, product_class = EXCLUDED.product_class,
+            imported_by = EXCLUDED.imported_by, imported_at = now()
+        RETURNING id
+        "#,
+    )
+    .bind(model.kind.as_str())
+    .bind(&model.name)
+    .bind(&model.root)
+    .bind(&model.base)
+    .bind(&model.spec)
+    .bind(&model.file)
+    .bind(oui)
+    .bind(product_class)
+    .bind(imported_by)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM data_model_entries WHERE model_id = $1").bind(id).execute(&mut *tx).await?;
+    for chunk in model.entries.chunks(INSERT_BATCH) {
+        let mut insert = QueryBuilder::<Postgres>::new(format!("INSERT INTO data_model_entries (model_id, {ENTRY_COLUMNS}) "));
+        insert.push_values(chunk, |mut row, e| {
+            row.push_bind(id)
+                .push_bind(&e.path)
+                .push_bind(e.is_object)
+                .push_bind(&e.data_type)
+                .push_bind(&e.access)
+                .push_bind(e.multi_instance)
+                .push_bind(e.list)
+                .push_bind(&e.enumerations)
+                .push_bind(&e.patterns)
+                .push_bind(&e.ranges)
+                .push_bind(e.max_length)
+                .push_bind(&e.description);
+        });
+        insert.build().execute(&mut *tx).await?;
+    }
+
+    let stored = sqlx::query_as::<_, DataModel>(&format!("SELECT {MODEL_COLUMNS} FROM data_models m WHERE m.id = $1"))
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(stored)
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> Catalog {
+        let models = import::parse(
+            r#"<document>
+              <model name="Device:2.16">
+                <object name="Device.ManagementServer." access="readOnly">
+                  <parameter name="PeriodicInformInterval" access="readWrite">
+                    <syntax><unsignedInt><range minInclusive="1"/></unsignedInt></syntax>
+                  </parameter>
+                  <parameter name="URL" access="readWrite"><syntax><string><size maxLength="8"/></string></syntax></parameter>
+                  <parameter name="ParameterKey" access="readOnly"><syntax><string/></syntax></parameter>
+                </object>
+                <object name="Device.IP.Interface.{i}." access="readWrite">
+                  <parameter name="Enable" access="readWrite"><syntax><boolean/></syntax></parameter>
+                </object>
+                <object name="Device.IP.Interface.{i}.Stats." access="readOnly"/>
+              </model>
+            </document>"#,
+        )
+        .unwrap();
+        Catalog {
+            roots:   HashSet::from(["Device.".to_string()]),
+            entries: models[0].entries.iter().map(|e| (e.path.clone(), e.clone())).collect(),
+        }
+    }
+
+    #[test]
+    fn normalizes_instances_and_aliases() {
+        assert_eq!(normalize("Device.IP.Interface.3.IPv4Address.[lan].Enable"), "Device.IP.Interface.{i}.IPv4Address.{i}.Enable");
+        assert_eq!(normalize("Device.IP."), "Device.IP.");
+    }
+
+    #[test]
+    fn checks_and_types_actions() {
+        let catalog = catalog();
+        let set = |pairs: &[(&str, &str)]| Action::SetParameterValues {
+            parameters: pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            types:      HashMap::new(),
+        };
+
+        let mut ok = set(&[
+            ("Device.ManagementServer.PeriodicInformInterval", "300"),
+            ("Device.IP.Interface.2.Enable", "true"),
+            ("Device.X_ACME_Thing", "anything"),
+            ("InternetGatewayDevice.Foo", "not covered"),
+        ]);
+        assert_eq!(catalog.check(&mut ok), Vec::<String>::new());
+        let Action::SetParameterValues { types, .. } = &ok else { unreachable!() };
+        assert_eq!(types["Device.ManagementServer.PeriodicInformInterval"], "xsd:unsignedInt");
+        assert_eq!(types["Device.IP.Interface.2.Enable"], "xsd:boolean");
+        assert_eq!(types.len(), 2);
+
+        let mut bad = set(&[
+            ("Device.ManagementServer.PeriodicInformInterval", "0"),
+            ("Device.ManagementServer.URL", "http://example.com"),
+            ("Device.ManagementServer.ParameterKey", "k"),
+            ("Device.IP.Interface.1.Enable", "yes"),
+            ("Device.Nope", "1"),
+        ]);
+        assert_eq!(
+            catalog.check(&mut bad),
+            [
+                "Device.IP.Interface.1.Enable: \"yes\" is not a valid boolean",
+                "Device.ManagementServer.ParameterKey: read-only",
+                "Device.ManagementServer.PeriodicInformInterval: 0 is outside 1..",
+                "Device.ManagementServer.URL: longer than 8 characters",
+                "Device.Nope: not in the data model",
+            ]
+        );
+
+        let mut add = Action::AddObject { path: "Device.IP.Interface.".into() };
+        assert!(catalog.check(&mut add).is_empty());
+        let mut add = Action::AddObject { path: "Device.IP.Interface.1.Stats.".into() };
+        assert_eq!(catalog.check(&mut add), ["Device.IP.Interface.1.Stats.: not a multi-instance object"]);
+
+        let mut get = Action::GetParameterValues {
+            paths: vec!["Device.IP.Interface.".into(), "Device.IP.Interface.1.".into(), "Device.Missing.".into()],
+        };
+        assert_eq!(catalog.check(&mut get), ["Device.Missing.: not in the data model"]);
+    }
+}
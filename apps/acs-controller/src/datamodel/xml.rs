@@ -0,0 +1,248 @@
+//! Just enough XML to read data model definitions.
+//!
+//! Reads a document into a tree of [`Element`]s: names, attributes and
+//! text (CDATA included, entities expanded). The prolog, comments,
+//! processing instructions and a DOCTYPE without an internal subset are
+//! skipped. Namespace prefixes are dropped from element names, which is
+//! all the Broadband Forum schemas need.
+
+/// One element and everything inside it.
+#[derive(Debug, Default)]
+pub struct Element {
+    /// Local name, without any namespace prefix.
+    pub name:     String,
+    pub attrs:    Vec<(String, String)>,
+    pub children: Vec<Element>,
+    /// Text directly inside the element, concatenated.
+    pub text:     String,
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("XML line {line}: {message}")]
+pub struct Error {
+    pub line:    usize,
+    pub message: String,
+}
+
+/// Parse `input` into its root element.
+pub fn parse(input: &str) -> Result<Element, Error> {
+    let mut p = Parser { s: input, pos: 0 };
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = None;
+
+    while p.pos < p.s.len() {
+        let rest = &p.s[p.pos..];
+        if rest.starts_with("<?") {
+            p.skip_past("?>")?;
+        } else if rest.starts_with("<!--") {
+            p.skip_past("-->")?;
+        } else if rest.starts_with("<![CDATA[") {
+            p.pos += "<![CDATA[".len();
+            let text = p.skip_past("]]>")?;
+            match stack.last_mut() {
+                Some(el) => el.text.push_str(text),
+                None => return Err(p.error("CDATA outside the root element")),
+            }
+        } else if rest.starts_with("<!") {
+            p.skip_past(">")?;
+        } else if rest.starts_with("</") {
+            p.pos += 2;
+            let name = p.name()?;
+            p.skip_whitespace();
+            p.expect(">")?;
+            let Some(el) = stack.pop() else {
+                return Err(p.error(format!("unexpected </{name}>")));
+            };
+            if local(name) != el.name {
+                return Err(p.error(format!("</{name}> closes <{}>", el.name)));
+            }
+            close(&p, &mut stack, &mut root, el)?;
+        } else if rest.starts_with('<') {
+            p.pos += 1;
+            let mut el = Element { name: local(p.name()?).to_string(), ..Default::default() };
+            let self_closing = loop {
+                p.skip_whitespace();
+                if p.eat("/>") {
+                    break true;
+                }
+                if p.eat(">") {
+                    break false;
+                }
+                let name = p.name()?.to_string();
+                p.skip_whitespace();
+                p.expect("=")?;
+                p.skip_whitespace();
+                let quote = if p.eat("\"") {
+                    "\""
+                } else if p.eat("'") {
+                    "'"
+                } else {
+                    return Err(p.error(format!("attribute {name} has no quoted value")));
+                };
+                let value = p.skip_past(quote)?;
+                el.attrs.push((name, p.unescape(value)?));
+            };
+            if self_closing {
+                close(&p, &mut stack, &mut root, el)?;
+            } else {
+                stack.push(el);
+            }
+        } else {
+            let end = rest.find('<').map_or(p.s.len(), |i| p.pos + i);
+            let text = &p.s[p.pos..end];
+            match stack.last_mut() {
+                Some(el) => {
+                    let text = p.unescape(text)?;
+                    el.text.push_str(&text);
+                }
+                None if text.trim().is_empty() => {}
+                None => return Err(p.error("text outside the root element")),
+            }
+            p.pos = end;
+        }
+    }
+
+    if let Some(el) = stack.last() {
+        return Err(p.error(format!("<{}> is never closed", el.name)));
+    }
+    root.ok_or_else(|| p.error("no root element"))
+}
+
+fn close(p: &Parser, stack: &mut [Element], root: &mut Option<Element>, el: Element) -> Result<(), Error> {
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(el),
+        None if root.is_none() => *root = Some(el),
+        None => return Err(p.error("more than one root element")),
+    }
+    Ok(())
+}
+
+fn local(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+struct Parser<'a> {
+    s:   &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: impl Into<String>) -> Error {
+        let line = self.s[..self.pos].matches('\n').count() + 1;
+        Error { line, message: message.into() }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.s[self.pos..].starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), Error> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected {token:?}")))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.s[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Move past the next `end` and return what came before it.
+    fn skip_past(&mut self, end: &str) -> Result<&'a str, Error> {
+        let Some(i) = self.s[self.pos..].find(end) else {
+            return Err(self.error(format!("missing {end:?}")));
+        };
+        let skipped = &self.s[self.pos..self.pos + i];
+        self.pos += i + end.len();
+        Ok(skipped)
+    }
+
+    fn name(&mut self) -> Result<&'a str, Error> {
+        let rest = &self.s[self.pos..];
+        let len = rest.find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '=')).unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected a name"));
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    fn unescape(&self, text: &str) -> Result<String, Error> {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(i) = rest.find('&') {
+            out.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+            let Some(end) = rest.find(';') else {
+                return Err(self.error("unterminated entity"));
+            };
+            let entity = &rest[..end];
+            let c = match entity {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => entity.strip_prefix('#').and_then(|d| d.parse().ok()).and_then(char::from_u32),
+                },
+            };
+            match c {
+                Some(c) => out.push(c),
+                None => return Err(self.error(format!("unknown entity &{entity};"))),
+            }
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_elements_attributes_and_text() {
+        let doc = parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <!-- comment -->
+            <dm:document xmlns:dm="urn:x" spec='a&amp;b'>
+              <description>x &lt; y<![CDATA[ <z> ]]>&#x41;</description>
+              <model name="Device:2.16"><object name="Device."/></model>
+            </dm:document>"#,
+        )
+        .unwrap();
+        assert_eq!(doc.name, "document");
+        assert_eq!(doc.attr("spec"), Some("a&b"));
+        assert_eq!(doc.child("description").unwrap().text, "x < y <z> A");
+        let model = doc.child("model").unwrap();
+        assert_eq!(model.children_named("object").count(), 1);
+
+        let err = parse("<a><b></a>").unwrap_err();
+        assert!(err.message.contains("closes"), "{err}");
+    }
+}
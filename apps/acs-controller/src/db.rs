@@ -6,9 +6,11 @@
 //! `.sqlx/` offline cache, then add `SQLX_OFFLINE=true` to your build env.
 
 use std::collections::HashMap;
+use std::future::Future;
 
 use serde::Deserialize;
 use sqlx::PgPool;
+use tracing::error;
 use uuid::Uuid;
 
 // ── Payload types ─────────────────────────────────────────────────────────────
@@ -35,6 +37,12 @@ pub struct InformPayload {
     /// the field.
     #[serde(default)]
     pub protocol:       Option<String>,
+    /// IP address the CPE connected from, as seen by the protocol pod.
+    #[serde(default)]
+    pub source_ip:      Option<String>,
+    /// HTTP path the CPE used to reach the ACS, e.g. `"/cwmp/acme"`.
+    #[serde(default)]
+    pub acs_path:       Option<String>,
 }
 
 impl InformPayload {
@@ -55,31 +63,69 @@ impl InformPayload {
             .map(String::as_str)
     }
 
+    /// Whether the Inform carries event `code`, e.g. `"1 BOOT"`. Protocol
+    /// pods send events as `"{code} {command_key}"`, so the command key
+    /// (possibly empty) is ignored.
+    pub fn has_event(&self, code: &str) -> bool {
+        self.events.iter().any(|event| event_is(event, code))
+    }
+
     /// The protocol string to store, defaulting to `"cwmp"` when absent.
     pub fn effective_protocol(&self) -> &str {
         self.protocol.as_deref().unwrap_or("cwmp")
     }
 }
 
+/// Whether Inform event `event` (`"{code} {command_key}"`) has event code
+/// `code`, ignoring case and the command key.
+pub fn event_is(event: &str, code: &str) -> bool {
+    let (event, code) = (event.trim(), code.trim());
+    event.get(..code.len()).is_some_and(|c| c.eq_ignore_ascii_case(code))
+        && event[code.len()..].chars().next().is_none_or(|c| c == ' ')
+}
+
 // ── Database operations ────────────────────────────────────────────────────────
 
+/// The device row touched by [`upsert_device`].
+#[derive(Debug, Clone)]
+pub struct UpsertedDevice {
+    pub id:        Uuid,
+    pub domain_id: Uuid,
+    /// `true` if this Inform created the row (first contact).
+    pub created:   bool,
+    /// `software_version` before this Inform.
+    pub previous_software_version: Option<String>,
+}
+
 /// Upsert a device row from an Inform payload.
 ///
+/// `domain_id` must be the domain the device already belongs to (see
+/// [`get_device_ids`]) or, for a device never seen before, the domain chosen
+/// by the onboarding rules. Passing the wrong domain for a known device
+/// would insert a second row, since `devices` is unique per domain.
+///
 /// ## First contact
-/// Inserts a new device row assigned to `default_domain_id`.
+/// Inserts a new device row assigned to `domain_id`.
 ///
 /// ## Subsequent Informs
 /// Updates `last_seen` and all observable fields.
 /// `software_version` and `hardware_version` use `COALESCE` so a sparse
 /// Inform that omits these parameters never overwrites a previously known
-/// good value.
+/// good value. The version it replaces is returned, to tell firmware
+/// changes apart.
 pub async fn upsert_device(
     pool: &PgPool,
     payload: &InformPayload,
-    default_domain_id: Uuid,
-) -> Result<Uuid, sqlx::Error> {
-    let row: (Uuid,) = sqlx::query_as(
+    domain_id: Uuid,
+) -> Result<UpsertedDevice, sqlx::Error> {
+    // `xmax = 0` only holds for a freshly inserted tuple, which tells the
+    // insert and update paths of the upsert apart in a single round trip.
+    // The CTE reads the row as it was before the upsert.
+    let row: (Uuid, Uuid, bool, Option<String>) = sqlx::query_as(
         r#"
+        WITH previous AS (
+            SELECT software_version FROM devices WHERE domain_id = $1 AND device_uid = $2
+        )
         INSERT INTO devices (
             domain_id,
             device_uid,
@@ -101,10 +147,10 @@ pub async fn upsert_device(
             current_protocol = EXCLUDED.current_protocol,
             software_version = COALESCE(EXCLUDED.software_version, devices.software_version),
             hardware_version = COALESCE(EXCLUDED.hardware_version, devices.hardware_version)
-        RETURNING id
+        RETURNING id, domain_id, (xmax = 0) AS created, (SELECT software_version FROM previous)
         "#,
     )
-    .bind(default_domain_id)
+    .bind(domain_id)
     .bind(&payload.device_id)       // device_uid = "{oui}-{serial}"
     .bind(&payload.manufacturer)
     .bind(&payload.oui)
@@ -116,7 +162,7 @@ pub async fn upsert_device(
     .fetch_one(pool)
     .await?;
 
-    Ok(row.0)
+    Ok(UpsertedDevice { id: row.0, domain_id: row.1, created: row.2, previous_software_version: row.3 })
 }
 
 /// Upserts connection parameters for a specific protocol.
@@ -176,3 +222,61 @@ pub async fn get_domain_slug(pool: &PgPool, domain_id: Uuid) -> Result<String, s
         .await?;
     Ok(row.0)
 }
+
+/// Resolves a domain slug to its ID. `None` if no such domain exists.
+pub async fn get_domain_id(pool: &PgPool, slug: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar("SELECT id FROM domains WHERE slug = $1")
+        .bind(slug)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Resolves a `device_uid` to `(device id, domain id)`. `None` if unknown.
+///
+/// Should the same uid exist in several domains, the most recently seen
+/// row wins.
+pub async fn get_device_ids(pool: &PgPool, device_uid: &str) -> Result<Option<(Uuid, Uuid)>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT id, domain_id FROM devices WHERE device_uid = $1 ORDER BY last_seen DESC LIMIT 1",
+    )
+    .bind(device_uid)
+    .fetch_optional(pool)
+    .await
+}
+
+// ── Scheduler locks ───────────────────────────────────────────────────────────
+
+/// Run `tick` under PostgreSQL advisory lock `key`, so only one replica
+/// runs it at a time. `None` if another replica holds the lock or it could
+/// not be taken; `name` prefixes the log messages.
+pub async fn with_tick_lock<T>(pool: &PgPool, key: i64, name: &str, tick: impl Future<Output = T>) -> Option<T> {
+    // Session-level lock: it must be released on the same connection.
+    let mut conn = match pool.acquire().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!(?e, "{name}: failed to acquire connection");
+            return None;
+        }
+    };
+    match sqlx::query_scalar::<_, bool>("SELECT pg_try_advisory_lock($1)")
+        .bind(key)
+        .fetch_one(&mut *conn)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => return None,
+        Err(e) => {
+            error!(?e, "{name}: failed to take lock");
+            return None;
+        }
+    }
+
+    let result = tick.await;
+
+    if let Err(e) = sqlx::query("SELECT pg_advisory_unlock($1)").bind(key).execute(&mut *conn).await {
+        // Dropping the connection from the pool releases the lock.
+        error!(?e, "{name}: failed to release lock");
+        conn.detach();
+    }
+    Some(result)
+}
@@ -0,0 +1,292 @@
+//! Concurrent, per-device ordered event processing.
+//!
+//! Device events are handled concurrently across devices but strictly in
+//! arrival order for each device. Every device with events outstanding has
+//! a lane — a queue drained by one task — and a shared semaphore limits how
+//! many lanes handle an event at once.
+//!
+//! The number of events queued or in progress is bounded: once the limit
+//! is reached, [`Dispatcher::dispatch`] waits, which stops the event loop
+//! pulling more from NATS. Each event gets a deadline; one that runs past
+//! it is abandoned so it cannot hold up its device's later events.
+//!
+//! Across replicas, all events of a device reach the same replica (see
+//! [`crate::partitions`]), so this order holds however many run.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{error, warn};
+
+/// What the dispatcher does with an event.
+pub trait EventHandler<T>: Send + Sync + 'static {
+    /// Handle the event. Runs under the dispatcher's timeout.
+    fn handle(&self, event: &T) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Called once the event is done with, whether it succeeded, failed or
+    /// timed out.
+    fn finish(&self, event: T) -> impl Future<Output = ()> + Send;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DispatchConfig {
+    /// Events handled at once, across all devices.
+    pub workers:  usize,
+    /// Events queued or in progress before `dispatch` waits.
+    pub capacity: usize,
+    /// Time allowed to handle one event.
+    pub timeout:  Duration,
+}
+
+pub struct Dispatcher<T, H> {
+    inner: Arc<Inner<T, H>>,
+}
+
+struct Inner<T, H> {
+    handler: H,
+    config:  DispatchConfig,
+    /// Queued events per device. A device has an entry exactly while its
+    /// lane task runs.
+    lanes:   Mutex<HashMap<String, VecDeque<Job<T>>>>,
+    slots:   Arc<Semaphore>,
+    workers: Semaphore,
+    metrics: Arc<EventMetrics>,
+}
+
+struct Job<T> {
+    event:     T,
+    queued_at: Instant,
+    /// Held until the event is finished, bounding queued + in-progress.
+    _slot:     OwnedSemaphorePermit,
+}
+
+impl<T: Send + 'static, H: EventHandler<T>> Dispatcher<T, H> {
+    pub fn new(handler: H, config: DispatchConfig, metrics: Arc<EventMetrics>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                handler,
+                config,
+                lanes: Mutex::new(HashMap::new()),
+                slots: Arc::new(Semaphore::new(config.capacity.max(1))),
+                workers: Semaphore::new(config.workers.max(1)),
+                metrics,
+            }),
+        }
+    }
+
+    /// Queue an event behind the earlier events of the same `key` (device).
+    /// Waits while the dispatcher is full.
+    pub async fn dispatch(&self, key: String, event: T) {
+        let slot = self.inner.slots.clone().acquire_owned().await.expect("semaphore never closed");
+        let job = Job { event, queued_at: Instant::now(), _slot: slot };
+        self.inner.metrics.queued.fetch_add(1, Ordering::Relaxed);
+
+        let mut lanes = self.inner.lanes.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(lane) = lanes.get_mut(&key) {
+            lane.push_back(job);
+            return;
+        }
+        lanes.insert(key.clone(), VecDeque::new());
+        self.inner.metrics.devices.store(lanes.len() as i64, Ordering::Relaxed);
+        drop(lanes);
+
+        tokio::spawn(Inner::run_lane(self.inner.clone(), key, job));
+    }
+}
+
+impl<T: Send + 'static, H: EventHandler<T>> Inner<T, H> {
+    /// Handle `first` and then the lane's queued events in order, removing
+    /// the lane once it is empty.
+    async fn run_lane(self: Arc<Self>, key: String, first: Job<T>) {
+        let mut job = first;
+        loop {
+            self.run_job(&key, job).await;
+
+            let mut lanes = self.lanes.lock().unwrap_or_else(|e| e.into_inner());
+            match lanes.get_mut(&key).and_then(VecDeque::pop_front) {
+                Some(next) => job = next,
+                None => {
+                    lanes.remove(&key);
+                    self.metrics.devices.store(lanes.len() as i64, Ordering::Relaxed);
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn run_job(&self, key: &str, job: Job<T>) {
+        let _worker = self.workers.acquire().await.expect("semaphore never closed");
+        let metrics = &self.metrics;
+        metrics.queued.fetch_sub(1, Ordering::Relaxed);
+        metrics.wait.observe(job.queued_at.elapsed());
+
+        metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+        let outcome = tokio::time::timeout(self.config.timeout, self.handler.handle(&job.event)).await;
+        metrics.handle.observe(started.elapsed());
+        metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
+
+        match outcome {
+            Ok(Ok(())) => metrics.ok.fetch_add(1, Ordering::Relaxed),
+            Ok(Err(e)) => {
+                error!(key, ?e, "Event handler failed");
+                metrics.failed.fetch_add(1, Ordering::Relaxed)
+            }
+            Err(_) => {
+                warn!(key, timeout = ?self.config.timeout, "Event handler timed out");
+                metrics.timed_out.fetch_add(1, Ordering::Relaxed)
+            }
+        };
+        self.handler.finish(job.event).await;
+    }
+}
+
+// ── Metrics ───────────────────────────────────────────────────────────────────
+
+/// Event processing metrics, rendered for Prometheus by [`Self::render`].
+#[derive(Debug, Default)]
+pub struct EventMetrics {
+    queued:    AtomicI64,
+    in_flight: AtomicI64,
+    devices:   AtomicI64,
+    ok:        AtomicU64,
+    failed:    AtomicU64,
+    timed_out: AtomicU64,
+    wait:      Histogram,
+    handle:    Histogram,
+}
+
+/// Upper bounds of the latency buckets, in seconds.
+const BUCKETS: [f64; 13] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Non-cumulative counts per bucket; the last one is `+Inf`.
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_us:  AtomicU64,
+    count:   AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let i = BUCKETS.iter().position(|b| secs <= *b).unwrap_or(BUCKETS.len());
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = BUCKETS.get(i).map_or("+Inf".to_string(), |b| b.to_string());
+            let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {cumulative}");
+        }
+        let sum = self.sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_sum {sum}\n{name}_count {}", self.count.load(Ordering::Relaxed));
+    }
+}
+
+impl EventMetrics {
+    /// Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let gauges = [
+            ("acs_controller_event_queue_depth", "Device events waiting to be handled.", &self.queued),
+            ("acs_controller_events_in_flight", "Device events being handled.", &self.in_flight),
+            ("acs_controller_event_devices", "Devices with events queued or being handled.", &self.devices),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {}", value.load(Ordering::Relaxed));
+        }
+
+        let name = "acs_controller_events_total";
+        let _ = writeln!(out, "# HELP {name} Device events handled, by outcome.\n# TYPE {name} counter");
+        for (outcome, value) in [("ok", &self.ok), ("failed", &self.failed), ("timeout", &self.timed_out)] {
+            let _ = writeln!(out, "{name}{{outcome=\"{outcome}\"}} {}", value.load(Ordering::Relaxed));
+        }
+
+        self.wait.render(&mut out, "acs_controller_event_wait_seconds", "Time device events spent queued.");
+        self.handle.render(&mut out, "acs_controller_event_handle_seconds", "Time spent handling device events.");
+        out
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records `(key, n)` in handling order; `n == 0` sleeps past the timeout.
+    struct Recorder {
+        seen: Arc<Mutex<Vec<(String, u32)>>>,
+    }
+
+    impl EventHandler<(String, u32)> for Recorder {
+        async fn handle(&self, event: &(String, u32)) -> anyhow::Result<()> {
+            if event.1 == 0 {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+            // Later events of a device finish sooner, to provoke reordering.
+            tokio::time::sleep(Duration::from_millis(u64::from(10 - event.1 % 10))).await;
+            Ok(())
+        }
+
+        async fn finish(&self, event: (String, u32)) {
+            self.seen.lock().unwrap().push(event);
+        }
+    }
+
+    fn dispatcher(seen: &Arc<Mutex<Vec<(String, u32)>>>) -> Dispatcher<(String, u32), Recorder> {
+        let config = DispatchConfig { workers: 4, capacity: 8, timeout: Duration::from_millis(200) };
+        Dispatcher::new(Recorder { seen: seen.clone() }, config, Arc::default())
+    }
+
+    async fn drained(d: &Dispatcher<(String, u32), Recorder>) {
+        while !d.inner.lanes.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_order_per_device() {
+        let seen = Arc::default();
+        let d = dispatcher(&seen);
+        for n in 1..=9 {
+            for key in ["a", "b", "c"] {
+                d.dispatch(key.to_string(), (key.to_string(), n)).await;
+            }
+        }
+        drained(&d).await;
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 27);
+        for key in ["a", "b", "c"] {
+            let order: Vec<u32> = seen.iter().filter(|(k, _)| k == key).map(|(_, n)| *n).collect();
+            assert_eq!(order, (1..=9).collect::<Vec<_>>(), "{key}");
+        }
+        assert_eq!(d.inner.metrics.ok.load(Ordering::Relaxed), 27);
+        assert_eq!(d.inner.metrics.queued.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn timed_out_event_does_not_block_its_device() {
+        let seen = Arc::default();
+        let d = dispatcher(&seen);
+        d.dispatch("a".into(), ("a".into(), 0)).await;
+        d.dispatch("a".into(), ("a".into(), 1)).await;
+        drained(&d).await;
+
+        assert_eq!(*seen.lock().unwrap(), vec![("a".to_string(), 0), ("a".to_string(), 1)]);
+        assert_eq!(d.inner.metrics.timed_out.load(Ordering::Relaxed), 1);
+        assert!(d.inner.metrics.render().contains("acs_controller_events_total{outcome=\"timeout\"} 1"));
+    }
+}
@@ -0,0 +1,375 @@
+//! Real-time event hub.
+//!
+//! The controller publishes what it sees — Informs, command responses,
+//! session start/end, task status and device health changes — to an [`EventHub`]. API
+//! clients follow it through `GET /api/v1/events` (SSE) or
+//! `GET /api/v1/events/ws` (WebSocket).
+//!
+//! With several controller replicas, each handles only part of the device
+//! traffic, so published events are relayed over NATS
+//! (`acs.controller.stream`) and every replica's hub records all of them.
+//!
+//! Every event gets a resume token, `{epoch}.{seq}`: `epoch` identifies
+//! this controller process and `seq` increases by one per event. The hub
+//! keeps the most recent events in a ring buffer, so a client reconnecting
+//! with the last token it saw receives what it missed. A token from another
+//! process, or older than the buffer, cannot be resumed from — the client is
+//! told to reset and re-read state from the REST API. Tokens are therefore
+//! only good on the replica that issued them; a client whose reconnect lands
+//! on another replica is told to reset.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::nats::{NatsClient, STREAM_SUBJECT};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Inform,
+    CommandResponse,
+    SessionStarted,
+    SessionEnded,
+    TaskStatus,
+    Decommissioned,
+    HealthChanged,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Inform          => "inform",
+            Self::CommandResponse => "command_response",
+            Self::SessionStarted  => "session_started",
+            Self::SessionEnded    => "session_ended",
+            Self::TaskStatus      => "task_status",
+            Self::Decommissioned  => "decommissioned",
+            Self::HealthChanged   => "health_changed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        [
+            Self::Inform,
+            Self::CommandResponse,
+            Self::SessionStarted,
+            Self::SessionEnded,
+            Self::TaskStatus,
+            Self::Decommissioned,
+            Self::HealthChanged,
+        ]
+            .into_iter()
+            .find(|k| k.as_str() == s)
+    }
+}
+
+/// One event as sent to stream clients.
+#[derive(Debug, Serialize)]
+pub struct StreamEvent {
+    /// Resume token of this event.
+    pub id:        String,
+    #[serde(rename = "type")]
+    pub kind:      EventKind,
+    pub device_id: String,
+    pub domain_id: Uuid,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub data:      JsonValue,
+    #[serde(skip)]
+    pub seq:       u64,
+}
+
+/// An event as published, before the hub numbers it. This is what is
+/// relayed between replicas.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Published {
+    pub kind:      EventKind,
+    pub device_id: String,
+    pub domain_id: Uuid,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub data:      JsonValue,
+}
+
+/// Which events a client wants. `None` means no restriction.
+#[derive(Debug, Default)]
+pub struct EventFilter {
+    pub domains: Option<Vec<Uuid>>,
+    pub device:  Option<String>,
+    pub kinds:   Option<Vec<EventKind>>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &StreamEvent) -> bool {
+        self.domains.as_ref().is_none_or(|d| d.contains(&event.domain_id))
+            && self.device.as_ref().is_none_or(|d| *d == event.device_id)
+            && self.kinds.as_ref().is_none_or(|k| k.contains(&event.kind))
+    }
+}
+
+// ── Hub ───────────────────────────────────────────────────────────────────────
+
+#[derive(Clone)]
+pub struct EventHub {
+    inner: Arc<HubInner>,
+}
+
+struct HubInner {
+    epoch:     u32,
+    capacity:  usize,
+    heartbeat: Duration,
+    log:       Mutex<Log>,
+    tx:        broadcast::Sender<Arc<StreamEvent>>,
+    /// Set when events are relayed through NATS rather than recorded
+    /// directly.
+    relay:     Option<mpsc::UnboundedSender<Published>>,
+}
+
+struct Log {
+    /// Sequence number of the next event. Events are numbered from 1, so
+    /// sequence 0 means "nothing seen yet".
+    next_seq: u64,
+    recent:   VecDeque<Arc<StreamEvent>>,
+}
+
+impl Log {
+    /// Buffered events after `seq`, or `None` if some were already dropped.
+    fn after(&self, seq: u64) -> Option<Vec<Arc<StreamEvent>>> {
+        if seq >= self.next_seq {
+            return None;
+        }
+        let oldest = self.recent.front().map_or(self.next_seq, |e| e.seq);
+        if seq + 1 < oldest {
+            return None;
+        }
+        Some(self.recent.iter().filter(|e| e.seq > seq).cloned().collect())
+    }
+}
+
+/// Where a new subscriber starts.
+pub enum Start {
+    /// Continue after the resume token: these buffered events come first.
+    Resume(Vec<Arc<StreamEvent>>),
+    /// Live events only. `reset` is set when a resume token was given but
+    /// could not be honoured.
+    Live { reset: bool },
+}
+
+impl EventHub {
+    /// A hub shared with the other replicas: published events go out on
+    /// NATS and every event arriving from NATS, this replica's included, is
+    /// recorded. `capacity` events are kept for resuming; `heartbeat` is how
+    /// often idle streams are sent a heartbeat.
+    pub async fn shared(capacity: usize, heartbeat: Duration, nats: NatsClient) -> anyhow::Result<Self> {
+        let mut incoming = nats.subscribe(STREAM_SUBJECT).await?;
+        let (relay, mut outgoing) = mpsc::unbounded_channel::<Published>();
+        let hub = Self::build(capacity, heartbeat, Some(relay));
+
+        // A single sender keeps this replica's events in order.
+        let local = hub.clone();
+        tokio::spawn(async move {
+            while let Some(event) = outgoing.recv().await {
+                let payload = serde_json::to_vec(&event).expect("Published serialises");
+                if let Err(e) = nats.broadcast(STREAM_SUBJECT, payload).await {
+                    // At least this replica's clients still see it.
+                    tracing::warn!(?e, "Failed to relay stream event");
+                    local.record(event);
+                }
+            }
+        });
+
+        let local = hub.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = incoming.next().await {
+                match serde_json::from_slice::<Published>(&msg.payload) {
+                    Ok(event) => local.record(event),
+                    Err(e) => tracing::warn!(?e, "Malformed relayed stream event"),
+                }
+            }
+            tracing::error!("Stream event relay subscription ended");
+        });
+
+        Ok(hub)
+    }
+
+    /// A hub recording its own events directly when `relay` is `None`.
+    fn build(capacity: usize, heartbeat: Duration, relay: Option<mpsc::UnboundedSender<Published>>) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            inner: Arc::new(HubInner {
+                epoch: rand_epoch(),
+                capacity,
+                heartbeat,
+                log: Mutex::new(Log { next_seq: 1, recent: VecDeque::with_capacity(capacity) }),
+                tx: broadcast::channel(capacity).0,
+                relay,
+            }),
+        }
+    }
+
+    pub fn heartbeat(&self) -> Duration {
+        self.inner.heartbeat
+    }
+
+    pub fn publish(&self, kind: EventKind, device_uid: &str, domain_id: Uuid, data: JsonValue) {
+        let event = Published {
+            kind,
+            device_id: device_uid.to_string(),
+            domain_id,
+            timestamp: chrono::Utc::now(),
+            data,
+        };
+        match &self.inner.relay {
+            // The sender task lives as long as the hub.
+            Some(relay) => {
+                let _ = relay.send(event);
+            }
+            None => self.record(event),
+        }
+    }
+
+    /// Number, buffer and broadcast an event to this replica's clients.
+    fn record(&self, event: Published) {
+        // The lock only guards in-memory bookkeeping and is never held
+        // across an await, so poisoning cannot leave it inconsistent.
+        let mut log = self.inner.log.lock().unwrap_or_else(|e| e.into_inner());
+        let seq = log.next_seq;
+        log.next_seq += 1;
+
+        let event = Arc::new(StreamEvent {
+            id: self.token(seq),
+            kind: event.kind,
+            device_id: event.device_id,
+            domain_id: event.domain_id,
+            timestamp: event.timestamp,
+            data: event.data,
+            seq,
+        });
+        if log.recent.len() == self.inner.capacity {
+            log.recent.pop_front();
+        }
+        log.recent.push_back(event.clone());
+        // Sent under the lock so subscribers see events in `seq` order.
+        // No receivers is normal when nobody is streaming.
+        let _ = self.inner.tx.send(event);
+    }
+
+    /// Subscribe to live events, first resolving `resume` against the
+    /// buffer. Returns the start position, the sequence number the stream
+    /// is at, and the live receiver.
+    pub fn subscribe(&self, resume: Option<&str>) -> (Start, u64, broadcast::Receiver<Arc<StreamEvent>>) {
+        let log = self.inner.log.lock().unwrap_or_else(|e| e.into_inner());
+        let rx = self.inner.tx.subscribe();
+        let head = log.next_seq - 1;
+
+        let start = match resume.map(|t| self.parse_token(t).and_then(|seq| log.after(seq))) {
+            None => Start::Live { reset: false },
+            Some(Some(events)) => Start::Resume(events),
+            Some(None) => Start::Live { reset: true },
+        };
+        (start, head, rx)
+    }
+
+    /// Sequence number of the latest event; 0 if none yet.
+    pub fn head(&self) -> u64 {
+        self.inner.log.lock().unwrap_or_else(|e| e.into_inner()).next_seq - 1
+    }
+
+    /// Buffered events after `seq`, for a subscriber that fell behind.
+    pub fn replay_after(&self, seq: u64) -> Option<Vec<Arc<StreamEvent>>> {
+        self.inner.log.lock().unwrap_or_else(|e| e.into_inner()).after(seq)
+    }
+
+    pub fn token(&self, seq: u64) -> String {
+        format!("{:08x}.{seq}", self.inner.epoch)
+    }
+
+    /// The sequence number of a token issued by this process.
+    fn parse_token(&self, token: &str) -> Option<u64> {
+        let (epoch, seq) = token.split_once('.')?;
+        (u32::from_str_radix(epoch, 16).ok()? == self.inner.epoch).then_some(())?;
+        seq.parse().ok()
+    }
+}
+
+fn rand_epoch() -> u32 {
+    Uuid::new_v4().as_u128() as u32
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hub(capacity: usize) -> EventHub {
+        EventHub::build(capacity, Duration::from_secs(15), None)
+    }
+
+    fn publish(hub: &EventHub, n: usize) {
+        for _ in 0..n {
+            hub.publish(EventKind::Inform, "AABB00-1", Uuid::nil(), JsonValue::Null);
+        }
+    }
+
+    #[test]
+    fn resume_replays_missed_events() {
+        let hub = hub(10);
+        publish(&hub, 5);
+        let (start, head, _) = hub.subscribe(Some(&hub.token(2)));
+        assert_eq!(head, 5);
+        let Start::Resume(events) = start else { panic!("expected resume") };
+        assert_eq!(events.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn resume_at_head_replays_nothing() {
+        let hub = hub(10);
+        publish(&hub, 3);
+        let (start, _, _) = hub.subscribe(Some(&hub.token(3)));
+        assert!(matches!(start, Start::Resume(events) if events.is_empty()));
+    }
+
+    #[test]
+    fn tokens_older_than_the_buffer_reset() {
+        let hub = hub(3);
+        publish(&hub, 6);
+        // 4..=6 are buffered: resuming after 3 works, after 2 does not.
+        assert!(matches!(hub.subscribe(Some(&hub.token(3))).0, Start::Resume(_)));
+        assert!(matches!(hub.subscribe(Some(&hub.token(2))).0, Start::Live { reset: true }));
+    }
+
+    #[test]
+    fn foreign_or_malformed_tokens_reset() {
+        let hub = hub(10);
+        publish(&hub, 1);
+        let other = EventHub::build(10, Duration::from_secs(15), None);
+        for token in [other.token(1), "garbage".to_string(), hub.token(99)] {
+            assert!(matches!(hub.subscribe(Some(&token)).0, Start::Live { reset: true }), "{token}");
+        }
+        assert!(matches!(hub.subscribe(None).0, Start::Live { reset: false }));
+    }
+
+    #[test]
+    fn filter_combines_criteria() {
+        let hub = hub(10);
+        let domain = Uuid::new_v4();
+        hub.publish(EventKind::TaskStatus, "AABB00-1", domain, JsonValue::Null);
+        let event = hub.replay_after(0).unwrap().pop().unwrap();
+
+        assert!(EventFilter::default().matches(&event));
+        let filter = EventFilter {
+            domains: Some(vec![domain]),
+            device:  Some("AABB00-1".into()),
+            kinds:   Some(vec![EventKind::TaskStatus]),
+        };
+        assert!(filter.matches(&event));
+        assert!(!EventFilter { kinds: Some(vec![EventKind::Inform]), ..Default::default() }.matches(&event));
+        assert!(!EventFilter { domains: Some(vec![Uuid::nil()]), ..Default::default() }.matches(&event));
+    }
+}
@@ -0,0 +1,271 @@
+//! Firmware catalog.
+//!
+//! Images of firmware versions live in `firmware_images`, each with the
+//! devices it suits (OUIs, product classes, hardware versions). Upgrading a
+//! device "to 3.2.1" goes through [`choose`], which picks the image and
+//! refuses devices no image of that version suits; [`Image::download`] then
+//! builds the Download with the image's URL, size and file type.
+//!
+//! Images are registered with an external URL, or uploaded to the
+//! controller's [`Storage`], which serves them to devices.
+
+use std::path::PathBuf;
+
+use nats_common::Action;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tokio::io::AsyncWriteExt;
+use tokio_stream::{Stream, StreamExt};
+use uuid::Uuid;
+
+/// One row of `firmware_images`.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Image {
+    pub id:                Uuid,
+    /// `None` for a shared image.
+    pub domain_id:         Option<Uuid>,
+    pub version:           String,
+    pub file_type:         String,
+    /// `None` while an upload is awaited.
+    pub url:               Option<String>,
+    pub file_size:         Option<i64>,
+    pub sha256:            Option<String>,
+    pub target_filename:   Option<String>,
+    /// Uploaded to and served by the controller.
+    pub stored:            bool,
+    pub ouis:              Vec<String>,
+    pub product_classes:   Vec<String>,
+    pub hardware_versions: Vec<String>,
+    pub release_notes:     Option<String>,
+    pub created_by:        Option<Uuid>,
+    pub created_at:        chrono::DateTime<chrono::Utc>,
+}
+
+pub const IMAGE_COLUMNS: &str = "id, domain_id, version, file_type, url, file_size, sha256, target_filename, \
+                                 stored, ouis, product_classes, hardware_versions, release_notes, \
+                                 created_by, created_at";
+
+/// What compatibility is checked against.
+#[derive(Debug, Default, sqlx::FromRow)]
+pub struct Device {
+    pub oui:              Option<String>,
+    pub product_class:    Option<String>,
+    pub hardware_version: Option<String>,
+}
+
+impl Image {
+    /// Why the image does not suit `device`, or `None` if it does. A list
+    /// left empty accepts anything; a device that did not report a value
+    /// a list restricts is not accepted.
+    pub fn incompatibility(&self, device: &Device) -> Option<String> {
+        let checks = [
+            ("OUI", &self.ouis, &device.oui, true),
+            ("product class", &self.product_classes, &device.product_class, false),
+            ("hardware version", &self.hardware_versions, &device.hardware_version, false),
+        ];
+        for (what, accepted, value, ignore_case) in checks {
+            if accepted.is_empty() {
+                continue;
+            }
+            let matches = |a: &String| value.as_ref().is_some_and(|v| if ignore_case { a.eq_ignore_ascii_case(v) } else { a == v });
+            if !accepted.iter().any(matches) {
+                let value = value.as_deref().unwrap_or("unknown");
+                return Some(format!("{what} {value} is not one of {}", accepted.join(", ")));
+            }
+        }
+        None
+    }
+
+    /// The Download installing this image. `None` while it has no URL.
+    pub fn download(&self) -> Option<Action> {
+        Some(Action::Download {
+            url:             self.url.clone()?,
+            file_type:       self.file_type.clone(),
+            file_size:       self.file_size.unwrap_or(0).clamp(0, u32::MAX as i64) as u32,
+            target_filename: self.target_filename.clone().unwrap_or_default(),
+        })
+    }
+}
+
+/// Outcome of looking for an image of a version for a device.
+#[derive(Debug)]
+pub enum Choice {
+    Image(Box<Image>),
+    /// The catalog has no ready image of the version for the device's domain.
+    Unknown,
+    /// No image of the version suits the device: why the first does not.
+    Incompatible(String),
+}
+
+/// The image of `version` to install on `device_id`: the newest compatible
+/// one of its domain, else the newest compatible shared one. Images still
+/// awaiting their upload are left out.
+pub async fn choose(pool: &PgPool, device_id: Uuid, version: &str) -> Result<Choice, sqlx::Error> {
+    let device = sqlx::query_as::<_, Device>("SELECT oui, product_class, hardware_version FROM devices WHERE id = $1")
+        .bind(device_id)
+        .fetch_optional(pool)
+        .await?
+        .unwrap_or_default();
+
+    let images = sqlx::query_as::<_, Image>(&format!(
+        r#"
+        SELECT {IMAGE_COLUMNS} FROM firmware_images
+        WHERE version = $2
+          AND url IS NOT NULL
+          AND (domain_id IS NULL OR domain_id = (SELECT domain_id FROM devices WHERE id = $1))
+        ORDER BY domain_id IS NULL, created_at DESC
+        "#
+    ))
+    .bind(device_id)
+    .bind(version)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(pick(images, &device))
+}
+
+fn pick(images: Vec<Image>, device: &Device) -> Choice {
+    let mut reason = None;
+    for image in images {
+        match image.incompatibility(device) {
+            None => return Choice::Image(Box::new(image)),
+            Some(why) => {
+                reason.get_or_insert(why);
+            }
+        }
+    }
+    reason.map_or(Choice::Unknown, Choice::Incompatible)
+}
+
+// ── Storage ───────────────────────────────────────────────────────────────────
+
+/// Where uploaded images are kept, and how devices reach them.
+#[derive(Debug, Clone, Default)]
+pub struct Storage {
+    /// Directory holding one file per uploaded image, named by its id.
+    /// Replicas must share it.
+    pub dir:      PathBuf,
+    /// Base URL devices reach the controller's `/firmware/files/` at, e.g.
+    /// `http://acs.example.net:8080`. Uploads are refused without one.
+    pub base_url: Option<String>,
+    /// Largest upload, in bytes.
+    pub max_size: u64,
+}
+
+/// Why an upload failed.
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("Image is larger than {0} bytes")]
+    TooLarge(u64),
+    #[error("Failed to read the upload: {0}")]
+    Body(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl Storage {
+    /// Path of image `id`'s file.
+    pub fn path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    /// URL devices download image `id` from.
+    pub fn url(&self, id: Uuid) -> Option<String> {
+        self.base_url.as_ref().map(|base| format!("{}/firmware/files/{id}", base.trim_end_matches('/')))
+    }
+
+    /// Write `body` as the file of image `id`, returning its size and
+    /// SHA-256 (lowercase hex). Nothing is left behind on failure.
+    pub async fn save<S, E>(&self, id: Uuid, mut body: S) -> Result<(u64, String), UploadError>
+    where
+        S: Stream<Item = Result<bytes::Bytes, E>> + Unpin,
+        E: std::fmt::Display,
+    {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        // Written aside and renamed, so a device never gets half an image.
+        let partial = self.dir.join(format!("{id}.partial"));
+        let mut file = tokio::fs::File::create(&partial).await?;
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+
+        let written: Result<(), UploadError> = async {
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.map_err(|e| UploadError::Body(e.to_string()))?;
+                size += chunk.len() as u64;
+                if size > self.max_size {
+                    return Err(UploadError::TooLarge(self.max_size));
+                }
+                hasher.update(&chunk);
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+            Ok(())
+        }
+        .await;
+        if let Err(e) = written {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e);
+        }
+
+        tokio::fs::rename(&partial, self.path(id)).await?;
+        let sha256 = hasher.finalize().iter().map(|b| format!("{b:02x}")).collect();
+        Ok((size, sha256))
+    }
+
+    /// Remove image `id`'s file, if there is one.
+    pub async fn remove(&self, id: Uuid) -> std::io::Result<()> {
+        match tokio::fs::remove_file(self.path(id)).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            other => other,
+        }
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(domain_id: Option<Uuid>, product_classes: &[&str], hardware_versions: &[&str]) -> Image {
+        Image {
+            id: Uuid::new_v4(),
+            domain_id,
+            version: "3.2.1".into(),
+            file_type: "1 Firmware Upgrade Image".into(),
+            url: Some("http://fw/3.2.1.bin".into()),
+            file_size: Some(1024),
+            sha256: None,
+            target_filename: None,
+            stored: false,
+            ouis: vec!["aabb00".into()],
+            product_classes: product_classes.iter().map(|s| s.to_string()).collect(),
+            hardware_versions: hardware_versions.iter().map(|s| s.to_string()).collect(),
+            release_notes: None,
+            created_by: None,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn the_first_compatible_image_is_chosen() {
+        let device = Device {
+            oui:              Some("AABB00".into()),
+            product_class:    Some("HGW-2".into()),
+            hardware_version: Some("HW2".into()),
+        };
+        let domain = image(Some(Uuid::new_v4()), &["HGW-1"], &[]);
+        let shared = image(None, &["HGW-1", "HGW-2"], &["HW2"]);
+
+        let Choice::Image(chosen) = pick(vec![domain.clone(), shared.clone()], &device) else { panic!() };
+        assert_eq!(chosen.id, shared.id);
+        let Some(Action::Download { file_size: 1024, .. }) = chosen.download() else { panic!() };
+
+        let Choice::Incompatible(why) = pick(vec![domain], &device) else { panic!() };
+        assert_eq!(why, "product class HGW-2 is not one of HGW-1");
+        let unknown = Device { product_class: None, ..device };
+        assert!(matches!(pick(vec![shared], &unknown), Choice::Incompatible(_)));
+        assert!(matches!(pick(Vec::new(), &unknown), Choice::Unknown));
+    }
+}
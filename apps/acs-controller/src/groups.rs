@@ -0,0 +1,394 @@
+//! Device groups.
+//!
+//! A group is a named set of devices within one domain. Static groups are
+//! edited through the API; dynamic groups hold the devices matching a stored
+//! search expression (see [`crate::search`]). Membership of both kinds lives
+//! in `device_group_members`, so scripts and campaigns target a dynamic group
+//! exactly like a static one.
+//!
+//! Dynamic membership is kept current two ways:
+//!
+//! - [`evaluate`] re-runs a group's filter over its whole domain (on create,
+//!   on filter change, on demand through the API, and before a campaign that
+//!   targets the group starts);
+//! - [`on_inform`] re-checks every dynamic group of the informing device's
+//!   domain against that one device.
+//!
+//! Every device that joins or leaves a group is announced on
+//! `acs.groups.{group_id}.membership` as a [`GroupMembershipChange`].
+
+use nats_common::{GroupMembershipChange, MembershipChange};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::nats::NatsClient;
+use crate::search::{self, SearchError, SqlFilter};
+
+// ── Types ─────────────────────────────────────────────────────────────────────
+
+/// One row of `device_groups`.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct DeviceGroup {
+    pub id:           Uuid,
+    pub domain_id:    Uuid,
+    pub name:         String,
+    pub description:  Option<String>,
+    /// `static` | `dynamic`
+    pub kind:         String,
+    /// Search expression of a dynamic group.
+    pub filter:       Option<String>,
+    pub created_by:   Option<Uuid>,
+    pub created_at:   chrono::DateTime<chrono::Utc>,
+    pub updated_at:   chrono::DateTime<chrono::Utc>,
+    pub evaluated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub const GROUP_COLUMNS: &str = "id, domain_id, name, description, kind, filter, created_by, \
+                                 created_at, updated_at, evaluated_at";
+
+impl DeviceGroup {
+    pub fn is_dynamic(&self) -> bool {
+        self.kind == "dynamic"
+    }
+
+    fn change(&self, device_uid: String, change: MembershipChange) -> GroupMembershipChange {
+        GroupMembershipChange {
+            group_id:   self.id,
+            group_name: self.name.clone(),
+            domain_id:  self.domain_id,
+            device_id:  device_uid,
+            change,
+            timestamp:  chrono::Utc::now().timestamp(),
+        }
+    }
+}
+
+/// Why a dynamic group could not be evaluated.
+#[derive(Debug, thiserror::Error)]
+pub enum EvaluateError {
+    /// The stored filter no longer compiles (e.g. a field was removed).
+    #[error("Stored group filter is invalid: {0}")]
+    Search(#[from] SearchError),
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+/// Look up a group by name within a domain.
+pub async fn find_by_name(pool: &PgPool, domain_id: Uuid, name: &str) -> Result<Option<DeviceGroup>, sqlx::Error> {
+    sqlx::query_as::<_, DeviceGroup>(&format!(
+        "SELECT {GROUP_COLUMNS} FROM device_groups WHERE domain_id = $1 AND name = $2"
+    ))
+    .bind(domain_id)
+    .bind(name)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Parse and compile a dynamic group filter, numbering placeholders from
+/// `$first_idx`. Used to validate filters before they are stored.
+pub fn compile_filter(filter: &str, first_idx: usize) -> Result<SqlFilter, SearchError> {
+    if filter.trim().is_empty() {
+        return Err(SearchError::Syntax(0, "filter must not be empty".into()));
+    }
+    Ok(search::compile(&search::parse(filter)?, first_idx))
+}
+
+/// Compile a group's stored filter, which may no longer compile (e.g. a
+/// field was removed).
+fn stored_filter(group: &DeviceGroup, first_idx: usize) -> Result<SqlFilter, SearchError> {
+    compile_filter(group.filter.as_deref().unwrap_or_default(), first_idx)
+}
+
+// ── Dynamic evaluation ────────────────────────────────────────────────────────
+
+/// Re-evaluate a dynamic group over its whole domain and publish the
+/// resulting joins and leaves. Static groups are left unchanged.
+pub async fn evaluate(
+    pool: &PgPool,
+    nats: &NatsClient,
+    group: &DeviceGroup,
+) -> Result<Vec<GroupMembershipChange>, EvaluateError> {
+    if !group.is_dynamic() {
+        return Ok(Vec::new());
+    }
+    let filter = stored_filter(group, 3)?;
+
+    let mut tx = pool.begin().await?;
+    // Serialise evaluations of the same group so their diffs don't overlap.
+    sqlx::query("SELECT 1 FROM device_groups WHERE id = $1 FOR UPDATE")
+        .bind(group.id)
+        .execute(&mut *tx)
+        .await?;
+
+    let sql = format!(
+        r#"
+        WITH matching AS (
+            SELECT d.id FROM devices d
+            JOIN domains dom ON dom.id = d.domain_id
+            WHERE d.domain_id = $2 AND ({})
+        ),
+        joined AS (
+            INSERT INTO device_group_members (group_id, device_id)
+            SELECT $1, id FROM matching
+            ON CONFLICT DO NOTHING
+            RETURNING device_id
+        ),
+        gone AS (
+            DELETE FROM device_group_members
+            WHERE group_id = $1 AND device_id NOT IN (SELECT id FROM matching)
+            RETURNING device_id
+        )
+        SELECT d.device_uid, true AS joined FROM joined j JOIN devices d ON d.id = j.device_id
+        UNION ALL
+        SELECT d.device_uid, false FROM gone g JOIN devices d ON d.id = g.device_id
+        "#,
+        filter.sql
+    );
+    let mut query = sqlx::query_as::<_, (String, bool)>(&sql).bind(group.id).bind(group.domain_id);
+    for value in &filter.binds {
+        query = query.bind(value);
+    }
+    let rows = query.fetch_all(&mut *tx).await?;
+
+    sqlx::query("UPDATE device_groups SET evaluated_at = now() WHERE id = $1")
+        .bind(group.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    let changes: Vec<_> = rows
+        .into_iter()
+        .map(|(uid, joined)| {
+            group.change(uid, if joined { MembershipChange::Joined } else { MembershipChange::Left })
+        })
+        .collect();
+    info!(group_id = %group.id, changes = changes.len(), "Dynamic group evaluated");
+    publish(nats, &changes).await;
+    Ok(changes)
+}
+
+/// Bring one device's group memberships up to date after an Inform.
+///
+/// Drops memberships in groups of other domains (the device was moved) and
+/// re-checks every dynamic group of its current domain. A group whose stored
+/// filter no longer compiles is skipped with a warning.
+pub async fn on_inform(
+    pool: &PgPool,
+    nats: &NatsClient,
+    device_id: Uuid,
+    device_uid: &str,
+    domain_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let mut changes = Vec::new();
+
+    let moved_out = sqlx::query_as::<_, DeviceGroup>(&format!(
+        r#"
+        WITH gone AS (
+            DELETE FROM device_group_members m
+            USING device_groups g
+            WHERE m.group_id = g.id AND m.device_id = $1 AND g.domain_id <> $2
+            RETURNING g.id
+        )
+        SELECT {GROUP_COLUMNS} FROM device_groups WHERE id IN (SELECT id FROM gone)
+        "#
+    ))
+    .bind(device_id)
+    .bind(domain_id)
+    .fetch_all(pool)
+    .await?;
+    changes.extend(moved_out.iter().map(|g| g.change(device_uid.to_string(), MembershipChange::Left)));
+
+    let dynamic = sqlx::query_as::<_, DeviceGroup>(&format!(
+        "SELECT {GROUP_COLUMNS} FROM device_groups WHERE domain_id = $1 AND kind = 'dynamic'"
+    ))
+    .bind(domain_id)
+    .fetch_all(pool)
+    .await?;
+
+    for group in &dynamic {
+        let filter = match stored_filter(group, 2) {
+            Ok(f) => f,
+            Err(e) => {
+                warn!(group_id = %group.id, ?e, "Skipping dynamic group with invalid filter");
+                continue;
+            }
+        };
+        if let Some(change) = check_device(pool, group, &filter, device_id).await? {
+            changes.push(group.change(device_uid.to_string(), change));
+        }
+    }
+
+    publish(nats, &changes).await;
+    Ok(())
+}
+
+/// Match one device against a dynamic group's compiled filter (placeholders
+/// from `$2`) and add or remove its membership accordingly.
+async fn check_device(
+    pool: &PgPool,
+    group: &DeviceGroup,
+    filter: &SqlFilter,
+    device_id: Uuid,
+) -> Result<Option<MembershipChange>, sqlx::Error> {
+    let sql = format!(
+        "SELECT EXISTS (SELECT 1 FROM devices d JOIN domains dom ON dom.id = d.domain_id \
+         WHERE d.id = $1 AND ({}))",
+        filter.sql
+    );
+    let mut query = sqlx::query_scalar::<_, bool>(&sql).bind(device_id);
+    for value in &filter.binds {
+        query = query.bind(value);
+    }
+    let matches = query.fetch_one(pool).await?;
+
+    let changed = if matches {
+        sqlx::query(
+            "INSERT INTO device_group_members (group_id, device_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+    } else {
+        sqlx::query("DELETE FROM device_group_members WHERE group_id = $1 AND device_id = $2")
+    }
+    .bind(group.id)
+    .bind(device_id)
+    .execute(pool)
+    .await?
+    .rows_affected()
+        > 0;
+
+    Ok(changed.then_some(if matches { MembershipChange::Joined } else { MembershipChange::Left }))
+}
+
+// ── Static membership ─────────────────────────────────────────────────────────
+
+/// Add devices of the group's domain to a static group. Returns the changes
+/// made and the uids that matched no device in the domain; devices already
+/// in the group are neither.
+pub async fn add_members(
+    pool: &PgPool,
+    nats: &NatsClient,
+    group: &DeviceGroup,
+    device_uids: &[String],
+) -> Result<(Vec<GroupMembershipChange>, Vec<String>), sqlx::Error> {
+    let rows: Vec<(String, bool)> = sqlx::query_as(
+        r#"
+        WITH found AS (
+            SELECT id, device_uid FROM devices
+            WHERE domain_id = $2 AND device_uid = ANY($3)
+        ),
+        added AS (
+            INSERT INTO device_group_members (group_id, device_id)
+            SELECT $1, id FROM found
+            ON CONFLICT DO NOTHING
+            RETURNING device_id
+        )
+        SELECT f.device_uid, EXISTS (SELECT 1 FROM added a WHERE a.device_id = f.id)
+        FROM found f
+        "#,
+    )
+    .bind(group.id)
+    .bind(group.domain_id)
+    .bind(device_uids)
+    .fetch_all(pool)
+    .await?;
+
+    let not_found = device_uids
+        .iter()
+        .filter(|uid| !rows.iter().any(|(found, _)| found == *uid))
+        .cloned()
+        .collect();
+    let changes: Vec<_> = rows
+        .into_iter()
+        .filter(|(_, added)| *added)
+        .map(|(uid, _)| group.change(uid, MembershipChange::Joined))
+        .collect();
+
+    publish(nats, &changes).await;
+    Ok((changes, not_found))
+}
+
+/// Remove a device from a static group. Returns `false` if it was not a member.
+pub async fn remove_member(
+    pool: &PgPool,
+    nats: &NatsClient,
+    group: &DeviceGroup,
+    device_uid: &str,
+) -> Result<bool, sqlx::Error> {
+    let removed = sqlx::query(
+        r#"
+        DELETE FROM device_group_members m
+        USING devices d
+        WHERE m.device_id = d.id AND m.group_id = $1 AND d.device_uid = $2
+        "#,
+    )
+    .bind(group.id)
+    .bind(device_uid)
+    .execute(pool)
+    .await?
+    .rows_affected()
+        > 0;
+
+    if removed {
+        publish(nats, &[group.change(device_uid.to_string(), MembershipChange::Left)]).await;
+    }
+    Ok(removed)
+}
+
+/// Delete a group, announcing that each of its members left.
+pub async fn delete(pool: &PgPool, nats: &NatsClient, group: &DeviceGroup) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let members: Vec<String> = sqlx::query_scalar(
+        r#"
+        DELETE FROM device_group_members m
+        USING devices d
+        WHERE m.device_id = d.id AND m.group_id = $1
+        RETURNING d.device_uid
+        "#,
+    )
+    .bind(group.id)
+    .fetch_all(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM device_groups WHERE id = $1")
+        .bind(group.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    let changes: Vec<_> = members
+        .into_iter()
+        .map(|uid| group.change(uid, MembershipChange::Left))
+        .collect();
+    publish(nats, &changes).await;
+    Ok(())
+}
+
+/// Publish membership changes. Failures are logged: membership itself is
+/// already committed, and listeners can resynchronise from the API.
+async fn publish(nats: &NatsClient, changes: &[GroupMembershipChange]) {
+    for change in changes {
+        if let Err(e) = nats.publish_group_change(change).await {
+            warn!(?e, group_id = %change.group_id, device_id = %change.device_id, "Failed to publish group change");
+        }
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_must_not_be_empty() {
+        assert!(compile_filter("  ", 1).is_err());
+    }
+
+    #[test]
+    fn filter_placeholders_follow_fixed_binds() {
+        let f = compile_filter(r#"tags has "pilot" AND hardware_version = "2.1""#, 3).unwrap();
+        assert!(f.sql.contains("$3") && f.sql.contains("$4"), "{}", f.sql);
+        assert!(!f.sql.contains("$1") && !f.sql.contains("$2"), "{}", f.sql);
+        assert_eq!(f.binds, vec!["pilot", "2.1"]);
+    }
+}
@@ -0,0 +1,102 @@
+//! Handler for the internal `decommission` event.
+//!
+//! Fired by `DELETE /inventory/devices/:uid` just before the device is
+//! deleted. The controller runs the `delete` scripts under `decommission/`,
+//! so a device can be wiped or released before it is forgotten.
+
+use anyhow::Context;
+use nats_common::DeviceCommand;
+use tracing::{error, info, warn};
+
+use crate::api::ApiState;
+use crate::db;
+use crate::provisioning::{Lifecycle, Target};
+use crate::sessions;
+
+#[derive(sqlx::FromRow)]
+struct DecommissionedDevice {
+    domain_id:        uuid::Uuid,
+    oui:              Option<String>,
+    serial_number:    Option<String>,
+    manufacturer:     Option<String>,
+    product_class:    Option<String>,
+    hardware_version: Option<String>,
+    software_version: Option<String>,
+}
+
+/// Run the decommission scripts of the device `device_id` (`device_uid`).
+///
+/// The device is about to be deleted, so nothing can be queued for it:
+/// returned actions go to its open session if it has one and are dropped
+/// otherwise, as are inventory updates. Returns how many actions were sent.
+pub async fn handle_decommission(state: &ApiState, device_id: uuid::Uuid, device_uid: &str) -> anyhow::Result<usize> {
+    let pool = &state.pool;
+    let Some(device) = sqlx::query_as::<_, DecommissionedDevice>(
+        r#"
+        SELECT domain_id, oui, serial_number, manufacturer, product_class,
+               hardware_version, software_version
+        FROM devices
+        WHERE id = $1
+        "#,
+    )
+    .bind(device_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch device")?
+    else {
+        return Ok(0);
+    };
+    let domain_slug = db::get_domain_slug(pool, device.domain_id)
+        .await
+        .context("Failed to fetch domain slug for provisioning")?;
+
+    let payload = serde_json::json!({
+        "event":            "decommission",
+        "device_id":        device_uid,
+        "oui":              device.oui,
+        "serial_number":    device.serial_number,
+        "manufacturer":     device.manufacturer,
+        "product_class":    device.product_class,
+        "hardware_version": device.hardware_version,
+        "software_version": device.software_version,
+    });
+    // A JSON value always serialises.
+    let payload = serde_json::to_vec(&payload).expect("payload serialises");
+
+    let target = Target {
+        event_type:  "decommission",
+        domain_slug: &domain_slug,
+        hw_version:  device.hardware_version.as_deref(),
+        sw_version:  device.software_version.as_deref(),
+        device_id:   device_uid,
+        lifecycle:   Lifecycle::Delete,
+    };
+    let actions = state
+        .provisioner
+        .run_scripts(&target, &payload, None)
+        .await
+        .context("Provisioning engine failed")?;
+    if actions.is_empty() {
+        return Ok(0);
+    }
+
+    let Some(session_id) = state.sessions.get(device_uid).await.context("Failed to look up session")? else {
+        warn!(device_uid, count = actions.len(), "Device not connected — dropping decommission actions");
+        return Ok(0);
+    };
+
+    info!(device_uid, count = actions.len(), "Publishing commands from decommission scripts");
+    let mut published = 0;
+    for action in actions {
+        let command = DeviceCommand { command_id: uuid::Uuid::new_v4(), device_id: device_uid.to_string(), action };
+        let payload = serde_json::to_vec(&command).context("Failed to serialize DeviceCommand")?;
+        match state.nats.publish_command(&session_id, payload).await {
+            Ok(()) => published += 1,
+            Err(e) => error!(?e, session_id, "Failed to publish DeviceCommand to NATS"),
+        }
+    }
+    sessions::count_commands(pool, &session_id, published)
+        .await
+        .context("Failed to count session commands")?;
+    Ok(published as usize)
+}
@@ -12,14 +12,24 @@ use tracing::{debug, error, info};
 use crate::db::{self, InformPayload};
 use crate::nats::NatsClient;
 use crate::Config;
-use crate::provisioning;
+use crate::events::EventKind;
+use crate::provisioning::runs::{self, Delivery};
+use crate::provisioning::updates;
+use crate::{campaigns, datamodel, groups, health, onboarding, provisioning, sessions, tasks, webhooks};
 
 /// Handle a raw `inform` event payload received from a protocol pod.
 ///
 /// Deserialises the JSON payload, logs key fields, then delegates to
 /// [`db::upsert_device`] to persist the device state. Then it executes
 /// provisioning scripts for the "inform" event and publishes resulting
-/// commands to NATS.
+/// commands to NATS, followed by any tasks queued for the device
+/// (see [`tasks::deliver_pending`]). Inventory updates the scripts returned
+/// are applied, and the scripts' run is recorded in the audit log (see
+/// [`runs`]).
+///
+/// A known device stays in the domain it belongs to. A device seen for the
+/// first time is placed by [`onboarding::assign_domain`], falling back to
+/// `config.default_domain_id` when no rule matches.
 pub async fn handle_inform(
     raw: &[u8],
     pool: &sqlx::PgPool,
@@ -40,9 +50,21 @@ pub async fn handle_inform(
         "Inform received — upserting device",
     );
 
-    let device_uuid = db::upsert_device(pool, &payload, config.default_domain_id)
+    let domain_id = match db::get_device_ids(pool, &payload.device_id)
+        .await
+        .context("Failed to look up device")?
+    {
+        Some((_, domain_id)) => domain_id,
+        None => onboarding::assign_domain(pool, &payload)
+            .await
+            .context("Failed to evaluate onboarding rules")?
+            .unwrap_or(config.default_domain_id),
+    };
+
+    let device = db::upsert_device(pool, &payload, domain_id)
         .await
         .context("Failed to upsert device in database")?;
+    let device_uuid = device.id;
 
     // Extract connection request URL
     let cr_url = payload.parameter_list.get("InternetGatewayDevice.ManagementServer.ConnectionRequestURL")
@@ -67,34 +89,98 @@ pub async fn handle_inform(
 
     debug!(
         device_id        = %payload.device_id,
+        domain_id        = %device.domain_id,
+        created          = device.created,
         software_version = ?payload.software_version(),
         hardware_version = ?payload.hardware_version(),
         "Device upserted successfully",
     );
 
-    state.active_sessions.insert(payload.device_id.clone(), payload.session_id.clone());
+    groups::on_inform(pool, nats, device_uuid, &payload.device_id, device.domain_id)
+        .await
+        .context("Failed to update group memberships")?;
+
+    campaigns::on_inform(pool, device_uuid, &payload)
+        .await
+        .context("Failed to update campaign progress")?;
+
+    let previous = state
+        .sessions
+        .record(&payload.device_id, &payload.session_id)
+        .await
+        .context("Failed to record session route")?;
     info!(device_id = %payload.device_id, "Session recorded in active sessions");
+    sessions::record_inform(pool, &payload.session_id, device_uuid, payload.effective_protocol())
+        .await
+        .context("Failed to record session")?;
+
+    if previous.as_deref() != Some(payload.session_id.as_str()) {
+        state.events.publish(
+            EventKind::SessionStarted,
+            &payload.device_id,
+            device.domain_id,
+            serde_json::json!({ "session_id": payload.session_id, "protocol": payload.effective_protocol() }),
+        );
+    }
+    state.events.publish(
+        EventKind::Inform,
+        &payload.device_id,
+        device.domain_id,
+        serde_json::json!({
+            "session_id":       payload.session_id,
+            "events":           payload.events,
+            "created":          device.created,
+            "software_version": payload.software_version(),
+            "hardware_version": payload.hardware_version(),
+        }),
+    );
 
-    let domain_slug = db::get_domain_slug(pool, config.default_domain_id)
+    webhooks::on_inform(pool, &payload, &device).await;
+
+    health::learn(pool, device_uuid, &health::PeriodicInform::from_values(&payload.parameter_list))
+        .await
+        .context("Failed to record periodic inform settings")?;
+    health::set(state, device_uuid, health::Health::InSession).await;
+
+    let domain_slug = db::get_domain_slug(pool, device.domain_id)
         .await
         .context("Failed to fetch domain slug for provisioning")?;
 
-    // Execute provisioning scripts
-    let actions = provisioning::run_scripts(
-        &config.provisioning_root,
-        "inform",
-        &domain_slug,
-        payload.hardware_version(),
-        payload.software_version(),
+    // Execute provisioning scripts. They may talk to the device in this
+    // session as they run.
+    let target = provisioning::Target {
+        event_type:  "inform",
+        domain_slug: &domain_slug,
+        hw_version:  payload.hardware_version(),
+        sw_version:  payload.software_version(),
+        device_id:   &payload.device_id,
+        lifecycle:   if device.created { provisioning::Lifecycle::Add } else { provisioning::Lifecycle::Update },
+    };
+    let conversation = provisioning::Conversation::new(
+        nats.clone(),
+        pool.clone(),
+        device_uuid,
         &payload.device_id,
-        raw,
-    )
-    .await
-    .context("Provisioning engine failed")?;
+        &payload.session_id,
+        std::time::Duration::from_secs(config.script_reply_timeout_secs),
+    );
+    let started_at = chrono::Utc::now();
+    let scripts = state
+        .provisioner
+        .run_scripts_traced(&target, raw, Some(&conversation))
+        .await
+        .context("Provisioning engine failed")?;
+    let actions: Vec<_> = scripts.iter().flat_map(|s| s.actions.iter().cloned()).collect();
 
-    if !actions.is_empty() {
+    let mut deliveries = Vec::with_capacity(actions.len());
+    if conversation.ended() {
+        info!(device_id = %payload.device_id, "Session ended during provisioning — nothing more to send");
+        deliveries.resize(actions.len(), Delivery::NotSent);
+    } else if !actions.is_empty() {
         info!("Publishing {} commands from provisioning scripts", actions.len());
-        for action in actions {
+        let mut published = 0;
+        for mut action in actions {
+            datamodel::prepare(pool, device_uuid, &mut action).await;
             let command = DeviceCommand {
                 command_id: uuid::Uuid::new_v4(),
                 device_id: payload.device_id.clone(),
@@ -106,11 +192,50 @@ pub async fn handle_inform(
             
             if let Err(e) = nats.publish_command(&payload.session_id, cmd_payload).await {
                 error!(?e, session_id = %payload.session_id, "Failed to publish DeviceCommand to NATS");
+                deliveries.push(Delivery::NotSent);
             } else {
                 debug!(command_id = %command.command_id, "Published command successfully");
+                deliveries.push(Delivery::Sent(command.command_id));
+                published += 1;
+            }
+        }
+        sessions::count_commands(pool, &payload.session_id, published)
+            .await
+            .context("Failed to count session commands")?;
+    }
+
+    let run = runs::NewRun {
+        device_id: device_uuid,
+        event_type: "inform",
+        lifecycle: target.lifecycle,
+        session_id: Some(&payload.session_id),
+        started_at,
+        scripts: &scripts,
+        deliveries: &deliveries,
+    };
+    // The audit log must not hold up provisioning.
+    if let Err(e) = runs::record(pool, &run).await {
+        error!(?e, device_id = %payload.device_id, "Failed to record provisioning run");
+    }
+    // The actions are out: failing now would run the scripts again.
+    match updates::apply(pool, device_uuid, &scripts).await {
+        Ok(0) => {}
+        // Dynamic groups may select on tags and properties.
+        Ok(_) => {
+            if let Err(e) = groups::on_inform(pool, nats, device_uuid, &payload.device_id, device.domain_id).await {
+                error!(?e, device_id = %payload.device_id, "Failed to update group memberships after script updates");
             }
         }
+        Err(e) => error!(?e, device_id = %payload.device_id, "Failed to apply inventory updates from scripts"),
     }
+    if conversation.ended() {
+        return Ok(());
+    }
+
+    let changes = tasks::deliver_pending(pool, nats, device_uuid, &payload.device_id, &payload.session_id)
+        .await
+        .context("Failed to deliver queued tasks")?;
+    tasks::announce(&state.events, &payload.device_id, device.domain_id, &changes);
 
     Ok(())
 }
@@ -1 +1,3 @@
+pub mod decommission;
 pub mod inform;
+pub mod session_ended;
@@ -0,0 +1,192 @@
+//! Handler for `session_ended` events received from protocol pods.
+//!
+//! A protocol pod reports `session_ended` once a device's session is over
+//! and no more commands can reach it. The controller drops the session's
+//! route, closes the session record, returns unanswered tasks to the queue,
+//! marks unanswered provisioning actions and runs `session_ended`
+//! provisioning scripts, applying the inventory updates they return. Callers still waiting on
+//! the session were told as soon as the event arrived (see
+//! `forward_signals` in `main.rs`).
+
+use anyhow::Context;
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+use crate::api::ApiState;
+use crate::events::EventKind;
+use crate::tasks::{self, NewTask, TaskStatus};
+use crate::provisioning::runs::{self, Delivery, NewRun};
+use crate::provisioning::updates;
+use crate::provisioning::{Lifecycle, Target};
+use crate::{db, groups, health, sessions};
+
+/// Payload of a `session_ended` event.
+#[derive(Debug, Deserialize)]
+struct SessionEnded {
+    session_id: String,
+    reason:     Option<String>,
+}
+
+/// Session id of a raw `session_ended` payload, if it parses.
+pub fn session_id(raw: &[u8]) -> Option<String> {
+    serde_json::from_slice::<SessionEnded>(raw).ok().map(|ended| ended.session_id)
+}
+
+/// Handle a raw `session_ended` payload for `device_uid` (taken from the
+/// subject).
+///
+/// Actions returned by `session_ended` scripts cannot reach the device in
+/// the session that just ended, so they are queued as tasks for its next one.
+pub async fn handle_session_ended(
+    device_uid: &str,
+    raw: &[u8],
+    pool: &sqlx::PgPool,
+    state: &ApiState,
+) -> anyhow::Result<()> {
+    let ended: SessionEnded = serde_json::from_slice(raw).context("Failed to deserialise session_ended payload")?;
+    let session_id = ended.session_id.as_str();
+
+    // A newer session keeps the device in_session.
+    let current = match state.sessions.end(device_uid, session_id).await {
+        Ok(true) => {
+            info!(device_uid, session_id, "Session ended, removed from active sessions");
+            true
+        }
+        Ok(false) => {
+            info!(device_uid, session_id, "Session ended, newer session kept");
+            false
+        }
+        Err(e) => {
+            error!(device_uid, ?e, "Failed to remove session route");
+            true
+        }
+    };
+
+    let summary = sessions::close(pool, session_id, ended.reason.as_deref())
+        .await
+        .context("Failed to close session record")?;
+
+    let Some((device_id, domain_id)) = db::get_device_ids(pool, device_uid)
+        .await
+        .context("Failed to look up device")?
+    else {
+        warn!(device_uid, "session_ended for unknown device");
+        return Ok(());
+    };
+
+    let requeued = tasks::requeue_unanswered(pool, device_id, session_id)
+        .await
+        .context("Failed to requeue unanswered tasks")?;
+    let changes: Vec<_> = requeued.into_iter().map(|id| (id, TaskStatus::Pending)).collect();
+    tasks::announce(&state.events, device_uid, domain_id, &changes);
+    if let Err(e) = runs::session_closed(pool, session_id).await {
+        error!(device_uid, ?e, "Failed to mark unanswered provisioning actions");
+    }
+
+    state.events.publish(
+        EventKind::SessionEnded,
+        device_uid,
+        domain_id,
+        serde_json::json!({
+            "session_id":    session_id,
+            "reason":        ended.reason,
+            "duration_secs": summary.as_ref().map(|s| s.duration_secs),
+            "commands":      summary.as_ref().map(|s| s.commands),
+            "responses":     summary.as_ref().map(|s| s.responses),
+            "faults":        summary.as_ref().map(|s| s.faults),
+        }),
+    );
+
+    if current {
+        let faults = summary.as_ref().map_or(0, |s| s.faults);
+        health::set(state, device_id, health::after_session(faults)).await;
+    }
+
+    run_scripts(pool, state, device_id, domain_id, device_uid, &ended, summary.as_ref()).await
+}
+
+async fn run_scripts(
+    pool: &sqlx::PgPool,
+    state: &ApiState,
+    device_id: uuid::Uuid,
+    domain_id: uuid::Uuid,
+    device_uid: &str,
+    ended: &SessionEnded,
+    summary: Option<&sessions::SessionSummary>,
+) -> anyhow::Result<()> {
+    let (hardware_version, software_version): (Option<String>, Option<String>) =
+        sqlx::query_as("SELECT hardware_version, software_version FROM devices WHERE id = $1")
+            .bind(device_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to fetch device versions")?;
+    let domain_slug = db::get_domain_slug(pool, domain_id)
+        .await
+        .context("Failed to fetch domain slug for provisioning")?;
+
+    let payload = serde_json::json!({
+        "event":            "session_ended",
+        "device_id":        device_uid,
+        "session_id":       ended.session_id,
+        "reason":           ended.reason,
+        "hardware_version": hardware_version,
+        "software_version": software_version,
+        "session":          summary,
+    });
+    // A JSON value always serialises.
+    let payload = serde_json::to_vec(&payload).expect("payload serialises");
+
+    let target = Target {
+        event_type:  "session_ended",
+        domain_slug: &domain_slug,
+        hw_version:  hardware_version.as_deref(),
+        sw_version:  software_version.as_deref(),
+        device_id:   device_uid,
+        lifecycle:   Lifecycle::Update,
+    };
+    // The session is over: these scripts cannot talk to the device.
+    let started_at = chrono::Utc::now();
+    let scripts = state
+        .provisioner
+        .run_scripts_traced(&target, &payload, None)
+        .await
+        .context("Provisioning engine failed")?;
+    let actions: Vec<_> = scripts.iter().flat_map(|s| s.actions.iter().cloned()).collect();
+
+    if !actions.is_empty() {
+        info!(device_uid, count = actions.len(), "Queueing actions from session_ended scripts");
+    }
+    let mut deliveries = Vec::with_capacity(actions.len());
+    for action in actions {
+        let task = NewTask { action, priority: None, expires_in_secs: None, max_attempts: None };
+        let task = tasks::enqueue(pool, device_id, &task, None)
+            .await
+            .context("Failed to queue session_ended action")?;
+        deliveries.push(Delivery::Queued(task.id));
+    }
+
+    let run = NewRun {
+        device_id,
+        event_type: "session_ended",
+        lifecycle: target.lifecycle,
+        session_id: Some(&ended.session_id),
+        started_at,
+        scripts: &scripts,
+        deliveries: &deliveries,
+    };
+    if let Err(e) = runs::record(pool, &run).await {
+        error!(device_uid, ?e, "Failed to record provisioning run");
+    }
+    // The actions are queued: failing now would queue them again.
+    match updates::apply(pool, device_id, &scripts).await {
+        Ok(0) => {}
+        // Dynamic groups may select on tags and properties.
+        Ok(_) => {
+            if let Err(e) = groups::on_inform(pool, &state.nats, device_id, device_uid, domain_id).await {
+                error!(device_uid, ?e, "Failed to update group memberships after script updates");
+            }
+        }
+        Err(e) => error!(device_uid, ?e, "Failed to apply inventory updates from scripts"),
+    }
+    Ok(())
+}
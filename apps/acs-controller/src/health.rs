@@ -0,0 +1,346 @@
+//! Device health.
+//!
+//! Each device has a health status (see `db/devices.sql`):
+//!
+//! | Status       | Meaning |
+//! |--------------|---------|
+//! | `in_session` | The device is talking to the ACS now |
+//! | `online`     | Its last session ended normally |
+//! | `faulting`   | Its last session ended with faulted responses |
+//! | `late`       | It has not called in for `late_factor` periodic inform intervals |
+//! | `offline`    | It has not called in for `offline_factor` intervals |
+//!
+//! The controller learns a device's `PeriodicInformInterval` and
+//! `PeriodicInformEnable` from its Informs and from the answers to
+//! GetParameterValues and SetParameterValues ([`learned`]). Informs and
+//! session ends move a device in and out of `in_session`;
+//! [`run_monitor`] marks silent devices `late` and `offline`. Devices whose
+//! periodic Informs are off, or whose interval is unknown without a
+//! default, are never judged late.
+//!
+//! Every change is published as a `health_changed` event; going `offline`
+//! also fires the `offline` webhook.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use nats_common::{Action, ActionResult, DeviceResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::api::ApiState;
+use crate::events::EventKind;
+use crate::webhooks::{self, WebhookEvent};
+
+/// Advisory lock held by the replica running the monitor.
+const MONITOR_LOCK: i64 = 0x6163_735f_6865_616c; // "acs_heal"
+
+// ── Types ─────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    Online,
+    InSession,
+    Late,
+    Offline,
+    Faulting,
+}
+
+impl Health {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Online    => "online",
+            Self::InSession => "in_session",
+            Self::Late      => "late",
+            Self::Offline   => "offline",
+            Self::Faulting  => "faulting",
+        }
+    }
+}
+
+/// When the monitor judges devices late and offline.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Intervals of silence after which a device is `late`.
+    pub late_factor:      f64,
+    /// Intervals of silence after which a device is `offline`.
+    pub offline_factor:   f64,
+    /// Interval assumed for devices whose own is unknown; `None` leaves
+    /// them unjudged.
+    pub default_interval: Option<u64>,
+    /// A device `in_session` this long without an Inform lost its
+    /// `session_ended`.
+    pub session_timeout:  Duration,
+}
+
+/// Periodic Inform settings seen in a set of parameter values. `None`:
+/// not among them.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PeriodicInform {
+    pub interval: Option<i32>,
+    pub enabled:  Option<bool>,
+}
+
+impl PeriodicInform {
+    /// Read `*.ManagementServer.PeriodicInform{Interval,Enable}` from
+    /// `params`.
+    pub fn from_values<'a>(params: impl IntoIterator<Item = (&'a String, &'a String)>) -> Self {
+        let mut found = Self::default();
+        for (name, value) in params {
+            let Some(param) = name.rsplit_once(".ManagementServer.").map(|(_, p)| p) else { continue };
+            match param {
+                "PeriodicInformInterval" => found.interval = value.trim().parse().ok().filter(|&i: &i32| i > 0),
+                "PeriodicInformEnable" => {
+                    found.enabled = match value.trim() {
+                        "true" | "1" => Some(true),
+                        "false" | "0" => Some(false),
+                        _ => None,
+                    }
+                }
+                _ => {}
+            }
+        }
+        found
+    }
+
+    fn is_empty(&self) -> bool {
+        self.interval.is_none() && self.enabled.is_none()
+    }
+}
+
+/// What the answer to `action` tells about the device's periodic Informs.
+pub fn learned(action: &Action, result: &ActionResult) -> PeriodicInform {
+    match (action, result) {
+        (Action::GetParameterValues { .. }, ActionResult::Success(values)) => PeriodicInform::from_values(values),
+        (Action::SetParameterValues { parameters, .. }, ActionResult::Success(_) | ActionResult::Done) => {
+            PeriodicInform::from_values(parameters)
+        }
+        _ => PeriodicInform::default(),
+    }
+}
+
+// ── Learning ──────────────────────────────────────────────────────────────────
+
+/// Record the periodic Inform settings found in `params` of `device_id`.
+pub async fn learn(pool: &PgPool, device_id: Uuid, found: &PeriodicInform) -> Result<(), sqlx::Error> {
+    if found.is_empty() {
+        return Ok(());
+    }
+    sqlx::query(
+        r#"
+        UPDATE devices
+        SET periodic_inform_interval = COALESCE($2, periodic_inform_interval),
+            periodic_inform_enabled  = COALESCE($3, periodic_inform_enabled)
+        WHERE id = $1
+        "#,
+    )
+    .bind(device_id)
+    .bind(found.interval)
+    .bind(found.enabled)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Learn from a device's answer to `action`. Failures are logged.
+pub async fn learn_from_answer(pool: &PgPool, device_id: Uuid, action: &Action, result: &ActionResult) {
+    if let Err(e) = learn(pool, device_id, &learned(action, result)).await {
+        error!(?e, %device_id, "Failed to record periodic inform settings");
+    }
+}
+
+/// Learn from the answer to a queued task or a provisioning action, found
+/// by its command id. Interactive commands learn where they are answered.
+pub async fn on_response(pool: &PgPool, response: &DeviceResponse) -> Result<(), sqlx::Error> {
+    let Some(command_id) = response.operation_id else {
+        return Ok(());
+    };
+    if matches!(response.result, ActionResult::Fault { .. }) {
+        return Ok(());
+    }
+    let row: Option<(Uuid, sqlx::types::Json<Action>)> = sqlx::query_as(
+        r#"
+        SELECT device_id, action FROM tasks WHERE command_id = $1
+        UNION ALL
+        SELECT r.device_id, a.action FROM provisioning_run_actions a
+        JOIN provisioning_runs r ON r.id = a.run_id
+        WHERE a.command_id = $1
+        LIMIT 1
+        "#,
+    )
+    .bind(command_id)
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some((device_id, action)) = row {
+        learn(pool, device_id, &learned(&action, &response.result)).await?;
+    }
+    Ok(())
+}
+
+// ── Transitions ───────────────────────────────────────────────────────────────
+
+/// Move `device_id` to `health`, announcing the change if it is one.
+/// Failures are logged: health must not hold up event handling.
+pub async fn set(state: &ApiState, device_id: Uuid, health: Health) {
+    let result = sqlx::query_as::<_, Change>(
+        r#"
+        UPDATE devices d
+        SET health = $2, health_changed_at = now()
+        FROM (SELECT id, health FROM devices WHERE id = $1 FOR UPDATE) prev
+        WHERE d.id = prev.id AND prev.health <> $2
+        RETURNING d.device_uid, d.domain_id, prev.health AS "from", d.health AS "to",
+                  d.periodic_inform_interval AS interval, d.last_seen
+        "#,
+    )
+    .bind(device_id)
+    .bind(health.as_str())
+    .fetch_optional(&state.pool)
+    .await;
+
+    match result {
+        Ok(Some(change)) => publish(state, &change).await,
+        Ok(None) => {}
+        Err(e) => error!(?e, %device_id, health = health.as_str(), "Failed to update device health"),
+    }
+}
+
+/// The health a device has once its session ends.
+pub fn after_session(faults: i32) -> Health {
+    if faults > 0 {
+        Health::Faulting
+    } else {
+        Health::Online
+    }
+}
+
+/// A change of health, as announced.
+#[derive(sqlx::FromRow)]
+struct Change {
+    device_uid: String,
+    domain_id:  Uuid,
+    from:       String,
+    to:         String,
+    interval:   Option<i32>,
+    last_seen:  DateTime<Utc>,
+}
+
+async fn publish(state: &ApiState, change: &Change) {
+    let data = serde_json::json!({
+        "from":                     change.from,
+        "to":                       change.to,
+        "last_seen":                change.last_seen,
+        "periodic_inform_interval": change.interval,
+    });
+    info!(device_uid = %change.device_uid, from = %change.from, to = %change.to, "Device health changed");
+    if change.to == Health::Offline.as_str() {
+        webhooks::emit(&state.pool, change.domain_id, WebhookEvent::Offline, &change.device_uid, data.clone()).await;
+    }
+    state.events.publish(EventKind::HealthChanged, &change.device_uid, change.domain_id, data);
+}
+
+// ── Monitor ───────────────────────────────────────────────────────────────────
+
+/// Mark silent devices `late` and `offline`, every `tick`. Only one replica
+/// runs it at a time.
+pub async fn run_monitor(state: ApiState, settings: Settings, tick: Duration) {
+    let mut interval = tokio::time::interval(tick);
+    loop {
+        interval.tick().await;
+        let tick = run_tick(&state, &settings);
+        if let Some(Err(e)) = crate::db::with_tick_lock(&state.pool, MONITOR_LOCK, "Health monitor", tick).await {
+            error!(?e, "Health monitor: tick failed");
+        }
+    }
+}
+
+async fn run_tick(state: &ApiState, settings: &Settings) -> Result<(), sqlx::Error> {
+    // `expected` is the interval a device is judged by. A device still
+    // `in_session` is only judged once its session has gone quiet for
+    // `session_timeout`; it is `online` if not overdue. The last condition
+    // skips devices that called in since they were judged.
+    let changes = sqlx::query_as::<_, Change>(
+        r#"
+        UPDATE devices d
+        SET health = j.judged, health_changed_at = now()
+        FROM (
+            SELECT id, health AS was, last_seen, periodic_inform_interval,
+                   CASE
+                       WHEN expected IS NOT NULL AND last_seen < now() - make_interval(secs => expected * $2) THEN 'offline'
+                       WHEN expected IS NOT NULL AND last_seen < now() - make_interval(secs => expected * $1) THEN 'late'
+                       WHEN health = 'in_session' THEN 'online'
+                       ELSE health
+                   END AS judged
+            FROM (
+                SELECT id, health, last_seen, periodic_inform_interval,
+                       CASE WHEN periodic_inform_enabled IS FALSE THEN NULL
+                            ELSE COALESCE(periodic_inform_interval::float8, $3::float8)
+                       END AS expected
+                FROM devices
+                WHERE health <> 'offline'
+                  AND (health <> 'in_session' OR last_seen < now() - make_interval(secs => $4))
+            ) c
+        ) j
+        WHERE d.id = j.id AND j.judged <> j.was AND d.health = j.was AND d.last_seen = j.last_seen
+        RETURNING d.device_uid, d.domain_id, j.was AS "from", j.judged AS "to",
+                  j.periodic_inform_interval AS interval, j.last_seen
+        "#,
+    )
+    .bind(settings.late_factor)
+    .bind(settings.offline_factor)
+    .bind(settings.default_interval.map(|i| i as f64))
+    .bind(settings.session_timeout.as_secs_f64())
+    .fetch_all(&state.pool)
+    .await?;
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for change in &changes {
+        *counts.entry(change.to.as_str()).or_default() += 1;
+        publish(state, change).await;
+    }
+    if !changes.is_empty() {
+        info!(?counts, "Health monitor: devices re-judged");
+    }
+    Ok(())
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn learns_periodic_inform_settings_from_answers() {
+        let gpv = Action::GetParameterValues { paths: vec!["Device.ManagementServer.".into()] };
+        let answer = ActionResult::Success(values(&[
+            ("Device.ManagementServer.PeriodicInformInterval", "300"),
+            ("Device.ManagementServer.PeriodicInformEnable", "true"),
+            ("Device.ManagementServer.URL", "http://acs"),
+        ]));
+        assert_eq!(learned(&gpv, &answer), PeriodicInform { interval: Some(300), enabled: Some(true) });
+
+        let spv = Action::SetParameterValues {
+            parameters: values(&[("InternetGatewayDevice.ManagementServer.PeriodicInformEnable", "0")]),
+            types:      HashMap::new(),
+        };
+        assert_eq!(learned(&spv, &ActionResult::Done), PeriodicInform { interval: None, enabled: Some(false) });
+        let fault = ActionResult::Fault { code: "9007".into(), string: "Invalid parameter value".into() };
+        assert_eq!(learned(&spv, &fault), PeriodicInform::default());
+
+        // GetParameterNames answers writability, not values.
+        let gpn = Action::GetParameterNames { path_prefix: "Device.ManagementServer.".into(), next_level: false };
+        assert_eq!(learned(&gpn, &answer), PeriodicInform::default());
+
+        let bad = values(&[("Device.ManagementServer.PeriodicInformInterval", "0")]);
+        assert_eq!(PeriodicInform::from_values(&bad), PeriodicInform::default());
+    }
+}
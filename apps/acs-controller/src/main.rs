@@ -2,7 +2,13 @@
 //!
 //! Connects to NATS and PostgreSQL, then runs the event loop that dispatches
 //! device events from all protocol pods to the appropriate handler.
+//!
+//! Any number of replicas can run side by side: device events are shared
+//! out through a JetStream consumer, session routing lives in NATS KV, and
+//! command responses and streamed events are relayed between replicas over
+//! NATS.
 
+use anyhow::Context;
 use clap::Parser;
 use sqlx::postgres::PgPoolOptions;
 use tokio_stream::StreamExt;
@@ -11,10 +17,26 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use uuid::Uuid;
 
 mod api;
+mod auth;
+mod backups;
+mod campaigns;
+mod datamodel;
 mod db;
+mod dispatch;
+mod events;
+mod firmware;
+mod groups;
 mod handlers;
+mod health;
 mod nats;
+mod onboarding;
+mod partitions;
 mod provisioning;
+mod refresh;
+mod search;
+mod sessions;
+mod tasks;
+mod webhooks;
 
 // ── Configuration ─────────────────────────────────────────────────────────────
 
@@ -30,7 +52,8 @@ pub struct Config {
     #[arg(long, env = "DATABASE_URL")]
     pub database_url: String,
 
-    /// UUID of the domain to assign newly-seen devices to.
+    /// UUID of the domain to assign newly-seen devices to when no onboarding
+    /// rule (`domain_assignment_rules`) matches.
     ///
     /// Bootstrap procedure:
     ///   1. Apply `db/domains.sql` to your Postgres instance.
@@ -43,13 +66,166 @@ pub struct Config {
     /// Maximum number of PostgreSQL connections to keep open.
     #[arg(long, env = "DB_MAX_CONNECTIONS", default_value_t = 5)]
     pub db_max_connections: u32,
-    /// Directory containing python provisioning scripts.
+    /// Directory containing provisioning scripts.
     #[arg(long, env = "PROVISIONING_ROOT", default_value = "./provisioning")]
     pub provisioning_root: std::path::PathBuf,
 
+    /// Also run Python (`.py`) provisioning scripts, as `python3` child
+    /// processes, where no Rhai script of the same name exists.
+    #[arg(long, env = "PROVISIONING_PYTHON", default_value_t = false)]
+    pub provisioning_python: bool,
+
+    /// Rhai operations a provisioning script may perform per run.
+    #[arg(long, env = "SCRIPT_MAX_OPERATIONS", default_value_t = 1_000_000)]
+    pub script_max_operations: u64,
+
+    /// Wall-clock time a provisioning script may take per run.
+    #[arg(long, env = "SCRIPT_TIMEOUT_MS", default_value_t = 2000)]
+    pub script_timeout_ms: u64,
+
+    /// Longest string a Rhai script may build, in bytes.
+    #[arg(long, env = "SCRIPT_MAX_STRING_SIZE", default_value_t = 64 * 1024)]
+    pub script_max_string_size: usize,
+
+    /// Most elements a Rhai script may put in one array or map.
+    #[arg(long, env = "SCRIPT_MAX_COLLECTION_SIZE", default_value_t = 10_000)]
+    pub script_max_collection_size: usize,
+
+    /// Time a provisioning script's `send()` waits for the device to
+    /// answer. Waiting also counts towards `EVENT_TIMEOUT_SECS`.
+    #[arg(long, env = "SCRIPT_REPLY_TIMEOUT_SECS", default_value_t = 30)]
+    pub script_reply_timeout_secs: u64,
+
     /// HTTP API Port.
     #[arg(long, env = "API_PORT", default_value_t = 8080)]
     pub api_port: u16,
+
+    /// Secret used to sign API session tokens (HMAC-SHA256).
+    /// At least 32 bytes ([`auth::MIN_SECRET_LEN`]); rotating it logs every
+    /// user out.
+    #[arg(long, env = "AUTH_SECRET", hide_env_values = true)]
+    pub auth_secret: String,
+
+    /// Lifetime of an API session token, in seconds.
+    #[arg(long, env = "SESSION_TTL_SECS", default_value_t = 8 * 3600)]
+    pub session_ttl_secs: i64,
+
+    /// Comma-separated origins allowed to call the API from a browser,
+    /// e.g. `http://localhost:5173`. Empty disables CORS (same-origin only).
+    #[arg(long, env = "CORS_ALLOWED_ORIGINS", value_delimiter = ',')]
+    pub cors_allowed_origins: Vec<String>,
+
+    /// How often the firmware campaign scheduler advances running campaigns.
+    #[arg(long, env = "CAMPAIGN_TICK_SECS", default_value_t = 30)]
+    pub campaign_tick_secs: u64,
+
+    /// Number of recent events kept so event stream clients can resume.
+    #[arg(long, env = "EVENT_BUFFER", default_value_t = 4096)]
+    pub event_buffer: usize,
+
+    /// Interval between heartbeats on idle event streams.
+    #[arg(long, env = "EVENT_HEARTBEAT_SECS", default_value_t = 15)]
+    pub event_heartbeat_secs: u64,
+
+    /// Name of this replica, recorded with the sessions it routes.
+    /// Defaults to a random UUID per process.
+    #[arg(long, env = "INSTANCE_ID")]
+    pub instance_id: Option<String>,
+
+    /// How long a device's session route survives without an Inform, in
+    /// case its `session_ended` event is lost.
+    #[arg(long, env = "SESSION_ROUTE_TTL_SECS", default_value_t = 600)]
+    pub session_route_ttl_secs: u64,
+
+    /// How long a replica may take to handle a device event before it is
+    /// redelivered to another replica.
+    #[arg(long, env = "EVENT_ACK_WAIT_SECS", default_value_t = 120)]
+    pub event_ack_wait_secs: u64,
+
+    /// Partitions device events are split into. Each is consumed by one
+    /// replica at a time; must be the same on every replica.
+    #[arg(long, env = "EVENT_PARTITIONS", default_value_t = 16)]
+    pub event_partitions: u32,
+
+    /// How long a replica's hold on an event partition survives without
+    /// being renewed, e.g. after the replica died.
+    #[arg(long, env = "EVENT_PARTITION_LEASE_SECS", default_value_t = 30)]
+    pub event_partition_lease_secs: u64,
+
+    /// Device events handled at once. Events of one device are always
+    /// handled one at a time, in order.
+    #[arg(long, env = "EVENT_WORKERS", default_value_t = 32)]
+    pub event_workers: usize,
+
+    /// Device events queued or being handled before the controller stops
+    /// pulling more from NATS.
+    #[arg(long, env = "EVENT_QUEUE_CAPACITY", default_value_t = 1024)]
+    pub event_queue_capacity: usize,
+
+    /// Time allowed to handle one device event, provisioning scripts
+    /// included. Must be below `EVENT_ACK_WAIT_SECS`.
+    #[arg(long, env = "EVENT_TIMEOUT_SECS", default_value_t = 60)]
+    pub event_timeout_secs: u64,
+
+    /// Directory uploaded firmware images are kept in. Replicas must share it.
+    #[arg(long, env = "FIRMWARE_DIR", default_value = "./firmware")]
+    pub firmware_dir: std::path::PathBuf,
+
+    /// Base URL devices reach this API at for uploaded firmware images, e.g.
+    /// `http://acs.example.net:8080`. Unset disables uploads; images must
+    /// then be registered with their own URL.
+    #[arg(long, env = "FIRMWARE_BASE_URL")]
+    pub firmware_base_url: Option<String>,
+
+    /// Largest firmware image upload, in MiB.
+    #[arg(long, env = "FIRMWARE_MAX_UPLOAD_MB", default_value_t = 512)]
+    pub firmware_max_upload_mb: u64,
+
+    /// How often the backup scheduler fails stalled backups, starts the
+    /// scheduled ones that are due and applies retention.
+    #[arg(long, env = "BACKUP_TICK_SECS", default_value_t = 300)]
+    pub backup_tick_secs: u64,
+
+    /// Base URL devices reach this API at to upload and download backup
+    /// files, e.g. `http://acs.example.net:8080`. Unset disables upload
+    /// backups.
+    #[arg(long, env = "BACKUP_BASE_URL")]
+    pub backup_base_url: Option<String>,
+
+    /// Largest backup file a device may upload, in MiB.
+    #[arg(long, env = "BACKUP_MAX_FILE_MB", default_value_t = 16)]
+    pub backup_max_file_mb: usize,
+
+    /// How often each replica sends the webhook deliveries that are due.
+    #[arg(long, env = "WEBHOOK_TICK_SECS", default_value_t = 5)]
+    pub webhook_tick_secs: u64,
+
+    /// Time a webhook endpoint has to answer one delivery.
+    #[arg(long, env = "WEBHOOK_TIMEOUT_SECS", default_value_t = 10)]
+    pub webhook_timeout_secs: u64,
+
+    /// Days delivered and dead-lettered webhook deliveries are kept.
+    #[arg(long, env = "WEBHOOK_HISTORY_DAYS", default_value_t = 30)]
+    pub webhook_history_days: i64,
+
+    /// How often the health monitor marks silent devices late or offline.
+    #[arg(long, env = "HEALTH_TICK_SECS", default_value_t = 60)]
+    pub health_tick_secs: u64,
+
+    /// Periodic inform intervals without an Inform after which a device is
+    /// `late`.
+    #[arg(long, env = "HEALTH_LATE_FACTOR", default_value_t = 1.5)]
+    pub health_late_factor: f64,
+
+    /// Periodic inform intervals without an Inform after which a device is
+    /// `offline`. Above `HEALTH_LATE_FACTOR`.
+    #[arg(long, env = "HEALTH_OFFLINE_FACTOR", default_value_t = 3.0)]
+    pub health_offline_factor: f64,
+
+    /// Periodic inform interval assumed for devices whose own is not known
+    /// yet. Unset: such devices are never judged late or offline.
+    #[arg(long, env = "HEALTH_DEFAULT_INTERVAL_SECS")]
+    pub health_default_interval_secs: Option<u64>,
 }
 
 // ── Entrypoint ────────────────────────────────────────────────────────────────
@@ -64,6 +240,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init();
 
     let config = Config::parse();
+    if config.auth_secret.len() < auth::MIN_SECRET_LEN {
+        return Err(format!("AUTH_SECRET must be at least {} bytes", auth::MIN_SECRET_LEN).into());
+    }
 
     // ── NATS ──────────────────────────────────────────────────────────────────
     info!(nats_url = %config.nats_url, "Connecting to NATS");
@@ -82,92 +261,338 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         "acs-controller ready — starting services",
     );
 
-    let state = api::ApiState::new(pool.clone(), nats.clone());
+    let instance_id = config.instance_id.clone().unwrap_or_else(|| Uuid::new_v4().to_string());
+    info!(instance_id, "Joining controller replicas");
+
+    let signer = auth::TokenSigner::new(config.auth_secret.as_bytes(), config.session_ttl_secs);
+    let events = events::EventHub::shared(
+        config.event_buffer,
+        std::time::Duration::from_secs(config.event_heartbeat_secs),
+        nats.clone(),
+    )
+    .await?;
+    let session_store = nats
+        .session_store(std::time::Duration::from_secs(config.session_route_ttl_secs))
+        .await?;
+    let sessions = sessions::SessionRegistry::new(session_store, instance_id.clone());
+    let provisioner = provisioning::Provisioner::new(
+        config.provisioning_root.clone(),
+        Some(pool.clone()),
+        provisioning::Limits {
+            max_operations:  config.script_max_operations,
+            timeout:         std::time::Duration::from_millis(config.script_timeout_ms),
+            max_string_size: config.script_max_string_size,
+            max_collection:  config.script_max_collection_size,
+        },
+        config.provisioning_python,
+    );
+    let rules = provisioner.load_rules().context("Invalid provisioning rules")?;
+    info!(rules, "Provisioning rules loaded");
+    let state = api::ApiState::new(
+        pool.clone(),
+        nats.clone(),
+        signer,
+        events,
+        sessions,
+        provisioner,
+        config.default_domain_id,
+    )
+    .with_firmware(firmware::Storage {
+        dir:      config.firmware_dir.clone(),
+        base_url: config.firmware_base_url.clone(),
+        max_size: config.firmware_max_upload_mb * 1024 * 1024,
+    })
+    .with_backups(backups::Settings {
+        base_url:      config.backup_base_url.clone(),
+        max_file_size: config.backup_max_file_mb * 1024 * 1024,
+    });
+    tasks::relay_updates(&nats, state.task_updates.clone()).await?;
 
     // Start HTTP API
     let api_state = state.clone();
     let api_port = config.api_port;
+    let cors_origins = config.cors_allowed_origins.clone();
     tokio::spawn(async move {
-        if let Err(e) = api::start_server(api_state, api_port).await {
+        if let Err(e) = api::start_server(api_state, api_port, cors_origins).await {
             error!(?e, "HTTP API server failed");
         }
     });
 
-    event_loop(nats, pool, config, state).await;
+    // Drive firmware campaigns. All campaign state is in the database, so
+    // running campaigns resume after a restart; only one replica advances
+    // them at a time.
+    tokio::spawn(campaigns::run_scheduler(
+        state.clone(),
+        std::time::Duration::from_secs(config.campaign_tick_secs),
+    ));
+
+    // Take scheduled backups and apply retention; like campaigns, one
+    // replica at a time.
+    tokio::spawn(backups::run_scheduler(
+        state.clone(),
+        std::time::Duration::from_secs(config.backup_tick_secs),
+    ));
+
+    // Send webhook deliveries. Every replica sends; each claims its own.
+    tokio::spawn(webhooks::run_dispatcher(
+        pool.clone(),
+        webhooks::Settings {
+            timeout:      std::time::Duration::from_secs(config.webhook_timeout_secs),
+            history_days: config.webhook_history_days,
+        },
+        std::time::Duration::from_secs(config.webhook_tick_secs),
+    ));
+
+    // Mark devices that stopped calling in late or offline; one replica at
+    // a time. A device whose session_ended was lost leaves in_session once
+    // its session route would have expired.
+    tokio::spawn(health::run_monitor(
+        state.clone(),
+        health::Settings {
+            late_factor:      config.health_late_factor,
+            offline_factor:   config.health_offline_factor,
+            default_interval: config.health_default_interval_secs,
+            session_timeout:  std::time::Duration::from_secs(config.session_route_ttl_secs),
+        },
+        std::time::Duration::from_secs(config.health_tick_secs),
+    ));
+
+    event_loop(nats, pool, config, state, instance_id).await;
 
     Ok(())
 }
 
 // ── Event loop ────────────────────────────────────────────────────────────────
 
-/// Receive and dispatch all device events from all protocol pods.
+/// Receive the device events of this replica's partitions (see
+/// [`partitions`]) and hand them to the [`dispatch::Dispatcher`], which
+/// handles them concurrently across devices and in order per device.
 ///
-/// Loops forever until the NATS connection drops. Event type is derived from
+/// Runs until the event stream cannot be opened. Event type is derived from
 /// the last token of the NATS subject so no separate metadata field is needed.
-async fn event_loop(nats: nats::NatsClient, pool: sqlx::PgPool, config: Config, state: api::ApiState) {
-    let mut subscriber = match nats.subscribe_events().await {
+///
+/// Each event is acknowledged once handled, whether or not the handler
+/// succeeded; only events whose replica dies mid-handling are redelivered.
+async fn event_loop(nats: nats::NatsClient, pool: sqlx::PgPool, config: Config, state: api::ApiState, instance_id: String) {
+    let settings = partitions::Settings {
+        count:       config.event_partitions.max(1),
+        lease:       std::time::Duration::from_secs(config.event_partition_lease_secs.max(3)),
+        ack_wait:    std::time::Duration::from_secs(config.event_ack_wait_secs),
+        max_pending: config.event_queue_capacity,
+    };
+    let dispatch_config = dispatch::DispatchConfig {
+        workers:  config.event_workers,
+        capacity: config.event_queue_capacity,
+        timeout:  std::time::Duration::from_secs(config.event_timeout_secs),
+    };
+    let metrics = state.event_metrics.clone();
+    let controller = Controller { nats: nats.clone(), pool, config, state };
+    let dispatcher = std::sync::Arc::new(dispatch::Dispatcher::new(controller, dispatch_config, metrics));
+
+    let consume = {
+        let nats = nats.clone();
+        move |consumer| consume_partition(consumer, nats.clone(), dispatcher.clone())
+    };
+    if let Err(e) = partitions::run(nats, settings, instance_id, consume).await {
+        error!(?e, "Failed to consume acs.events.> — cannot start event loop");
+    }
+}
+
+/// Pull the events of one partition into the dispatcher until the consumer
+/// ends or the partition is taken away.
+async fn consume_partition(
+    consumer: async_nats::jetstream::consumer::PullConsumer,
+    nats: nats::NatsClient,
+    dispatcher: std::sync::Arc<dispatch::Dispatcher<async_nats::jetstream::Message, Controller>>,
+) {
+    let consumer_name = consumer.cached_info().name.clone();
+    let mut messages = match consumer.messages().await {
         Ok(s) => s,
         Err(e) => {
-            error!(?e, "Failed to subscribe to acs.events.> — cannot start event loop");
+            error!(consumer_name, ?e, "Failed to pull device events");
             return;
         }
     };
+    while let Some(msg) = messages.next().await {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) => {
+                warn!(consumer_name, ?e, "Failed to pull device event");
+                continue;
+            }
+        };
+        forward_signals(&msg, &nats).await;
+        let key = device_key(&msg.subject).to_string();
+        dispatcher.dispatch(key, msg).await;
+    }
+    // The stream only ends if the NATS server closed the connection.
+    error!(consumer_name, "Device event consumer ended");
+}
 
-    while let Some(msg) = subscriber.next().await {
-        let subject = msg.subject.as_str();
+/// Pass on what waiting callers need from an event as soon as it is pulled,
+/// ahead of the device's queued events: a command response to whoever sent
+/// the command, a session end to whoever waits on the session.
+///
+/// A provisioning script talking to its device holds up the device's events
+/// until it is done, so the response it waits for cannot wait its turn.
+async fn forward_signals(msg: &async_nats::jetstream::Message, nats: &nats::NatsClient) {
+    let subject = msg.subject.as_str();
+    match subject.rsplit('.').next() {
+        Some("command_response") => {
+            let Ok(response) = serde_json::from_slice::<nats_common::DeviceResponse>(&msg.payload) else {
+                return; // Reported when the event is handled.
+            };
+            if let Some(op_id) = response.operation_id {
+                if let Err(e) = nats.publish_command_reply(op_id, msg.payload.clone()).await {
+                    error!(subject, %op_id, ?e, "Failed to forward command reply");
+                }
+            }
+        }
+        Some("session_ended") => {
+            let Some(session_id) = handlers::session_ended::session_id(&msg.payload) else {
+                return;
+            };
+            if let Err(e) = nats.publish_session_ended(&session_id).await {
+                error!(session_id, ?e, "Failed to notify waiters of session end");
+            }
+        }
+        _ => {}
+    }
+}
 
-        // Derive the event type from the last dot-separated segment.
-        // "acs.events.AABBCC.1234567.inform" → "inform"
-        let event_type = subject.rsplit('.').next().unwrap_or("unknown");
+/// `{oui}.{serial}` of an `acs.events.{partition}.{oui}.{serial}.{event_type}`
+/// subject, or the whole subject if it has another shape.
+fn device_key(subject: &str) -> &str {
+    subject
+        .strip_prefix("acs.events.")
+        .and_then(|rest| rest.split_once('.'))
+        .and_then(|(_, rest)| rest.rsplit_once('.'))
+        .map_or(subject, |(device, _)| device)
+}
+
+/// Everything event handling needs.
+struct Controller {
+    nats:   nats::NatsClient,
+    pool:   sqlx::PgPool,
+    config: Config,
+    state:  api::ApiState,
+}
+
+impl dispatch::EventHandler<async_nats::jetstream::Message> for Controller {
+    async fn handle(&self, msg: &async_nats::jetstream::Message) -> anyhow::Result<()> {
+        // Restart the ack timer: the event may have been queued a while.
+        if let Err(e) = msg.ack_with(async_nats::jetstream::AckKind::Progress).await {
+            warn!(subject = %msg.subject, ?e, "Failed to extend device event deadline");
+        }
+        handle_event(msg, &self.nats, &self.pool, &self.config, &self.state).await
+    }
 
-        match event_type {
-            "inform" => {
-                if let Err(e) =
-                    handlers::inform::handle_inform(&msg.payload, &pool, &nats, &config, &state).await
-                {
-                    error!(subject, ?e, "inform handler failed");
+    async fn finish(&self, msg: async_nats::jetstream::Message) {
+        if let Err(e) = msg.ack().await {
+            warn!(subject = %msg.subject, ?e, "Failed to acknowledge device event");
+        }
+    }
+}
+
+async fn handle_event(
+    msg: &async_nats::Message,
+    nats: &nats::NatsClient,
+    pool: &sqlx::PgPool,
+    config: &Config,
+    state: &api::ApiState,
+) -> anyhow::Result<()> {
+    let subject = msg.subject.as_str();
+
+    // Derive the event type from the last dot-separated segment.
+    // "acs.events.5.AABBCC.1234567.inform" → "inform"
+    let event_type = subject.rsplit('.').next().unwrap_or("unknown");
+
+    match event_type {
+        "inform" => {
+            handlers::inform::handle_inform(&msg.payload, pool, nats, config, state)
+                .await
+                .context("inform handler failed")?;
+        }
+
+        "command_response" => {
+            let payload = serde_json::from_slice::<nats_common::DeviceResponse>(&msg.payload)
+                .context("Failed to deserialize DeviceResponse")?;
+
+            let domain_id = device_domain(pool, &payload.device_id).await;
+            if let Some(domain_id) = domain_id {
+                state.events.publish(
+                    events::EventKind::CommandResponse,
+                    &payload.device_id,
+                    domain_id,
+                    serde_json::json!({ "operation_id": payload.operation_id, "result": payload.result }),
+                );
+                if let nats_common::ActionResult::Fault { code, string } = &payload.result {
+                    let data = serde_json::json!({ "operation_id": payload.operation_id, "code": code, "string": string });
+                    webhooks::emit(pool, domain_id, webhooks::WebhookEvent::Fault, &payload.device_id, data).await;
                 }
             }
 
-            "command_response" => {
-                let payload = match serde_json::from_slice::<nats_common::DeviceResponse>(&msg.payload) {
-                    Ok(p) => p,
-                    Err(e) => {
-                        error!(?e, "Failed to deserialize DeviceResponse");
-                        continue;
-                    }
-                };
+            let fault = matches!(payload.result, nats_common::ActionResult::Fault { .. });
+            if let Err(e) = sessions::count_response(pool, &payload.device_id, fault).await {
+                error!(subject, ?e, "Failed to count session response");
+            }
 
-                if let Some(op_id) = payload.operation_id {
-                    if let Some((_, sender)) = state.pending_commands.remove(&op_id) {
-                        let _ = sender.send(payload);
+            if let Some(op_id) = payload.operation_id {
+                match tasks::record_response(pool, &payload).await {
+                    Ok(Some(update)) => {
+                        if let Some(domain_id) = domain_id {
+                            tasks::announce(&state.events, &payload.device_id, domain_id, &[(update.task_id, update.status)]);
+                        }
+                        if let Err(e) = backups::on_task_update(state, &update).await {
+                            error!(subject, %op_id, ?e, "Failed to advance backup");
+                        }
+                        if let Err(e) = refresh::on_task_update(state, &update).await {
+                            error!(subject, %op_id, ?e, "Failed to advance parameter refresh");
+                        }
+                        tasks::share_update(nats, &state.task_updates, update).await;
                     }
-                } else {
-                    info!(subject, ?payload, "command_response received without operation_id");
+                    Ok(None) => {}
+                    Err(e) => error!(subject, %op_id, ?e, "Failed to record task response"),
                 }
-            }
-
-            "session_ended" => {
-                // Determine device UID from the subject.
-                // Subject is acs.events.{oui}.{serial}.session_ended
-                let parts: Vec<&str> = subject.split('.').collect();
-                if parts.len() >= 5 {
-                    let oui = parts[2];
-                    let serial = parts[3];
-                    let device_uid = format!("{}-{}", oui, serial);
-                    state.active_sessions.remove(&device_uid);
-                    info!(device_uid, "Session ended, removed from active sessions");
+                if let Err(e) = provisioning::runs::record_response(pool, &payload).await {
+                    error!(subject, %op_id, ?e, "Failed to record provisioning action response");
+                }
+                if let Err(e) = health::on_response(pool, &payload).await {
+                    error!(subject, %op_id, ?e, "Failed to record periodic inform settings");
                 }
-                
-                // Future: update last_seen in devices.
+                // Waiters were handed the response by forward_signals.
+            } else {
+                info!(subject, ?payload, "command_response received without operation_id");
             }
+        }
 
-            other => {
-                warn!(subject, event_type = other, "Unknown event type — ignoring");
+        "session_ended" => {
+            // Determine device UID from the subject.
+            // Subject is acs.events.{partition}.{oui}.{serial}.session_ended
+            let parts: Vec<&str> = subject.split('.').collect();
+            if parts.len() >= 6 {
+                let device_uid = format!("{}-{}", parts[3], parts[4]);
+                handlers::session_ended::handle_session_ended(&device_uid, &msg.payload, pool, state)
+                    .await
+                    .context("session_ended handler failed")?;
             }
         }
+
+        other => {
+            warn!(subject, event_type = other, "Unknown event type — ignoring");
+        }
     }
+    Ok(())
+}
 
-    // The subscriber only ends if the NATS server closed the connection.
-    error!("NATS event subscriber ended — acs-controller shutting down");
+/// Domain of a device, for tagging streamed events. Unknown devices and
+/// lookup failures yield `None` and the event is not streamed.
+async fn device_domain(pool: &sqlx::PgPool, device_uid: &str) -> Option<Uuid> {
+    match db::get_device_ids(pool, device_uid).await {
+        Ok(ids) => ids.map(|(_, domain_id)| domain_id),
+        Err(e) => {
+            error!(device_uid, ?e, "Failed to look up device domain");
+            None
+        }
+    }
 }
@@ -2,15 +2,42 @@
 //!
 //! ## Subject layout (controller perspective)
 //!
-//! Protocol pods → Controller (subscribed here):
+//! Protocol pods → Controller (JetStream stream `ACS_EVENTS`, stored as
+//! `acs.events.{partition}.…` and consumed one partition per replica):
 //!   `acs.events.{oui}.{serial}.inform`
 //!   `acs.events.{oui}.{serial}.command_response`
 //!   `acs.events.{oui}.{serial}.session_ended`
 //!
-//! Controller → Protocol pods (published elsewhere, not yet implemented):
+//! Controller → Protocol pods:
 //!   `acs.sessions.{session_id}.command`
+//!
+//! Controller → any interested component:
+//!   `acs.groups.{group_id}.membership`
+//!
+//! Controller replica → controller replicas:
+//!   `acs.controller.replies.{command_id}`  response to a waiting API call
+//!   `acs.controller.sessions.{session_id}.ended`  session gone; stop waiting
+//!   `acs.controller.stream`                events for `/api/v1/events`
+//!   `acs.controller.task_updates`          task responses for `/api/v1/commands`
 
+use std::time::Duration;
+
+use async_nats::jetstream::{self, consumer::pull, kv};
 use async_nats::{Client, Subscriber};
+use uuid::Uuid;
+
+/// JetStream stream capturing every `acs.events.>` message.
+const EVENTS_STREAM: &str = "ACS_EVENTS";
+/// Durable consumer once shared by all controller replicas, replaced by one
+/// consumer per partition; removed on startup.
+const SHARED_EVENTS_CONSUMER: &str = "acs-controller";
+/// KV bucket of partition leases and live replicas (see [`crate::partitions`]).
+const PARTITIONS_BUCKET: &str = "acs_event_partitions";
+/// KV bucket mapping `device_uid` to its open session.
+const SESSIONS_BUCKET: &str = "acs_sessions";
+
+pub const STREAM_SUBJECT: &str = "acs.controller.stream";
+pub const TASK_UPDATES_SUBJECT: &str = "acs.controller.task_updates";
 
 /// Thin wrapper around the async-nats client for the controller.
 #[derive(Clone)]
@@ -23,19 +50,114 @@ impl NatsClient {
         Self { inner }
     }
 
-    /// Subscribe to **all** device events from every protocol pod.
-    ///
-    /// Uses the NATS `>` wildcard which matches any number of subject tokens,
-    /// so this single subscription receives every event regardless of device
-    /// OUI, serial, or event type.
+    /// Open the `ACS_EVENTS` stream, which captures the device events of
+    /// every protocol pod, split into `partitions` partitions.
     ///
-    /// Extract the event type from the last dot-separated token of each
-    /// message's subject:
+    /// Protocol pods publish with plain NATS. The stream rewrites each
+    /// subject to carry a partition number derived from the device, so all
+    /// events of one device land in the same partition:
     /// ```text
-    /// "acs.events.AABBCC.1234567.inform" → event_type = "inform"
+    /// "acs.events.AABBCC.1234567.inform" → "acs.events.5.AABBCC.1234567.inform"
     /// ```
-    pub async fn subscribe_events(&self) -> Result<Subscriber, async_nats::SubscribeError> {
-        self.inner.subscribe("acs.events.>").await
+    /// Every replica must use the same partition count.
+    pub async fn event_stream(&self, partitions: u32) -> anyhow::Result<jetstream::stream::Stream> {
+        let js = jetstream::new(self.inner.clone());
+        js.create_or_update_stream(jetstream::stream::Config {
+            name: EVENTS_STREAM.to_string(),
+            subjects: vec!["acs.events.>".to_string()],
+            subject_transform: Some(jetstream::stream::SubjectTransform {
+                source:      "acs.events.*.*.*".to_string(),
+                destination: format!(
+                    "acs.events.{{{{partition({partitions},1,2)}}}}.{{{{wildcard(1)}}}}.{{{{wildcard(2)}}}}.{{{{wildcard(3)}}}}"
+                ),
+            }),
+            // Kept for an hour after delivery, for replay when debugging.
+            max_age: Duration::from_secs(3600),
+            ..Default::default()
+        })
+        .await?;
+        let stream = js.get_stream(EVENTS_STREAM).await?;
+        // Events still pending on the shared consumer of earlier versions
+        // are not redelivered through the partition consumers.
+        if stream.delete_consumer(SHARED_EVENTS_CONSUMER).await.is_ok() {
+            tracing::info!(consumer = SHARED_EVENTS_CONSUMER, "Removed the shared event consumer");
+        }
+        Ok(stream)
+    }
+
+    /// Open (creating if needed) the partition lease bucket. Entries not
+    /// renewed within `ttl` disappear on their own.
+    pub async fn partition_store(&self, ttl: Duration) -> anyhow::Result<kv::Store> {
+        let js = jetstream::new(self.inner.clone());
+        match js.get_key_value(PARTITIONS_BUCKET).await {
+            Ok(store) => Ok(store),
+            Err(_) => Ok(js
+                .create_key_value(kv::Config {
+                    bucket: PARTITIONS_BUCKET.to_string(),
+                    description: "event partition leases, maintained by acs-controller".to_string(),
+                    history: 1,
+                    max_age: ttl,
+                    ..Default::default()
+                })
+                .await?),
+        }
+    }
+
+    /// Open (creating if needed) the session routing bucket. Entries not
+    /// refreshed by an Inform within `ttl` disappear on their own.
+    pub async fn session_store(&self, ttl: Duration) -> anyhow::Result<kv::Store> {
+        let js = jetstream::new(self.inner.clone());
+        match js.get_key_value(SESSIONS_BUCKET).await {
+            Ok(store) => Ok(store),
+            Err(_) => Ok(js
+                .create_key_value(kv::Config {
+                    bucket: SESSIONS_BUCKET.to_string(),
+                    description: "device_uid -> open session, maintained by acs-controller".to_string(),
+                    history: 1,
+                    max_age: ttl,
+                    ..Default::default()
+                })
+                .await?),
+        }
+    }
+
+    /// Subscribe to the response for `command_id` before publishing the
+    /// command, so it cannot be missed.
+    ///
+    /// Subject: `acs.controller.replies.{command_id}`
+    pub async fn subscribe_command_reply(&self, command_id: Uuid) -> Result<Subscriber, async_nats::SubscribeError> {
+        self.inner.subscribe(format!("acs.controller.replies.{command_id}")).await
+    }
+
+    /// Forward a raw `DeviceResponse` to whichever replica is waiting for it.
+    /// Nobody listening is normal — most commands have no waiter.
+    pub async fn publish_command_reply(&self, command_id: Uuid, payload: bytes::Bytes) -> anyhow::Result<()> {
+        let subject = format!("acs.controller.replies.{command_id}");
+        self.inner.publish(subject, payload).await.map_err(Into::into)
+    }
+
+    /// Subscribe to the end of `session_id`, so a caller waiting for a
+    /// response learns the device will not answer.
+    ///
+    /// Subject: `acs.controller.sessions.{session_id}.ended`
+    pub async fn subscribe_session_ended(&self, session_id: &str) -> Result<Subscriber, async_nats::SubscribeError> {
+        self.inner.subscribe(format!("acs.controller.sessions.{session_id}.ended")).await
+    }
+
+    /// Tell every replica that `session_id` has ended.
+    pub async fn publish_session_ended(&self, session_id: &str) -> anyhow::Result<()> {
+        let subject = format!("acs.controller.sessions.{session_id}.ended");
+        self.inner.publish(subject, bytes::Bytes::new()).await.map_err(Into::into)
+    }
+
+    /// Publish to a subject every replica subscribes to (see [`Self::subscribe`]).
+    pub async fn broadcast(&self, subject: &'static str, payload: Vec<u8>) -> anyhow::Result<()> {
+        self.inner.publish(subject, payload.into()).await.map_err(Into::into)
+    }
+
+    /// Plain (non-queue) subscription: every replica receives every message.
+    pub async fn subscribe(&self, subject: &'static str) -> Result<Subscriber, async_nats::SubscribeError> {
+        self.inner.subscribe(subject).await
     }
 
     /// Publish a command to a specific session.
@@ -57,4 +179,45 @@ impl NatsClient {
         let subject = "acs.connection.request";
         self.inner.publish(subject, payload.into()).await.map_err(Into::into)
     }
+
+    /// Announce that a device joined or left a device group.
+    ///
+    /// Subject: `acs.groups.{group_id}.membership`
+    pub async fn publish_group_change(
+        &self,
+        change: &nats_common::GroupMembershipChange,
+    ) -> anyhow::Result<()> {
+        let subject = format!("acs.groups.{}.membership", change.group_id);
+        let payload = serde_json::to_vec(change)?;
+        self.inner.publish(subject, payload.into()).await.map_err(Into::into)
+    }
+}
+
+/// Durable consumer of one partition of [`NatsClient::event_stream`]. Only the
+/// replica holding the partition's lease pulls from it.
+///
+/// Messages must be acknowledged once handled; unacknowledged ones are
+/// redelivered after `ack_wait`. At most `max_pending` events are handed
+/// out without having been acknowledged.
+pub async fn partition_consumer(
+    stream: &jetstream::stream::Stream,
+    partition: u32,
+    ack_wait: Duration,
+    max_pending: usize,
+) -> anyhow::Result<jetstream::consumer::PullConsumer> {
+    let name = format!("acs-controller-{partition}");
+    Ok(stream
+        .get_or_create_consumer(
+            &name,
+            pull::Config {
+                durable_name: Some(name.clone()),
+                filter_subject: format!("acs.events.{partition}.>"),
+                ack_policy: jetstream::consumer::AckPolicy::Explicit,
+                ack_wait,
+                max_deliver: 5,
+                max_ack_pending: max_pending as i64,
+                ..Default::default()
+            },
+        )
+        .await?)
 }
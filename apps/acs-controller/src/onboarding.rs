@@ -0,0 +1,207 @@
+//! First-contact domain assignment.
+//!
+//! When an Inform arrives from a `device_uid` the controller has never seen,
+//! the rules in `domain_assignment_rules` decide which domain the new device
+//! row is created in. Rules are evaluated in ascending `priority`; the first
+//! rule whose criteria all match wins. Criteria left `NULL` match anything.
+//!
+//! Known devices never go through this module — their stored `domain_id` is
+//! authoritative.
+
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::db::InformPayload;
+
+/// One row of `domain_assignment_rules`.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct OnboardingRule {
+    pub id:             Uuid,
+    pub domain_id:      Uuid,
+    pub name:           String,
+    pub priority:       i32,
+    pub enabled:        bool,
+    pub oui:            Option<String>,
+    pub product_class:  Option<String>,
+    pub serial_pattern: Option<String>,
+    pub source_cidr:    Option<IpNet>,
+    pub acs_path:       Option<String>,
+    pub created_at:     chrono::DateTime<chrono::Utc>,
+}
+
+impl OnboardingRule {
+    /// `true` if every criterion set on this rule matches `payload`.
+    ///
+    /// A criterion that needs data the payload lacks (e.g. `source_cidr`
+    /// when the pod did not report a source IP) does not match. An invalid
+    /// `serial_pattern` never matches and is logged.
+    pub fn matches(&self, payload: &InformPayload) -> bool {
+        if let Some(oui) = &self.oui {
+            if !oui.eq_ignore_ascii_case(&payload.oui) {
+                return false;
+            }
+        }
+
+        if let Some(class) = &self.product_class {
+            if class != &payload.product_class {
+                return false;
+            }
+        }
+
+        if let Some(pattern) = &self.serial_pattern {
+            match Regex::new(pattern) {
+                Ok(re) if re.is_match(&payload.serial_number) => {}
+                Ok(_) => return false,
+                Err(e) => {
+                    warn!(rule = %self.name, error = %e, "Invalid serial_pattern — rule skipped");
+                    return false;
+                }
+            }
+        }
+
+        if let Some(net) = &self.source_cidr {
+            let ip: Option<IpAddr> = payload.source_ip.as_deref().and_then(|s| s.parse().ok());
+            if !ip.is_some_and(|ip| net.contains(&ip)) {
+                return false;
+            }
+        }
+
+        if let Some(path) = &self.acs_path {
+            if payload.acs_path.as_deref() != Some(path.as_str()) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Pick the domain for a never-seen device, or `None` if no rule matches.
+pub async fn assign_domain(pool: &PgPool, payload: &InformPayload) -> Result<Option<Uuid>, sqlx::Error> {
+    let rules = load_rules(pool).await?;
+
+    let chosen = first_match(&rules, payload);
+    if let Some(rule) = chosen {
+        info!(
+            device_id = %payload.device_id,
+            rule      = %rule.name,
+            domain_id = %rule.domain_id,
+            "Onboarding rule matched",
+        );
+    }
+    Ok(chosen.map(|r| r.domain_id))
+}
+
+/// Enabled rules in evaluation order.
+async fn load_rules(pool: &PgPool) -> Result<Vec<OnboardingRule>, sqlx::Error> {
+    sqlx::query_as::<_, OnboardingRule>(
+        r#"
+        SELECT id, domain_id, name, priority, enabled, oui, product_class,
+               serial_pattern, source_cidr, acs_path, created_at
+        FROM domain_assignment_rules
+        WHERE enabled
+        ORDER BY priority, created_at
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+fn first_match<'a>(rules: &'a [OnboardingRule], payload: &InformPayload) -> Option<&'a OnboardingRule> {
+    rules.iter().find(|r| r.enabled && r.matches(payload))
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn payload() -> InformPayload {
+        InformPayload {
+            session_id:     "s".to_string(),
+            device_id:      "AABB00-CPE123".to_string(),
+            oui:            "AABB00".to_string(),
+            serial_number:  "CPE123".to_string(),
+            manufacturer:   "ExampleCorp".to_string(),
+            product_class:  "HGW-2".to_string(),
+            events:         vec!["0 BOOTSTRAP".to_string()],
+            parameter_list: HashMap::new(),
+            protocol:       None,
+            source_ip:      Some("10.20.0.7".to_string()),
+            acs_path:       Some("/cwmp/acme".to_string()),
+        }
+    }
+
+    fn rule(name: &str) -> OnboardingRule {
+        OnboardingRule {
+            id:             Uuid::new_v4(),
+            domain_id:      Uuid::new_v4(),
+            name:           name.to_string(),
+            priority:       100,
+            enabled:        true,
+            oui:            None,
+            product_class:  None,
+            serial_pattern: None,
+            source_cidr:    None,
+            acs_path:       None,
+            created_at:     chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn empty_rule_matches_everything() {
+        assert!(rule("catch-all").matches(&payload()));
+    }
+
+    #[test]
+    fn every_criterion_must_match() {
+        let mut r = rule("acme");
+        r.oui = Some("aabb00".to_string());
+        r.product_class = Some("HGW-2".to_string());
+        r.serial_pattern = Some("^CPE\\d+$".to_string());
+        r.source_cidr = Some("10.20.0.0/16".parse().unwrap());
+        r.acs_path = Some("/cwmp/acme".to_string());
+        assert!(r.matches(&payload()));
+
+        r.source_cidr = Some("192.168.0.0/16".parse().unwrap());
+        assert!(!r.matches(&payload()));
+    }
+
+    #[test]
+    fn missing_source_ip_fails_cidr_rule() {
+        let mut r = rule("net");
+        r.source_cidr = Some("0.0.0.0/0".parse().unwrap());
+        let mut p = payload();
+        p.source_ip = None;
+        assert!(!r.matches(&p));
+    }
+
+    #[test]
+    fn invalid_regex_never_matches() {
+        let mut r = rule("broken");
+        r.serial_pattern = Some("(".to_string());
+        assert!(!r.matches(&payload()));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let mut wrong_path = rule("other-path");
+        wrong_path.acs_path = Some("/cwmp/other".to_string());
+        let by_oui = {
+            let mut r = rule("by-oui");
+            r.oui = Some("AABB00".to_string());
+            r
+        };
+        let catch_all = rule("catch-all");
+        let rules = vec![wrong_path, by_oui.clone(), catch_all];
+        assert_eq!(first_match(&rules, &payload()).map(|r| r.id), Some(by_oui.id));
+    }
+}
@@ -0,0 +1,201 @@
+//! Event partitions: each device's events go to one replica.
+//!
+//! The `ACS_EVENTS` stream splits device events into partitions by device
+//! (see [`NatsClient::event_stream`]), each with its own durable consumer.
+//! A replica pulls from a partition only while it holds the partition's
+//! lease in the KV bucket `acs_event_partitions`. One device's `inform`,
+//! `command_response` and `session_ended` therefore all reach the same
+//! replica, whose [`Dispatcher`](crate::dispatch::Dispatcher) handles them
+//! in order.
+//!
+//! Every third of the lease TTL each replica renews its leases and its
+//! own liveness entry, then takes or gives up partitions until it holds
+//! its share (partitions ÷ live replicas, rounded up):
+//!
+//! - a lease that cannot be renewed is dropped and the partition is no
+//!   longer pulled from;
+//! - a newly taken partition is not pulled from until the events handed to
+//!   its previous holder are acknowledged, so they never run alongside the
+//!   events after them. A holder that stops acknowledging for `ack_wait`
+//!   is taken to be gone; its events are then redelivered to the new one.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::time::Duration;
+
+use async_nats::jetstream::consumer::PullConsumer;
+use async_nats::jetstream::{kv, stream};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+use tokio_stream::StreamExt;
+use tracing::{info, warn};
+
+use crate::nats::{self, NatsClient};
+
+/// Prefix of the lease key of each partition.
+const PARTITION_PREFIX: &str = "partitions.";
+/// Prefix of the liveness key of each replica.
+const REPLICA_PREFIX: &str = "replicas.";
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// Partitions the event stream is split into. Same on every replica.
+    pub count:       u32,
+    /// Time a lease survives without being renewed.
+    pub lease:       Duration,
+    /// See [`nats::partition_consumer`].
+    pub ack_wait:    Duration,
+    pub max_pending: usize,
+}
+
+/// A partition this replica holds.
+struct Held {
+    revision: u64,
+    /// Waits for the previous holder, then pulls from the partition.
+    task:     JoinHandle<()>,
+}
+
+/// Hold this replica's share of the event partitions, running `consume` on
+/// the consumer of each partition while its lease is held.
+///
+/// Only returns if the stream or the lease bucket cannot be opened.
+pub async fn run<F, Fut>(nats: NatsClient, settings: Settings, instance: String, consume: F) -> anyhow::Result<()>
+where
+    F: Fn(PullConsumer) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let stream = nats.event_stream(settings.count).await?;
+    let store = nats.partition_store(settings.lease).await?;
+    let me = format!("{REPLICA_PREFIX}{}", URL_SAFE_NO_PAD.encode(&instance));
+    info!(partitions = settings.count, "Consuming device events by partition");
+
+    let mut held: BTreeMap<u32, Held> = BTreeMap::new();
+    let mut ticker = tokio::time::interval(settings.lease / 3);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+
+        // Renew, dropping partitions whose lease was lost or whose
+        // consumer ended (taken again below).
+        let partitions: Vec<u32> = held.keys().copied().collect();
+        for partition in partitions {
+            let Some(h) = held.get_mut(&partition) else { continue };
+            if h.task.is_finished() {
+                warn!(partition, "Event partition consumer ended — releasing it");
+                release(&store, partition, held.remove(&partition)).await;
+                continue;
+            }
+            match store.update(lease_key(partition), instance.clone().into(), h.revision).await {
+                Ok(revision) => h.revision = revision,
+                Err(e) => {
+                    warn!(partition, ?e, "Lost event partition lease — no longer consuming it");
+                    if let Some(h) = held.remove(&partition) {
+                        h.task.abort();
+                    }
+                }
+            }
+        }
+
+        let (replicas, taken) = match survey(&store, &me, &instance).await {
+            Ok(survey) => survey,
+            Err(e) => {
+                warn!(?e, "Failed to read event partition leases");
+                continue;
+            }
+        };
+        let share = settings.count.div_ceil(replicas.max(1)) as usize;
+
+        // Give up partitions beyond our share, e.g. when a replica joins.
+        while held.len() > share {
+            let Some((partition, h)) = held.pop_last() else { break };
+            info!(partition, replicas, "Handing event partition over");
+            release(&store, partition, Some(h)).await;
+        }
+
+        // Take free partitions up to our share. Our own are among `taken`.
+        for partition in (0..settings.count).filter(|p| !taken.contains(p)) {
+            if held.len() >= share {
+                break;
+            }
+            // Another replica may have taken it since the survey.
+            let Ok(revision) = store.create(lease_key(partition), instance.clone().into()).await else {
+                continue;
+            };
+            info!(partition, "Took event partition");
+            let task = tokio::spawn(take_over(stream.clone(), partition, settings, consume.clone()));
+            held.insert(partition, Held { revision, task });
+        }
+    }
+}
+
+/// Stop pulling from `partition` and free its lease for another replica.
+/// Events already pulled are still handled here.
+async fn release(store: &kv::Store, partition: u32, held: Option<Held>) {
+    let Some(held) = held else { return };
+    held.task.abort();
+    if let Err(e) = store.delete_expect_revision(lease_key(partition), Some(held.revision)).await {
+        // It expires after the lease TTL instead.
+        warn!(partition, ?e, "Failed to release event partition lease");
+    }
+}
+
+/// Mark this replica live and count live replicas. Returns that count and
+/// the partitions leased by any replica.
+async fn survey(store: &kv::Store, me: &str, instance: &str) -> anyhow::Result<(u32, BTreeSet<u32>)> {
+    store.put(me, instance.to_string().into()).await?;
+    let mut keys = store.keys().await?;
+    let mut replicas = 0;
+    let mut taken = BTreeSet::new();
+    while let Some(key) = keys.next().await {
+        let key = key?;
+        if key.starts_with(REPLICA_PREFIX) {
+            replicas += 1;
+        } else if let Some(partition) = key.strip_prefix(PARTITION_PREFIX).and_then(|p| p.parse().ok()) {
+            taken.insert(partition);
+        }
+    }
+    Ok((replicas, taken))
+}
+
+/// Wait for the previous holder of `partition`, then run `consume` on it.
+async fn take_over<F, Fut>(stream: stream::Stream, partition: u32, settings: Settings, consume: F)
+where
+    F: Fn(PullConsumer) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut consumer =
+        match nats::partition_consumer(&stream, partition, settings.ack_wait, settings.max_pending).await {
+            Ok(consumer) => consumer,
+            Err(e) => {
+                warn!(partition, ?e, "Failed to open event partition consumer");
+                return;
+            }
+        };
+
+    // Events handed out but not acknowledged belong to the previous holder.
+    let mut pending = usize::MAX;
+    let mut progress = Instant::now();
+    loop {
+        match consumer.info().await {
+            Ok(info) if info.num_ack_pending == 0 => break,
+            Ok(info) if info.num_ack_pending < pending => {
+                pending = info.num_ack_pending;
+                progress = Instant::now();
+            }
+            Ok(_) => {}
+            Err(e) => warn!(partition, ?e, "Failed to read event partition consumer"),
+        }
+        if progress.elapsed() >= settings.ack_wait {
+            warn!(partition, pending, "Previous holder of event partition is gone — taking over its events");
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    consume(consumer).await;
+}
+
+fn lease_key(partition: u32) -> String {
+    format!("{PARTITION_PREFIX}{partition}")
+}
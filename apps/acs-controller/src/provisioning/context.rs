@@ -0,0 +1,83 @@
+//! Device context: what the inventory knows about the device, given to
+//! scripts as `payload.context` next to the event itself.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "domain":     {"id": "…", "slug": "acme", "name": "Acme"},
+//!   "device":     {"id": "…", "device_uid": "AABB00-1234567", "oui": "AABB00",
+//!                  "serial_number": "1234567", "manufacturer": "…", "product_class": "…",
+//!                  "first_seen": "…", "last_seen": "…", "tags": ["pilot"],
+//!                  "metadata": {"site": "x"}},
+//!   "properties": {"ntp_server": "ntp.acme.net"},
+//!   "profiles":   [{"id": "…", "name": "residential", "config": {…}}],
+//!   "groups":     ["pilot-fleet"],
+//!   "parameters": {"Device.DeviceInfo.UpTime": "3600"}
+//! }
+//! ```
+//!
+//! `parameters` are the values last stored for the device, not those of the
+//! current Inform. Fields are only ever added within a version; a change
+//! that breaks scripts bumps [`VERSION`].
+
+use serde_json::Value as JsonValue;
+use sqlx::PgPool;
+
+use super::Target;
+
+/// Version of the context's shape.
+pub const VERSION: i32 = 1;
+
+/// The context of `target`'s device, or `None` if it is not in the
+/// inventory (e.g. a dry run for a new device).
+pub async fn load(pool: &PgPool, target: &Target<'_>) -> Result<Option<JsonValue>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT jsonb_build_object(
+            'version',    $3::INT,
+            'domain',     jsonb_build_object('id', dm.id, 'slug', dm.slug, 'name', dm.name),
+            'device',     jsonb_build_object(
+                              'id',            d.id,
+                              'device_uid',    d.device_uid,
+                              'oui',           d.oui,
+                              'serial_number', d.serial_number,
+                              'manufacturer',  d.manufacturer,
+                              'product_class', d.product_class,
+                              'first_seen',    d.first_seen,
+                              'last_seen',     d.last_seen,
+                              'tags',          to_jsonb(d.tags),
+                              'metadata',      d.metadata),
+            'properties', COALESCE(
+                (SELECT jsonb_object_agg(p.property_name, p.property_value)
+                 FROM device_properties p WHERE p.device_id = d.id),
+                '{}'::jsonb),
+            'profiles',   COALESCE(
+                (SELECT jsonb_agg(jsonb_build_object('id', pp.id, 'name', pp.name, 'config', pp.config)
+                                  ORDER BY a.assigned_at)
+                 FROM device_profile_assignments a
+                 JOIN provisioning_profiles pp ON pp.id = a.profile_id
+                 WHERE a.device_id = d.id),
+                '[]'::jsonb),
+            'groups',     COALESCE(
+                (SELECT jsonb_agg(g.name ORDER BY g.name)
+                 FROM device_group_members m
+                 JOIN device_groups g ON g.id = m.group_id
+                 WHERE m.device_id = d.id),
+                '[]'::jsonb),
+            'parameters', COALESCE(
+                (SELECT jsonb_object_agg(dp.parameter_name, dp.parameter_value)
+                 FROM device_parameters dp
+                 WHERE dp.device_id = d.id AND dp.parameter_value IS NOT NULL),
+                '{}'::jsonb)
+        )
+        FROM devices d
+        JOIN domains dm ON dm.id = d.domain_id
+        WHERE d.device_uid = $1 AND dm.slug = $2
+        "#,
+    )
+    .bind(target.device_id)
+    .bind(target.domain_slug)
+    .bind(VERSION)
+    .fetch_optional(pool)
+    .await
+}
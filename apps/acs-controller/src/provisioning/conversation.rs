@@ -0,0 +1,167 @@
+//! Interactive provisioning: scripts talking to the device mid-session.
+//!
+//! A [`Conversation`] lets a script send one action at a time to the device
+//! whose Inform it is handling and wait for the `DeviceResponse`, so it can
+//! read a value and then decide, or add an object and use its instance
+//! number. Every round trip happens in the session the Inform opened.
+//!
+//! The script holds up its device's event lane while it waits, so the
+//! response cannot be handed over by the `command_response` handler: the
+//! event loop forwards responses and session ends as soon as they are
+//! pulled (see `forward_signals` in `main.rs`).
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use nats_common::{Action, ActionResult, DeviceCommand, DeviceResponse};
+use tokio::sync::watch;
+use tokio_stream::StreamExt;
+use tracing::{debug, error};
+use uuid::Uuid;
+
+use crate::nats::NatsClient;
+use crate::sessions;
+
+/// An action a script sent, and what came of it.
+#[derive(Debug)]
+pub struct Exchange {
+    pub command_id: Uuid,
+    pub action:     Action,
+    /// The device's result, or why there is none.
+    pub result:     Result<ActionResult, String>,
+}
+
+/// A device session provisioning scripts can send commands into.
+/// Clones share the session's state.
+#[derive(Clone)]
+pub struct Conversation {
+    nats:        NatsClient,
+    pool:        sqlx::PgPool,
+    device_uuid: Uuid,
+    device_id:   String,
+    session_id:  String,
+    timeout:     Duration,
+    /// Runtime the blocking script thread sends commands from.
+    runtime:     tokio::runtime::Handle,
+    ended:       Arc<AtomicBool>,
+    /// Closed once the script run is abandoned (see [`Self::for_script`]).
+    abandoned:   Option<watch::Receiver<()>>,
+    /// What the current script sent.
+    exchanges:   Arc<Mutex<Vec<Exchange>>>,
+}
+
+impl Conversation {
+    /// Must be called from within the Tokio runtime. Each send waits at
+    /// most `timeout` for the device to answer.
+    pub fn new(
+        nats: NatsClient,
+        pool: sqlx::PgPool,
+        device_uuid: Uuid,
+        device_id: &str,
+        session_id: &str,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            nats,
+            pool,
+            device_uuid,
+            device_id: device_id.to_string(),
+            session_id: session_id.to_string(),
+            timeout,
+            runtime: tokio::runtime::Handle::current(),
+            ended: Arc::new(AtomicBool::new(false)),
+            abandoned: None,
+            exchanges: Arc::default(),
+        }
+    }
+
+    /// Whether the session ended while a script was waiting on it.
+    pub fn ended(&self) -> bool {
+        self.ended.load(Ordering::Relaxed)
+    }
+
+    /// A copy for one script run. Its sends give up once `abandoned`'s
+    /// sender is dropped, i.e. when the event handling the script belongs
+    /// to is abandoned, and are kept apart for [`Self::take_exchanges`].
+    pub(super) fn for_script(&self, abandoned: watch::Receiver<()>) -> Self {
+        Self { abandoned: Some(abandoned), exchanges: Arc::default(), ..self.clone() }
+    }
+
+    /// What was sent so far, in order.
+    pub(super) fn take_exchanges(&self) -> Vec<Exchange> {
+        std::mem::take(&mut *self.exchanges.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// [`Self::send`] from a blocking script thread.
+    pub(super) fn send_blocking(&self, action: Action) -> Result<ActionResult> {
+        self.runtime.block_on(self.send(action))
+    }
+
+    /// Send `action` to the device and wait for its result.
+    pub async fn send(&self, action: Action) -> Result<ActionResult> {
+        if self.ended() {
+            bail!("Device session has ended");
+        }
+
+        let command_id = Uuid::new_v4();
+        let result = self.round_trip(command_id, action.clone()).await;
+        let exchange = Exchange {
+            command_id,
+            action,
+            result: result.as_ref().map(Clone::clone).map_err(|e| format!("{e:#}")),
+        };
+        self.exchanges.lock().unwrap_or_else(|e| e.into_inner()).push(exchange);
+        result
+    }
+
+    async fn round_trip(&self, command_id: Uuid, mut action: Action) -> Result<ActionResult> {
+        crate::datamodel::prepare(&self.pool, self.device_uuid, &mut action).await;
+
+        let (mut replies, mut session_ended) = tokio::try_join!(
+            self.nats.subscribe_command_reply(command_id),
+            self.nats.subscribe_session_ended(&self.session_id),
+        )
+        .context("Failed to subscribe to command reply")?;
+
+        let command = DeviceCommand { command_id, device_id: self.device_id.clone(), action };
+        let payload = serde_json::to_vec(&command).context("Failed to serialize DeviceCommand")?;
+        self.nats
+            .publish_command(&self.session_id, payload)
+            .await
+            .context("Failed to publish DeviceCommand")?;
+        debug!(%command_id, session_id = %self.session_id, "Script command published, awaiting response");
+        if let Err(e) = sessions::count_commands(&self.pool, &self.session_id, 1).await {
+            error!(?e, session_id = %self.session_id, "Failed to count session command");
+        }
+
+        let mut abandoned = self.abandoned.clone();
+        let abandoned = async {
+            match abandoned.as_mut() {
+                // Only ever closed, never sent to.
+                Some(rx) => while rx.changed().await.is_ok() {},
+                None => std::future::pending().await,
+            }
+        };
+
+        // A response is always relayed before the end of its session, so
+        // check for it first.
+        tokio::select! {
+            biased;
+            reply = replies.next() => {
+                let reply = reply.context("Reply subscription closed")?;
+                let response: DeviceResponse =
+                    serde_json::from_slice(&reply.payload).context("Malformed command reply")?;
+                crate::health::learn_from_answer(&self.pool, self.device_uuid, &command.action, &response.result).await;
+                Ok(response.result)
+            }
+            _ = session_ended.next() => {
+                self.ended.store(true, Ordering::Relaxed);
+                bail!("Device session ended before the device answered")
+            }
+            _ = abandoned => bail!("Provisioning abandoned"),
+            _ = tokio::time::sleep(self.timeout) => bail!("Device did not answer within {:?}", self.timeout),
+        }
+    }
+}
@@ -0,0 +1,496 @@
+//! Embedded backend: Rhai scripts run in-process.
+//!
+//! Scripts are compiled once and cached until the file changes; stored
+//! versions never change, so they are cached for good. They run
+//! in a sandbox: there is no filesystem, network or process access, no
+//! `import` and no `eval`. Each run is limited in operations and wall-clock
+//! time, and strings, arrays and maps are limited in size, which bounds the
+//! memory a script can use.
+//!
+//! A script sees the event payload as the object map `payload` and returns
+//! an array of actions built with the SDK functions below, or nothing:
+//!
+//! ```rhai
+//! if !payload.has_event("0 BOOTSTRAP") { return; }
+//! [
+//!     set_parameter_values(#{ "Device.ManagementServer.PeriodicInformEnable": "true" }),
+//!     reboot(),
+//! ]
+//! ```
+//!
+//! With a database, `payload` also holds the device's inventory context,
+//! read through accessors such as `payload.has_tag("pilot")`,
+//! `payload.property("ntp_server")` or `payload.in_group("beta")`. Scripts can
+//! return inventory updates among their actions:
+//!
+//! ```rhai
+//! if payload.has_tag("migrated") { return; }
+//! [set_property("migrated_from", payload.software_version()), add_tag("migrated")]
+//! ```
+//!
+//! Scripts handling an Inform can also `send()` an action to the device: it
+//! is sent at once and the script resumes with the device's answer. Time spent
+//! waiting does not count towards the script's time limit.
+//!
+//! ```rhai
+//! let mapping = send(add_object("Device.NAT.PortMapping."));
+//! let params = #{};
+//! params[`Device.NAT.PortMapping.${mapping.instance_number}.Enable`] = "true";
+//! [set_parameter_values(params)]
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use nats_common::{Action, ActionResult};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Position, Scope, AST};
+use serde_json::Value as JsonValue;
+
+use super::updates::{self, InventoryUpdate};
+use super::{append_output, Conversation, Outcome, Source};
+
+/// Limits applied to every script run.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Rhai operations (roughly: expressions evaluated) per run.
+    pub max_operations:  u64,
+    /// Wall-clock time per run.
+    pub timeout:         Duration,
+    /// Longest string, in bytes.
+    pub max_string_size: usize,
+    /// Most elements in one array or map.
+    pub max_collection:  usize,
+}
+
+pub struct RhaiBackend {
+    engine:  Engine,
+    timeout: Duration,
+    cache:   Mutex<HashMap<PathBuf, Compiled>>,
+    /// Stored versions, by id.
+    stored:  Mutex<HashMap<uuid::Uuid, Arc<AST>>>,
+}
+
+struct Compiled {
+    modified: SystemTime,
+    ast:      Arc<AST>,
+}
+
+thread_local! {
+    /// Deadline of the run on this thread, checked from `on_progress`.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+    /// Device session of the run on this thread, used by `send()`.
+    static CONVERSATION: RefCell<Option<Conversation>> = const { RefCell::new(None) };
+    /// `print` and `debug` output of the run on this thread.
+    static OUTPUT: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+impl RhaiBackend {
+    pub fn new(limits: Limits) -> Self {
+        let mut engine = Engine::new();
+
+        // Sandbox: no modules from disk, no evaluating strings as code.
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.disable_symbol("eval");
+
+        engine.set_max_operations(limits.max_operations);
+        engine.set_max_string_size(limits.max_string_size);
+        engine.set_max_array_size(limits.max_collection);
+        engine.set_max_map_size(limits.max_collection);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        engine.on_progress(|ops| {
+            // Checking the clock on every operation would be costly.
+            if ops % 1024 != 0 {
+                return None;
+            }
+            let expired = DEADLINE.with(|d| d.get().is_some_and(|d| Instant::now() >= d));
+            expired.then(|| Dynamic::from("timeout"))
+        });
+
+        engine.on_print(|s| {
+            tracing::info!(target: "provisioning::script", "{s}");
+            OUTPUT.with(|o| append_output(&mut o.borrow_mut(), s));
+        });
+        engine.on_debug(|s, source, pos| {
+            tracing::debug!(target: "provisioning::script", source = source.unwrap_or(""), %pos, "{s}");
+            OUTPUT.with(|o| append_output(&mut o.borrow_mut(), &format!("{pos}: {s}")));
+        });
+
+        register_sdk(&mut engine);
+        Self { engine, timeout: limits.timeout, cache: Mutex::default(), stored: Mutex::default() }
+    }
+
+    /// Run a script. Blocks, also while the script waits on the device;
+    /// call from a blocking thread.
+    pub(super) fn execute(&self, source: &Source, payload: &JsonValue, conversation: Option<Conversation>) -> Outcome {
+        let compiled = match source {
+            Source::File(path) => self.compiled(path),
+            Source::Stored { script, .. } => self.compiled_stored(script.id, &script.source),
+        };
+        let ast = match compiled {
+            Ok(ast) => ast,
+            Err(e) => return Outcome::failed(e),
+        };
+        CONVERSATION.with(|c| *c.borrow_mut() = conversation);
+        let returned = self.eval(&ast, payload);
+        CONVERSATION.with(|c| *c.borrow_mut() = None);
+        Outcome { returned, output: OUTPUT.with(|o| std::mem::take(&mut *o.borrow_mut())) }
+    }
+
+    /// The cached AST of `path`, recompiling it if the file changed.
+    fn compiled(&self, path: &Path) -> Result<Arc<AST>> {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).context("Failed to stat script")?;
+
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(c) = cache.get(path).filter(|c| c.modified == modified) {
+            return Ok(c.ast.clone());
+        }
+        let ast = Arc::new(
+            self.engine
+                .compile_file(path.to_path_buf())
+                .map_err(|e| anyhow::anyhow!("Failed to compile script: {e}"))?,
+        );
+        cache.insert(path.to_path_buf(), Compiled { modified, ast: ast.clone() });
+        Ok(ast)
+    }
+
+    /// The cached AST of a stored version.
+    fn compiled_stored(&self, id: uuid::Uuid, source: &str) -> Result<Arc<AST>> {
+        let mut cache = self.stored.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(ast) = cache.get(&id) {
+            return Ok(ast.clone());
+        }
+        let ast = Arc::new(self.engine.compile(source).map_err(|e| anyhow::anyhow!("Failed to compile script: {e}"))?);
+        cache.insert(id, ast.clone());
+        Ok(ast)
+    }
+
+    /// Check that `source` compiles.
+    pub fn check(&self, source: &str) -> Result<()> {
+        self.engine.compile(source).map(drop).map_err(|e| anyhow::anyhow!("{e}"))
+    }
+
+    fn eval(&self, ast: &AST, payload: &JsonValue) -> Result<(Vec<Action>, Vec<InventoryUpdate>)> {
+        let mut scope = Scope::new();
+        let payload = rhai::serde::to_dynamic(payload).map_err(|e| anyhow::anyhow!("Invalid payload: {e}"))?;
+        scope.push_constant("payload", payload);
+
+        DEADLINE.with(|d| d.set(Some(Instant::now() + self.timeout)));
+        let result = self.engine.eval_ast_with_scope::<Dynamic>(&mut scope, ast);
+        DEADLINE.with(|d| d.set(None));
+
+        let result = result.map_err(|e| match *e {
+            rhai::EvalAltResult::ErrorTerminated(..) => anyhow::anyhow!("Script timed out after {:?}", self.timeout),
+            e => anyhow::anyhow!("Script failed: {e}"),
+        })?;
+        if result.is_unit() {
+            return Ok(Default::default());
+        }
+        let returned: Vec<JsonValue> = rhai::serde::from_dynamic(&result)
+            .map_err(|e| anyhow::anyhow!("Script did not return an array of actions: {e}"))?;
+        updates::split(returned)
+    }
+}
+
+// ── SDK ───────────────────────────────────────────────────────────────────────
+
+/// The Rhai counterpart of the Python `acs_sdk`.
+fn register_sdk(engine: &mut Engine) {
+    // Payload helpers, called as methods: `payload.has_event("1 BOOT")`.
+    engine.register_fn("has_event", |payload: &mut Map, code: &str| -> bool {
+        payload
+            .get("events")
+            .and_then(|e| e.read_lock::<Array>().map(|a| a.clone()))
+            .unwrap_or_default()
+            .iter()
+            .any(|e| crate::db::event_is(&e.to_string(), code))
+    });
+    engine.register_fn("param", param);
+    engine.register_fn("software_version", |payload: &mut Map| -> Dynamic {
+        first_param(payload, &["Device.DeviceInfo.SoftwareVersion", "InternetGatewayDevice.DeviceInfo.SoftwareVersion"])
+    });
+    engine.register_fn("hardware_version", |payload: &mut Map| -> Dynamic {
+        first_param(payload, &["Device.DeviceInfo.HardwareVersion", "InternetGatewayDevice.DeviceInfo.HardwareVersion"])
+    });
+
+    // Context helpers, also methods of `payload`. Without a context (no
+    // database, or a device not in the inventory yet) they find nothing.
+    engine.register_fn("domain", |payload: &mut Map| -> Dynamic {
+        context_entry(payload, "domain", "slug")
+    });
+    engine.register_fn("tags", |payload: &mut Map| -> Array { context_list(payload, "device", "tags") });
+    engine.register_fn("has_tag", |payload: &mut Map, tag: &str| -> bool {
+        context_list(payload, "device", "tags").iter().any(|t| t.to_string() == tag)
+    });
+    engine.register_fn("metadata", |payload: &mut Map, key: &str| -> Dynamic {
+        context_map(payload, "device").and_then(|d| map_entry(&d, "metadata", key)).unwrap_or(Dynamic::UNIT)
+    });
+    engine.register_fn("property", |payload: &mut Map, name: &str| -> Dynamic {
+        context_entry(payload, "properties", name)
+    });
+    engine.register_fn("in_group", |payload: &mut Map, name: &str| -> bool {
+        context_list(payload, "", "groups").iter().any(|g| g.to_string() == name)
+    });
+    engine.register_fn("profile", |payload: &mut Map, name: &str| -> Dynamic {
+        context_list(payload, "", "profiles")
+            .into_iter()
+            .filter_map(|p| p.try_cast::<Map>())
+            .find(|p| p.get("name").is_some_and(|n| n.to_string() == name))
+            .and_then(|p| p.get("config").cloned())
+            .unwrap_or(Dynamic::UNIT)
+    });
+    engine.register_fn("cached_param", |payload: &mut Map, path: &str| -> Dynamic {
+        context_entry(payload, "parameters", path)
+    });
+
+    // Action builders.
+    engine.register_fn("set_parameter_values", |parameters: Map| {
+        let parameters = parameters.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        action(Action::SetParameterValues { parameters, types: HashMap::new() })
+    });
+    engine.register_fn("get_parameter_values", |paths: Array| {
+        action(Action::GetParameterValues { paths: paths.iter().map(Dynamic::to_string).collect() })
+    });
+    engine.register_fn("get_parameter_names", |path_prefix: &str| {
+        action(Action::GetParameterNames { path_prefix: path_prefix.to_string(), next_level: true })
+    });
+    engine.register_fn("get_parameter_names", |path_prefix: &str, next_level: bool| {
+        action(Action::GetParameterNames { path_prefix: path_prefix.to_string(), next_level })
+    });
+    engine.register_fn("add_object", |path: &str| action(Action::AddObject { path: path.to_string() }));
+    engine.register_fn("delete_object", |path: &str| action(Action::DeleteObject { path: path.to_string() }));
+    engine.register_fn("reboot", || action(Action::Reboot));
+    engine.register_fn("factory_reset", || action(Action::FactoryReset));
+    engine.register_fn("download", |url: &str, file_type: &str| download(url, file_type, 0, ""));
+    engine.register_fn("download", download);
+
+    // Inventory update builders.
+    engine.register_fn("set_property", |name: &str, value: Dynamic| -> Result<Dynamic, Box<EvalAltResult>> {
+        let value = rhai::serde::from_dynamic(&value)?;
+        Ok(update(InventoryUpdate::SetProperty { name: name.to_string(), value }))
+    });
+    engine.register_fn("delete_property", |name: &str| update(InventoryUpdate::DeleteProperty { name: name.to_string() }));
+    engine.register_fn("add_tag", |tag: &str| update(InventoryUpdate::AddTag { tag: tag.to_string() }));
+    engine.register_fn("remove_tag", |tag: &str| update(InventoryUpdate::RemoveTag { tag: tag.to_string() }));
+
+    // Round trip to the device: `let r = send(get_parameter_values([...]));`
+    engine.register_fn("send", send);
+}
+
+/// Send `action` to the device and return its result: parameter values, or
+/// `instance_number` for `add_object`, as a map. A fault is thrown as
+/// `#{ code, string }`, which scripts can `catch`.
+fn send(action: Dynamic) -> Result<Map, Box<EvalAltResult>> {
+    let action: Action = rhai::serde::from_dynamic(&action)?;
+    let Some(conversation) = CONVERSATION.with(|c| c.borrow().clone()) else {
+        return Err("send() needs an open device session; return actions instead".into());
+    };
+
+    let started = Instant::now();
+    let result = conversation.send_blocking(action);
+    // Waiting on the device does not use up the script's time.
+    DEADLINE.with(|d| d.set(d.get().map(|d| d + started.elapsed())));
+
+    match result.map_err(|e| e.to_string())? {
+        ActionResult::Success(values) => Ok(values.into_iter().map(|(k, v)| (k.into(), v.into())).collect()),
+        ActionResult::Done => Ok(Map::new()),
+        ActionResult::Fault { code, string } => {
+            let fault = Map::from_iter([("code".into(), code.into()), ("string".into(), string.into())]);
+            Err(Box::new(EvalAltResult::ErrorRuntime(fault.into(), Position::NONE)))
+        }
+    }
+}
+
+fn param(payload: &mut Map, path: &str) -> Dynamic {
+    first_param(payload, &[path])
+}
+
+/// Value of the first of `paths` in the payload's `parameter_list`, or `()`.
+fn first_param(payload: &Map, paths: &[&str]) -> Dynamic {
+    let Some(list) = payload.get("parameter_list").and_then(|p| p.read_lock::<Map>().map(|m| m.clone())) else {
+        return Dynamic::UNIT;
+    };
+    paths.iter().find_map(|p| list.get(*p).cloned()).unwrap_or(Dynamic::UNIT)
+}
+
+/// `payload.context`, if the payload has one.
+fn context(payload: &Map) -> Option<Map> {
+    payload.get("context").and_then(|c| c.read_lock::<Map>().map(|m| m.clone()))
+}
+
+/// `payload.context[section]` as a map.
+fn context_map(payload: &Map, section: &str) -> Option<Map> {
+    context(payload).and_then(|c| c.get(section).and_then(|s| s.read_lock::<Map>().map(|m| m.clone())))
+}
+
+/// `payload.context[section][key]`, or `()`.
+fn context_entry(payload: &Map, section: &str, key: &str) -> Dynamic {
+    context(payload).and_then(|c| map_entry(&c, section, key)).unwrap_or(Dynamic::UNIT)
+}
+
+/// `payload.context[section][key]` (`payload.context[key]` with no section)
+/// as an array, or an empty one.
+fn context_list(payload: &Map, section: &str, key: &str) -> Array {
+    let parent = if section.is_empty() { context(payload) } else { context_map(payload, section) };
+    parent
+        .and_then(|p| p.get(key).and_then(|l| l.read_lock::<Array>().map(|a| a.clone())))
+        .unwrap_or_default()
+}
+
+/// `map[field][key]`, if `map[field]` is a map holding `key`.
+fn map_entry(map: &Map, field: &str, key: &str) -> Option<Dynamic> {
+    map.get(field).and_then(|f| f.read_lock::<Map>().and_then(|m| m.get(key).cloned()))
+}
+
+fn download(url: &str, file_type: &str, file_size: i64, target_filename: &str) -> Dynamic {
+    action(Action::Download {
+        url:             url.to_string(),
+        file_type:       file_type.to_string(),
+        file_size:       file_size.clamp(0, u32::MAX as i64) as u32,
+        target_filename: target_filename.to_string(),
+    })
+}
+
+fn action(action: Action) -> Dynamic {
+    // Actions hold only strings, maps, integers and booleans.
+    rhai::serde::to_dynamic(action).expect("Action converts to Dynamic")
+}
+
+fn update(update: InventoryUpdate) -> Dynamic {
+    // Updates hold only strings and JSON values.
+    rhai::serde::to_dynamic(update).expect("InventoryUpdate converts to Dynamic")
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend() -> RhaiBackend {
+        RhaiBackend::new(Limits {
+            max_operations:  100_000,
+            timeout:         Duration::from_secs(5),
+            max_string_size: 1024,
+            max_collection:  100,
+        })
+    }
+
+    fn run(script: &str, payload: JsonValue) -> Result<Vec<Action>> {
+        run_with_updates(script, payload).map(|(actions, _)| actions)
+    }
+
+    fn run_with_updates(script: &str, payload: JsonValue) -> Result<(Vec<Action>, Vec<InventoryUpdate>)> {
+        let b = backend();
+        let ast = b.engine.compile(script).map_err(|e| anyhow::anyhow!("{e}"))?;
+        b.eval(&ast, &payload)
+    }
+
+    #[test]
+    fn sdk_builds_actions_from_payload() {
+        let payload = serde_json::json!({
+            "device_id": "AABB00-1",
+            "events": ["0 BOOTSTRAP"],
+            "parameter_list": { "Device.DeviceInfo.SoftwareVersion": "1.2" },
+        });
+        let actions = run(
+            r#"
+            if !payload.has_event("0 bootstrap") { return; }
+            [
+                set_parameter_values(#{ "Device.X": payload.software_version(), "Device.Y": 3 }),
+                get_parameter_names("Device."),
+                download("http://fw/img", "1 Firmware Upgrade Image"),
+                reboot(),
+            ]
+            "#,
+            payload,
+        )
+        .unwrap();
+
+        assert_eq!(actions.len(), 4);
+        let Action::SetParameterValues { parameters, .. } = &actions[0] else { panic!("{actions:?}") };
+        assert_eq!(parameters["Device.X"], "1.2");
+        assert_eq!(parameters["Device.Y"], "3");
+        assert!(matches!(&actions[1], Action::GetParameterNames { next_level: true, .. }));
+        assert!(matches!(actions[3], Action::Reboot));
+
+        assert!(run(r#"if payload.has_event("1 BOOT") { [reboot()] }"#, serde_json::json!({ "events": [] }))
+            .unwrap()
+            .is_empty());
+
+        // Events carry their command key after the code.
+        let events = serde_json::json!({ "events": ["M Download fw-42", "7 TRANSFER COMPLETE"] });
+        let actions = run(
+            r#"if payload.has_event("m download") && !payload.has_event("1 BOOT") { [reboot()] }"#,
+            events,
+        );
+        assert_eq!(actions.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn sdk_reads_context_and_returns_updates() {
+        let payload = serde_json::json!({
+            "context": {
+                "version": 1,
+                "domain": { "slug": "acme" },
+                "device": { "tags": ["pilot"], "metadata": { "site": "north" } },
+                "properties": { "vlan": 42 },
+                "profiles": [{ "name": "residential", "config": { "ssid": "home" } }],
+                "groups": ["beta"],
+                "parameters": { "Device.DeviceInfo.UpTime": "60" },
+            },
+        });
+        let (actions, updates) = run_with_updates(
+            r#"
+            if payload.domain() != "acme" || !payload.has_tag("pilot") || !payload.in_group("beta") { return; }
+            [
+                set_property("site", `${payload.metadata("site")}/${payload.property("vlan")}`),
+                set_property("ssid", payload.profile("residential")),
+                add_tag("seen"),
+                remove_tag("pilot"),
+                delete_property(payload.cached_param("Device.DeviceInfo.UpTime")),
+                reboot(),
+            ]
+            "#,
+            payload,
+        )
+        .unwrap();
+
+        assert!(matches!(actions[..], [Action::Reboot]));
+        assert_eq!(updates, [
+            InventoryUpdate::SetProperty { name: "site".into(), value: "north/42".into() },
+            InventoryUpdate::SetProperty { name: "ssid".into(), value: serde_json::json!({ "ssid": "home" }) },
+            InventoryUpdate::AddTag { tag: "seen".into() },
+            InventoryUpdate::RemoveTag { tag: "pilot".into() },
+            InventoryUpdate::DeleteProperty { name: "60".into() },
+        ]);
+
+        // Without a context the accessors find nothing.
+        let (actions, updates) = run_with_updates(
+            r#"if payload.tags().is_empty() && payload.property("x") == () { [add_tag("new")] }"#,
+            serde_json::json!({ "context": null }),
+        )
+        .unwrap();
+        assert!(actions.is_empty());
+        assert_eq!(updates, [InventoryUpdate::AddTag { tag: "new".into() }]);
+    }
+
+    #[test]
+    fn limits_and_sandbox_hold() {
+        let err = run("loop {}", JsonValue::Null).unwrap_err().to_string();
+        assert!(err.contains("operations"), "{err}");
+        assert!(run(r#"import "x" as x;"#, JsonValue::Null).is_err());
+        assert!(run(r#"eval("1")"#, JsonValue::Null).is_err());
+        assert!(run(r#"let s = "x"; for i in 0..20 { s += s; } s"#, JsonValue::Null).is_err());
+        assert!(run("42", JsonValue::Null).is_err());
+
+        // Without a session, e.g. in session_ended scripts, there is no one to send to.
+        let err = run("send(reboot())", JsonValue::Null).unwrap_err().to_string();
+        assert!(err.contains("open device session"), "{err}");
+    }
+}
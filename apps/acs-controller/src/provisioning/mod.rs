@@ -0,0 +1,543 @@
+//! Provisioning engine: finds the scripts matching an event and runs them.
+//!
+//! Scripts are Rhai, run in-process by [`embedded`]. Python scripts run as
+//! `python3` child processes by [`python`] when enabled with
+//! `PROVISIONING_PYTHON`, for compatibility with existing scripts.
+//!
+//! Scripts come from files under the provisioning root, or from the
+//! [`repository`] in the database, whose versions take precedence.
+//!
+//! Rhai scripts handling an Inform can also talk to the device while they
+//! run, through a [`Conversation`].
+//!
+//! With a database, scripts also get the device's inventory [`context`], and
+//! may return [`updates`] to its inventory record next to their actions.
+//!
+//! Declarative [`rules`] cover the simple cases without a script; matching
+//! rules run before the scripts.
+
+pub mod context;
+mod conversation;
+mod embedded;
+mod python;
+pub mod repository;
+pub mod rules;
+pub mod runs;
+pub mod updates;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use nats_common::Action;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn};
+
+pub use conversation::{Conversation, Exchange};
+pub use embedded::Limits;
+use embedded::RhaiBackend;
+use repository::Runnable;
+use rules::RuleBook;
+use updates::InventoryUpdate;
+
+/// Runs provisioning scripts. Cheap to clone; compiled scripts are shared.
+#[derive(Clone)]
+pub struct Provisioner {
+    inner: Arc<Inner>,
+}
+
+/// The event scripts run for, and the device it concerns: together they
+/// select the scripts.
+#[derive(Debug, Clone, Copy)]
+pub struct Target<'a> {
+    /// e.g. "inform", "session_ended".
+    pub event_type:  &'a str,
+    pub domain_slug: &'a str,
+    pub hw_version:  Option<&'a str>,
+    pub sw_version:  Option<&'a str>,
+    /// "{oui}-{serial}".
+    pub device_id:   &'a str,
+    pub lifecycle:   Lifecycle,
+}
+
+/// Where the device is in its life. Picks the one script run at each level,
+/// and is passed to scripts as `lifecycle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lifecycle {
+    /// First seen by this ACS: `add`.
+    Add,
+    /// Known device: `update`.
+    Update,
+    /// Being decommissioned: `delete`.
+    Delete,
+}
+
+impl Lifecycle {
+    /// Script name, without extension.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Add    => "add",
+            Self::Update => "update",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+/// What one script returned and wrote.
+#[derive(Debug)]
+pub struct ScriptRun {
+    /// Path relative to the provisioning root, e.g. `inform/default/add.rhai`.
+    pub script:  String,
+    /// Version of a stored script; `None` for a file.
+    pub version: Option<i32>,
+    pub actions: Vec<Action>,
+    /// Changes to the device's inventory record, applied with [`updates::apply`].
+    pub updates:  Vec<InventoryUpdate>,
+    /// Sent to the device with `send()` while the script ran.
+    pub sent:     Vec<Exchange>,
+    /// Rhai `print` / `debug` output, or Python stderr.
+    pub output:   String,
+    /// Why the script failed; its actions and updates are then empty.
+    pub error:    Option<String>,
+    pub duration: Duration,
+}
+
+/// Where a script found for a target comes from.
+#[derive(Debug)]
+enum Source {
+    /// A file under the provisioning root.
+    File(PathBuf),
+    /// A version from the repository. `name` is its path relative to the
+    /// provisioning root.
+    Stored { name: String, script: Runnable },
+}
+
+impl Source {
+    /// The file to run with Python, if this is a Python script. Stored
+    /// scripts are always Rhai: domain admins upload them, and Python runs
+    /// unsandboxed on the controller host.
+    fn python_file(&self) -> Option<&Path> {
+        match self {
+            Self::File(path) => path.extension().is_some_and(|e| e == "py").then_some(path.as_path()),
+            Self::Stored { .. } => None,
+        }
+    }
+}
+
+/// A backend's result for one script.
+struct Outcome {
+    returned: Result<(Vec<Action>, Vec<InventoryUpdate>)>,
+    output:   String,
+}
+
+impl Outcome {
+    fn failed(error: anyhow::Error) -> Self {
+        Self { returned: Err(error), output: String::new() }
+    }
+}
+
+/// Output kept per script run; the rest is dropped.
+const MAX_OUTPUT: usize = 64 * 1024;
+
+/// Append `text` to a script's `output` as a line, up to [`MAX_OUTPUT`].
+fn append_output(output: &mut String, text: &str) {
+    let text = text.trim_end();
+    if text.is_empty() || output.len() >= MAX_OUTPUT {
+        return;
+    }
+    let mut end = text.len().min(MAX_OUTPUT - output.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    output.push_str(&text[..end]);
+    output.push('\n');
+}
+
+struct Inner {
+    root:    PathBuf,
+    /// Database holding the script repository.
+    store:   Option<sqlx::PgPool>,
+    rules:   RuleBook,
+    rhai:    RhaiBackend,
+    python:  bool,
+    timeout: Duration,
+}
+
+impl Provisioner {
+    /// Scripts are looked up under `root`, and in the repository in `store`
+    /// if given. With `python` set, `.py` scripts run too, limited to
+    /// `limits.timeout` each.
+    pub fn new(root: PathBuf, store: Option<sqlx::PgPool>, limits: Limits, python: bool) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                rules: RuleBook::new(root.join(rules::DIR)),
+                root,
+                store,
+                rhai: RhaiBackend::new(limits),
+                python,
+                timeout: limits.timeout,
+            }),
+        }
+    }
+
+    /// Load the rules under `{root}/rules`, failing if any is invalid.
+    /// Returns how many there are. Rules not loaded this way are loaded on
+    /// first use, and reloaded whenever their files change.
+    pub fn load_rules(&self) -> Result<usize> {
+        self.inner.rules.load()
+    }
+
+    /// Check that `source` compiles as a Rhai script.
+    pub fn check_rhai(&self, source: &str) -> Result<()> {
+        self.inner.rhai.check(source)
+    }
+
+    /// Scans the provisioning directory hierarchy for matching scripts and executes them.
+    ///
+    /// Directory layout (event-type first, general → specific):
+    ///
+    /// ```text
+    /// {root}/
+    /// └── {event_type}/               ← "inform", "session_ended", "decommission"
+    ///     └── {domain_slug}/          ← e.g. "default", "acme"
+    ///         ├── add.rhai            ← new device (inform)
+    ///         ├── update.rhai         ← known device (inform, session_ended)
+    ///         ├── delete.rhai         ← device being deleted (decommission)
+    ///         └── {hw_version}/
+    ///             ├── add.rhai
+    ///             └── {sw_version}/
+    ///                 ├── add.rhai
+    ///                 └── {device_id}/    ← "{oui}-{serial}", e.g. "AABB00-1234567"
+    ///                     └── add.rhai
+    /// ```
+    ///
+    /// At each level the script named after `target.lifecycle` runs, if it
+    /// exists, in order (general → specific). Their returned actions are
+    /// merged into a single list; inventory updates are left out, see
+    /// [`Self::run_scripts_traced`]. With Python enabled, `add.py` runs where
+    /// there is no `add.rhai`, and so on.
+    ///
+    /// A script stored in the repository at the same place, with a version
+    /// to run for the device, replaces the file.
+    ///
+    /// The [`rules`] matching the event run first, in order; each is
+    /// reported like a script named `rules/{file}#{rule}`.
+    ///
+    /// Scripts get the payload with `lifecycle` added and, with a database,
+    /// `context`: the device's [`context`], or null if it is not in the
+    /// inventory yet.
+    ///
+    /// With a `conversation`, Rhai scripts may `send()` actions to the
+    /// device as they run. Once its session ends, the remaining scripts are skipped.
+    pub async fn run_scripts(
+        &self,
+        target: &Target<'_>,
+        payload_bytes: &[u8],
+        conversation: Option<&Conversation>,
+    ) -> Result<Vec<Action>> {
+        let runs = self.run_scripts_traced(target, payload_bytes, conversation).await?;
+        Ok(runs.into_iter().flat_map(|run| run.actions).collect())
+    }
+
+    /// [`Self::run_scripts`], reporting what each script returned and wrote.
+    pub async fn run_scripts_traced(
+        &self,
+        target: &Target<'_>,
+        payload_bytes: &[u8],
+        conversation: Option<&Conversation>,
+    ) -> Result<Vec<ScriptRun>> {
+        let mut payload: serde_json::Value =
+            serde_json::from_slice(payload_bytes).context("Provisioning payload is not JSON")?;
+        if let Some(fields) = payload.as_object_mut() {
+            fields.insert("lifecycle".into(), target.lifecycle.as_str().into());
+            if let Some(pool) = &self.inner.store {
+                let context = context::load(pool, target).await.context("Failed to load device context")?;
+                fields.insert("context".into(), context.unwrap_or_default());
+            }
+        }
+        // A JSON value always serialises.
+        let payload_bytes = serde_json::to_vec(&payload).expect("payload serialises");
+        let payload = Arc::new(payload);
+
+        let mut runs = rules::evaluate(&self.inner.rules.current(), target, &payload);
+        for source in self.scripts(target).await? {
+            let (script, version) = match &source {
+                Source::File(path) => {
+                    (path.strip_prefix(&self.inner.root).unwrap_or(path).display().to_string(), None)
+                }
+                Source::Stored { name, script } => (name.clone(), Some(script.version)),
+            };
+            if conversation.is_some_and(Conversation::ended) {
+                warn!(device_id = target.device_id, script, "Session ended — skipping remaining scripts");
+                break;
+            }
+
+            debug!(script, ?version, "Executing provisioning script");
+
+            let started = Instant::now();
+            let (outcome, sent) = self.execute(source, &payload, &payload_bytes, conversation).await;
+            let duration = started.elapsed();
+            let run = match outcome.returned {
+                Ok((actions, updates)) => {
+                    info!(
+                        script,
+                        ?version,
+                        count = actions.len(),
+                        updates = updates.len(),
+                        "Script returned actions",
+                    );
+                    ScriptRun { script, version, actions, updates, sent, output: outcome.output, error: None, duration }
+                }
+                Err(e) => {
+                    // Log and continue — a failing script must not abort the others.
+                    error!(script, ?version, error = ?e, "Provisioning script failed");
+                    let error = Some(format!("{e:#}"));
+                    ScriptRun {
+                        script,
+                        version,
+                        actions: Vec::new(),
+                        updates: Vec::new(),
+                        sent,
+                        output: outcome.output,
+                        error,
+                        duration,
+                    }
+                }
+            };
+            runs.push(run);
+        }
+
+        Ok(runs)
+    }
+
+    /// The directories searched for `target`, from general to specific,
+    /// relative to the provisioning root. They need not exist.
+    pub fn directories(&self, target: &Target<'_>) -> Vec<String> {
+        self.dirs(target)
+            .iter()
+            .map(|dir| dir.strip_prefix(&self.inner.root).unwrap_or(dir).display().to_string())
+            .collect()
+    }
+
+    /// The scripts for `target`, from general to specific.
+    async fn scripts(&self, target: &Target<'_>) -> Result<Vec<Source>> {
+        let base = self.inner.root.join(target.event_type).join(target.domain_slug);
+        let name = target.lifecycle.as_str();
+        // Stored scripts are named by their path below the domain directory.
+        let dirs: Vec<(PathBuf, String)> = self
+            .dirs(target)
+            .into_iter()
+            .map(|dir| {
+                let relative = dir.strip_prefix(&base).unwrap_or(&dir).display().to_string();
+                (dir, relative)
+            })
+            .collect();
+        let candidate = |relative: &str| match relative {
+            "" => format!("{name}.rhai"),
+            dir => format!("{dir}/{name}.rhai"),
+        };
+        let mut stored = match &self.inner.store {
+            Some(pool) => {
+                let paths: Vec<String> = dirs.iter().map(|(_, relative)| candidate(relative)).collect();
+                repository::runnable(pool, target, &paths).await.context("Failed to look up stored scripts")?
+            }
+            None => Default::default(),
+        };
+
+        let mut scripts = Vec::new();
+        for (dir, relative) in &dirs {
+            let stored = stored.remove(&candidate(relative));
+            if let Some(script) = stored {
+                let name = format!("{}/{}/{}", target.event_type, target.domain_slug, script.path);
+                scripts.push(Source::Stored { name, script });
+            } else if let Some(path) = self.find_script(dir, name) {
+                scripts.push(Source::File(path));
+            }
+        }
+        Ok(scripts)
+    }
+
+    fn dirs(&self, target: &Target<'_>) -> Vec<PathBuf> {
+        // Base: root / event_type / domain_slug
+        let base = self.inner.root.join(target.event_type).join(target.domain_slug);
+
+        // Build candidate directories from general to specific
+        let mut dirs_to_scan: Vec<PathBuf> = vec![base.clone()];
+
+        // Versions and device IDs come from the device (or a dry-run
+        // request): one that is not a plain directory name could climb into
+        // another domain's scripts, so it and the levels below are skipped.
+        if let Some(hw) = target.hw_version.filter(|hw| is_dir_name(hw)) {
+            let hw_dir = base.join(hw);
+            dirs_to_scan.push(hw_dir.clone());
+
+            if let Some(sw) = target.sw_version.filter(|sw| is_dir_name(sw)) {
+                let sw_dir = hw_dir.join(sw);
+                dirs_to_scan.push(sw_dir.clone());
+
+                if is_dir_name(target.device_id) {
+                    dirs_to_scan.push(sw_dir.join(target.device_id));
+                }
+            }
+        }
+
+        dirs_to_scan
+    }
+
+    /// `{name}.rhai` in `dir`, else `{name}.py` if Python is enabled.
+    /// `None` if neither exists or `dir` does not.
+    fn find_script(&self, dir: &Path, name: &str) -> Option<PathBuf> {
+        let rhai = dir.join(format!("{name}.rhai"));
+        if rhai.is_file() {
+            return Some(rhai);
+        }
+        let py = dir.join(format!("{name}.py"));
+        (self.inner.python && py.is_file()).then_some(py)
+    }
+
+    /// Run one script; also returns what it sent to the device.
+    async fn execute(
+        &self,
+        source: Source,
+        payload: &Arc<serde_json::Value>,
+        payload_bytes: &[u8],
+        conversation: Option<&Conversation>,
+    ) -> (Outcome, Vec<Exchange>) {
+        if let Some(path) = source.python_file() {
+            let outcome = python::execute(path, &self.inner.root, payload_bytes, self.inner.timeout).await;
+            return (outcome, Vec::new());
+        }
+
+        // Dropped if this future is, e.g. when the event times out, which
+        // stops the script waiting on the device.
+        let (_running, abandoned) = watch::channel(());
+        let conversation = conversation.map(|c| c.for_script(abandoned));
+
+        // Scripts are CPU-bound, and block while waiting on the device;
+        // keep them off the async workers.
+        let inner = self.inner.clone();
+        let payload = payload.clone();
+        let script_conversation = conversation.clone();
+        let outcome = tokio::task::spawn_blocking(move || inner.rhai.execute(&source, &payload, script_conversation))
+            .await
+            .unwrap_or_else(|e| Outcome::failed(anyhow::Error::new(e).context("Script task panicked")));
+        (outcome, conversation.map(|c| c.take_exchanges()).unwrap_or_default())
+    }
+}
+
+/// Whether `name` names one directory inside its parent.
+fn is_dir_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch script root, removed when dropped even if a test fails.
+    struct TempRoot(PathBuf);
+
+    impl TempRoot {
+        fn new(files: &[&str]) -> Self {
+            let root = std::env::temp_dir().join(format!("acs-provisioning-{}", uuid::Uuid::new_v4()));
+            for file in files {
+                let path = root.join(file);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, "").unwrap();
+            }
+            Self(root)
+        }
+    }
+
+    impl Drop for TempRoot {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn provisioner(root: &TempRoot) -> Provisioner {
+        let limits = Limits {
+            max_operations:  1000,
+            timeout:         Duration::from_secs(1),
+            max_string_size: 1024,
+            max_collection:  100,
+        };
+        Provisioner::new(root.0.clone(), None, limits, false)
+    }
+
+    /// Paths of the scripts that run for `target`.
+    async fn file_paths(provisioner: &Provisioner, target: &Target<'_>) -> Vec<PathBuf> {
+        provisioner
+            .scripts(target)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| match s {
+                Source::File(path) => path,
+                Source::Stored { .. } => unreachable!("no repository"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn runs_only_the_lifecycle_script() {
+        let root = TempRoot::new(&[
+            "inform/default/add.rhai",
+            "inform/default/update.rhai",
+            "inform/default/delete.rhai",
+            "inform/default/HW1/add.rhai",
+            "inform/default/HW1/SW1/update.py",
+        ]);
+        let base = root.0.join("inform").join("default");
+        let provisioner = provisioner(&root);
+        let scripts = |lifecycle| {
+            let target = Target {
+                event_type: "inform",
+                domain_slug: "default",
+                hw_version: Some("HW1"),
+                sw_version: Some("SW1"),
+                device_id: "AABB00-1",
+                lifecycle,
+            };
+            let provisioner = provisioner.clone();
+            async move { file_paths(&provisioner, &target).await }
+        };
+
+        assert_eq!(scripts(Lifecycle::Add).await, vec![base.join("add.rhai"), base.join("HW1/add.rhai")]);
+        // Python is off, so HW1/SW1/update.py does not run.
+        assert_eq!(scripts(Lifecycle::Update).await, vec![base.join("update.rhai")]);
+        assert_eq!(scripts(Lifecycle::Delete).await, vec![base.join("delete.rhai")]);
+    }
+
+    #[tokio::test]
+    async fn versions_cannot_leave_the_domain() {
+        let root = TempRoot::new(&["inform/acme/add.rhai", "inform/globex/add.rhai"]);
+        let provisioner = provisioner(&root);
+
+        for (hw, sw, device_id) in [
+            ("../globex", "SW1", "AABB00-1"),
+            ("..", "globex", "AABB00-1"),
+            ("HW1", "..\\..\\globex", "AABB00-1"),
+            ("HW1", "SW1", "../../../globex"),
+        ] {
+            let target = Target {
+                event_type: "inform",
+                domain_slug: "acme",
+                hw_version: Some(hw),
+                sw_version: Some(sw),
+                device_id,
+                lifecycle: Lifecycle::Add,
+            };
+            assert_eq!(
+                file_paths(&provisioner, &target).await,
+                vec![root.0.join("inform/acme/add.rhai")],
+                "{hw} / {sw} / {device_id}",
+            );
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Python backend: each script runs as a `python3` child process.
+//!
+//! Opt-in (`PROVISIONING_PYTHON=true`), for scripts written against the
+//! Python `acs_sdk`. The script reads the payload JSON on stdin and writes a
+//! JSON array of actions and inventory updates to stdout. Python scripts are not sandboxed; only
+//! their run time is limited.
+//!
+//! `acs_sdk` is importable from the provisioning root. Only files under the
+//! provisioning root run here; the script repository holds Rhai only.
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use nats_common::Action;
+use serde_json::Value as JsonValue;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use super::updates::{self, InventoryUpdate};
+use super::{append_output, Outcome};
+
+/// Run a script. Its stderr is the run's output.
+pub async fn execute(path: &Path, root: &Path, payload_bytes: &[u8], timeout: Duration) -> Outcome {
+    let mut output = String::new();
+    let returned = run(path, root, payload_bytes, timeout, &mut output).await;
+    Outcome { returned, output }
+}
+
+async fn run(
+    script_path: &Path,
+    root: &Path,
+    payload_bytes: &[u8],
+    timeout: Duration,
+    stderr: &mut String,
+) -> Result<(Vec<Action>, Vec<InventoryUpdate>)> {
+    let mut child = Command::new("python3")
+        .arg(script_path)
+        .env("PYTHONPATH", root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Killed on timeout, and when its event is abandoned.
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to spawn python3 process")?;
+
+    // Write payload to stdin
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(payload_bytes).await.context("Failed to write to script stdin")?;
+    } else {
+        anyhow::bail!("Failed to open stdin for python3 process");
+    }
+
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| anyhow::anyhow!("Script timed out after {timeout:?}"))?
+        .context("Failed to wait on python3 process")?;
+
+    append_output(stderr, &String::from_utf8_lossy(&output.stderr));
+    if !output.status.success() {
+        anyhow::bail!("Script exited with status {}:\n{}", output.status, stderr);
+    }
+
+    if output.stdout.is_empty() {
+        return Ok(Default::default());
+    }
+
+    // Try to parse stdout as a JSON array of actions and updates
+    let returned: Vec<JsonValue> = serde_json::from_slice(&output.stdout)
+        .context("Failed to parse script stdout as JSON array of Actions")?;
+
+    updates::split(returned)
+}
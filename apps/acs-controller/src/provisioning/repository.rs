@@ -0,0 +1,265 @@
+//! Script repository: provisioning scripts stored in the database.
+//!
+//! A stored script sits at a place of the provisioning tree, given by an
+//! event type, a domain and a path below the domain's directory, and has a
+//! history of immutable versions. At that place its active version (or,
+//! for members of its staging group, its staged version) runs instead of
+//! the file on disk. Scripts are managed through `/api/v1/provisioning/scripts`.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{Lifecycle, Target};
+
+/// Event types with provisioning scripts.
+pub const EVENT_TYPES: [&str; 3] = ["inform", "session_ended", "decommission"];
+
+/// One row of `provisioning_scripts`.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Script {
+    pub id:               Uuid,
+    pub event_type:       String,
+    pub domain_id:        Uuid,
+    /// Below `{event_type}/{domain slug}/`, e.g. `HW1/2.0.0/update.rhai`.
+    pub path:             String,
+    pub active_version:   Option<i32>,
+    pub previous_version: Option<i32>,
+    pub staged_version:   Option<i32>,
+    pub staged_group_id:  Option<Uuid>,
+    pub created_by:       Option<Uuid>,
+    pub created_at:       chrono::DateTime<chrono::Utc>,
+    pub updated_at:       chrono::DateTime<chrono::Utc>,
+}
+
+pub const SCRIPT_COLUMNS: &str = "id, event_type, domain_id, path, active_version, previous_version, \
+                                  staged_version, staged_group_id, created_by, created_at, updated_at";
+
+/// One row of `provisioning_script_versions`, without its source.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ScriptVersion {
+    pub version:    i32,
+    pub comment:    Option<String>,
+    pub created_by: Option<Uuid>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// The version of a stored script that runs for a device.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Runnable {
+    /// `provisioning_script_versions.id`; versions never change, so this
+    /// identifies the source.
+    pub id:      Uuid,
+    /// Below the domain's directory.
+    pub path:    String,
+    pub version: i32,
+    pub source:  String,
+}
+
+/// Check `path` names a script of the provisioning tree, relative to a
+/// domain directory: up to three directories (hardware version, software
+/// version, device) and an `{add,update,delete}.rhai` file. Stored scripts
+/// are Rhai only: Python scripts run unsandboxed on the controller host.
+pub fn validate_path(path: &str) -> Result<(), String> {
+    let segments: Vec<&str> = path.split('/').collect();
+    let Some((file, dirs)) = segments.split_last() else {
+        return Err("path is empty".into());
+    };
+    if dirs.len() > 3 {
+        return Err("path has more than three directories (hardware, software, device)".into());
+    }
+    for dir in dirs {
+        if dir.is_empty() || *dir == "." || *dir == ".." {
+            return Err(format!("invalid directory {dir:?}"));
+        }
+        if !dir.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)) {
+            return Err(format!("directory {dir:?} may only hold letters, digits, '.', '_' and '-'"));
+        }
+    }
+    let valid_file = [Lifecycle::Add, Lifecycle::Update, Lifecycle::Delete]
+        .iter()
+        .any(|l| *file == format!("{}.rhai", l.as_str()));
+    if !valid_file {
+        return Err(format!("file {file:?} must be add.rhai, update.rhai or delete.rhai"));
+    }
+    Ok(())
+}
+
+/// The stored scripts to run for `target` among `paths` (below the domain's
+/// directory), keyed by path. Members of a script's staging group get its
+/// staged version; other devices get its active version, if any.
+pub async fn runnable(pool: &PgPool, target: &Target<'_>, paths: &[String]) -> Result<HashMap<String, Runnable>, sqlx::Error> {
+    let scripts = sqlx::query_as::<_, Runnable>(
+        r#"
+        SELECT v.id, s.path, v.version, v.source
+        FROM provisioning_scripts s
+        JOIN provisioning_script_versions v
+          ON v.script_id = s.id
+         AND v.version = CASE
+                WHEN s.staged_version IS NOT NULL AND EXISTS (
+                    SELECT 1 FROM device_group_members m
+                    JOIN devices d ON d.id = m.device_id
+                    WHERE m.group_id = s.staged_group_id AND d.device_uid = $4)
+                THEN s.staged_version
+                ELSE s.active_version
+             END
+        WHERE s.event_type = $1
+          AND s.domain_id = (SELECT id FROM domains WHERE slug = $2)
+          AND s.path = ANY($3)
+        "#,
+    )
+    .bind(target.event_type)
+    .bind(target.domain_slug)
+    .bind(paths)
+    .bind(target.device_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(scripts.into_iter().map(|s| (s.path.clone(), s)).collect())
+}
+
+/// A new version of the script at a place of the tree.
+pub struct Upload<'a> {
+    pub event_type: &'a str,
+    pub domain_id:  Uuid,
+    /// Checked with [`validate_path`].
+    pub path:       &'a str,
+    pub source:     &'a str,
+    pub comment:    Option<&'a str>,
+    /// Make the new version the active one.
+    pub activate:   bool,
+}
+
+/// Add a version of the script at `upload.path`, creating the script if
+/// needed. Returns the script and the new version number.
+pub async fn upload(pool: &PgPool, upload: &Upload<'_>, user_id: Uuid) -> Result<(Script, i32), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // The upsert locks the script row, which serialises uploads to it.
+    let script_id: Uuid = sqlx::query_scalar(
+        r#"
+        INSERT INTO provisioning_scripts (event_type, domain_id, path, created_by)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (event_type, domain_id, path) DO UPDATE SET updated_at = now()
+        RETURNING id
+        "#,
+    )
+    .bind(upload.event_type)
+    .bind(upload.domain_id)
+    .bind(upload.path)
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let version: i32 = sqlx::query_scalar(
+        r#"
+        INSERT INTO provisioning_script_versions (script_id, version, source, comment, created_by)
+        SELECT $1, COALESCE(MAX(version), 0) + 1, $2, $3, $4
+        FROM provisioning_script_versions WHERE script_id = $1
+        RETURNING version
+        "#,
+    )
+    .bind(script_id)
+    .bind(upload.source)
+    .bind(upload.comment)
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if upload.activate {
+        sqlx::query(ACTIVATE).bind(script_id).bind(version).execute(&mut *tx).await?;
+    }
+    let script = sqlx::query_as::<_, Script>(&format!("SELECT {SCRIPT_COLUMNS} FROM provisioning_scripts WHERE id = $1"))
+        .bind(script_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok((script, version))
+}
+
+/// Make `version` (`None`: no version) the active one, remembering the
+/// current one for [`rollback`]. Staging of `version` ends.
+pub async fn activate(pool: &PgPool, script_id: Uuid, version: Option<i32>) -> Result<Script, sqlx::Error> {
+    sqlx::query_as::<_, Script>(&format!("{ACTIVATE} RETURNING {SCRIPT_COLUMNS}"))
+        .bind(script_id)
+        .bind(version)
+        .fetch_one(pool)
+        .await
+}
+
+const ACTIVATE: &str = r#"
+    UPDATE provisioning_scripts
+    SET previous_version = active_version,
+        active_version   = $2,
+        staged_version   = CASE WHEN staged_version = $2 THEN NULL ELSE staged_version END,
+        staged_group_id  = CASE WHEN staged_version = $2 THEN NULL ELSE staged_group_id END,
+        updated_at       = now()
+    WHERE id = $1
+"#;
+
+/// Swap the active and previous versions. `None` if there is no previous
+/// version to go back to.
+pub async fn rollback(pool: &PgPool, script_id: Uuid) -> Result<Option<Script>, sqlx::Error> {
+    sqlx::query_as::<_, Script>(&format!(
+        r#"
+        UPDATE provisioning_scripts
+        SET active_version   = previous_version,
+            previous_version = active_version,
+            updated_at       = now()
+        WHERE id = $1 AND previous_version IS NOT NULL
+        RETURNING {SCRIPT_COLUMNS}
+        "#
+    ))
+    .bind(script_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Run a version instead of the active one for the members of a group,
+/// given as `(version, group_id)`. `None` stops staging.
+pub async fn stage(pool: &PgPool, script_id: Uuid, staged: Option<(i32, Uuid)>) -> Result<Script, sqlx::Error> {
+    sqlx::query_as::<_, Script>(&format!(
+        r#"
+        UPDATE provisioning_scripts
+        SET staged_version  = $2,
+            staged_group_id = $3,
+            updated_at      = now()
+        WHERE id = $1
+        RETURNING {SCRIPT_COLUMNS}
+        "#
+    ))
+    .bind(script_id)
+    .bind(staged.map(|(version, _)| version))
+    .bind(staged.map(|(_, group_id)| group_id))
+    .fetch_one(pool)
+    .await
+}
+
+/// Whether `version` of the script exists.
+pub async fn has_version(pool: &PgPool, script_id: Uuid, version: i32) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM provisioning_script_versions WHERE script_id = $1 AND version = $2)")
+        .bind(script_id)
+        .bind(version)
+        .fetch_one(pool)
+        .await
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_stay_inside_the_tree() {
+        for ok in ["add.rhai", "HW1/add.rhai", "HW1/2.0.0/AABB00-1234567/delete.rhai"] {
+            assert_eq!(validate_path(ok), Ok(()), "{ok}");
+        }
+        for bad in ["", "x.rhai", "add.sh", "../add.rhai", "HW1/../add.rhai", "a//add.rhai", "/add.rhai", "a/b/c/d/add.rhai", "a b/add.rhai", "update.py"] {
+            assert!(validate_path(bad).is_err(), "{bad}");
+        }
+    }
+}
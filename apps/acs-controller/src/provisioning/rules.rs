@@ -0,0 +1,567 @@
+//! Declarative provisioning rules, for the common "if the device is X and
+//! sent event Y, set these parameters" cases that need no script.
+//!
+//! Rules are YAML files under `{root}/rules/`:
+//!
+//! ```yaml
+//! rules:
+//!   - name: periodic-inform
+//!     event: inform                   # default; also session_ended, decommission
+//!     domain: acme                    # any domain if left out
+//!     lifecycle: add                  # any if left out
+//!     match:
+//!       events: ["0 BOOTSTRAP"]       # any of them; command keys ignored
+//!       product_class: "HG8*"         # `*` matches anything; a list matches any
+//!       software_version: ["1.*", "2.0.*"]
+//!       tags: [pilot]                 # all of them
+//!       parameters:
+//!         Device.ManagementServer.PeriodicInformEnable: "false"
+//!     actions:
+//!       - SetParameterValues:
+//!           parameters:
+//!             Device.ManagementServer.PeriodicInformInterval: "{{ property.inform_interval | 3600 }}"
+//!       - AddTag: { tag: periodic }
+//! ```
+//!
+//! A rule matches when all its criteria do. Its actions are [`Action`]s, or
+//! inventory updates, in their JSON form. Strings in them may hold
+//! `{{ source.key }}` placeholders, with an optional `| default`:
+//! `device_id`, `domain`, `param.<path>` (from the event, else the stored
+//! value), `property.<name>` and `metadata.<key>` (from the device context).
+//!
+//! Files are read in name order and their rules kept in file order; that is
+//! the order matching rules run in, all before the scripts. Everything is
+//! checked when the files are loaded; a rule set that fails the checks is
+//! not used.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use nats_common::Action;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value as JsonValue;
+use tracing::{error, info};
+
+use super::repository::EVENT_TYPES;
+use super::{updates, Lifecycle, ScriptRun, Target};
+
+/// Directory holding the rule files, below the provisioning root.
+pub const DIR: &str = "rules";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    rules: Vec<Rule>,
+}
+
+/// One rule of a rule file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name:      String,
+    #[serde(default = "default_event")]
+    pub event:     String,
+    pub domain:    Option<String>,
+    pub lifecycle: Option<Lifecycle>,
+    #[serde(default, rename = "match")]
+    pub criteria:  Criteria,
+    pub actions:   Vec<JsonValue>,
+    /// `rules/{file}#{name}`, as reported in runs.
+    #[serde(skip)]
+    pub id:        String,
+}
+
+fn default_event() -> String {
+    "inform".into()
+}
+
+/// What a rule matches on. Criteria left out match anything.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Criteria {
+    /// Inform event codes; any of them.
+    #[serde(default)]
+    pub events:           Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub oui:              Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub manufacturer:     Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub product_class:    Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub hardware_version: Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub software_version: Vec<String>,
+    /// Device tags; all of them.
+    #[serde(default)]
+    pub tags:             Vec<String>,
+    /// Parameter path → patterns its value must match.
+    #[serde(default)]
+    pub parameters:       BTreeMap<String, OneOrMany>,
+}
+
+/// A pattern, or a list of patterns any of which may match.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn patterns(&self) -> &[String] {
+        match self {
+            Self::One(p) => std::slice::from_ref(p),
+            Self::Many(ps) => ps,
+        }
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(p) => vec![p],
+        OneOrMany::Many(ps) => ps,
+    })
+}
+
+// ── Loading ───────────────────────────────────────────────────────────────────
+
+/// The rules under a directory, reloaded when its files change.
+pub struct RuleBook {
+    dir:    PathBuf,
+    loaded: Mutex<Loaded>,
+}
+
+#[derive(Default)]
+struct Loaded {
+    /// Files and modification times the rules were loaded from.
+    files: Option<Vec<(PathBuf, SystemTime)>>,
+    rules: Arc<Vec<Rule>>,
+}
+
+impl RuleBook {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir, loaded: Mutex::default() }
+    }
+
+    /// Load the rules, failing if any file does not pass the checks.
+    /// Returns how many rules there are.
+    pub fn load(&self) -> Result<usize> {
+        let files = self.files()?;
+        let rules = load_files(&files)?;
+        let count = rules.len();
+        *self.loaded.lock().unwrap_or_else(|e| e.into_inner()) = Loaded { files: Some(files), rules: Arc::new(rules) };
+        Ok(count)
+    }
+
+    /// The current rules. Changed files are loaded again; if they fail the
+    /// checks, the error is logged once and the previous rules stay.
+    pub fn current(&self) -> Arc<Vec<Rule>> {
+        let mut loaded = self.loaded.lock().unwrap_or_else(|e| e.into_inner());
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => {
+                error!(dir = %self.dir.display(), error = ?e, "Failed to list provisioning rules");
+                return loaded.rules.clone();
+            }
+        };
+        if loaded.files.as_ref() != Some(&files) {
+            match load_files(&files) {
+                Ok(rules) => {
+                    info!(count = rules.len(), "Provisioning rules reloaded");
+                    loaded.rules = Arc::new(rules);
+                }
+                Err(e) => error!(error = format!("{e:#}"), "Invalid provisioning rules — keeping the previous ones"),
+            }
+            loaded.files = Some(files);
+        }
+        loaded.rules.clone()
+    }
+
+    /// The `.yaml` / `.yml` files of the directory, in name order. None if
+    /// it does not exist.
+    fn files(&self) -> Result<Vec<(PathBuf, SystemTime)>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("Failed to read rules directory"),
+        };
+        let mut files = Vec::new();
+        for entry in entries {
+            let path = entry.context("Failed to read rules directory")?.path();
+            if path.extension().is_some_and(|e| e == "yaml" || e == "yml") {
+                let modified = std::fs::metadata(&path).and_then(|m| m.modified()).context("Failed to stat rule file")?;
+                files.push((path, modified));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+fn load_files(files: &[(PathBuf, SystemTime)]) -> Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    for (path, _) in files {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        rules.extend(parse(&name, &text).with_context(|| format!("In {}", path.display()))?);
+    }
+    Ok(rules)
+}
+
+/// Parse and check the rules of the file `name`.
+pub fn parse(name: &str, text: &str) -> Result<Vec<Rule>> {
+    let file: RuleFile = serde_yaml::from_str(text).context("Invalid rule file")?;
+    let mut names = HashSet::new();
+    let mut rules = file.rules;
+    for rule in &mut rules {
+        if rule.name.trim().is_empty() {
+            bail!("A rule has no name");
+        }
+        if !names.insert(rule.name.clone()) {
+            bail!("Rule {:?} is defined twice", rule.name);
+        }
+        check(rule).with_context(|| format!("Rule {:?}", rule.name))?;
+        rule.id = format!("{DIR}/{name}#{}", rule.name);
+    }
+    Ok(rules)
+}
+
+fn check(rule: &Rule) -> Result<()> {
+    if !EVENT_TYPES.contains(&rule.event.as_str()) {
+        bail!("event must be one of {}", EVENT_TYPES.join(", "));
+    }
+    if rule.actions.is_empty() {
+        bail!("no actions");
+    }
+    let c = &rule.criteria;
+    let lists = [&c.events, &c.oui, &c.manufacturer, &c.product_class, &c.hardware_version, &c.software_version, &c.tags];
+    if lists.iter().any(|l| l.iter().any(|p| p.is_empty())) || c.parameters.values().any(|p| p.patterns().is_empty()) {
+        bail!("match has an empty value or list");
+    }
+    for (i, action) in rule.actions.iter().enumerate() {
+        each_string(action, &mut |s| parse_template(s).map(drop)).with_context(|| format!("Action {i}"))?;
+    }
+    // Placeholders sit inside strings, so the templates parse as they are.
+    updates::split(rule.actions.clone())?;
+    Ok(())
+}
+
+// ── Matching ──────────────────────────────────────────────────────────────────
+
+/// What rules match against: the target and the payload scripts get, with
+/// `context`.
+struct Facts<'a> {
+    target:  &'a Target<'a>,
+    payload: &'a JsonValue,
+}
+
+impl Facts<'_> {
+    fn context(&self, section: &str) -> &JsonValue {
+        &self.payload["context"][section]
+    }
+
+    /// A payload field, else the device's field in the context.
+    fn device_field(&self, field: &str) -> Option<&str> {
+        self.payload[field].as_str().or_else(|| self.context("device")[field].as_str())
+    }
+
+    fn param(&self, path: &str) -> Option<&str> {
+        self.payload["parameter_list"][path].as_str().or_else(|| self.context("parameters")[path].as_str())
+    }
+
+    fn events(&self) -> impl Iterator<Item = &str> {
+        self.payload["events"].as_array().into_iter().flatten().filter_map(JsonValue::as_str)
+    }
+
+    fn tags(&self) -> impl Iterator<Item = &str> {
+        self.context("device")["tags"].as_array().into_iter().flatten().filter_map(JsonValue::as_str)
+    }
+}
+
+impl Rule {
+    fn applies(&self, facts: &Facts<'_>) -> bool {
+        let target = facts.target;
+        if self.event != target.event_type
+            || self.domain.as_deref().is_some_and(|d| d != target.domain_slug)
+            || self.lifecycle.is_some_and(|l| l != target.lifecycle)
+        {
+            return false;
+        }
+
+        let c = &self.criteria;
+        let any = |patterns: &[String], value: Option<&str>| {
+            patterns.is_empty() || value.is_some_and(|v| patterns.iter().any(|p| glob(p, v)))
+        };
+        (c.events.is_empty()
+            || facts.events().any(|e| c.events.iter().any(|want| crate::db::event_is(e, want))))
+            && any(&c.oui, facts.device_field("oui"))
+            && any(&c.manufacturer, facts.device_field("manufacturer"))
+            && any(&c.product_class, facts.device_field("product_class"))
+            && any(&c.hardware_version, target.hw_version)
+            && any(&c.software_version, target.sw_version)
+            && c.tags.iter().all(|t| facts.tags().any(|have| have == t))
+            && c.parameters.iter().all(|(path, p)| any(p.patterns(), facts.param(path)))
+    }
+
+    /// The rule's actions and updates, placeholders filled in.
+    fn render(&self, facts: &Facts<'_>) -> Result<(Vec<Action>, Vec<updates::InventoryUpdate>)> {
+        let mut rendered = Vec::with_capacity(self.actions.len());
+        for action in &self.actions {
+            rendered.push(render_value(action, facts)?);
+        }
+        updates::split(rendered)
+    }
+}
+
+/// Run the rules matching `target`, in order, each reported like a script.
+pub(super) fn evaluate(rules: &[Rule], target: &Target<'_>, payload: &JsonValue) -> Vec<ScriptRun> {
+    let facts = Facts { target, payload };
+    let mut runs = Vec::new();
+    for rule in rules.iter().filter(|r| r.applies(&facts)) {
+        let started = Instant::now();
+        let (actions, updates, error) = match rule.render(&facts) {
+            Ok((actions, updates)) => {
+                info!(rule = rule.id, count = actions.len(), updates = updates.len(), "Rule matched");
+                (actions, updates, None)
+            }
+            Err(e) => {
+                error!(rule = rule.id, error = ?e, "Provisioning rule failed");
+                (Vec::new(), Vec::new(), Some(format!("{e:#}")))
+            }
+        };
+        runs.push(ScriptRun {
+            script: rule.id.clone(),
+            version: None,
+            actions,
+            updates,
+            sent: Vec::new(),
+            output: String::new(),
+            error,
+            duration: started.elapsed(),
+        });
+    }
+    runs
+}
+
+/// Whether `value` matches `pattern`, where `*` stands for any characters.
+fn glob(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // `split` always yields at least one part.
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No `*`: exact match.
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+// ── Templates ─────────────────────────────────────────────────────────────────
+
+enum Piece<'a> {
+    Text(&'a str),
+    Value { source: &'a str, key: &'a str, default: Option<&'a str> },
+}
+
+/// Split `template` into text and `{{ source.key | default }}` placeholders.
+fn parse_template(template: &str) -> Result<Vec<Piece<'_>>> {
+    let mut pieces = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        pieces.push(Piece::Text(&rest[..start]));
+        let Some(len) = rest[start..].find("}}") else {
+            bail!("unclosed placeholder in {template:?}");
+        };
+        let inner = &rest[start + 2..start + len];
+        let (expr, default) = match inner.split_once('|') {
+            Some((expr, default)) => (expr.trim(), Some(default.trim())),
+            None => (inner.trim(), None),
+        };
+        let (source, key) = expr.split_once('.').unwrap_or((expr, ""));
+        match (source, key.is_empty()) {
+            ("device_id" | "domain", true) | ("param" | "property" | "metadata", false) => {}
+            _ => bail!(
+                "unknown placeholder {{{{{inner}}}}}; use device_id, domain, param.<path>, property.<name> or metadata.<key>"
+            ),
+        }
+        pieces.push(Piece::Value { source, key, default });
+        rest = &rest[start + len + 2..];
+    }
+    pieces.push(Piece::Text(rest));
+    Ok(pieces)
+}
+
+fn render(template: &str, facts: &Facts<'_>) -> Result<String> {
+    let mut out = String::new();
+    for piece in parse_template(template)? {
+        let (source, key, default) = match piece {
+            Piece::Text(text) => {
+                out.push_str(text);
+                continue;
+            }
+            Piece::Value { source, key, default } => (source, key, default),
+        };
+        let value = match source {
+            "device_id" => facts.target.device_id.to_string().into(),
+            "domain" => facts.target.domain_slug.to_string().into(),
+            "param" => facts.param(key).map(JsonValue::from).unwrap_or_default(),
+            "property" => facts.context("properties")[key].clone(),
+            _ => facts.context("device")["metadata"][key].clone(),
+        };
+        match (value, default) {
+            (JsonValue::String(s), _) => out.push_str(&s),
+            (JsonValue::Null, Some(default)) => out.push_str(default),
+            (JsonValue::Null, None) => bail!("{source}.{key} has no value and no default"),
+            (value, _) => out.push_str(&value.to_string()),
+        }
+    }
+    Ok(out)
+}
+
+fn render_value(value: &JsonValue, facts: &Facts<'_>) -> Result<JsonValue> {
+    Ok(match value {
+        JsonValue::String(s) => render(s, facts)?.into(),
+        JsonValue::Array(items) => items.iter().map(|v| render_value(v, facts)).collect::<Result<_>>()?,
+        JsonValue::Object(fields) => fields
+            .iter()
+            .map(|(k, v)| Ok((render(k, facts)?, render_value(v, facts)?)))
+            .collect::<Result<_>>()?,
+        other => other.clone(),
+    })
+}
+
+/// Call `f` on every string of `value`, keys included.
+fn each_string(value: &JsonValue, f: &mut impl FnMut(&str) -> Result<()>) -> Result<()> {
+    match value {
+        JsonValue::String(s) => f(s),
+        JsonValue::Array(items) => items.iter().try_for_each(|v| each_string(v, f)),
+        JsonValue::Object(fields) => fields.iter().try_for_each(|(k, v)| {
+            f(k)?;
+            each_string(v, f)
+        }),
+        _ => Ok(()),
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+rules:
+  - name: periodic-inform
+    lifecycle: add
+    match:
+      events: ["0 BOOTSTRAP"]
+      product_class: "HG8*"
+      software_version: ["1.*", "2.0.*"]
+      tags: [pilot]
+      parameters:
+        Device.ManagementServer.PeriodicInformEnable: "false"
+    actions:
+      - SetParameterValues:
+          parameters:
+            Device.ManagementServer.PeriodicInformInterval: "{{ property.interval | 3600 }}"
+            Device.X.Site: "{{ metadata.site }}/{{ device_id }}"
+      - AddTag: { tag: periodic }
+  - name: reboot-acme
+    domain: acme
+    actions: [Reboot]
+"#;
+
+    fn target(lifecycle: Lifecycle) -> Target<'static> {
+        Target {
+            event_type:  "inform",
+            domain_slug: "default",
+            hw_version:  Some("HW1"),
+            sw_version:  Some("2.0.1"),
+            device_id:   "AABB00-1",
+            lifecycle,
+        }
+    }
+
+    #[test]
+    fn matching_rules_render_their_actions() {
+        let rules = parse("base.yaml", RULES).unwrap();
+        let payload = serde_json::json!({
+            "events": ["1 BOOT", "0 bootstrap"],
+            "product_class": "HG8245",
+            "parameter_list": { "Device.ManagementServer.PeriodicInformEnable": "false" },
+            "context": {
+                "device": { "tags": ["pilot"], "metadata": { "site": "north" } },
+                "properties": { "interval": 600 },
+            },
+        });
+
+        let runs = evaluate(&rules, &target(Lifecycle::Add), &payload);
+        assert_eq!(runs.len(), 1, "{runs:?}");
+        assert_eq!(runs[0].script, "rules/base.yaml#periodic-inform");
+        let Action::SetParameterValues { parameters, .. } = &runs[0].actions[0] else { panic!("{runs:?}") };
+        assert_eq!(parameters["Device.ManagementServer.PeriodicInformInterval"], "600");
+        assert_eq!(parameters["Device.X.Site"], "north/AABB00-1");
+        assert_eq!(runs[0].updates, [updates::InventoryUpdate::AddTag { tag: "periodic".into() }]);
+
+        // Wrong lifecycle, and a missing value without a default.
+        assert!(evaluate(&rules, &target(Lifecycle::Update), &payload).is_empty());
+        let mut payload = payload;
+        payload["context"]["device"]["metadata"] = serde_json::json!({});
+        let runs = evaluate(&rules, &target(Lifecycle::Add), &payload);
+        assert!(runs[0].error.as_deref().is_some_and(|e| e.contains("metadata.site")), "{runs:?}");
+    }
+
+    #[test]
+    fn events_match_on_their_code() {
+        let rules = parse(
+            "base.yaml",
+            "rules:\n  - name: downloaded\n    match: { events: [\"M Download\"] }\n    actions: [Reboot]\n",
+        )
+        .unwrap();
+        let runs = |events: JsonValue| evaluate(&rules, &target(Lifecycle::Add), &serde_json::json!({ "events": events }));
+
+        assert_eq!(runs(serde_json::json!(["m download fw-42"])).len(), 1);
+        assert_eq!(runs(serde_json::json!(["M Download"])).len(), 1);
+        assert!(runs(serde_json::json!(["M DownloadX", "7 TRANSFER COMPLETE"])).is_empty());
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected_at_load() {
+        for (bad, why) in [
+            ("rules: [{name: a, actions: [Reboot]}, {name: a, actions: [Reboot]}]", "twice"),
+            ("rules: [{name: a, event: boot, actions: [Reboot]}]", "event"),
+            ("rules: [{name: a, actions: []}]", "no actions"),
+            ("rules: [{name: a, actions: [Explode]}]", "action"),
+            ("rules: [{name: a, match: {serial: x}, actions: [Reboot]}]", "unknown field"),
+            ("rules: [{name: a, actions: [{AddObject: {path: '{{ oops }}'}}]}]", "unknown placeholder"),
+            ("rules: [{name: a, actions: [{AddObject: {path: '{{ param.x'}}]}]", "unclosed"),
+        ] {
+            let err = format!("{:#}", parse("x.yaml", bad).unwrap_err());
+            assert!(err.contains(why), "{bad}: {err}");
+        }
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("1.2.3", "1.2.3"));
+        assert!(!glob("1.2", "1.2.3"));
+        assert!(glob("1.*", "1.2.3"));
+        assert!(glob("*-beta*", "2.0-beta1"));
+        assert!(glob("a*b*c", "abc"));
+        assert!(!glob("a*bc", "abc-bc-x"));
+        assert!(!glob("ab*ba", "aba"));
+    }
+}
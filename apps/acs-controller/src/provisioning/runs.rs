@@ -0,0 +1,362 @@
+//! Provisioning run audit log.
+//!
+//! Every event provisioning scripts ran for leaves a `provisioning_runs`
+//! row listing the scripts, and one `provisioning_run_actions` row per
+//! action they sent or returned. An action's row follows it to its outcome:
+//! [`record_response`] as the device answers, [`session_closed`] when the
+//! session ends first. Actions queued as tasks take the task's outcome.
+
+use std::collections::HashMap;
+
+use nats_common::{Action, ActionResult, DeviceResponse};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{Lifecycle, ScriptRun};
+
+/// Output kept per script in the log.
+const OUTPUT_EXCERPT: usize = 4 * 1024;
+
+/// What became of an action a script returned.
+#[derive(Debug, Clone, Copy)]
+pub enum Delivery {
+    /// Published to the device's session as this command.
+    Sent(Uuid),
+    /// Queued as this task.
+    Queued(Uuid),
+    /// Not published: it failed, or the session had ended.
+    NotSent,
+}
+
+/// A finished run, to [`record`].
+pub struct NewRun<'a> {
+    pub device_id:  Uuid,
+    pub event_type: &'a str,
+    pub lifecycle:  Lifecycle,
+    pub session_id: Option<&'a str>,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub scripts:    &'a [ScriptRun],
+    /// One per returned action, in order.
+    pub deliveries: &'a [Delivery],
+}
+
+/// Record a run. Nothing is recorded if no script ran; otherwise returns
+/// the run's id.
+pub async fn record(pool: &PgPool, run: &NewRun<'_>) -> Result<Option<Uuid>, sqlx::Error> {
+    if run.scripts.is_empty() {
+        return Ok(None);
+    }
+    let scripts: Vec<JsonValue> = run
+        .scripts
+        .iter()
+        .map(|s| {
+            serde_json::json!({
+                "script":      s.script,
+                "version":     s.version,
+                "duration_ms": s.duration.as_millis() as u64,
+                "status":      if s.error.is_some() { "error" } else { "ok" },
+                "error":       s.error,
+                "updates":     s.updates,
+                "output":      excerpt(&s.output),
+            })
+        })
+        .collect();
+
+    let mut tx = pool.begin().await?;
+    let run_id: Uuid = sqlx::query_scalar(
+        r#"
+        INSERT INTO provisioning_runs (device_id, event_type, lifecycle, session_id, scripts, started_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id
+        "#,
+    )
+    .bind(run.device_id)
+    .bind(run.event_type)
+    .bind(run.lifecycle.as_str())
+    .bind(run.session_id)
+    .bind(JsonValue::Array(scripts))
+    .bind(run.started_at)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    for row in action_rows(run) {
+        // Serialising an Action (strings, maps, integers) cannot fail.
+        let action = serde_json::to_value(row.action).expect("Action serialises");
+        sqlx::query(
+            r#"
+            INSERT INTO provisioning_run_actions (run_id, seq, script, via, action, status, command_id, task_id, result)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(run_id)
+        .bind(row.seq)
+        .bind(row.script)
+        .bind(row.via)
+        .bind(action)
+        .bind(row.status)
+        .bind(row.command_id)
+        .bind(row.task_id)
+        .bind(row.result)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(Some(run_id))
+}
+
+/// One `provisioning_run_actions` row to insert.
+struct ActionRow<'a> {
+    seq:        i32,
+    script:     &'a str,
+    via:        &'static str,
+    action:     &'a Action,
+    status:     &'static str,
+    command_id: Option<Uuid>,
+    task_id:    Option<Uuid>,
+    result:     Option<JsonValue>,
+}
+
+fn action_rows<'a>(run: &'a NewRun<'_>) -> Vec<ActionRow<'a>> {
+    let mut rows = Vec::new();
+    let mut deliveries = run.deliveries.iter();
+    // Sent actions reached the device while their script ran, so they come
+    // before the ones it returned.
+    for script in run.scripts {
+        for exchange in &script.sent {
+            let (status, result) = match &exchange.result {
+                Ok(result) => (outcome(result), serde_json::to_value(result).expect("ActionResult serialises")),
+                Err(e) => ("unanswered", serde_json::json!({ "error": e })),
+            };
+            rows.push(ActionRow {
+                seq: rows.len() as i32,
+                script: &script.script,
+                via: "send",
+                action: &exchange.action,
+                status,
+                command_id: Some(exchange.command_id),
+                task_id: None,
+                result: Some(result),
+            });
+        }
+        for action in &script.actions {
+            let (status, command_id, task_id) = match deliveries.next().copied().unwrap_or(Delivery::NotSent) {
+                Delivery::Sent(command_id) => ("sent", Some(command_id), None),
+                Delivery::Queued(task_id) => ("queued", None, Some(task_id)),
+                Delivery::NotSent => ("not_sent", None, None),
+            };
+            rows.push(ActionRow {
+                seq: rows.len() as i32,
+                script: &script.script,
+                via: "return",
+                action,
+                status,
+                command_id,
+                task_id,
+                result: None,
+            });
+        }
+    }
+    rows
+}
+
+/// Final status of an action the device answered.
+fn outcome(result: &ActionResult) -> &'static str {
+    match result {
+        ActionResult::Fault { .. } => "faulted",
+        _ => "succeeded",
+    }
+}
+
+/// The first [`OUTPUT_EXCERPT`] bytes of `output`, cut at a char boundary.
+fn excerpt(output: &str) -> &str {
+    let mut end = output.len().min(OUTPUT_EXCERPT);
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    &output[..end]
+}
+
+/// Record the device's answer to an action sent by a run. Returns whether
+/// the response was for one.
+pub async fn record_response(pool: &PgPool, response: &DeviceResponse) -> Result<bool, sqlx::Error> {
+    let Some(command_id) = response.operation_id else {
+        return Ok(false);
+    };
+    // Serialising an ActionResult (strings, maps, integers) cannot fail.
+    let result = serde_json::to_value(&response.result).expect("ActionResult serialises");
+    let updated = sqlx::query(
+        r#"
+        UPDATE provisioning_run_actions
+        SET status = $2, result = $3, updated_at = now()
+        WHERE command_id = $1 AND status = 'sent'
+        "#,
+    )
+    .bind(command_id)
+    .bind(outcome(&response.result))
+    .bind(result)
+    .execute(pool)
+    .await?;
+    Ok(updated.rows_affected() > 0)
+}
+
+/// Mark the actions of `session_id`'s runs still waiting for an answer as
+/// unanswered. Returns how many there were.
+pub async fn session_closed(pool: &PgPool, session_id: &str) -> Result<u64, sqlx::Error> {
+    let updated = sqlx::query(
+        r#"
+        UPDATE provisioning_run_actions
+        SET status = 'unanswered', updated_at = now()
+        WHERE status = 'sent'
+          AND run_id IN (SELECT id FROM provisioning_runs WHERE session_id = $1)
+        "#,
+    )
+    .bind(session_id)
+    .execute(pool)
+    .await?;
+    Ok(updated.rows_affected())
+}
+
+// ── Reading ───────────────────────────────────────────────────────────────────
+
+/// A recorded run with its actions.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Run {
+    pub id:          Uuid,
+    pub event_type:  String,
+    pub lifecycle:   String,
+    pub session_id:  Option<String>,
+    /// `[{script, version, duration_ms, status, error, updates, output}]`
+    pub scripts:     JsonValue,
+    pub started_at:  chrono::DateTime<chrono::Utc>,
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+    #[sqlx(skip)]
+    pub actions:     Vec<RunAction>,
+}
+
+/// One action of a run.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct RunAction {
+    #[serde(skip)]
+    pub run_id:      Uuid,
+    pub seq:         i32,
+    pub script:      String,
+    /// `send` | `return`
+    pub via:         String,
+    pub action:      JsonValue,
+    pub status:      String,
+    pub command_id:  Option<Uuid>,
+    pub task_id:     Option<Uuid>,
+    /// Status of the task a queued action became.
+    pub task_status: Option<String>,
+    pub result:      Option<JsonValue>,
+    pub updated_at:  chrono::DateTime<chrono::Utc>,
+}
+
+/// The latest `limit` runs of a device, newest first.
+pub async fn for_device(pool: &PgPool, device_id: Uuid, limit: i64) -> Result<Vec<Run>, sqlx::Error> {
+    let mut runs = sqlx::query_as::<_, Run>(
+        r#"
+        SELECT id, event_type, lifecycle, session_id, scripts, started_at, finished_at
+        FROM provisioning_runs
+        WHERE device_id = $1
+        ORDER BY started_at DESC
+        LIMIT $2
+        "#,
+    )
+    .bind(device_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    let ids: Vec<Uuid> = runs.iter().map(|r| r.id).collect();
+    let actions = sqlx::query_as::<_, RunAction>(
+        r#"
+        SELECT a.run_id, a.seq, a.script, a.via, a.action, a.status, a.command_id, a.task_id,
+               t.status AS task_status, a.result, a.updated_at
+        FROM provisioning_run_actions a
+        LEFT JOIN tasks t ON t.id = a.task_id
+        WHERE a.run_id = ANY($1)
+        ORDER BY a.run_id, a.seq
+        "#,
+    )
+    .bind(&ids)
+    .fetch_all(pool)
+    .await?;
+
+    let mut by_run: HashMap<Uuid, Vec<RunAction>> = HashMap::new();
+    for action in actions {
+        by_run.entry(action.run_id).or_default().push(action);
+    }
+    for run in &mut runs {
+        run.actions = by_run.remove(&run.id).unwrap_or_default();
+    }
+    Ok(runs)
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::provisioning::Exchange;
+
+    fn script(name: &str, sent: Vec<Exchange>, actions: Vec<Action>) -> ScriptRun {
+        ScriptRun {
+            script: name.into(),
+            version: None,
+            actions,
+            updates: Vec::new(),
+            sent,
+            output: String::new(),
+            error: None,
+            duration: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn actions_are_logged_in_the_order_the_device_got_them() {
+        let (sent_id, command_id, task_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let scripts = [
+            script("a.rhai", Vec::new(), vec![Action::Reboot]),
+            script(
+                "b.rhai",
+                vec![Exchange {
+                    command_id: sent_id,
+                    action:     Action::AddObject { path: "Device.X.".into() },
+                    result:     Ok(ActionResult::Fault { code: "9005".into(), string: "Invalid".into() }),
+                }],
+                vec![Action::FactoryReset, Action::Reboot],
+            ),
+        ];
+        let run = NewRun {
+            device_id:  Uuid::new_v4(),
+            event_type: "inform",
+            lifecycle:  Lifecycle::Update,
+            session_id: None,
+            started_at: chrono::Utc::now(),
+            scripts:    &scripts,
+            deliveries: &[Delivery::Sent(command_id), Delivery::Queued(task_id)],
+        };
+
+        let rows = action_rows(&run);
+        let summary: Vec<_> = rows.iter().map(|r| (r.seq, r.script, r.via, r.status)).collect();
+        assert_eq!(
+            summary,
+            [
+                (0, "a.rhai", "return", "sent"),
+                (1, "b.rhai", "send", "faulted"),
+                (2, "b.rhai", "return", "queued"),
+                // More actions than deliveries: the rest were not sent.
+                (3, "b.rhai", "return", "not_sent"),
+            ]
+        );
+        assert_eq!(rows[0].command_id, Some(command_id));
+        assert_eq!(rows[1].command_id, Some(sent_id));
+        assert_eq!(rows[2].task_id, Some(task_id));
+    }
+}
@@ -0,0 +1,124 @@
+//! Inventory updates: changes to the device's record that scripts return
+//! next to device actions, e.g. `[add_tag("migrated"), reboot()]`.
+//!
+//! They are applied once the scripts have run. Properties set by scripts
+//! have source `script` and priority [`PRIORITY`], so they never replace a
+//! value set with a higher priority (a lower number), e.g. by an operator.
+
+use anyhow::{Context, Result};
+use nats_common::Action;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::ScriptRun;
+
+/// Priority of properties set by scripts.
+pub const PRIORITY: i32 = 100;
+
+/// A change to the device's inventory record.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum InventoryUpdate {
+    SetProperty { name: String, value: JsonValue },
+    DeleteProperty { name: String },
+    AddTag { tag: String },
+    RemoveTag { tag: String },
+}
+
+const KINDS: [&str; 4] = ["SetProperty", "DeleteProperty", "AddTag", "RemoveTag"];
+
+/// Split what a script returned into device actions and inventory updates.
+pub(super) fn split(values: Vec<JsonValue>) -> Result<(Vec<Action>, Vec<InventoryUpdate>)> {
+    let mut actions = Vec::new();
+    let mut updates = Vec::new();
+    for (i, value) in values.into_iter().enumerate() {
+        let is_update = value.as_object().is_some_and(|o| o.len() == 1 && o.keys().all(|k| KINDS.contains(&k.as_str())));
+        if is_update {
+            updates.push(serde_json::from_value(value).with_context(|| format!("Invalid inventory update at index {i}"))?);
+        } else {
+            actions.push(serde_json::from_value(value).with_context(|| format!("Invalid action at index {i}"))?);
+        }
+    }
+    Ok((actions, updates))
+}
+
+/// Apply the updates the scripts returned to the device, in order.
+/// Returns how many changed something.
+pub async fn apply(pool: &PgPool, device_id: Uuid, scripts: &[ScriptRun]) -> Result<usize, sqlx::Error> {
+    let mut changed = 0;
+    for update in scripts.iter().flat_map(|s| &s.updates) {
+        let result = match update {
+            InventoryUpdate::SetProperty { name, value } => {
+                sqlx::query(
+                    r#"
+                    INSERT INTO device_properties (device_id, property_name, property_value, source, priority)
+                    VALUES ($1, $2, $3, 'script', $4)
+                    ON CONFLICT (device_id, property_name) DO UPDATE SET
+                        property_value = EXCLUDED.property_value,
+                        source         = EXCLUDED.source,
+                        priority       = EXCLUDED.priority,
+                        updated_at     = now()
+                    WHERE device_properties.priority >= EXCLUDED.priority
+                    "#,
+                )
+                .bind(device_id)
+                .bind(name)
+                .bind(value)
+                .bind(PRIORITY)
+                .execute(pool)
+                .await?
+            }
+            InventoryUpdate::DeleteProperty { name } => {
+                sqlx::query("DELETE FROM device_properties WHERE device_id = $1 AND property_name = $2 AND priority >= $3")
+                    .bind(device_id)
+                    .bind(name)
+                    .bind(PRIORITY)
+                    .execute(pool)
+                    .await?
+            }
+            InventoryUpdate::AddTag { tag } => {
+                sqlx::query("UPDATE devices SET tags = array_append(tags, $2) WHERE id = $1 AND NOT ($2 = ANY(tags))")
+                    .bind(device_id)
+                    .bind(tag)
+                    .execute(pool)
+                    .await?
+            }
+            InventoryUpdate::RemoveTag { tag } => {
+                sqlx::query("UPDATE devices SET tags = array_remove(tags, $2) WHERE id = $1 AND $2 = ANY(tags)")
+                    .bind(device_id)
+                    .bind(tag)
+                    .execute(pool)
+                    .await?
+            }
+        };
+        changed += result.rows_affected() as usize;
+    }
+    Ok(changed)
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_are_told_apart_from_actions() {
+        let values = serde_json::json!([
+            {"AddTag": {"tag": "pilot"}},
+            "Reboot",
+            {"SetProperty": {"name": "ntp", "value": {"server": "x"}}},
+        ]);
+        let (actions, updates) = split(serde_json::from_value(values).unwrap()).unwrap();
+        assert!(matches!(actions[..], [Action::Reboot]));
+        assert_eq!(updates, [
+            InventoryUpdate::AddTag { tag: "pilot".into() },
+            InventoryUpdate::SetProperty { name: "ntp".into(), value: serde_json::json!({"server": "x"}) },
+        ]);
+
+        let err = split(vec![serde_json::json!({"AddTag": {}})]).unwrap_err();
+        assert!(format!("{err:#}").contains("inventory update at index 0"), "{err:#}");
+        assert!(split(vec![serde_json::json!({"Explode": null})]).is_err());
+    }
+}
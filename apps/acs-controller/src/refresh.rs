@@ -0,0 +1,471 @@
+//! Parameter tree refresh.
+//!
+//! A refresh re-reads one subtree of a device's data model into
+//! `device_parameters`: a GetParameterNames over the subtree stores every
+//! object and parameter with its writable flag and drops what the device no
+//! longer reports, then GetParameterValues read the values a chunk at a
+//! time, so no request exceeds what the CPE can answer. Each step is a task
+//! queued when the previous one is answered (see [`on_task_update`]), so a
+//! refresh of an offline device simply waits for its next session.
+//!
+//! [`tree`] turns the stored parameters back into the device's object tree.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use nats_common::{Action, ActionResult};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::api::ApiState;
+use crate::tasks::{self, NewTask, TaskStatus, TaskUpdate};
+
+/// A refresh still incomplete after this long fails; its tasks expire
+/// then too.
+const PENDING_TIMEOUT_SECS: i64 = 24 * 3600;
+
+/// Parameters per GetParameterValues unless the request says otherwise.
+pub const DEFAULT_CHUNK_SIZE: i32 = 100;
+pub const MAX_CHUNK_SIZE: i32 = 1000;
+
+// ── Types ─────────────────────────────────────────────────────────────────────
+
+/// One row of `parameter_refreshes`.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Refresh {
+    pub id:           Uuid,
+    pub device_id:    Uuid,
+    pub path_prefix:  String,
+    pub chunk_size:   i32,
+    pub status:       String,
+    pub task_id:      Option<Uuid>,
+    pub discovered:   Option<i32>,
+    pub fetched:      i32,
+    /// Parameters whose values are still to be read.
+    pub remaining:    i32,
+    pub error:        Option<String>,
+    pub created_by:   Option<Uuid>,
+    pub created_at:   DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+pub const REFRESH_COLUMNS: &str = "id, device_id, path_prefix, chunk_size, status, task_id, discovered, fetched, \
+                                   cardinality(remaining) AS remaining, error, created_by, created_at, completed_at";
+
+#[derive(Debug, thiserror::Error)]
+pub enum StartError {
+    #[error("A refresh of the device is already in progress")]
+    InProgress,
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+// ── Refreshing ────────────────────────────────────────────────────────────────
+
+/// Start a refresh of `path_prefix` (empty, or an object path) on
+/// `device_id` and queue its GetParameterNames. The caller decides whether
+/// to wake the device.
+pub async fn start(
+    pool: &PgPool,
+    device_id: Uuid,
+    path_prefix: &str,
+    chunk_size: i32,
+    created_by: Option<Uuid>,
+) -> Result<Refresh, StartError> {
+    fail_stalled(pool, device_id).await?;
+    let in_progress: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM parameter_refreshes WHERE device_id = $1 AND status = 'pending')")
+            .bind(device_id)
+            .fetch_one(pool)
+            .await?;
+    if in_progress {
+        return Err(StartError::InProgress);
+    }
+
+    let task = NewTask {
+        action:          Action::GetParameterNames { path_prefix: path_prefix.to_string(), next_level: false },
+        priority:        None,
+        expires_in_secs: Some(PENDING_TIMEOUT_SECS),
+        max_attempts:    None,
+    };
+    let task = tasks::enqueue(pool, device_id, &task, created_by).await?;
+
+    let refresh = sqlx::query_as::<_, Refresh>(&format!(
+        r#"
+        INSERT INTO parameter_refreshes (device_id, path_prefix, chunk_size, task_id, created_by)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING {REFRESH_COLUMNS}
+        "#
+    ))
+    .bind(device_id)
+    .bind(path_prefix)
+    .bind(chunk_size)
+    .bind(task.id)
+    .bind(created_by)
+    .fetch_one(pool)
+    .await;
+
+    match refresh {
+        Ok(refresh) => {
+            info!(refresh_id = %refresh.id, %device_id, path_prefix, chunk_size, "Parameter refresh started");
+            Ok(refresh)
+        }
+        Err(e) => {
+            // Another refresh started meanwhile; withdraw this one's task.
+            sqlx::query("DELETE FROM tasks WHERE id = $1 AND status = 'pending'").bind(task.id).execute(pool).await?;
+            match e.as_database_error().and_then(|d| d.code()).as_deref() {
+                Some("23505") => Err(StartError::InProgress),
+                _ => Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Fail the device's pending refresh if its task did not succeed, or it
+/// never completed.
+pub async fn fail_stalled(pool: &PgPool, device_id: Uuid) -> Result<(), sqlx::Error> {
+    let failed = sqlx::query(
+        r#"
+        UPDATE parameter_refreshes r
+        SET status = 'failed', completed_at = now(),
+            error = CASE
+                WHEN t.id IS NULL                         THEN 'Task cancelled'
+                WHEN t.status IN ('faulted', 'expired')   THEN COALESCE(t.last_error, 'Task ' || t.status)
+                ELSE 'Timed out'
+            END
+        FROM parameter_refreshes r2
+        LEFT JOIN tasks t ON t.id = r2.task_id
+        WHERE r.id = r2.id
+          AND r.device_id = $1
+          AND r.status = 'pending'
+          AND (t.id IS NULL
+               OR t.status IN ('faulted', 'expired')
+               OR r.created_at < now() - make_interval(secs => $2))
+        "#,
+    )
+    .bind(device_id)
+    .bind(PENDING_TIMEOUT_SECS as f64)
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if failed > 0 {
+        warn!(%device_id, "Stalled parameter refresh failed");
+    }
+    Ok(())
+}
+
+/// Advance the refresh a task response belongs to, if any.
+pub async fn on_task_update(state: &ApiState, update: &TaskUpdate) -> Result<(), sqlx::Error> {
+    let pool = &state.pool;
+    let row: Option<(Uuid, Uuid, Uuid, String, String, i32, bool)> = sqlx::query_as(
+        r#"
+        SELECT r.id, r.device_id, d.domain_id, d.device_uid, r.path_prefix, r.chunk_size,
+               t.action ? 'GetParameterNames'
+        FROM parameter_refreshes r
+        JOIN tasks t ON t.id = r.task_id
+        JOIN devices d ON d.id = r.device_id
+        WHERE r.task_id = $1 AND r.status = 'pending'
+        "#,
+    )
+    .bind(update.task_id)
+    .fetch_optional(pool)
+    .await?;
+
+    let Some((refresh_id, device_id, domain_id, device_uid, path_prefix, chunk_size, names)) = row else {
+        return Ok(());
+    };
+
+    let values = match (&update.status, &update.response.result) {
+        (TaskStatus::Faulted, ActionResult::Fault { code, string }) => {
+            return fail(pool, refresh_id, &format!("{code}: {string}")).await;
+        }
+        (TaskStatus::Succeeded, ActionResult::Success(values)) => values,
+        _ => return Ok(()),
+    };
+
+    if names {
+        let leaves = store_names(pool, device_id, &path_prefix, values).await?;
+        sqlx::query("UPDATE parameter_refreshes SET discovered = $2, remaining = $3 WHERE id = $1")
+            .bind(refresh_id)
+            .bind(leaves.len() as i32)
+            .bind(&leaves)
+            .execute(pool)
+            .await?;
+    } else {
+        store_values(pool, device_id, values).await?;
+        sqlx::query("UPDATE parameter_refreshes SET fetched = fetched + $2 WHERE id = $1")
+            .bind(refresh_id)
+            .bind(values.len() as i32)
+            .execute(pool)
+            .await?;
+    }
+
+    let remaining: Vec<String> = sqlx::query_scalar("SELECT remaining FROM parameter_refreshes WHERE id = $1")
+        .bind(refresh_id)
+        .fetch_one(pool)
+        .await?;
+    if remaining.is_empty() {
+        sqlx::query(
+            "UPDATE parameter_refreshes SET status = 'complete', completed_at = now() WHERE id = $1 AND status = 'pending'",
+        )
+        .bind(refresh_id)
+        .execute(pool)
+        .await?;
+        info!(%refresh_id, %device_uid, path_prefix, "Parameter refresh complete");
+        return Ok(());
+    }
+
+    let split = remaining.len().min(chunk_size as usize);
+    let task = NewTask {
+        action:          Action::GetParameterValues { paths: remaining[..split].to_vec() },
+        priority:        None,
+        expires_in_secs: Some(PENDING_TIMEOUT_SECS),
+        max_attempts:    None,
+    };
+    let task = tasks::enqueue(pool, device_id, &task, None).await?;
+    sqlx::query("UPDATE parameter_refreshes SET task_id = $2, remaining = $3 WHERE id = $1 AND status = 'pending'")
+        .bind(refresh_id)
+        .bind(task.id)
+        .bind(&remaining[split..])
+        .execute(pool)
+        .await?;
+    // The device is most likely still in the session that answered.
+    crate::api::tasks::deliver_or_wake(state, device_id, domain_id, &device_uid).await;
+    Ok(())
+}
+
+/// Store a GetParameterNames result under `path_prefix`, dropping the
+/// paths there it no longer lists. Returns its parameters (not objects),
+/// sorted.
+async fn store_names(
+    pool: &PgPool,
+    device_id: Uuid,
+    path_prefix: &str,
+    names: &HashMap<String, String>,
+) -> Result<Vec<String>, sqlx::Error> {
+    let (paths, writable): (Vec<&String>, Vec<bool>) = names.iter().map(|(name, w)| (name, w == "true" || w == "1")).unzip();
+
+    let mut tx = pool.begin().await?;
+    let dropped = sqlx::query(
+        r#"
+        DELETE FROM device_parameters
+        WHERE device_id = $1 AND starts_with(parameter_name, $2) AND NOT (parameter_name = ANY($3))
+        "#,
+    )
+    .bind(device_id)
+    .bind(path_prefix)
+    .bind(&paths)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    // Objects have no value to wait for: their discovery is their update.
+    sqlx::query(
+        r#"
+        INSERT INTO device_parameters (device_id, parameter_name, writable)
+        SELECT $1, name, writable FROM UNNEST($2::TEXT[], $3::BOOLEAN[]) AS n(name, writable)
+        ON CONFLICT (device_id, parameter_name) DO UPDATE
+        SET writable   = EXCLUDED.writable,
+            updated_at = CASE WHEN EXCLUDED.parameter_name LIKE '%.' THEN now() ELSE device_parameters.updated_at END
+        "#,
+    )
+    .bind(device_id)
+    .bind(&paths)
+    .bind(&writable)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    if dropped > 0 {
+        info!(%device_id, path_prefix, dropped, "Dropped parameters the device no longer reports");
+    }
+    let mut leaves: Vec<String> = names.keys().filter(|n| !n.ends_with('.')).cloned().collect();
+    leaves.sort();
+    Ok(leaves)
+}
+
+async fn store_values(pool: &PgPool, device_id: Uuid, values: &HashMap<String, String>) -> Result<(), sqlx::Error> {
+    let (paths, values): (Vec<&String>, Vec<&String>) = values.iter().unzip();
+    sqlx::query(
+        r#"
+        INSERT INTO device_parameters (device_id, parameter_name, parameter_value)
+        SELECT $1, name, value FROM UNNEST($2::TEXT[], $3::TEXT[]) AS v(name, value)
+        ON CONFLICT (device_id, parameter_name) DO UPDATE
+        SET parameter_value = EXCLUDED.parameter_value, updated_at = now()
+        "#,
+    )
+    .bind(device_id)
+    .bind(&paths)
+    .bind(&values)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn fail(pool: &PgPool, refresh_id: Uuid, error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE parameter_refreshes SET status = 'failed', error = $2, completed_at = now() WHERE id = $1 AND status = 'pending'",
+    )
+    .bind(refresh_id)
+    .bind(error)
+    .execute(pool)
+    .await?;
+    warn!(%refresh_id, error, "Parameter refresh failed");
+    Ok(())
+}
+
+// ── Tree ──────────────────────────────────────────────────────────────────────
+
+/// One row of `device_parameters`.
+#[derive(Debug, sqlx::FromRow)]
+pub struct Row {
+    pub parameter_name:  String,
+    pub parameter_value: Option<String>,
+    pub writable:        Option<bool>,
+    pub updated_at:      DateTime<Utc>,
+}
+
+/// A node of a device's object tree.
+#[derive(Debug, Serialize)]
+pub struct Node {
+    /// Last path segment: `Enable`, `WiFi`, `1`.
+    pub name:       String,
+    /// Full path; objects end in `.`.
+    pub path:       String,
+    pub object:     bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value:      Option<String>,
+    /// `None` if never discovered by GetParameterNames.
+    pub writable:   Option<bool>,
+    /// Parameters: when the value was last stored. Objects: the latest
+    /// update in their subtree.
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children:   Vec<Node>,
+}
+
+impl Node {
+    fn new(name: &str, path: String, object: bool) -> Self {
+        Node { name: name.to_string(), path, object, value: None, writable: None, updated_at: None, children: Vec::new() }
+    }
+
+    /// Sort children (instance numbers numerically) and roll the latest
+    /// update up to each object.
+    fn finish(&mut self) {
+        for child in &mut self.children {
+            child.finish();
+        }
+        self.children.sort_by(|a, b| segment_order(&a.name, &b.name));
+        let latest = self.children.iter().filter_map(|c| c.updated_at).max();
+        self.updated_at = self.updated_at.max(latest);
+    }
+}
+
+fn segment_order(a: &str, b: &str) -> Ordering {
+    match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+/// The object tree under `path_prefix` (empty, or an object path) of
+/// `rows`, all of which lie under it.
+pub fn tree(path_prefix: &str, rows: Vec<Row>) -> Node {
+    let name = path_prefix.trim_end_matches('.').rsplit('.').next().unwrap_or_default();
+    let mut root = Node::new(name, path_prefix.to_string(), true);
+
+    for row in rows {
+        let Some(rest) = row.parameter_name.strip_prefix(path_prefix) else { continue };
+        let is_object = rest.is_empty() || rest.ends_with('.');
+        let segments: Vec<&str> = rest.trim_end_matches('.').split('.').filter(|s| !s.is_empty()).collect();
+
+        let mut node = &mut root;
+        let mut path = path_prefix.to_string();
+        for (i, segment) in segments.iter().enumerate() {
+            let object = is_object || i + 1 < segments.len();
+            path.push_str(segment);
+            if object {
+                path.push('.');
+            }
+            let index = match node.children.iter().rposition(|c| c.name == *segment && c.object == object) {
+                Some(index) => index,
+                None => {
+                    node.children.push(Node::new(segment, path.clone(), object));
+                    node.children.len() - 1
+                }
+            };
+            node = &mut node.children[index];
+        }
+        node.value = row.parameter_value;
+        node.writable = row.writable;
+        node.updated_at = Some(row.updated_at);
+    }
+
+    root.finish();
+    root
+}
+
+/// The stored tree of `device_id` under `path_prefix`; `None` if nothing is
+/// stored there.
+pub async fn stored_tree(pool: &PgPool, device_id: Uuid, path_prefix: &str) -> Result<Option<Node>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, Row>(
+        r#"
+        SELECT parameter_name, parameter_value, writable, updated_at
+        FROM device_parameters
+        WHERE device_id = $1 AND starts_with(parameter_name, $2)
+        ORDER BY parameter_name
+        "#,
+    )
+    .bind(device_id)
+    .bind(path_prefix)
+    .fetch_all(pool)
+    .await?;
+    Ok((!rows.is_empty()).then(|| tree(path_prefix, rows)))
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, value: Option<&str>, minute: u32) -> Row {
+        Row {
+            parameter_name:  name.into(),
+            parameter_value: value.map(Into::into),
+            writable:        Some(value.is_some()),
+            updated_at:      DateTime::parse_from_rfc3339(&format!("2026-01-01T00:{minute:02}:00Z")).unwrap().into(),
+        }
+    }
+
+    #[test]
+    fn builds_the_object_tree() {
+        let root = tree(
+            "Device.WiFi.",
+            vec![
+                row("Device.WiFi.", None, 1),
+                row("Device.WiFi.SSID.10.SSID", Some("guest"), 3),
+                row("Device.WiFi.SSID.2.", None, 1),
+                row("Device.WiFi.SSID.2.SSID", Some("home"), 2),
+                row("Device.WiFi.SSIDNumberOfEntries", Some("2"), 1),
+            ],
+        );
+
+        assert_eq!((root.name.as_str(), root.path.as_str()), ("WiFi", "Device.WiFi."));
+        let names: Vec<_> = root.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["SSID", "SSIDNumberOfEntries"]);
+
+        let ssid = &root.children[0];
+        assert!(ssid.object);
+        assert_eq!(ssid.writable, None);
+        let instances: Vec<_> = ssid.children.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(instances, ["Device.WiFi.SSID.2.", "Device.WiFi.SSID.10."]);
+
+        let home = &ssid.children[0].children[0];
+        assert_eq!((home.path.as_str(), home.value.as_deref(), home.writable), ("Device.WiFi.SSID.2.SSID", Some("home"), Some(true)));
+        assert_eq!(root.updated_at, Some(row("", None, 3).updated_at));
+        assert_eq!(ssid.children[0].updated_at, Some(row("", None, 2).updated_at));
+    }
+}
@@ -0,0 +1,752 @@
+//! Device search query language.
+//!
+//! A filter expression is parsed into an [`Expr`] tree and compiled to a
+//! parameterised SQL condition over `devices d` (joined with `domains dom`).
+//! Literals never reach the SQL text — every value, and every metadata,
+//! property or parameter name, is bound as a `$n` parameter.
+//!
+//! ```text
+//! software_version < "2.0" AND tags has "pilot"
+//!     AND param["Device.WiFi.SSID.1.SSID"] ~ "Guest*"
+//! ```
+//!
+//! Grammar (keywords are case-insensitive):
+//!
+//! ```text
+//! expr      := and ("OR" and)*
+//! and       := unary ("AND" unary)*
+//! unary     := "NOT" unary | "(" expr ")" | predicate
+//! predicate := field op literal | "tags" "has" string
+//! field     := column | ("metadata" | "prop" | "param") "[" string "]"
+//! op        := "=" | "!=" | "<" | "<=" | ">" | ">=" | "~"
+//! literal   := string | number
+//! ```
+//!
+//! `~` is a glob match (`*` any run, `?` one character). Text compares
+//! lexicographically; a number literal compares numerically against
+//! metadata, properties and parameters whose value is numeric.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// Longest accepted filter expression, in bytes.
+const MAX_QUERY_LEN: usize = 4096;
+/// Deepest accepted nesting of `NOT` and parentheses.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SearchError {
+    #[error("query too long (max {MAX_QUERY_LEN} bytes)")]
+    TooLong,
+    #[error("query nested too deeply (max {MAX_DEPTH} levels)")]
+    TooDeep,
+    #[error("syntax error at position {0}: {1}")]
+    Syntax(usize, String),
+    #[error("unknown field '{0}'")]
+    UnknownField(String),
+    #[error("operator {op} not supported for {field}")]
+    BadOperator { field: String, op: &'static str },
+    #[error("invalid value for {field}: {reason}")]
+    BadValue { field: String, reason: &'static str },
+    #[error("unknown sort field '{0}'")]
+    UnknownSort(String),
+    #[error("invalid cursor")]
+    BadCursor,
+}
+
+// ── AST ───────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Glob,
+}
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq   => "=",
+            Op::Ne   => "!=",
+            Op::Lt   => "<",
+            Op::Le   => "<=",
+            Op::Gt   => ">",
+            Op::Ge   => ">=",
+            Op::Glob => "~",
+        }
+    }
+
+    fn is_ordering(self) -> bool {
+        matches!(self, Op::Lt | Op::Le | Op::Gt | Op::Ge)
+    }
+
+    fn sql(self) -> &'static str {
+        match self {
+            Op::Eq   => "=",
+            Op::Ne   => "<>",
+            Op::Lt   => "<",
+            Op::Le   => "<=",
+            Op::Gt   => ">",
+            Op::Ge   => ">=",
+            Op::Glob => "LIKE",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Str(String),
+    Num(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    Text,
+    Timestamp,
+    /// Text compared component by component with `<`, `>`, `<=` and `>=`:
+    /// `"10.0" > "2.0"`.
+    Version,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    /// SQL expression and type of a plain device column.
+    Column(&'static str, ColumnKind),
+    Metadata(String),
+    Property(String),
+    Param(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cmp { field: Field, op: Op, value: Literal },
+    HasTag(String),
+}
+
+/// Queryable device columns: name → (SQL expression, type).
+const COLUMNS: &[(&str, &str, ColumnKind)] = &[
+    ("device_uid",       "d.device_uid",       ColumnKind::Text),
+    ("domain",           "dom.slug",           ColumnKind::Text),
+    ("manufacturer",     "d.manufacturer",     ColumnKind::Text),
+    ("oui",              "d.oui",              ColumnKind::Text),
+    ("product_class",    "d.product_class",    ColumnKind::Text),
+    ("serial_number",    "d.serial_number",    ColumnKind::Text),
+    ("current_protocol", "d.current_protocol", ColumnKind::Text),
+    ("software_version", "d.software_version", ColumnKind::Version),
+    ("hardware_version", "d.hardware_version", ColumnKind::Version),
+    ("first_seen",       "d.first_seen",       ColumnKind::Timestamp),
+    ("last_seen",        "d.last_seen",        ColumnKind::Timestamp),
+    ("health",           "d.health",           ColumnKind::Text),
+];
+
+// ── Lexer ─────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(String),
+    Op(Op),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+}
+
+fn lex(input: &str) -> Result<Vec<(usize, Token)>, SearchError> {
+    let chars: Vec<(usize, char)> = input.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (pos, c) = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => { tokens.push((pos, Token::LParen));   i += 1; }
+            ')' => { tokens.push((pos, Token::RParen));   i += 1; }
+            '[' => { tokens.push((pos, Token::LBracket)); i += 1; }
+            ']' => { tokens.push((pos, Token::RBracket)); i += 1; }
+            '~' => { tokens.push((pos, Token::Op(Op::Glob))); i += 1; }
+            '=' => {
+                // Accept `==` as a synonym for `=`.
+                i += if chars.get(i + 1).map(|c| c.1) == Some('=') { 2 } else { 1 };
+                tokens.push((pos, Token::Op(Op::Eq)));
+            }
+            '!' | '<' | '>' => {
+                let eq = chars.get(i + 1).map(|c| c.1) == Some('=');
+                let op = match (c, eq) {
+                    ('!', true)  => Op::Ne,
+                    ('<', true)  => Op::Le,
+                    ('<', false) => Op::Lt,
+                    ('>', true)  => Op::Ge,
+                    ('>', false) => Op::Gt,
+                    _ => return Err(SearchError::Syntax(pos, "expected '!='".to_string())),
+                };
+                tokens.push((pos, Token::Op(op)));
+                i += if eq { 2 } else { 1 };
+            }
+            '"' => {
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(SearchError::Syntax(pos, "unterminated string".to_string())),
+                        Some((_, '"')) => { i += 1; break; }
+                        Some((_, '\\')) => {
+                            match chars.get(i + 1) {
+                                Some((_, c @ ('"' | '\\'))) => s.push(*c),
+                                _ => return Err(SearchError::Syntax(chars[i].0, "invalid escape".to_string())),
+                            }
+                            i += 2;
+                        }
+                        Some((_, c)) => { s.push(*c); i += 1; }
+                    }
+                }
+                tokens.push((pos, Token::Str(s)));
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let start = i;
+                i += 1;
+                while chars.get(i).is_some_and(|c| c.1.is_ascii_digit() || c.1 == '.') {
+                    i += 1;
+                }
+                let num: String = chars[start..i].iter().map(|c| c.1).collect();
+                if num.parse::<f64>().is_err() {
+                    return Err(SearchError::Syntax(pos, format!("invalid number '{num}'")));
+                }
+                tokens.push((pos, Token::Num(num)));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let start = i;
+                while chars.get(i).is_some_and(|c| c.1.is_ascii_alphanumeric() || c.1 == '_') {
+                    i += 1;
+                }
+                tokens.push((pos, Token::Ident(chars[start..i].iter().map(|c| c.1).collect())));
+            }
+            other => return Err(SearchError::Syntax(pos, format!("unexpected character '{other}'"))),
+        }
+    }
+    Ok(tokens)
+}
+
+// ── Parser ────────────────────────────────────────────────────────────────────
+
+/// Parse a filter expression.
+pub fn parse(input: &str) -> Result<Expr, SearchError> {
+    if input.len() > MAX_QUERY_LEN {
+        return Err(SearchError::TooLong);
+    }
+    let mut p = Parser { tokens: lex(input)?, pos: 0, end: input.len(), depth: 0 };
+    let expr = p.or()?;
+    if let Some((pos, _)) = p.tokens.get(p.pos) {
+        return Err(SearchError::Syntax(*pos, "unexpected trailing input".to_string()));
+    }
+    Ok(expr)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos:    usize,
+    end:    usize,
+    depth:  usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.1)
+    }
+
+    fn here(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |t| t.0)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).map(|t| t.1.clone());
+        self.pos += 1;
+        t
+    }
+
+    fn keyword(&mut self, kw: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(s)) if s.eq_ignore_ascii_case(kw) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, SearchError> {
+        Err(SearchError::Syntax(self.here(), msg.to_string()))
+    }
+
+    fn or(&mut self) -> Result<Expr, SearchError> {
+        let mut lhs = self.and()?;
+        while self.keyword("OR") {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, SearchError> {
+        let mut lhs = self.unary()?;
+        while self.keyword("AND") {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, SearchError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(SearchError::TooDeep);
+        }
+
+        let expr = if self.keyword("NOT") {
+            Expr::Not(Box::new(self.unary()?))
+        } else if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            let inner = self.or()?;
+            if self.next() != Some(Token::RParen) {
+                self.pos -= 1;
+                return self.error("expected ')'");
+            }
+            inner
+        } else {
+            self.predicate()?
+        };
+
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn predicate(&mut self) -> Result<Expr, SearchError> {
+        let name = match self.next() {
+            Some(Token::Ident(name)) => name,
+            _ => {
+                self.pos -= 1;
+                return self.error("expected a field name");
+            }
+        };
+
+        if name == "tags" {
+            if !self.keyword("has") {
+                return self.error("expected 'has' after tags");
+            }
+            return match self.next() {
+                Some(Token::Str(tag)) => Ok(Expr::HasTag(tag)),
+                _ => {
+                    self.pos -= 1;
+                    self.error("expected a quoted tag")
+                }
+            };
+        }
+
+        let field = match name.as_str() {
+            "metadata" | "prop" | "param" => {
+                let key = self.subscript()?;
+                match name.as_str() {
+                    "metadata" => Field::Metadata(key),
+                    "prop" => Field::Property(key),
+                    _ => Field::Param(key),
+                }
+            }
+            _ => match COLUMNS.iter().find(|(n, _, _)| *n == name) {
+                Some((_, sql, kind)) => Field::Column(sql, *kind),
+                None => return Err(SearchError::UnknownField(name)),
+            },
+        };
+
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            _ => {
+                self.pos -= 1;
+                return self.error("expected an operator");
+            }
+        };
+
+        let value = match self.next() {
+            Some(Token::Str(s)) => Literal::Str(s),
+            Some(Token::Num(n)) => Literal::Num(n),
+            _ => {
+                self.pos -= 1;
+                return self.error("expected a quoted string or number");
+            }
+        };
+
+        if op == Op::Glob
+            && (matches!(value, Literal::Num(_)) || matches!(field, Field::Column(_, ColumnKind::Timestamp)))
+        {
+            return Err(SearchError::BadOperator { field: name, op: op.as_str() });
+        }
+        // Timestamps are checked here so a typo is a 422, not a database error.
+        if matches!(field, Field::Column(_, ColumnKind::Timestamp)) {
+            let valid = match &value {
+                Literal::Str(s) => {
+                    chrono::DateTime::parse_from_rfc3339(s).is_ok()
+                        || chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok()
+                }
+                Literal::Num(_) => false,
+            };
+            if !valid {
+                return Err(SearchError::BadValue { field: name, reason: "expected an RFC 3339 timestamp or YYYY-MM-DD" });
+            }
+        }
+
+        if matches!(field, Field::Column(_, ColumnKind::Version)) && op.is_ordering() {
+            let raw = match &value {
+                Literal::Str(s) | Literal::Num(s) => s,
+            };
+            if !raw.contains(|c: char| c.is_ascii_digit()) {
+                return Err(SearchError::BadValue { field: name, reason: "expected a version number, e.g. \"2.0\"" });
+            }
+        }
+
+        Ok(Expr::Cmp { field, op, value })
+    }
+
+    fn subscript(&mut self) -> Result<String, SearchError> {
+        if self.next() != Some(Token::LBracket) {
+            self.pos -= 1;
+            return self.error("expected '['");
+        }
+        let key = match self.next() {
+            Some(Token::Str(key)) => key,
+            _ => {
+                self.pos -= 1;
+                return self.error("expected a quoted name");
+            }
+        };
+        if self.next() != Some(Token::RBracket) {
+            self.pos -= 1;
+            return self.error("expected ']'");
+        }
+        Ok(key)
+    }
+}
+
+// ── SQL compilation ───────────────────────────────────────────────────────────
+
+/// The numeric components of a version string as a `numeric[]`, which
+/// Postgres orders element by element: `1.10` → `{1,10}` > `{1,9}`.
+fn version_key(text: &str) -> String {
+    format!("ARRAY(SELECT m[1]::numeric FROM regexp_matches({text}, '[0-9]+', 'g') m)")
+}
+
+/// A compiled condition plus the text values for its placeholders.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SqlFilter {
+    pub sql:   String,
+    pub binds: Vec<String>,
+}
+
+/// Compile `expr` to SQL, numbering placeholders from `$first_idx`.
+pub fn compile(expr: &Expr, first_idx: usize) -> SqlFilter {
+    let mut c = Compiler { binds: Vec::new(), first_idx };
+    let sql = c.expr(expr);
+    SqlFilter { sql, binds: c.binds }
+}
+
+struct Compiler {
+    binds:     Vec<String>,
+    first_idx: usize,
+}
+
+impl Compiler {
+    fn bind(&mut self, value: impl Into<String>) -> String {
+        self.binds.push(value.into());
+        format!("${}", self.first_idx + self.binds.len() - 1)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::And(a, b) => format!("({} AND {})", self.expr(a), self.expr(b)),
+            Expr::Or(a, b) => format!("({} OR {})", self.expr(a), self.expr(b)),
+            Expr::Not(e) => format!("NOT COALESCE({}, false)", self.expr(e)),
+            Expr::HasTag(tag) => format!("{} = ANY(d.tags)", self.bind(tag.as_str())),
+            Expr::Cmp { field, op, value } => self.cmp(field, *op, value),
+        }
+    }
+
+    fn cmp(&mut self, field: &Field, op: Op, value: &Literal) -> String {
+        let sql_op = op.sql();
+        match field {
+            Field::Column(col, ColumnKind::Timestamp) => {
+                let v = self.value(op, value);
+                format!("{col} {sql_op} {v}::timestamptz")
+            }
+            Field::Column(col, ColumnKind::Version) if op.is_ordering() => {
+                let v = self.value(op, value);
+                format!("({col} IS NOT NULL AND {} {sql_op} {})", version_key(col), version_key(&v))
+            }
+            Field::Column(col, ColumnKind::Text | ColumnKind::Version) => {
+                let v = self.value(op, value);
+                format!("{col} {sql_op} {v}")
+            }
+            Field::Metadata(key) => {
+                let k = self.bind(key.as_str());
+                let lhs = self.typed(format!("d.metadata -> {k}"), value);
+                let v = self.value(op, value);
+                format!("{lhs} {sql_op} {}", Self::cast(v, value))
+            }
+            Field::Property(name) => {
+                let k = self.bind(name.as_str());
+                let lhs = self.typed("p.property_value".to_string(), value);
+                let v = self.value(op, value);
+                format!(
+                    "EXISTS (SELECT 1 FROM device_properties p WHERE p.device_id = d.id \
+                     AND p.property_name = {k} AND {lhs} {sql_op} {})",
+                    Self::cast(v, value)
+                )
+            }
+            Field::Param(path) => {
+                let k = self.bind(path.as_str());
+                let lhs = match value {
+                    Literal::Str(_) => "p.parameter_value".to_string(),
+                    Literal::Num(_) => "CASE WHEN p.parameter_value ~ '^-?[0-9]+(\\.[0-9]+)?$' \
+                                        THEN p.parameter_value::numeric END"
+                        .to_string(),
+                };
+                let v = self.value(op, value);
+                format!(
+                    "EXISTS (SELECT 1 FROM device_parameters p WHERE p.device_id = d.id \
+                     AND p.parameter_name = {k} AND {lhs} {sql_op} {})",
+                    Self::cast(v, value)
+                )
+            }
+        }
+    }
+
+    /// A JSONB expression as text, or as numeric when compared to a number
+    /// (non-numeric JSON then yields NULL and never matches).
+    fn typed(&self, json: String, value: &Literal) -> String {
+        match value {
+            Literal::Str(_) => format!("({json}) #>> '{{}}'"),
+            Literal::Num(_) => format!(
+                "CASE WHEN jsonb_typeof({json}) = 'number' THEN ({json})::numeric END"
+            ),
+        }
+    }
+
+    fn value(&mut self, op: Op, value: &Literal) -> String {
+        let raw = match value {
+            Literal::Str(s) | Literal::Num(s) => s.as_str(),
+        };
+        if op == Op::Glob {
+            self.bind(glob_to_like(raw))
+        } else {
+            self.bind(raw)
+        }
+    }
+
+    fn cast(placeholder: String, value: &Literal) -> String {
+        match value {
+            Literal::Str(_) => placeholder,
+            Literal::Num(_) => format!("{placeholder}::numeric"),
+        }
+    }
+}
+
+/// Translate a glob (`*`, `?`) into a `LIKE` pattern, escaping `%`, `_`
+/// and `\`.
+fn glob_to_like(glob: &str) -> String {
+    let mut out = String::with_capacity(glob.len());
+    for c in glob.chars() {
+        match c {
+            '*' => out.push('%'),
+            '?' => out.push('_'),
+            '%' | '_' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// ── Sorting and keyset pagination ─────────────────────────────────────────────
+
+/// Sortable fields: name → (SQL expression, type used to cast the cursor).
+///
+/// Nullable columns sort as `''` so the keyset comparison never meets NULL.
+/// Versions sort on [`version_key`], as they compare in filters; a missing
+/// version has no components and sorts first.
+const SORTS: &[(&str, &str, &str)] = &[
+    ("device_uid",       "d.device_uid",                      "text"),
+    ("first_seen",       "d.first_seen",                      "timestamptz"),
+    ("last_seen",        "d.last_seen",                       "timestamptz"),
+    ("health_changed_at", "d.health_changed_at",              "timestamptz"),
+    ("manufacturer",     "COALESCE(d.manufacturer, '')",      "text"),
+    ("oui",              "COALESCE(d.oui, '')",               "text"),
+    ("product_class",    "COALESCE(d.product_class, '')",     "text"),
+    ("serial_number",    "COALESCE(d.serial_number, '')",     "text"),
+    ("software_version", "ARRAY(SELECT m[1]::numeric FROM regexp_matches(d.software_version, '[0-9]+', 'g') m)", "numeric[]"),
+    ("hardware_version", "ARRAY(SELECT m[1]::numeric FROM regexp_matches(d.hardware_version, '[0-9]+', 'g') m)", "numeric[]"),
+];
+
+/// A resolved `sort` parameter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sort {
+    pub name: String,
+    pub expr: &'static str,
+    pub cast: &'static str,
+    pub desc: bool,
+}
+
+impl Sort {
+    /// Parse `field` or `-field` (descending).
+    pub fn parse(spec: &str) -> Result<Self, SearchError> {
+        let (desc, name) = match spec.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, spec),
+        };
+        SORTS
+            .iter()
+            .find(|(n, _, _)| *n == name)
+            .map(|(n, expr, cast)| Sort { name: n.to_string(), expr, cast, desc })
+            .ok_or_else(|| SearchError::UnknownSort(spec.to_string()))
+    }
+
+    /// `ORDER BY` clause, with the device id as tie-breaker.
+    pub fn order_by(&self) -> String {
+        let dir = if self.desc { "DESC" } else { "ASC" };
+        format!("ORDER BY {} {dir}, d.id {dir}", self.expr)
+    }
+
+    /// Condition selecting rows after the cursor position.
+    pub fn after(&self, key_idx: usize, id_idx: usize) -> String {
+        let cmp = if self.desc { "<" } else { ">" };
+        format!("({}, d.id) {cmp} (${key_idx}::{}, ${id_idx})", self.expr, self.cast)
+    }
+}
+
+/// Opaque position of the last row of a page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    /// Sort spec the cursor was produced for, e.g. `-last_seen`.
+    pub sort: String,
+    /// The last row's sort key, as text.
+    pub key:  String,
+    pub id:   Uuid,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        // A struct of strings and a UUID always serialises.
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursor serialises"))
+    }
+
+    pub fn decode(s: &str) -> Result<Self, SearchError> {
+        let raw = URL_SAFE_NO_PAD.decode(s).map_err(|_| SearchError::BadCursor)?;
+        serde_json::from_slice(&raw).map_err(|_| SearchError::BadCursor)
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sql(q: &str) -> SqlFilter {
+        compile(&parse(q).unwrap(), 1)
+    }
+
+    #[test]
+    fn example_query_compiles() {
+        let f = sql(r#"software_version < "2.0" AND tags has "pilot" AND param["Device.WiFi.SSID.1.SSID"] ~ "Guest*""#);
+        assert_eq!(
+            f.sql,
+            "(((d.software_version IS NOT NULL AND \
+             ARRAY(SELECT m[1]::numeric FROM regexp_matches(d.software_version, '[0-9]+', 'g') m) < \
+             ARRAY(SELECT m[1]::numeric FROM regexp_matches($1, '[0-9]+', 'g') m)) AND $2 = ANY(d.tags)) \
+             AND EXISTS (SELECT 1 FROM device_parameters p \
+             WHERE p.device_id = d.id AND p.parameter_name = $3 AND p.parameter_value LIKE $4))"
+        );
+        assert_eq!(f.binds, vec!["2.0", "pilot", "Device.WiFi.SSID.1.SSID", "Guest%"]);
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let f = sql(r#"oui = "A" OR oui = "B" AND NOT product_class = "C""#);
+        assert_eq!(
+            f.sql,
+            "(d.oui = $1 OR (d.oui = $2 AND NOT COALESCE(d.product_class = $3, false)))"
+        );
+    }
+
+    #[test]
+    fn placeholders_start_at_offset() {
+        let f = compile(&parse(r#"device_uid = "X""#).unwrap(), 3);
+        assert_eq!(f.sql, "d.device_uid = $3");
+    }
+
+    #[test]
+    fn number_against_metadata_is_numeric() {
+        let f = sql(r#"metadata["floor"] >= 3"#);
+        assert!(f.sql.contains("::numeric END >= $2::numeric"), "{}", f.sql);
+        assert_eq!(f.binds, vec!["floor", "3"]);
+    }
+
+    #[test]
+    fn versions_order_by_component() {
+        let f = sql(r#"software_version >= 10"#);
+        assert!(f.sql.contains("regexp_matches($1, '[0-9]+', 'g') m))"), "{}", f.sql);
+        assert_eq!(f.binds, vec!["10"]);
+        assert_eq!(sql(r#"software_version = "2.0""#).sql, "d.software_version = $1");
+        assert!(matches!(parse(r#"hardware_version >= "rev-b""#), Err(SearchError::BadValue { .. })));
+    }
+
+    #[test]
+    fn values_never_reach_sql_text() {
+        let f = sql(r#"device_uid = "x'; DROP TABLE devices; --""#);
+        assert!(!f.sql.contains("DROP"));
+    }
+
+    #[test]
+    fn glob_escapes_like_metacharacters() {
+        assert_eq!(glob_to_like("a%b_c*?\\"), "a\\%b\\_c%_\\\\");
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        assert_eq!(parse("colour = \"red\""), Err(SearchError::UnknownField("colour".to_string())));
+        assert!(matches!(parse("oui = "), Err(SearchError::Syntax(..))));
+        assert!(matches!(parse("(oui = \"A\""), Err(SearchError::Syntax(..))));
+        assert!(matches!(parse("last_seen ~ \"2026*\""), Err(SearchError::BadOperator { .. })));
+        assert!(matches!(parse("param[\"x\"] ~ 3"), Err(SearchError::BadOperator { .. })));
+        assert!(matches!(parse("last_seen > \"yesterday\""), Err(SearchError::BadValue { .. })));
+        assert!(parse("last_seen > \"2026-10-01\"").is_ok());
+        assert_eq!(parse(&"NOT ".repeat(40)), Err(SearchError::TooDeep));
+    }
+
+    #[test]
+    fn sort_and_cursor() {
+        let s = Sort::parse("-last_seen").unwrap();
+        assert_eq!(s.order_by(), "ORDER BY d.last_seen DESC, d.id DESC");
+        assert_eq!(s.after(4, 5), "(d.last_seen, d.id) < ($4::timestamptz, $5)");
+        assert!(Sort::parse("metadata").is_err());
+
+        let s = Sort::parse("software_version").unwrap();
+        assert_eq!(s.expr, version_key("d.software_version"));
+        assert_eq!(
+            s.after(1, 2),
+            format!("({}, d.id) > ($1::numeric[], $2)", version_key("d.software_version")),
+        );
+
+        let c = Cursor { sort: "-last_seen".to_string(), key: "2026-01-01 00:00:00+00".to_string(), id: Uuid::nil() };
+        assert_eq!(Cursor::decode(&c.encode()), Ok(c));
+        assert_eq!(Cursor::decode("garbage"), Err(SearchError::BadCursor));
+    }
+}
@@ -0,0 +1,234 @@
+//! Device sessions: routing and records.
+//!
+//! Which protocol-pod session a device currently has open is kept in the
+//! NATS KV bucket `acs_sessions`, so any controller replica can route a
+//! command to the device no matter which replica handled its Inform.
+//! Entries are refreshed on every Inform and removed when the session ends;
+//! ones whose `session_ended` was lost expire after the bucket's TTL.
+//!
+//! Each session is also recorded in `device_sessions`, with its duration
+//! and how many commands, responses and faults it saw.
+
+use anyhow::Context;
+use async_nats::jetstream::kv;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Prefix of keys holding an encoded device UID.
+const ENCODED_PREFIX: &str = "b64.";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Route {
+    session_id: String,
+    /// Controller replica that recorded the session, for debugging.
+    instance:   String,
+    updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Clone)]
+pub struct SessionRegistry {
+    store:    kv::Store,
+    instance: String,
+}
+
+impl SessionRegistry {
+    pub fn new(store: kv::Store, instance: String) -> Self {
+        Self { store, instance }
+    }
+
+    /// Session currently open for `device_uid`, if any.
+    pub async fn get(&self, device_uid: &str) -> anyhow::Result<Option<String>> {
+        let value = self
+            .store
+            .get(key(device_uid))
+            .await
+            .context("Failed to read session route")?;
+        Ok(value.and_then(|v| serde_json::from_slice::<Route>(&v).ok()).map(|r| r.session_id))
+    }
+
+    /// Record `session_id` as the device's open session. Returns the
+    /// session it replaces, if any.
+    pub async fn record(&self, device_uid: &str, session_id: &str) -> anyhow::Result<Option<String>> {
+        let previous = self.get(device_uid).await?;
+        let route = Route {
+            session_id: session_id.to_string(),
+            instance:   self.instance.clone(),
+            updated_at: chrono::Utc::now(),
+        };
+        self.store
+            .put(key(device_uid), serde_json::to_vec(&route)?.into())
+            .await
+            .context("Failed to write session route")?;
+        Ok(previous)
+    }
+
+    /// Forget `session_id`. A newer session recorded in the meantime is
+    /// left alone. Returns whether a route was removed.
+    pub async fn end(&self, device_uid: &str, session_id: &str) -> anyhow::Result<bool> {
+        let key = key(device_uid);
+        let Some(entry) = self.store.entry(key.clone()).await.context("Failed to read session route")? else {
+            return Ok(false);
+        };
+        if entry.operation != kv::Operation::Put {
+            return Ok(false);
+        }
+        match serde_json::from_slice::<Route>(&entry.value) {
+            Ok(route) if route.session_id == session_id => {}
+            // Another session has been recorded since.
+            Ok(_) => return Ok(false),
+            // Unreadable routes are removed too.
+            Err(_) => {}
+        }
+        // Fails if the route changed since it was read: a new session won.
+        Ok(self.store.delete_expect_revision(key, Some(entry.revision)).await.is_ok())
+    }
+}
+
+// ── Session records ───────────────────────────────────────────────────────────
+
+/// A closed session, as returned by [`close`].
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct SessionSummary {
+    pub session_id:    String,
+    pub protocol:      String,
+    pub started_at:    chrono::DateTime<chrono::Utc>,
+    pub ended_at:      chrono::DateTime<chrono::Utc>,
+    pub duration_secs: f64,
+    pub informs:       i32,
+    pub commands:      i32,
+    pub responses:     i32,
+    pub faults:        i32,
+}
+
+/// Open the record of `session_id` on its first Inform and count the Inform.
+pub async fn record_inform(pool: &PgPool, session_id: &str, device_id: Uuid, protocol: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO device_sessions (session_id, device_id, protocol, informs)
+        VALUES ($1, $2, $3, 1)
+        ON CONFLICT (session_id) DO UPDATE SET informs = device_sessions.informs + 1
+        "#,
+    )
+    .bind(session_id)
+    .bind(device_id)
+    .bind(protocol)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Count `n` commands published to `session_id`.
+pub async fn count_commands(pool: &PgPool, session_id: &str, n: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE device_sessions SET commands = commands + $2 WHERE session_id = $1")
+        .bind(session_id)
+        .bind(n)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Count a response against the device's open session, if it has one.
+/// Responses do not carry a session id; the latest open session is the
+/// one the device is answering in.
+pub async fn count_response(pool: &PgPool, device_uid: &str, fault: bool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE device_sessions SET responses = responses + 1, faults = faults + $2::int
+        WHERE session_id = (
+            SELECT s.session_id FROM device_sessions s
+            JOIN devices d ON d.id = s.device_id
+            WHERE d.device_uid = $1 AND s.ended_at IS NULL
+            ORDER BY s.started_at DESC
+            LIMIT 1
+        )
+        "#,
+    )
+    .bind(device_uid)
+    .bind(fault)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Close the record of `session_id` and stamp the device's `last_seen` and
+/// its protocol's `last_session_at`. `None` if the session was never
+/// recorded or is already closed.
+pub async fn close(pool: &PgPool, session_id: &str, reason: Option<&str>) -> Result<Option<SessionSummary>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let summary: Option<(Uuid, SessionSummary)> = sqlx::query_as::<_, SessionRow>(
+        r#"
+        UPDATE device_sessions SET ended_at = now(), end_reason = $2
+        WHERE session_id = $1 AND ended_at IS NULL
+        RETURNING device_id, session_id, protocol, started_at, ended_at,
+                  EXTRACT(EPOCH FROM ended_at - started_at)::float8 AS duration_secs,
+                  informs, commands, responses, faults
+        "#,
+    )
+    .bind(session_id)
+    .bind(reason)
+    .fetch_optional(&mut *tx)
+    .await?
+    .map(|row| (row.device_id, row.summary));
+
+    let Some((device_id, summary)) = summary else {
+        return Ok(None);
+    };
+
+    sqlx::query("UPDATE devices SET last_seen = $2 WHERE id = $1")
+        .bind(device_id)
+        .bind(summary.ended_at)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE device_protocols SET last_session_at = $3 WHERE device_id = $1 AND protocol = $2")
+        .bind(device_id)
+        .bind(&summary.protocol)
+        .bind(summary.ended_at)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(Some(summary))
+}
+
+#[derive(sqlx::FromRow)]
+struct SessionRow {
+    device_id: Uuid,
+    #[sqlx(flatten)]
+    summary:   SessionSummary,
+}
+
+/// KV key of a device. Keys are limited to `[-/_=.a-zA-Z0-9]`; UIDs using
+/// anything else are stored base64url-encoded.
+fn key(device_uid: &str) -> String {
+    let plain = !device_uid.is_empty()
+        && !device_uid.starts_with('.')
+        && !device_uid.ends_with('.')
+        && !device_uid.starts_with(ENCODED_PREFIX)
+        && device_uid.chars().all(|c| c.is_ascii_alphanumeric() || "-/_=.".contains(c));
+    if plain {
+        device_uid.to_string()
+    } else {
+        format!("{ENCODED_PREFIX}{}", URL_SAFE_NO_PAD.encode(device_uid))
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_valid_and_distinct() {
+        assert_eq!(key("AABBCC-SN12345"), "AABBCC-SN12345");
+        for uid in ["AABBCC-SN 1", "AABBCC-SN*", ".hidden", "b64.QUJD", "ÆØÅ-1"] {
+            let k = key(uid);
+            assert!(k.starts_with(ENCODED_PREFIX), "{uid} -> {k}");
+            assert!(k.chars().all(|c| c.is_ascii_alphanumeric() || "-/_=.".contains(c)), "{k}");
+        }
+        assert_ne!(key("b64.QUJD"), key("ABC"));
+    }
+}
@@ -0,0 +1,523 @@
+//! Persistent device task queue.
+//!
+//! A task is an [`Action`] stored in `tasks` until the device next opens a
+//! session. [`deliver_pending`] runs on every Inform — periodic, boot or
+//! connection request alike — and publishes the device's pending tasks to
+//! that session in priority order. [`record_response`] correlates the
+//! device's `command_response` back to the task by `command_id`.
+//!
+//! A faulted delivery is retried on a later session until `max_attempts`
+//! deliveries have been made; after that the task stays `faulted`. A
+//! delivery left unanswered when its session ends does not count as an
+//! attempt and is made again (see [`requeue_unanswered`]).
+//!
+//! Tasks created together by the async command API share a `batch_id` and
+//! are delivered in `batch_seq` order within one session.
+
+use nats_common::{Action, ActionResult, DeviceCommand, DeviceResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sqlx::PgPool;
+use tokio::sync::broadcast;
+use tokio_stream::StreamExt;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::events::{EventHub, EventKind};
+use crate::nats::{NatsClient, TASK_UPDATES_SUBJECT};
+
+// ── Types ─────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Pending,
+    Sent,
+    Succeeded,
+    Faulted,
+    Expired,
+}
+
+impl TaskStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TaskStatus::Pending   => "pending",
+            TaskStatus::Sent      => "sent",
+            TaskStatus::Succeeded => "succeeded",
+            TaskStatus::Faulted   => "faulted",
+            TaskStatus::Expired   => "expired",
+        }
+    }
+}
+
+/// One row of `tasks`.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Task {
+    pub id:           Uuid,
+    pub device_id:    Uuid,
+    pub action:       JsonValue,
+    pub status:       String,
+    pub priority:     i32,
+    pub expires_at:   Option<chrono::DateTime<chrono::Utc>>,
+    pub max_attempts: i32,
+    pub attempts:     i32,
+    pub command_id:   Option<Uuid>,
+    pub last_error:   Option<String>,
+    pub created_by:   Option<Uuid>,
+    pub batch_id:     Option<Uuid>,
+    pub batch_seq:    Option<i32>,
+    pub created_at:   chrono::DateTime<chrono::Utc>,
+    pub updated_at:   chrono::DateTime<chrono::Utc>,
+    pub sent_at:      Option<chrono::DateTime<chrono::Utc>>,
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// One row of `task_results`.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct TaskResult {
+    pub command_id:  Uuid,
+    pub attempt:     i32,
+    pub result:      JsonValue,
+    pub received_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A task's response, as broadcast to API clients following it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskUpdate {
+    pub task_id:  Uuid,
+    pub batch_id: Option<Uuid>,
+    pub status:   TaskStatus,
+    pub response: DeviceResponse,
+}
+
+/// Row claimed for delivery by [`deliver_pending`].
+#[derive(sqlx::FromRow)]
+struct Claimed {
+    id:         Uuid,
+    command_id: Uuid,
+    action:     JsonValue,
+    priority:   i32,
+    created_at: chrono::DateTime<chrono::Utc>,
+    batch_seq:  Option<i32>,
+}
+
+/// Parameters for a new task. `None` fields take the table defaults.
+#[derive(Debug, Deserialize)]
+pub struct NewTask {
+    pub action:          Action,
+    pub priority:        Option<i32>,
+    /// Seconds from now after which an undelivered task expires.
+    pub expires_in_secs: Option<i64>,
+    pub max_attempts:    Option<i32>,
+}
+
+pub const TASK_COLUMNS: &str = "id, device_id, action, status, priority, expires_at, max_attempts, \
+                                attempts, command_id, last_error, created_by, batch_id, \
+                                batch_seq, created_at, updated_at, sent_at, completed_at";
+
+// ── Queue operations ──────────────────────────────────────────────────────────
+
+/// Store `actions` as one batch of pending tasks and return the batch id.
+///
+/// Batched actions are attempted once each — retrying one out of order
+/// would break the sequence the caller asked for.
+pub async fn enqueue_batch(
+    pool: &PgPool,
+    device_id: Uuid,
+    actions: &[Action],
+    expires_in_secs: Option<i64>,
+    created_by: Option<Uuid>,
+) -> Result<Uuid, sqlx::Error> {
+    let batch_id = Uuid::new_v4();
+    let mut tx = pool.begin().await?;
+
+    for (seq, action) in actions.iter().enumerate() {
+        // Serialising an Action (strings, maps, integers) cannot fail.
+        let action = serde_json::to_value(action).expect("Action serialises");
+        sqlx::query(
+            r#"
+            INSERT INTO tasks (device_id, action, expires_at, max_attempts, created_by, batch_id, batch_seq)
+            VALUES (
+                $1, $2,
+                CASE WHEN $3::BIGINT IS NULL THEN NULL ELSE now() + make_interval(secs => $3) END,
+                1, $4, $5, $6
+            )
+            "#,
+        )
+        .bind(device_id)
+        .bind(action)
+        .bind(expires_in_secs)
+        .bind(created_by)
+        .bind(batch_id)
+        .bind(seq as i32)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(batch_id)
+}
+
+/// Store a new pending task for `device_id`.
+pub async fn enqueue(
+    pool: &PgPool,
+    device_id: Uuid,
+    task: &NewTask,
+    created_by: Option<Uuid>,
+) -> Result<Task, sqlx::Error> {
+    // Serialising an Action (strings, maps, integers) cannot fail.
+    let action = serde_json::to_value(&task.action).expect("Action serialises");
+
+    sqlx::query_as::<_, Task>(&format!(
+        r#"
+        INSERT INTO tasks (device_id, action, priority, expires_at, max_attempts, created_by)
+        VALUES (
+            $1, $2, COALESCE($3, 100),
+            CASE WHEN $4::BIGINT IS NULL THEN NULL ELSE now() + make_interval(secs => $4) END,
+            COALESCE($5, 3), $6
+        )
+        RETURNING {TASK_COLUMNS}
+        "#
+    ))
+    .bind(device_id)
+    .bind(action)
+    .bind(task.priority)
+    .bind(task.expires_in_secs)
+    .bind(task.max_attempts)
+    .bind(created_by)
+    .fetch_one(pool)
+    .await
+}
+
+/// Publish every deliverable task for `device_id` to `session_id`.
+///
+/// Overdue tasks are marked `expired` first. The remaining pending tasks
+/// are claimed (`sent`, fresh `command_id`, `attempts + 1`) in a single
+/// statement so two sessions for the same device cannot both deliver a
+/// task. Once a publish fails nothing further is sent, so later actions
+/// never overtake it: that task and the rest of the claim go back to
+/// `pending` with their attempt refunded.
+///
+/// Returns the status changes made, in order (see [`announce`]).
+pub async fn deliver_pending(
+    pool: &PgPool,
+    nats: &NatsClient,
+    device_id: Uuid,
+    device_uid: &str,
+    session_id: &str,
+) -> Result<Vec<(Uuid, TaskStatus)>, sqlx::Error> {
+    let expired: Vec<Uuid> = sqlx::query_scalar(
+        r#"
+        UPDATE tasks
+        SET status = 'expired', completed_at = now(), updated_at = now()
+        WHERE device_id = $1 AND status = 'pending' AND expires_at <= now()
+        RETURNING id
+        "#,
+    )
+    .bind(device_id)
+    .fetch_all(pool)
+    .await?;
+    if !expired.is_empty() {
+        info!(device_uid, expired = expired.len(), "Expired undelivered tasks");
+    }
+    let mut changes: Vec<_> = expired.into_iter().map(|id| (id, TaskStatus::Expired)).collect();
+
+    let mut claimed: Vec<Claimed> = sqlx::query_as(
+        r#"
+        UPDATE tasks
+        SET status     = 'sent',
+            attempts   = attempts + 1,
+            command_id = gen_random_uuid(),
+            session_id = $2,
+            sent_at    = now(),
+            updated_at = now()
+        WHERE id IN (
+            SELECT id FROM tasks
+            WHERE device_id = $1 AND status = 'pending'
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, command_id, action, priority, created_at, batch_seq
+        "#,
+    )
+    .bind(device_id)
+    .bind(session_id)
+    .fetch_all(pool)
+    .await?;
+
+    // UPDATE … RETURNING has no defined order. A batch shares one
+    // created_at, so batch_seq keeps its actions in request order.
+    claimed.sort_by_key(|c| (c.priority, c.created_at, c.batch_seq));
+
+    let mut published = 0;
+    for (i, &Claimed { id: task_id, command_id, ref action, .. }) in claimed.iter().enumerate() {
+        let mut action: Action = match serde_json::from_value(action.clone()) {
+            Ok(a) => a,
+            Err(e) => {
+                warn!(%task_id, error = %e, "Stored task action is not a valid Action — faulting task");
+                finish(pool, task_id, TaskStatus::Faulted, Some(&format!("Invalid action: {e}"))).await?;
+                changes.push((task_id, TaskStatus::Faulted));
+                continue;
+            }
+        };
+        // API requests were checked against the data model when queued;
+        // what provisioning and backups queue is only typed.
+        crate::datamodel::prepare(pool, device_id, &mut action).await;
+
+        let command = DeviceCommand { command_id, device_id: device_uid.to_string(), action };
+        // DeviceCommand holds only serialisable data.
+        let payload = serde_json::to_vec(&command).expect("DeviceCommand serialises");
+
+        if let Err(e) = nats.publish_command(session_id, payload).await {
+            let unsent: Vec<Uuid> = claimed[i..].iter().map(|c| c.id).collect();
+            error!(?e, %task_id, session_id, unsent = unsent.len(), "Failed to publish task — returning the rest to pending");
+            sqlx::query(
+                r#"
+                UPDATE tasks
+                SET status = 'pending', attempts = attempts - 1, command_id = NULL,
+                    session_id = NULL, sent_at = NULL, updated_at = now()
+                WHERE id = ANY($1)
+                "#,
+            )
+            .bind(&unsent)
+            .execute(pool)
+            .await?;
+            break;
+        }
+
+        debug!(%task_id, %command_id, session_id, "Task delivered");
+        changes.push((task_id, TaskStatus::Sent));
+        published += 1;
+    }
+
+    if published > 0 {
+        info!(device_uid, session_id, published, "Delivered queued tasks");
+        crate::sessions::count_commands(pool, session_id, published).await?;
+    }
+    Ok(changes)
+}
+
+/// Return tasks delivered to `session_id` but never answered to `pending`,
+/// refunding the attempt, so the device's next session delivers them again.
+///
+/// Returns the ids of the requeued tasks.
+pub async fn requeue_unanswered(pool: &PgPool, device_id: Uuid, session_id: &str) -> Result<Vec<Uuid>, sqlx::Error> {
+    let requeued: Vec<Uuid> = sqlx::query_scalar(
+        r#"
+        UPDATE tasks
+        SET status = 'pending', attempts = attempts - 1, command_id = NULL,
+            session_id = NULL, sent_at = NULL, updated_at = now()
+        WHERE device_id = $1 AND session_id = $2 AND status = 'sent'
+        RETURNING id
+        "#,
+    )
+    .bind(device_id)
+    .bind(session_id)
+    .fetch_all(pool)
+    .await?;
+    if !requeued.is_empty() {
+        info!(session_id, requeued = requeued.len(), "Requeued tasks left unanswered by ended session");
+    }
+    Ok(requeued)
+}
+
+/// Stream task status changes to API clients as `task_status` events.
+pub fn announce(events: &EventHub, device_uid: &str, domain_id: Uuid, changes: &[(Uuid, TaskStatus)]) {
+    for (task_id, status) in changes {
+        events.publish(
+            EventKind::TaskStatus,
+            device_uid,
+            domain_id,
+            serde_json::json!({ "task_id": task_id, "status": status }),
+        );
+    }
+}
+
+/// Share a task update with the API clients of every replica. Falls back to
+/// this replica's clients if NATS is unavailable.
+pub async fn share_update(nats: &NatsClient, local: &broadcast::Sender<TaskUpdate>, update: TaskUpdate) {
+    let payload = serde_json::to_vec(&update).expect("TaskUpdate serialises");
+    if let Err(e) = nats.broadcast(TASK_UPDATES_SUBJECT, payload).await {
+        warn!(?e, task_id = %update.task_id, "Failed to relay task update");
+        // No receivers is normal when nobody is streaming.
+        let _ = local.send(update);
+    }
+}
+
+/// Feed task updates shared by any replica to this replica's clients.
+pub async fn relay_updates(nats: &NatsClient, local: broadcast::Sender<TaskUpdate>) -> anyhow::Result<()> {
+    let mut incoming = nats.subscribe(TASK_UPDATES_SUBJECT).await?;
+    tokio::spawn(async move {
+        while let Some(msg) = incoming.next().await {
+            match serde_json::from_slice::<TaskUpdate>(&msg.payload) {
+                Ok(update) => {
+                    let _ = local.send(update);
+                }
+                Err(e) => warn!(?e, "Malformed relayed task update"),
+            }
+        }
+        error!("Task update relay subscription ended");
+    });
+    Ok(())
+}
+
+/// Record a device response against the task it answers, if any.
+///
+/// Returns the resulting [`TaskUpdate`], or `None` when `operation_id` does
+/// not belong to a task that is awaiting a response (e.g. an interactive
+/// `send_command` or a provisioning action).
+pub async fn record_response(pool: &PgPool, response: &DeviceResponse) -> Result<Option<TaskUpdate>, sqlx::Error> {
+    let Some(command_id) = response.operation_id else {
+        return Ok(None);
+    };
+
+    let mut tx = pool.begin().await?;
+
+    let row: Option<(Uuid, i32, i32, Option<Uuid>)> = sqlx::query_as(
+        r#"
+        SELECT id, attempts, max_attempts, batch_id
+        FROM tasks WHERE command_id = $1 AND status = 'sent'
+        FOR UPDATE
+        "#,
+    )
+    .bind(command_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some((task_id, attempts, max_attempts, batch_id)) = row else {
+        return Ok(None);
+    };
+
+    // ActionResult holds only serialisable data.
+    let result = serde_json::to_value(&response.result).expect("ActionResult serialises");
+    sqlx::query("INSERT INTO task_results (task_id, command_id, attempt, result) VALUES ($1, $2, $3, $4)")
+        .bind(task_id)
+        .bind(command_id)
+        .bind(attempts)
+        .bind(result)
+        .execute(&mut *tx)
+        .await?;
+
+    let (status, error) = outcome(&response.result, attempts, max_attempts);
+    sqlx::query(
+        r#"
+        UPDATE tasks
+        SET status       = $2,
+            last_error   = COALESCE($3, last_error),
+            completed_at = CASE WHEN $2 = 'pending' THEN NULL ELSE now() END,
+            updated_at   = now()
+        WHERE id = $1
+        "#,
+    )
+    .bind(task_id)
+    .bind(status.as_str())
+    .bind(error)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    info!(%task_id, %command_id, status = status.as_str(), attempts, "Task response recorded");
+    Ok(Some(TaskUpdate { task_id, batch_id, status, response: response.clone() }))
+}
+
+/// Move a task to a terminal status.
+async fn finish(pool: &PgPool, task_id: Uuid, status: TaskStatus, error: Option<&str>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE tasks
+        SET status = $2, last_error = COALESCE($3, last_error),
+            completed_at = now(), updated_at = now()
+        WHERE id = $1
+        "#,
+    )
+    .bind(task_id)
+    .bind(status.as_str())
+    .bind(error)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Status a task moves to after `result`, and the error to record.
+///
+/// A fault is retried (back to `pending`) while deliveries remain.
+fn outcome(result: &ActionResult, attempts: i32, max_attempts: i32) -> (TaskStatus, Option<String>) {
+    match result {
+        ActionResult::Success(_) | ActionResult::Done => (TaskStatus::Succeeded, None),
+        ActionResult::Fault { code, string } => {
+            let error = Some(format!("{code}: {string}"));
+            if attempts < max_attempts {
+                (TaskStatus::Pending, error)
+            } else {
+                (TaskStatus::Faulted, error)
+            }
+        }
+    }
+}
+
+/// Overall status of a batch given the status of each of its tasks.
+///
+/// `queued` until any action is sent, `running` until every action is
+/// final, then `succeeded` or — if any action faulted or expired — `failed`.
+pub fn batch_status<'a>(statuses: impl IntoIterator<Item = &'a str>) -> &'static str {
+    let (mut pending, mut open, mut failed, mut total) = (0, 0, 0, 0);
+    for status in statuses {
+        total += 1;
+        match status {
+            "pending" => pending += 1,
+            "sent" => open += 1,
+            "faulted" | "expired" => failed += 1,
+            _ => {}
+        }
+    }
+
+    if total > 0 && pending == total {
+        "queued"
+    } else if pending + open > 0 {
+        "running"
+    } else if failed > 0 {
+        "failed"
+    } else {
+        "succeeded"
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn fault() -> ActionResult {
+        ActionResult::Fault { code: "9002".to_string(), string: "Internal error".to_string() }
+    }
+
+    #[test]
+    fn success_completes_task() {
+        assert_eq!(outcome(&ActionResult::Done, 1, 3), (TaskStatus::Succeeded, None));
+        assert_eq!(outcome(&ActionResult::Success(HashMap::new()), 3, 3).0, TaskStatus::Succeeded);
+    }
+
+    #[test]
+    fn fault_with_attempts_left_is_retried() {
+        let (status, error) = outcome(&fault(), 1, 3);
+        assert_eq!(status, TaskStatus::Pending);
+        assert_eq!(error.as_deref(), Some("9002: Internal error"));
+    }
+
+    #[test]
+    fn fault_on_last_attempt_is_final() {
+        assert_eq!(outcome(&fault(), 3, 3).0, TaskStatus::Faulted);
+    }
+
+    #[test]
+    fn batch_status_follows_its_tasks() {
+        assert_eq!(batch_status(["pending", "pending"]), "queued");
+        assert_eq!(batch_status(["succeeded", "pending"]), "running");
+        assert_eq!(batch_status(["sent", "pending"]), "running");
+        assert_eq!(batch_status(["succeeded", "succeeded"]), "succeeded");
+        assert_eq!(batch_status(["succeeded", "faulted"]), "failed");
+        assert_eq!(batch_status(["expired"]), "failed");
+    }
+}
@@ -0,0 +1,536 @@
+//! Outbound webhooks.
+//!
+//! Domains subscribe HTTP endpoints to device lifecycle events (see
+//! `db/webhooks.sql`). [`emit`] writes an event to `webhook_deliveries`
+//! once per matching subscription; [`run_dispatcher`], on every replica,
+//! claims the deliveries that are due and POSTs them. A delivery that gets
+//! no `2xx` is retried with exponential backoff ([`retry_delay`]) until its
+//! subscription's `max_attempts` are used up, then left `dead` until it is
+//! redelivered through the API.
+//!
+//! Every request carries the event type, the event id and a timestamp, and
+//! is signed with HMAC-SHA256 over `{timestamp}.{body}` ([`signature`]):
+//!
+//! ```text
+//! X-ACS-Event:     boot
+//! X-ACS-Delivery:  <event id>
+//! X-ACS-Timestamp: 1767225600
+//! X-ACS-Signature: sha256=<hex>
+//! ```
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sha2::Sha256;
+use sqlx::PgPool;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::db::{InformPayload, UpsertedDevice};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Deliveries claimed per round.
+const BATCH: i64 = 50;
+
+/// First retry delay; it doubles with every failed attempt.
+const RETRY_BASE_SECS: u64 = 60;
+const RETRY_MAX_SECS: u64 = 6 * 3600;
+
+/// Response body kept in `last_error`, in bytes.
+const ERROR_BODY_LIMIT: usize = 200;
+
+/// Event type of test deliveries; not subscribable.
+pub const PING: &str = "ping";
+
+// ── Types ─────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A device's first Inform.
+    FirstContact,
+    /// An Inform with `1 BOOT`.
+    Boot,
+    /// An Inform reporting another software version than the last one.
+    FirmwareChange,
+    /// A device answered an action with a fault.
+    Fault,
+    /// A device stopped calling in.
+    Offline,
+}
+
+impl WebhookEvent {
+    pub const ALL: [Self; 5] = [Self::FirstContact, Self::Boot, Self::FirmwareChange, Self::Fault, Self::Offline];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::FirstContact   => "first_contact",
+            Self::Boot           => "boot",
+            Self::FirmwareChange => "firmware_change",
+            Self::Fault          => "fault",
+            Self::Offline        => "offline",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|e| e.as_str() == s)
+    }
+}
+
+/// One row of `webhook_subscriptions`, without its secret.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Subscription {
+    pub id:           Uuid,
+    pub domain_id:    Uuid,
+    pub name:         String,
+    pub url:          String,
+    /// Empty: every event type.
+    pub event_types:  Vec<String>,
+    pub enabled:      bool,
+    pub max_attempts: i32,
+    pub created_by:   Option<Uuid>,
+    pub created_at:   DateTime<Utc>,
+    pub updated_at:   DateTime<Utc>,
+}
+
+pub const SUBSCRIPTION_COLUMNS: &str = "id, domain_id, name, url, event_types, enabled, max_attempts, \
+                                        created_by, created_at, updated_at";
+
+/// One row of `webhook_deliveries`.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Delivery {
+    pub id:              Uuid,
+    pub subscription_id: Uuid,
+    pub event_id:        Uuid,
+    pub event_type:      String,
+    pub device_uid:      Option<String>,
+    pub payload:         JsonValue,
+    pub status:          String,
+    pub attempts:        i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    pub response_status: Option<i32>,
+    pub last_error:      Option<String>,
+    pub created_at:      DateTime<Utc>,
+    pub delivered_at:    Option<DateTime<Utc>>,
+}
+
+pub const DELIVERY_COLUMNS: &str = "id, subscription_id, event_id, event_type, device_uid, payload, status, attempts, \
+                                    next_attempt_at, last_attempt_at, response_status, last_error, created_at, delivered_at";
+
+/// How the dispatcher sends.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Time allowed per request.
+    pub timeout:      Duration,
+    /// Delivered and dead deliveries older than this are deleted.
+    pub history_days: i64,
+}
+
+// ── Emitting ──────────────────────────────────────────────────────────────────
+
+/// The body POSTed for an event.
+fn payload(event_id: Uuid, event_type: &str, domain_id: Uuid, device_uid: Option<&str>, data: JsonValue) -> JsonValue {
+    serde_json::json!({
+        "id":        event_id,
+        "type":      event_type,
+        "domain_id": domain_id,
+        "device_id": device_uid,
+        "timestamp": Utc::now(),
+        "data":      data,
+    })
+}
+
+/// Queue `event` about `device_uid` for every enabled subscription of
+/// `domain_id` that wants it. Failures are logged: webhooks must not hold up
+/// event handling.
+pub async fn emit(pool: &PgPool, domain_id: Uuid, event: WebhookEvent, device_uid: &str, data: JsonValue) {
+    let event_id = Uuid::new_v4();
+    let body = payload(event_id, event.as_str(), domain_id, Some(device_uid), data);
+    let result = sqlx::query(
+        r#"
+        INSERT INTO webhook_deliveries (subscription_id, event_id, event_type, device_uid, payload)
+        SELECT id, $2, $3, $4, $5 FROM webhook_subscriptions
+        WHERE domain_id = $1 AND enabled AND (cardinality(event_types) = 0 OR $3 = ANY(event_types))
+        "#,
+    )
+    .bind(domain_id)
+    .bind(event_id)
+    .bind(event.as_str())
+    .bind(device_uid)
+    .bind(&body)
+    .execute(pool)
+    .await;
+
+    match result {
+        Ok(r) if r.rows_affected() > 0 => {
+            info!(%event_id, event = event.as_str(), device_uid, subscriptions = r.rows_affected(), "Webhook event queued");
+        }
+        Ok(_) => {}
+        Err(e) => error!(?e, event = event.as_str(), device_uid, "Failed to queue webhook event"),
+    }
+}
+
+/// Queue the lifecycle events an Inform reveals.
+pub async fn on_inform(pool: &PgPool, payload: &InformPayload, device: &UpsertedDevice) {
+    for (event, data) in inform_events(payload, device) {
+        emit(pool, device.domain_id, event, &payload.device_id, data).await;
+    }
+}
+
+/// The lifecycle events an Inform reveals, with their data.
+fn inform_events(payload: &InformPayload, device: &UpsertedDevice) -> Vec<(WebhookEvent, JsonValue)> {
+    let mut events = Vec::new();
+    if device.created {
+        let data = serde_json::json!({
+            "oui":              payload.oui,
+            "product_class":    payload.product_class,
+            "serial_number":    payload.serial_number,
+            "software_version": payload.software_version(),
+            "hardware_version": payload.hardware_version(),
+        });
+        events.push((WebhookEvent::FirstContact, data));
+    }
+    if payload.has_event("1 BOOT") {
+        let data = serde_json::json!({ "events": payload.events, "software_version": payload.software_version() });
+        events.push((WebhookEvent::Boot, data));
+    }
+    if let (Some(from), Some(to)) = (&device.previous_software_version, payload.software_version()) {
+        if from != to {
+            events.push((WebhookEvent::FirmwareChange, serde_json::json!({ "from": from, "to": to })));
+        }
+    }
+    events
+}
+
+/// Queue a test delivery to one subscription, whatever its event types. It
+/// is sent once the subscription is enabled.
+pub async fn ping(pool: &PgPool, subscription: &Subscription) -> Result<Delivery, sqlx::Error> {
+    let event_id = Uuid::new_v4();
+    let body = payload(event_id, PING, subscription.domain_id, None, serde_json::json!({ "subscription": subscription.name }));
+    sqlx::query_as::<_, Delivery>(&format!(
+        r#"
+        INSERT INTO webhook_deliveries (subscription_id, event_id, event_type, payload)
+        VALUES ($1, $2, $3, $4)
+        RETURNING {DELIVERY_COLUMNS}
+        "#
+    ))
+    .bind(subscription.id)
+    .bind(event_id)
+    .bind(PING)
+    .bind(&body)
+    .fetch_one(pool)
+    .await
+}
+
+// ── Dispatching ───────────────────────────────────────────────────────────────
+
+/// A claimed delivery, with where it goes.
+#[derive(sqlx::FromRow)]
+struct Claimed {
+    id:           Uuid,
+    event_id:     Uuid,
+    event_type:   String,
+    payload:      JsonValue,
+    attempts:     i32,
+    url:          String,
+    secret:       String,
+    max_attempts: i32,
+}
+
+/// What an attempt came to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Delivered(u16),
+    Failed { status: Option<u16>, error: String },
+}
+
+/// Send the deliveries that are due, every `tick`. Replicas share the work:
+/// each claims its own rows.
+pub async fn run_dispatcher(pool: PgPool, settings: Settings, tick: Duration) {
+    let client = match reqwest::Client::builder()
+        .timeout(settings.timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent(concat!("acs-controller/", env!("CARGO_PKG_VERSION")))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            error!(?e, "Webhook dispatcher: failed to build HTTP client");
+            return;
+        }
+    };
+    // An attempt in flight is not claimed again before it can have timed out.
+    let lease = settings.timeout.as_secs_f64() * 2.0 + 30.0;
+
+    let mut interval = tokio::time::interval(tick);
+    loop {
+        interval.tick().await;
+        loop {
+            match dispatch_batch(&pool, &client, lease).await {
+                Ok(n) if n < BATCH as usize => break,
+                Ok(_) => {}
+                Err(e) => {
+                    error!(?e, "Webhook dispatcher: db error");
+                    break;
+                }
+            }
+        }
+        if let Err(e) = prune(&pool, settings.history_days).await {
+            error!(?e, "Webhook dispatcher: failed to prune history");
+        }
+    }
+}
+
+/// Claim, send and record one batch. Returns the number claimed.
+async fn dispatch_batch(pool: &PgPool, client: &reqwest::Client, lease: f64) -> Result<usize, sqlx::Error> {
+    let claimed = sqlx::query_as::<_, Claimed>(
+        r#"
+        UPDATE webhook_deliveries d
+        SET attempts        = d.attempts + 1,
+            last_attempt_at = now(),
+            next_attempt_at = now() + make_interval(secs => $1)
+        FROM webhook_subscriptions s
+        WHERE d.id IN (
+                SELECT d2.id FROM webhook_deliveries d2
+                JOIN webhook_subscriptions s2 ON s2.id = d2.subscription_id
+                WHERE d2.status = 'pending' AND d2.next_attempt_at <= now() AND s2.enabled
+                ORDER BY d2.next_attempt_at
+                LIMIT $2
+                FOR UPDATE OF d2 SKIP LOCKED
+              )
+          AND s.id = d.subscription_id
+        RETURNING d.id, d.event_id, d.event_type, d.payload, d.attempts, s.url, s.secret, s.max_attempts
+        "#,
+    )
+    .bind(lease)
+    .bind(BATCH)
+    .fetch_all(pool)
+    .await?;
+    let count = claimed.len();
+
+    let mut sends = JoinSet::new();
+    for delivery in claimed {
+        let client = client.clone();
+        sends.spawn(async move {
+            let body = delivery.payload.to_string();
+            let outcome = send(&client, &delivery.url, &delivery.secret, &delivery.event_type, delivery.event_id, &body).await;
+            (delivery, outcome)
+        });
+    }
+    while let Some(joined) = sends.join_next().await {
+        let Ok((delivery, outcome)) = joined else { continue };
+        record(pool, &delivery, &outcome).await?;
+    }
+    Ok(count)
+}
+
+/// POST `body` to `url`, signed with `secret`.
+pub async fn send(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &str,
+    event_type: &str,
+    event_id: Uuid,
+    body: &str,
+) -> Outcome {
+    let timestamp = Utc::now().timestamp();
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-ACS-Event", event_type)
+        .header("X-ACS-Delivery", event_id.to_string())
+        .header("X-ACS-Timestamp", timestamp.to_string())
+        .header("X-ACS-Signature", signature(secret, timestamp, body))
+        .body(body.to_string())
+        .send()
+        .await;
+
+    match response {
+        Ok(r) if r.status().is_success() => Outcome::Delivered(r.status().as_u16()),
+        Ok(r) => {
+            let status = r.status();
+            let text = r.text().await.unwrap_or_default();
+            let mut end = text.len().min(ERROR_BODY_LIMIT);
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            Outcome::Failed { status: Some(status.as_u16()), error: format!("HTTP {status}: {}", text[..end].trim()) }
+        }
+        Err(e) => Outcome::Failed { status: None, error: e.to_string() },
+    }
+}
+
+async fn record(pool: &PgPool, delivery: &Claimed, outcome: &Outcome) -> Result<(), sqlx::Error> {
+    match outcome {
+        Outcome::Delivered(status) => {
+            sqlx::query(
+                r#"
+                UPDATE webhook_deliveries
+                SET status = 'delivered', delivered_at = now(), response_status = $2, last_error = NULL
+                WHERE id = $1
+                "#,
+            )
+            .bind(delivery.id)
+            .bind(i32::from(*status))
+            .execute(pool)
+            .await?;
+        }
+        Outcome::Failed { status, error } => {
+            let dead = delivery.attempts >= delivery.max_attempts;
+            sqlx::query(
+                r#"
+                UPDATE webhook_deliveries
+                SET status          = CASE WHEN $2 THEN 'dead' ELSE 'pending' END,
+                    next_attempt_at = now() + make_interval(secs => $3),
+                    response_status = $4,
+                    last_error      = $5
+                WHERE id = $1
+                "#,
+            )
+            .bind(delivery.id)
+            .bind(dead)
+            .bind(retry_delay(delivery.attempts).as_secs_f64())
+            .bind(status.map(i32::from))
+            .bind(error)
+            .execute(pool)
+            .await?;
+            if dead {
+                warn!(delivery_id = %delivery.id, url = %delivery.url, attempts = delivery.attempts, error, "Webhook delivery dead-lettered");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Delete history older than `days`.
+async fn prune(pool: &PgPool, days: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        DELETE FROM webhook_deliveries
+        WHERE id IN (
+            SELECT id FROM webhook_deliveries
+            WHERE status IN ('delivered', 'dead') AND created_at < now() - make_interval(days => $1)
+            LIMIT 1000
+        )
+        "#,
+    )
+    .bind(days as i32)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Delay after failed attempt number `attempt` (from 1): one minute,
+/// doubling up to six hours.
+pub fn retry_delay(attempt: i32) -> Duration {
+    let doublings = attempt.saturating_sub(1).clamp(0, 16) as u32;
+    Duration::from_secs((RETRY_BASE_SECS << doublings).min(RETRY_MAX_SECS))
+}
+
+/// `X-ACS-Signature` of `body` sent at `timestamp`.
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC key of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    let hex: String = mac.finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect();
+    format!("sha256={hex}")
+}
+
+/// A new random secret: 64 hex characters.
+pub fn generate_secret() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{http::HeaderMap, http::StatusCode, routing::post, Router};
+
+    use super::*;
+
+    #[test]
+    fn retries_back_off_exponentially() {
+        let delays: Vec<u64> = [1, 2, 3, 9, 10, 50].into_iter().map(|a| retry_delay(a).as_secs()).collect();
+        assert_eq!(delays, [60, 120, 240, 15360, RETRY_MAX_SECS, RETRY_MAX_SECS]);
+    }
+
+    #[test]
+    fn inform_events_match_event_codes() {
+        // As acs-cwmp sends them: "{code} {command_key}".
+        let payload: InformPayload = serde_json::from_value(serde_json::json!({
+            "session_id": "s1", "device_id": "AABB00-1", "oui": "AABB00", "serial_number": "1",
+            "manufacturer": "Example", "product_class": "Gateway",
+            "events": ["1 BOOT ", "4 VALUE CHANGE "],
+            "parameter_list": { "Device.DeviceInfo.SoftwareVersion": "2.0" },
+        }))
+        .unwrap();
+        let device = UpsertedDevice {
+            id:                        Uuid::new_v4(),
+            domain_id:                 Uuid::new_v4(),
+            created:                   false,
+            previous_software_version: Some("1.0".to_string()),
+        };
+        let events: Vec<WebhookEvent> = inform_events(&payload, &device).into_iter().map(|(e, _)| e).collect();
+        assert_eq!(events, [WebhookEvent::Boot, WebhookEvent::FirmwareChange]);
+    }
+
+    /// A local stand-in for a subscriber: records what it receives and
+    /// answers with `status`.
+    async fn receiver(status: StatusCode) -> (String, Arc<Mutex<Vec<(HeaderMap, String)>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: String| {
+                let log = log.clone();
+                async move {
+                    log.lock().unwrap().push((headers, body));
+                    (status, "nope")
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, received)
+    }
+
+    #[tokio::test]
+    async fn sends_signed_payloads() {
+        let client = reqwest::Client::new();
+        let event_id = Uuid::new_v4();
+        let body = payload(event_id, "boot", Uuid::nil(), Some("AABB00-1"), serde_json::json!({}));
+        let body = body.to_string();
+
+        let (url, received) = receiver(StatusCode::NO_CONTENT).await;
+        assert_eq!(send(&client, &url, "s3cret", "boot", event_id, &body).await, Outcome::Delivered(204));
+        let (headers, got) = received.lock().unwrap().pop().unwrap();
+        assert_eq!(got, body);
+        assert_eq!(headers["x-acs-event"], "boot");
+        assert_eq!(headers["x-acs-delivery"], event_id.to_string().as_str());
+        let timestamp: i64 = headers["x-acs-timestamp"].to_str().unwrap().parse().unwrap();
+        assert_eq!(headers["x-acs-signature"], signature("s3cret", timestamp, &body).as_str());
+        assert_ne!(signature("other", timestamp, &body), signature("s3cret", timestamp, &body));
+        assert_eq!(
+            signature("s3cret", 1_700_000_000, r#"{"a":1}"#),
+            "sha256=1698a50bc74d1ff1db85c4e0a5297c2ad9fdba245d5737cdb789e4cc6e098940"
+        );
+
+        let (url, _) = receiver(StatusCode::SERVICE_UNAVAILABLE).await;
+        let Outcome::Failed { status, error } = send(&client, &url, "s3cret", "boot", event_id, &body).await else {
+            panic!("expected failure");
+        };
+        assert_eq!(status, Some(503));
+        assert!(error.ends_with("nope"), "{error}");
+    }
+}
@@ -72,15 +72,13 @@ fn action_to_body_element(action: &Action) -> Result<BodyElement, TranslateError
         ))),
 
         // ── SetParameterValues ────────────────────────────────────────────────
-        Action::SetParameterValues { parameters } => {
+        Action::SetParameterValues { parameters, types } => {
             // Build owned ParameterValue objects first, then borrow them.
             let owned: Vec<ParameterValue> = parameters
                 .iter()
                 .map(|(k, v)| {
-                    // Type defaults to "xsd:string" — the controller can
-                    // override via the key convention "path|type" if needed
-                    // in a future iteration.
-                    ParameterValue::new(k, "xsd:string", v)
+                    let ty = types.get(k).map_or("xsd:string", String::as_str);
+                    ParameterValue::new(k, ty, v)
                 })
                 .collect();
             let refs: Vec<&ParameterValue> = owned.iter().collect();
@@ -249,9 +247,30 @@ mod tests {
             "Device.ManagementServer.PeriodicInformInterval".to_string(),
             "3600".to_string(),
         );
-        let xml = command_to_xml(&cmd(Action::SetParameterValues { parameters: params })).unwrap();
+        let xml = command_to_xml(&cmd(Action::SetParameterValues { parameters: params, types: HashMap::new() })).unwrap();
         assert!(xml.contains("SetParameterValues"), "xml={xml}");
         assert!(xml.contains("PeriodicInformInterval"), "xml={xml}");
+        assert!(xml.contains("xsd:string"), "xml={xml}");
+    }
+
+    #[test]
+    fn set_parameter_values_uses_given_types() {
+        let mut params = HashMap::new();
+        params.insert(
+            "Device.ManagementServer.PeriodicInformInterval".to_string(),
+            "3600".to_string(),
+        );
+        let mut types = HashMap::new();
+        types.insert(
+            "Device.ManagementServer.PeriodicInformInterval".to_string(),
+            "xsd:unsignedInt".to_string(),
+        );
+        let xml = command_to_xml(&cmd(Action::SetParameterValues {
+            parameters: params,
+            types,
+        }))
+        .unwrap();
+        assert!(xml.contains("xsd:unsignedInt"), "xml={xml}");
     }
 
     #[test]
@@ -1,3 +1,4 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use cwmp::protocol::{BodyElement, DeviceId, HeaderElement, Inform};
@@ -284,12 +285,17 @@ fn empty_xml_reply() -> Box<dyn warp::Reply> {
 /// 5. Reply with `InformResponse` and set the `session` cookie so subsequent
 ///    POSTs are routed to this session.
 ///
+/// `source_ip` and `acs_path` are forwarded in the event so the controller's
+/// onboarding rules can place a never-seen device in the right domain.
+///
 /// Note: the CPE will immediately follow up with an empty POST — that is handled
 /// by [`handle_empty_post`] which calls [`poll_next_command`].
 async fn handle_inform_post(
     state: Arc<AppState>,
     inform: &cwmp::protocol::Inform,
     header_element: Option<&HeaderElement>,
+    acs_path: &str,
+    remote: Option<SocketAddr>,
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
     let session_id = Uuid::new_v4().to_string();
     let device_id = inform.device_id.clone();
@@ -327,6 +333,8 @@ async fn handle_inform_post(
         "manufacturer": &device_id.manufacturer.0,
         "product_class": &device_id.product_class.0,
         "protocol": "cwmp",
+        "source_ip": remote.map(|a| a.ip().to_string()),
+        "acs_path": acs_path,
         "events": inform.event.iter()
             .map(|e| format!("{} {}", e.event_code.0, e.command_key.0))
             .collect::<Vec<_>>(),
@@ -376,6 +384,8 @@ async fn handle_inform_post(
 
 
 pub(crate) async fn handle_cwmp_request(
+    path: warp::path::FullPath,
+    remote: Option<SocketAddr>,
     cookie: Option<String>,
     body: bytes::Bytes,
     state: std::sync::Arc<AppState>,
@@ -453,7 +463,14 @@ pub(crate) async fn handle_cwmp_request(
                         .header
                         .iter()
                         .find(|h| matches!(h, HeaderElement::ID(_)));
-                    return handle_inform_post(state.clone(), &inform, header_element).await;
+                    return handle_inform_post(
+                        state.clone(),
+                        &inform,
+                        header_element,
+                        path.as_str(),
+                        remote,
+                    )
+                    .await;
                 } else {
                     error!("Non-Inform body received without a session cookie — rejecting");
                     return Ok(Box::new(warp::reply::with_status(
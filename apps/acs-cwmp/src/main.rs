@@ -67,9 +67,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .and(warp::get())
         .map(|| warp::reply::json(&"OK"));
 
-    // The main CWMP endpoint
-    let cwmp_route = warp::path!("cwmp")
+    // The main CWMP endpoint. Sub-paths (e.g. `/cwmp/acme`) are accepted so
+    // a CPE's configured ACS URL can steer first-contact domain assignment.
+    let cwmp_route = warp::path("cwmp")
         .and(warp::post())
+        .and(warp::path::full())
+        .and(warp::addr::remote())
         .and(warp::header::optional::<String>("cookie"))
         .and(warp::body::bytes())
         .and(state_filter.clone())
@@ -19,7 +19,7 @@ export default function DeviceSearch({ onSelectDevice }: DeviceSearchProps) {
         return res.json();
       })
       .then(data => {
-        setDevices(data);
+        setDevices(data.items);
         setLoading(false);
       })
       .catch(err => {
@@ -5,12 +5,22 @@ interface ObjectBrowserProps {
   uid: string;
 }
 
+// Definition of a path in the device's data model, from the catalog.
+interface Definition {
+  data_type?: string;
+  access: string;
+  list: boolean;
+  enumerations: string[];
+  description?: string;
+}
+
 interface ParamNode {
   name: string;
   fullPath: string;
   isObject: boolean;
   writable: boolean;
   value?: string;
+  definition?: Definition;
   children?: Record<string, ParamNode>;
   expanded?: boolean;
 }
@@ -25,6 +35,26 @@ export default function ObjectBrowser({ uid }: ObjectBrowserProps) {
   const [editValue, setEditValue] = useState<string>('');
   const [saving, setSaving] = useState(false);
 
+  // Catalog definitions of what is directly below pathPrefix, by name.
+  // Devices whose data model was never imported simply get none.
+  const fetchDefinitions = async (pathPrefix: string): Promise<Record<string, Definition>> => {
+    if (pathPrefix === '') return {};
+    try {
+      const response = await fetch(
+        `http://localhost:8080/api/v1/device/${uid}/datamodel?path=${encodeURIComponent(pathPrefix)}`
+      );
+      if (!response.ok) return {};
+      const data = await response.json();
+      const byName: Record<string, Definition> = {};
+      for (const child of data.children ?? []) {
+        byName[child.name] = child;
+      }
+      return byName;
+    } catch {
+      return {};
+    }
+  };
+
   // Fetch a level of parameters
   const fetchLevel = async (pathPrefix: string) => {
     setLoading(true);
@@ -49,6 +79,7 @@ export default function ObjectBrowser({ uid }: ObjectBrowserProps) {
       const data = await response.json();
       
       if (data.result && data.result.Success) {
+        const definitions = await fetchDefinitions(pathPrefix);
         // Build new nodes
         const newNodes: Record<string, ParamNode> = {};
         for (const [path, writableStr] of Object.entries(data.result.Success)) {
@@ -65,6 +96,7 @@ export default function ObjectBrowser({ uid }: ObjectBrowserProps) {
             fullPath: path,
             isObject,
             writable: writableStr === 'true',
+            definition: definitions[name],
             children: isObject ? {} : undefined,
             expanded: false
           };
@@ -143,7 +175,11 @@ export default function ObjectBrowser({ uid }: ObjectBrowserProps) {
         })
       });
 
-      if (!response.ok) throw new Error('Set value failed');
+      if (!response.ok) {
+        // 422 lists what the data model refuses.
+        const errText = await response.text();
+        throw new Error(errText || 'Set value failed');
+      }
       const data = await response.json();
       
       if (data.result && data.result.Fault) {
@@ -197,6 +233,15 @@ export default function ObjectBrowser({ uid }: ObjectBrowserProps) {
     }
   };
 
+  // Values a parameter can take, when the catalog restricts them.
+  const choices = (node: ParamNode): string[] | null => {
+    const def = node.definition;
+    if (!def || def.list) return null;
+    if (def.enumerations.length > 0) return def.enumerations;
+    if (def.data_type === 'boolean') return ['true', 'false'];
+    return null;
+  };
+
   useEffect(() => {
     // Initial fetch of root objects (e.g. Device., InternetGatewayDevice.)
     fetchLevel('');
@@ -216,12 +261,17 @@ export default function ObjectBrowser({ uid }: ObjectBrowserProps) {
             <>
               <div style={{ width: '16px' }} /> {/* indent for alignment */}
               <FileText size={16} color="var(--text-secondary)" />
-              <span className="tree-label">{node.name}</span>
+              <span className="tree-label" title={node.definition?.description}>{node.name}</span>
+              {node.definition?.data_type && (
+                <span style={{ marginLeft: '6px', fontSize: '0.75rem', color: 'var(--text-secondary)' }}>
+                  {node.definition.list ? `list of ${node.definition.data_type}` : node.definition.data_type}
+                </span>
+              )}
               
               {node.value !== undefined && editingPath !== node.fullPath && (
                 <span className="tree-value">
                   {node.value || '""'}
-                  {node.writable && (
+                  {node.writable && node.definition?.access !== 'readOnly' && (
                     <button 
                       className="btn-icon" 
                       style={{ padding: '0 4px', marginLeft: '8px' }}
@@ -235,13 +285,25 @@ export default function ObjectBrowser({ uid }: ObjectBrowserProps) {
 
               {editingPath === node.fullPath && (
                 <div style={{ marginLeft: 'auto', display: 'flex', alignItems: 'center', gap: '4px' }} onClick={e => e.stopPropagation()}>
-                  <input 
-                    type="text" 
-                    value={editValue} 
-                    onChange={e => setEditValue(e.target.value)}
-                    style={{ padding: '4px 8px', fontSize: '0.875rem', width: '200px' }}
-                    autoFocus
-                  />
+                  {choices(node) ? (
+                    <select
+                      value={editValue}
+                      onChange={e => setEditValue(e.target.value)}
+                      style={{ padding: '4px 8px', fontSize: '0.875rem', width: '200px' }}
+                      autoFocus
+                    >
+                      {!choices(node)!.includes(editValue) && <option value={editValue}>{editValue || '""'}</option>}
+                      {choices(node)!.map(c => <option key={c} value={c}>{c}</option>)}
+                    </select>
+                  ) : (
+                    <input 
+                      type="text" 
+                      value={editValue} 
+                      onChange={e => setEditValue(e.target.value)}
+                      style={{ padding: '4px 8px', fontSize: '0.875rem', width: '200px' }}
+                      autoFocus
+                    />
+                  )}
                   <button className="btn-icon" onClick={() => saveValue(node, editValue)} disabled={saving}>
                     {saving ? <RefreshCw size={14} className="spin" /> : <Check size={14} color="var(--success-color)" />}
                   </button>
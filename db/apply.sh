@@ -28,6 +28,18 @@ FILES=(
     "device_desired_config.sql"
     "device_profile_assignments.sql"
     "device_events.sql"
+    "domain_assignment_rules.sql"
+    "tasks.sql"
+    "campaigns.sql"
+    "device_groups.sql"
+    "device_sessions.sql"
+    "provisioning_scripts.sql"
+    "provisioning_runs.sql"
+    "firmware_images.sql"
+    "config_backups.sql"
+    "data_models.sql"
+    "parameter_refreshes.sql"
+    "webhooks.sql"
 )
 
 for FILE in "${FILES[@]}"; do
@@ -0,0 +1,96 @@
+-- Firmware upgrade campaigns.
+--
+-- A campaign rolls a Download action out to every device in a domain that
+-- matches its target filter. When started, the matching devices are
+-- snapshotted into campaign_devices and split into waves of batch_size.
+-- The controller's campaign scheduler then works through one wave at a
+-- time, keeping at most max_concurrent devices in flight and only starting
+-- new devices inside the maintenance window.
+--
+-- Per-device progress:
+--
+--   pending ──► queued ──► downloading ──► transferred ──► succeeded
+--                 │             │               │
+--                 └─────────────┴───────────────┴──► failed
+--
+--   queued       Download task stored in `tasks`, waiting for a session
+--   downloading  device accepted the Download RPC
+--   transferred  Inform carried "7 TRANSFER COMPLETE"
+--   succeeded    device booted and reports target_version
+--   failed       task faulted/expired, timeout, or wrong version after boot
+--
+-- All state lives in these tables, so a campaign survives controller
+-- restarts: the scheduler simply resumes from the stored statuses.
+
+DROP TABLE IF EXISTS campaign_devices;
+DROP TABLE IF EXISTS campaigns;
+
+CREATE TABLE campaigns (
+    id                  UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    domain_id           UUID        NOT NULL REFERENCES domains(id) ON DELETE CASCADE,
+    name                TEXT        NOT NULL,
+    status              TEXT        NOT NULL DEFAULT 'draft'
+                                    CHECK (status IN ('draft', 'running', 'paused', 'completed', 'cancelled')),
+    -- Target selection, e.g. {"product_class": "HGW-2", "tags": ["pilot"]}.
+    target_filter       JSONB       NOT NULL DEFAULT '{}',
+    -- nats_common::Action — always a Download. NULL: each device gets the
+    -- firmware_images image of target_version compatible with it.
+    action              JSONB,
+    target_version      TEXT        NOT NULL,
+    batch_size          INTEGER     NOT NULL DEFAULT 100 CHECK (batch_size > 0),
+    max_concurrent      INTEGER     NOT NULL DEFAULT 10  CHECK (max_concurrent > 0),
+    window_start        TIME,
+    window_end          TIME,
+    failure_threshold   REAL        NOT NULL DEFAULT 0.1 CHECK (failure_threshold BETWEEN 0 AND 1),
+    device_timeout_secs INTEGER     NOT NULL DEFAULT 3600 CHECK (device_timeout_secs > 0),
+    current_wave        INTEGER     NOT NULL DEFAULT 0,
+    failures_accepted   INTEGER     NOT NULL DEFAULT 0,
+    pause_reason        TEXT,
+    created_by          UUID        REFERENCES users(id) ON DELETE SET NULL,
+    created_at          TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at          TIMESTAMPTZ NOT NULL DEFAULT now(),
+    started_at          TIMESTAMPTZ,
+    completed_at        TIMESTAMPTZ,
+    UNIQUE (domain_id, name),
+    CHECK ((window_start IS NULL) = (window_end IS NULL))
+);
+
+CREATE INDEX idx_campaigns_running ON campaigns(status) WHERE status = 'running';
+
+COMMENT ON TABLE  campaigns                     IS 'Staged firmware rollouts to the devices of one domain.';
+COMMENT ON COLUMN campaigns.target_filter       IS 'AND-ed criteria: oui, product_class, hardware_version, software_version (exact), tags (device must carry all) and group (member of this device group).';
+COMMENT ON COLUMN campaigns.action              IS 'nats_common::Action::Download sent to every target device. NULL = picked per device from the firmware catalog.';
+COMMENT ON COLUMN campaigns.target_version      IS 'SoftwareVersion a device must report after rebooting. Devices already on it are not targeted.';
+COMMENT ON COLUMN campaigns.batch_size          IS 'Devices per wave.';
+COMMENT ON COLUMN campaigns.max_concurrent      IS 'Devices allowed between queued and transferred at once.';
+COMMENT ON COLUMN campaigns.window_start        IS 'Maintenance window start (UTC). New devices are only started inside the window; may wrap midnight. NULL = always.';
+COMMENT ON COLUMN campaigns.failure_threshold   IS 'Fraction of a wave that may fail before the campaign pauses itself.';
+COMMENT ON COLUMN campaigns.device_timeout_secs IS 'A device not finished this long after being queued is marked failed.';
+COMMENT ON COLUMN campaigns.current_wave        IS 'Wave being rolled out; waves are numbered from 0.';
+COMMENT ON COLUMN campaigns.failures_accepted   IS 'Failures in the current wave the operator accepted by resuming; only failures beyond these count towards the threshold.';
+COMMENT ON COLUMN campaigns.pause_reason        IS 'Why the campaign is paused — set by the scheduler or the operator.';
+
+CREATE TABLE campaign_devices (
+    campaign_id  UUID        NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    device_id    UUID        NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    wave         INTEGER     NOT NULL,
+    state        TEXT        NOT NULL DEFAULT 'pending'
+                             CHECK (state IN ('pending', 'queued', 'downloading', 'transferred', 'succeeded', 'failed')),
+    task_id      UUID        REFERENCES tasks(id) ON DELETE SET NULL,
+    error        TEXT,
+    started_at   TIMESTAMPTZ,
+    completed_at TIMESTAMPTZ,
+    updated_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (campaign_id, device_id)
+);
+
+CREATE INDEX idx_campaign_devices_wave   ON campaign_devices(campaign_id, wave, state);
+-- Inform handling looks up a device's in-flight campaign.
+CREATE INDEX idx_campaign_devices_active ON campaign_devices(device_id)
+    WHERE state IN ('queued', 'downloading', 'transferred');
+
+COMMENT ON TABLE  campaign_devices         IS 'Target devices of a campaign and their upgrade progress.';
+COMMENT ON COLUMN campaign_devices.wave    IS 'Wave the device belongs to, assigned when the campaign starts.';
+COMMENT ON COLUMN campaign_devices.state   IS 'pending | queued | downloading | transferred | succeeded | failed.';
+COMMENT ON COLUMN campaign_devices.task_id IS 'Download task delivering the firmware to the device.';
+COMMENT ON COLUMN campaign_devices.error   IS 'Why the device failed: fault string, timeout, or version mismatch.';
@@ -0,0 +1,95 @@
+-- Device configuration backups.
+--
+-- A backup is one numbered version of a device's configuration, taken in
+-- one of two ways:
+--
+--   parameters  GetParameterNames over path_prefix, then GetParameterValues
+--               of every writable parameter, a chunk at a time; stored as
+--               {path: value}
+--   upload      Upload of the device's vendor configuration file to the
+--               controller; stored as the file itself
+--
+-- Lifecycle:
+--
+--   pending ──task(s) answered / file received──► complete
+--      │
+--      └──task faulted, expired or cancelled, or timed out──► failed
+--
+-- Backups are taken on demand through the API, or on the schedule of the
+-- device's domain in backup_policies. Retention keeps the newest `retain`
+-- complete backups of each device, drops those older than `retain_days`
+-- (never the newest), and drops failed backups once a later one completes.
+-- Restoring replays a parameters backup as one SetParameterValues, or has
+-- the device Download an upload backup back.
+
+DROP TABLE IF EXISTS config_backups;
+DROP TABLE IF EXISTS backup_policies;
+
+CREATE TABLE backup_policies (
+    domain_id      UUID        PRIMARY KEY REFERENCES domains(id) ON DELETE CASCADE,
+    method         TEXT        NOT NULL DEFAULT 'parameters' CHECK (method IN ('parameters', 'upload')),
+    interval_hours INTEGER     CHECK (interval_hours > 0),
+    retain         INTEGER     NOT NULL DEFAULT 10 CHECK (retain > 0),
+    retain_days    INTEGER     CHECK (retain_days > 0),
+    path_prefix    TEXT        NOT NULL DEFAULT '',
+    file_type      TEXT        NOT NULL DEFAULT '1 Vendor Configuration File',
+    updated_by     UUID        REFERENCES users(id) ON DELETE SET NULL,
+    updated_at     TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+COMMENT ON TABLE  backup_policies                IS 'How the devices of a domain are backed up and how long backups are kept. Domains without one keep 10 backups per device and are not backed up on a schedule.';
+COMMENT ON COLUMN backup_policies.method         IS 'parameters | upload: how scheduled backups, and manual ones that do not say, are taken.';
+COMMENT ON COLUMN backup_policies.interval_hours IS 'Hours between scheduled backups of each device. NULL = manual backups only.';
+COMMENT ON COLUMN backup_policies.retain         IS 'Complete backups kept per device.';
+COMMENT ON COLUMN backup_policies.retain_days    IS 'Complete backups older than this are dropped, except the newest. NULL = no age limit.';
+COMMENT ON COLUMN backup_policies.path_prefix    IS 'Subtree parameters backups cover, e.g. "Device.". Empty = the whole data model.';
+COMMENT ON COLUMN backup_policies.file_type      IS 'Upload FileType of upload backups.';
+
+CREATE TABLE config_backups (
+    id           UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_id    UUID        NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    version      INTEGER     NOT NULL,
+    method       TEXT        NOT NULL CHECK (method IN ('parameters', 'upload')),
+    trigger      TEXT        NOT NULL CHECK (trigger IN ('scheduled', 'manual')),
+    status       TEXT        NOT NULL DEFAULT 'pending'
+                             CHECK (status IN ('pending', 'complete', 'failed')),
+    -- Task currently collecting the backup.
+    task_id      UUID        REFERENCES tasks(id) ON DELETE SET NULL,
+    path_prefix  TEXT,
+    -- parameters backups: writable parameters whose values are still to
+    -- be read, a chunk per GetParameterValues.
+    remaining    TEXT[]      NOT NULL DEFAULT '{}',
+    parameters   JSONB,
+    file_type    TEXT,
+    -- Secret part of the URLs the device uploads the file to and, on
+    -- restore, downloads it from.
+    file_token   TEXT,
+    file         BYTEA,
+    file_size    BIGINT,
+    sha256       TEXT,
+    error        TEXT,
+    created_by   UUID        REFERENCES users(id) ON DELETE SET NULL,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    completed_at TIMESTAMPTZ,
+    UNIQUE (device_id, version),
+    CHECK (status <> 'complete' OR parameters IS NOT NULL OR file IS NOT NULL)
+);
+
+-- One backup in progress per device.
+CREATE UNIQUE INDEX idx_config_backups_pending ON config_backups(device_id) WHERE status = 'pending';
+CREATE INDEX idx_config_backups_task_id ON config_backups(task_id) WHERE task_id IS NOT NULL;
+
+COMMENT ON TABLE  config_backups              IS 'Versioned configuration backups of devices.';
+COMMENT ON COLUMN config_backups.version      IS 'Per-device backup number, starting at 1.';
+COMMENT ON COLUMN config_backups.method       IS 'parameters = writable parameter values; upload = vendor configuration file.';
+COMMENT ON COLUMN config_backups.trigger      IS 'scheduled = taken on the domain''s backup_policies schedule; manual = requested through the API.';
+COMMENT ON COLUMN config_backups.status       IS 'pending | complete | failed.';
+COMMENT ON COLUMN config_backups.task_id      IS 'Task collecting the backup: GetParameterNames then each GetParameterValues, or Upload.';
+COMMENT ON COLUMN config_backups.path_prefix  IS 'Subtree a parameters backup covers. Empty = the whole data model.';
+COMMENT ON COLUMN config_backups.remaining    IS 'parameters backups: writable parameters not read yet.';
+COMMENT ON COLUMN config_backups.parameters   IS 'parameters backups: {"Device.ManagementServer.PeriodicInformInterval": "3600", ...}.';
+COMMENT ON COLUMN config_backups.file_type    IS 'upload backups: Upload FileType requested.';
+COMMENT ON COLUMN config_backups.file_token   IS 'upload backups: random token in the file URLs, which devices use without credentials.';
+COMMENT ON COLUMN config_backups.file         IS 'upload backups: the file as uploaded by the device.';
+COMMENT ON COLUMN config_backups.sha256       IS 'SHA-256 of the file, lowercase hex.';
+COMMENT ON COLUMN config_backups.error        IS 'Why a failed backup failed.';
@@ -0,0 +1,72 @@
+-- Data model catalog.
+--
+-- Broadband Forum data model definitions imported from their XML: the
+-- cwmp-datamodel documents (e.g. tr-181-2-16-0-cwmp-full.xml, one model
+-- "Device:2.16") and device type documents describing what one kind of
+-- device supports of such a model. Every object and parameter becomes an
+-- entry, with instance numbers written as {i}:
+--
+--   Device.IP.Interface.{i}.          object (multi-instance)
+--   Device.IP.Interface.{i}.Enable    parameter, boolean, readWrite
+--
+-- The controller checks SetParameterValues, AddObject and
+-- GetParameterValues against the model that applies to the device before
+-- sending them, and types SetParameterValues parameters from it. A device
+-- type applies to devices matching its oui and product_class (either may be
+-- NULL = any); otherwise the newest data model with the path's root does.
+
+DROP TABLE IF EXISTS data_model_entries;
+DROP TABLE IF EXISTS data_models;
+
+CREATE TABLE data_models (
+    id            UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    kind          TEXT        NOT NULL CHECK (kind IN ('datamodel', 'device_type')),
+    name          TEXT        NOT NULL,
+    root          TEXT        NOT NULL,
+    base          TEXT,
+    spec          TEXT,
+    file_name     TEXT,
+    oui           TEXT,
+    product_class TEXT,
+    imported_by   UUID        REFERENCES users(id) ON DELETE SET NULL,
+    imported_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (kind, name, root),
+    CHECK (kind = 'device_type' OR (oui IS NULL AND product_class IS NULL))
+);
+
+CREATE INDEX idx_data_models_root ON data_models(root, imported_at DESC);
+
+COMMENT ON TABLE  data_models               IS 'Imported data models and device types. Re-importing one replaces it.';
+COMMENT ON COLUMN data_models.kind          IS 'datamodel = a cwmp-datamodel <model>; device_type = a device type document''s <model ref>.';
+COMMENT ON COLUMN data_models.name          IS 'Model name, e.g. "Device:2.16", or the device type URN.';
+COMMENT ON COLUMN data_models.root          IS 'Root object of every entry, e.g. "Device." or "InternetGatewayDevice.".';
+COMMENT ON COLUMN data_models.base          IS 'Model this one extends (datamodel) or restricts (device_type).';
+COMMENT ON COLUMN data_models.spec          IS 'spec URN of the document.';
+COMMENT ON COLUMN data_models.oui           IS 'device_type: devices it applies to. NULL = any OUI.';
+COMMENT ON COLUMN data_models.product_class IS 'device_type: devices it applies to. NULL = any product class.';
+
+CREATE TABLE data_model_entries (
+    model_id       UUID    NOT NULL REFERENCES data_models(id) ON DELETE CASCADE,
+    path           TEXT    NOT NULL,
+    is_object      BOOLEAN NOT NULL,
+    data_type      TEXT,
+    access         TEXT    NOT NULL,
+    multi_instance BOOLEAN NOT NULL DEFAULT false,
+    list           BOOLEAN NOT NULL DEFAULT false,
+    enumerations   TEXT[]  NOT NULL DEFAULT '{}',
+    patterns       TEXT[]  NOT NULL DEFAULT '{}',
+    ranges         JSONB   NOT NULL DEFAULT '[]',
+    max_length     INTEGER,
+    description    TEXT,
+    PRIMARY KEY (model_id, path)
+);
+
+COMMENT ON TABLE  data_model_entries                IS 'Objects and parameters of data_models.';
+COMMENT ON COLUMN data_model_entries.path           IS 'Instance numbers as {i}; object paths end with ".".';
+COMMENT ON COLUMN data_model_entries.data_type      IS 'Parameters: string, boolean, unsignedInt, int, unsignedLong, long, dateTime, base64, hexBinary or decimal. NULL for objects, and parameters of unknown type.';
+COMMENT ON COLUMN data_model_entries.access         IS 'readOnly | readWrite. On a multi-instance object: whether instances can be added and deleted.';
+COMMENT ON COLUMN data_model_entries.list           IS 'The value is a comma-separated list of data_type values.';
+COMMENT ON COLUMN data_model_entries.enumerations   IS 'Allowed values. Empty = any.';
+COMMENT ON COLUMN data_model_entries.patterns       IS 'Regular expressions one of which the value must match. Empty = any.';
+COMMENT ON COLUMN data_model_entries.ranges         IS 'Integer bounds, [{"min": 1, "max": 65535}], either may be null. Empty = any.';
+COMMENT ON COLUMN data_model_entries.max_length     IS 'Maximum characters, of the whole value for lists.';
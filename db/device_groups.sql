@@ -0,0 +1,48 @@
+-- Named device groups.
+--
+-- A group belongs to a domain and is either:
+--
+--   static   members are added and removed explicitly through the API
+--   dynamic  members are the domain's devices matching `filter`, a device
+--            search expression (same language as GET /inventory/devices?q=)
+--
+-- Membership of both kinds is stored in device_group_members. For dynamic
+-- groups it is a materialised view of the filter: the controller
+-- re-evaluates the whole group on demand and a single device on each of its
+-- Informs, and publishes a NATS event for every device that joins or leaves.
+
+DROP TABLE IF EXISTS device_group_members;
+DROP TABLE IF EXISTS device_groups;
+
+CREATE TABLE device_groups (
+    id           UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    domain_id    UUID        NOT NULL REFERENCES domains(id) ON DELETE CASCADE,
+    name         TEXT        NOT NULL,
+    description  TEXT,
+    kind         TEXT        NOT NULL CHECK (kind IN ('static', 'dynamic')),
+    filter       TEXT,
+    created_by   UUID        REFERENCES users(id) ON DELETE SET NULL,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    evaluated_at TIMESTAMPTZ,
+    UNIQUE (domain_id, name),
+    CHECK ((kind = 'dynamic') = (filter IS NOT NULL))
+);
+
+COMMENT ON TABLE  device_groups              IS 'Named sets of devices within one domain, used as targets by scripts and campaigns.';
+COMMENT ON COLUMN device_groups.kind         IS 'static = explicit membership; dynamic = devices matching filter.';
+COMMENT ON COLUMN device_groups.filter       IS 'Device search expression of a dynamic group, e.g. tags has "pilot" AND hardware_version = "2.1". NULL for static groups.';
+COMMENT ON COLUMN device_groups.evaluated_at IS 'Last full re-evaluation of a dynamic group.';
+
+CREATE TABLE device_group_members (
+    group_id  UUID        NOT NULL REFERENCES device_groups(id) ON DELETE CASCADE,
+    device_id UUID        NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    added_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (group_id, device_id)
+);
+
+-- Inform handling looks up the groups of one device.
+CREATE INDEX idx_device_group_members_device ON device_group_members(device_id);
+
+COMMENT ON TABLE  device_group_members          IS 'Current members of each group. Maintained by the API (static) or the controller (dynamic).';
+COMMENT ON COLUMN device_group_members.added_at IS 'When the device joined the group.';
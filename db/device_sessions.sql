@@ -0,0 +1,32 @@
+-- Protocol sessions of devices.
+--
+-- A row is opened by the controller on the first Inform of a session and
+-- closed when the protocol pod reports session_ended. Commands published
+-- to the session and the device's responses are counted while it is open.
+-- Sessions whose session_ended was lost stay open (ended_at NULL).
+
+DROP TABLE IF EXISTS device_sessions;
+
+CREATE TABLE device_sessions (
+    session_id   TEXT        PRIMARY KEY,
+    device_id    UUID        NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    protocol     TEXT        NOT NULL,
+    started_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ended_at     TIMESTAMPTZ,
+    end_reason   TEXT,
+    informs      INTEGER     NOT NULL DEFAULT 0,
+    commands     INTEGER     NOT NULL DEFAULT 0,
+    responses    INTEGER     NOT NULL DEFAULT 0,
+    faults       INTEGER     NOT NULL DEFAULT 0
+);
+
+-- Responses are counted against the device's latest open session.
+CREATE INDEX idx_device_sessions_device ON device_sessions(device_id, started_at DESC);
+
+COMMENT ON TABLE  device_sessions            IS 'One row per protocol session, with its duration and command counts.';
+COMMENT ON COLUMN device_sessions.session_id IS 'Session id assigned by the protocol pod; also the NATS routing key acs.sessions.{session_id}.command.';
+COMMENT ON COLUMN device_sessions.ended_at   IS 'When session_ended was handled. NULL while open, or if the event was lost.';
+COMMENT ON COLUMN device_sessions.end_reason IS 'Reason reported by the protocol pod, e.g. "idle_timeout".';
+COMMENT ON COLUMN device_sessions.commands   IS 'Commands published to the session: provisioning actions, tasks and interactive commands.';
+COMMENT ON COLUMN device_sessions.responses  IS 'Device responses received while the session was open, faults included.';
+COMMENT ON COLUMN device_sessions.faults     IS 'Responses that were faults.';
@@ -25,12 +25,24 @@ CREATE TABLE devices (
     hardware_version TEXT,
     tags             TEXT[]      NOT NULL DEFAULT '{}',
     metadata         JSONB       NOT NULL DEFAULT '{}',
+    -- Health: see the controller's health monitor.
+    periodic_inform_interval INTEGER CHECK (periodic_inform_interval > 0),
+    periodic_inform_enabled  BOOLEAN,
+    health            TEXT        NOT NULL DEFAULT 'online'
+                                  CHECK (health IN ('online', 'in_session', 'late', 'offline', 'faulting')),
+    health_changed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
     UNIQUE (domain_id, device_uid)
 );
 
 -- Most queries filter by domain first.
 CREATE INDEX idx_devices_domain_id ON devices(domain_id);
+-- Fleet health views, and the monitor skipping offline devices.
+CREATE INDEX idx_devices_health ON devices(domain_id, health);
 
 COMMENT ON TABLE  devices            IS 'CPE devices observed by the ACS, scoped to a domain.';
 COMMENT ON COLUMN devices.domain_id  IS 'FK to domains. ON DELETE RESTRICT prevents silent domain deletion with live devices.';
-COMMENT ON COLUMN devices.device_uid IS 'Composite key "{oui}-{serial_number}". Unique within a domain.';
\ No newline at end of file
+COMMENT ON COLUMN devices.device_uid IS 'Composite key "{oui}-{serial_number}". Unique within a domain.';
+COMMENT ON COLUMN devices.periodic_inform_interval IS 'ManagementServer.PeriodicInformInterval in seconds, as last seen in an Inform or a Get/SetParameterValues. NULL until known.';
+COMMENT ON COLUMN devices.periodic_inform_enabled  IS 'ManagementServer.PeriodicInformEnable. Devices with it false are never judged late or offline.';
+COMMENT ON COLUMN devices.health                   IS 'online, in_session, late, offline or faulting (last session had faulted responses).';
+COMMENT ON COLUMN devices.health_changed_at        IS 'When health last changed.';
@@ -0,0 +1,40 @@
+-- Onboarding rules that choose the domain for a device on first contact.
+--
+-- When an Inform arrives from a device_uid the ACS has never seen, the
+-- controller evaluates enabled rules in ascending priority order and assigns
+-- the device to the domain of the first rule whose criteria all match.
+-- If no rule matches, the controller's DEFAULT_DOMAIN_ID is used.
+--
+-- Every criterion column is optional; NULL means "don't care". A rule with
+-- no criteria at all matches every new device.
+--
+-- Rules are only consulted for unknown devices. Once a device exists its
+-- domain_id is authoritative and can only be changed through the API.
+
+DROP TABLE IF EXISTS domain_assignment_rules;
+
+CREATE TABLE domain_assignment_rules (
+    id             UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    domain_id      UUID        NOT NULL REFERENCES domains(id) ON DELETE CASCADE,
+    name           TEXT        NOT NULL UNIQUE,
+    priority       INTEGER     NOT NULL DEFAULT 100,
+    enabled        BOOLEAN     NOT NULL DEFAULT true,
+    -- Match criteria (all optional, AND-ed together).
+    oui            TEXT,
+    product_class  TEXT,
+    serial_pattern TEXT,
+    source_cidr    CIDR,
+    acs_path       TEXT,
+    created_at     TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_domain_assignment_rules_priority ON domain_assignment_rules(priority) WHERE enabled;
+
+COMMENT ON TABLE  domain_assignment_rules                IS 'Rules that assign never-seen devices to a domain on their first Inform.';
+COMMENT ON COLUMN domain_assignment_rules.domain_id      IS 'Domain a matching device is assigned to. Rules are removed with their domain.';
+COMMENT ON COLUMN domain_assignment_rules.priority       IS 'Lower number = evaluated first. The first matching rule wins.';
+COMMENT ON COLUMN domain_assignment_rules.oui            IS 'Exact, case-insensitive match on the Inform DeviceId OUI.';
+COMMENT ON COLUMN domain_assignment_rules.product_class  IS 'Exact match on the Inform DeviceId ProductClass.';
+COMMENT ON COLUMN domain_assignment_rules.serial_pattern IS 'Regular expression matched against the serial number (unanchored; use ^...$ for a full match).';
+COMMENT ON COLUMN domain_assignment_rules.source_cidr    IS 'Network the CPE connection must originate from, as seen by the protocol pod.';
+COMMENT ON COLUMN domain_assignment_rules.acs_path       IS 'Exact HTTP path the CPE used to reach the ACS, e.g. "/cwmp/acme".';
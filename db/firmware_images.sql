@@ -0,0 +1,51 @@
+-- Firmware catalog.
+--
+-- Each row is one image of a firmware version, with the devices it may be
+-- installed on. An image is either registered with the URL devices fetch
+-- it from, or uploaded to the controller, which stores and serves it.
+--
+--   registered  url, file_size and sha256 as given
+--   uploaded    url NULL until the file arrives; the controller then sets
+--               url, file_size and sha256 and marks it stored
+--
+-- Upgrading a device to a version picks the newest image of that version
+-- compatible with it, preferring the device's domain over shared images.
+--
+-- domain_id = NULL  →  shared image, usable in every domain; only
+--                      super_admins manage shared images.
+
+DROP TABLE IF EXISTS firmware_images;
+
+CREATE TABLE firmware_images (
+    id                UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    domain_id         UUID        REFERENCES domains(id) ON DELETE CASCADE,
+    version           TEXT        NOT NULL,
+    file_type         TEXT        NOT NULL DEFAULT '1 Firmware Upgrade Image',
+    url               TEXT,
+    file_size         BIGINT      CHECK (file_size BETWEEN 0 AND 4294967295),
+    sha256            TEXT        CHECK (sha256 ~ '^[0-9a-f]{64}$'),
+    target_filename   TEXT,
+    stored            BOOLEAN     NOT NULL DEFAULT false,
+    ouis              TEXT[]      NOT NULL DEFAULT '{}',
+    product_classes   TEXT[]      NOT NULL DEFAULT '{}',
+    hardware_versions TEXT[]      NOT NULL DEFAULT '{}',
+    release_notes     TEXT,
+    created_by        UUID        REFERENCES users(id) ON DELETE SET NULL,
+    created_at        TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK (NOT stored OR url IS NOT NULL)
+);
+
+CREATE INDEX idx_firmware_images_version ON firmware_images(version);
+
+COMMENT ON TABLE  firmware_images                   IS 'Firmware catalog: images of firmware versions and the devices they are compatible with.';
+COMMENT ON COLUMN firmware_images.domain_id         IS 'Owning domain. NULL = shared image, usable in every domain.';
+COMMENT ON COLUMN firmware_images.version           IS 'SoftwareVersion devices report once running the image, e.g. "3.2.1".';
+COMMENT ON COLUMN firmware_images.file_type         IS 'CWMP Download FileType, e.g. "1 Firmware Upgrade Image".';
+COMMENT ON COLUMN firmware_images.url               IS 'URL devices download the image from. NULL while an upload is awaited.';
+COMMENT ON COLUMN firmware_images.file_size         IS 'Size in bytes, sent in the Download. NULL = unknown (sent as 0).';
+COMMENT ON COLUMN firmware_images.sha256            IS 'SHA-256 of the file, lowercase hex. Computed for uploads; an upload that does not match a registered checksum is refused.';
+COMMENT ON COLUMN firmware_images.target_filename   IS 'CWMP Download TargetFileName, if the device needs one.';
+COMMENT ON COLUMN firmware_images.stored            IS 'The file was uploaded and is served by the controller.';
+COMMENT ON COLUMN firmware_images.ouis              IS 'OUIs the image is for. Empty = any.';
+COMMENT ON COLUMN firmware_images.product_classes   IS 'Product classes the image is for. Empty = any.';
+COMMENT ON COLUMN firmware_images.hardware_versions IS 'Hardware versions the image is for. Empty = any.';
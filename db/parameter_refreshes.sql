@@ -0,0 +1,50 @@
+-- Parameter tree refreshes.
+--
+-- A refresh re-reads one subtree of a device's data model into
+-- device_parameters:
+--
+--   GetParameterNames(path_prefix) ──► every object and parameter stored with
+--       its writable flag; paths under the prefix the device no longer
+--       reports are deleted
+--   GetParameterValues, chunk_size parameters at a time ──► values stored
+--
+-- Each step is a task, so a refresh of an offline device waits for its next
+-- session. Lifecycle:
+--
+--   pending ──last chunk answered──► complete
+--      │
+--      └──task faulted, expired or cancelled, or timed out──► failed
+
+DROP TABLE IF EXISTS parameter_refreshes;
+
+CREATE TABLE parameter_refreshes (
+    id            UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_id     UUID        NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    path_prefix   TEXT        NOT NULL DEFAULT '',
+    chunk_size    INTEGER     NOT NULL CHECK (chunk_size > 0),
+    status        TEXT        NOT NULL DEFAULT 'pending'
+                              CHECK (status IN ('pending', 'complete', 'failed')),
+    -- Task currently running the refresh.
+    task_id       UUID        REFERENCES tasks(id) ON DELETE SET NULL,
+    -- Parameters whose values are still to be read, in order.
+    remaining     TEXT[]      NOT NULL DEFAULT '{}',
+    discovered    INTEGER,
+    fetched       INTEGER     NOT NULL DEFAULT 0,
+    error         TEXT,
+    created_by    UUID        REFERENCES users(id) ON DELETE SET NULL,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT now(),
+    completed_at  TIMESTAMPTZ
+);
+
+-- One refresh in progress per device.
+CREATE UNIQUE INDEX idx_parameter_refreshes_pending ON parameter_refreshes(device_id) WHERE status = 'pending';
+CREATE INDEX idx_parameter_refreshes_device ON parameter_refreshes(device_id, created_at DESC);
+CREATE INDEX idx_parameter_refreshes_task_id ON parameter_refreshes(task_id) WHERE task_id IS NOT NULL;
+
+COMMENT ON TABLE  parameter_refreshes             IS 'Walks of a device subtree with GetParameterNames and chunked GetParameterValues into device_parameters.';
+COMMENT ON COLUMN parameter_refreshes.path_prefix IS 'Subtree refreshed, e.g. "Device.WiFi.". Empty = the whole data model.';
+COMMENT ON COLUMN parameter_refreshes.chunk_size  IS 'Parameters per GetParameterValues.';
+COMMENT ON COLUMN parameter_refreshes.task_id     IS 'Task of the current step: GetParameterNames, then one GetParameterValues per chunk.';
+COMMENT ON COLUMN parameter_refreshes.discovered  IS 'Parameters GetParameterNames reported. NULL until it answers.';
+COMMENT ON COLUMN parameter_refreshes.fetched     IS 'Parameter values received so far.';
+COMMENT ON COLUMN parameter_refreshes.error       IS 'Why a failed refresh failed.';
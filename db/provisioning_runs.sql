@@ -0,0 +1,64 @@
+-- Audit log of provisioning script runs.
+--
+-- One provisioning_runs row per event the controller ran scripts for
+-- (inform, session_ended). It lists every script that ran, and
+-- provisioning_run_actions holds every action the scripts sent or returned,
+-- followed until its outcome is known:
+--
+--   via = 'send'    sent mid-script with send(); recorded with its result
+--   via = 'return'  returned by the script, then
+--                     sent      ──response──► succeeded | faulted
+--                       │
+--                       └──session ended, no response──► unanswered
+--                     queued    as a task for the next session (see tasks.status)
+--                     not_sent  could not be published, or the session had ended
+--
+-- Dry runs are not recorded. Runs are deleted with their device, so the
+-- decommission scripts run before a deletion leave no record.
+
+DROP TABLE IF EXISTS provisioning_run_actions;
+DROP TABLE IF EXISTS provisioning_runs;
+
+CREATE TABLE provisioning_runs (
+    id          UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_id   UUID        NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    event_type  TEXT        NOT NULL,
+    lifecycle   TEXT        NOT NULL CHECK (lifecycle IN ('add', 'update', 'delete')),
+    session_id  TEXT,
+    -- [{script, version, duration_ms, status, error, updates, output}]
+    scripts     JSONB       NOT NULL DEFAULT '[]',
+    started_at  TIMESTAMPTZ NOT NULL,
+    finished_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- The device API lists a device's latest runs.
+CREATE INDEX idx_provisioning_runs_device ON provisioning_runs(device_id, started_at DESC);
+
+COMMENT ON TABLE  provisioning_runs            IS 'One row per event provisioning scripts ran for.';
+COMMENT ON COLUMN provisioning_runs.session_id IS 'Session of the Inform or session end the scripts handled.';
+COMMENT ON COLUMN provisioning_runs.scripts    IS 'Scripts in run order: path below PROVISIONING_ROOT, stored version (null for a file), duration_ms, status ok | error, error message, output excerpt (Rhai print/debug, Python stderr).';
+
+CREATE TABLE provisioning_run_actions (
+    run_id     UUID        NOT NULL REFERENCES provisioning_runs(id) ON DELETE CASCADE,
+    seq        INTEGER     NOT NULL,
+    script     TEXT        NOT NULL,
+    via        TEXT        NOT NULL CHECK (via IN ('send', 'return')),
+    -- nats_common::Action
+    action     JSONB       NOT NULL,
+    status     TEXT        NOT NULL
+                           CHECK (status IN ('sent', 'succeeded', 'faulted', 'unanswered', 'queued', 'not_sent')),
+    command_id UUID,
+    task_id    UUID        REFERENCES tasks(id) ON DELETE SET NULL,
+    -- nats_common::ActionResult, or {"error": "..."} for a send() with no answer.
+    result     JSONB,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (run_id, seq)
+);
+
+-- Responses are matched to the action by command id.
+CREATE INDEX idx_provisioning_run_actions_command ON provisioning_run_actions(command_id) WHERE status = 'sent';
+
+COMMENT ON TABLE  provisioning_run_actions         IS 'Actions of a provisioning run in the order the device got them, with their outcome.';
+COMMENT ON COLUMN provisioning_run_actions.script  IS 'Script that sent or returned the action.';
+COMMENT ON COLUMN provisioning_run_actions.status  IS 'sent | succeeded | faulted | unanswered | queued | not_sent.';
+COMMENT ON COLUMN provisioning_run_actions.task_id IS 'Task the action was queued as (session_ended scripts); its outcome is the task''s.';
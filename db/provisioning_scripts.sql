@@ -0,0 +1,50 @@
+-- Provisioning scripts stored in the database, managed through the API.
+--
+-- A script has a fixed place in the provisioning tree: an event type, a
+-- domain and a path below that domain's directory, e.g.
+--
+--   inform / acme / HW1/2.0.0/update.rhai
+--
+-- Every upload adds an immutable version. The controller runs the script's
+-- active version, or its staged version for devices in the staging group,
+-- in place of a file at the same place under PROVISIONING_ROOT. A script
+-- with no version to run for a device leaves the file, if any, in effect.
+
+DROP TABLE IF EXISTS provisioning_script_versions;
+DROP TABLE IF EXISTS provisioning_scripts;
+
+CREATE TABLE provisioning_scripts (
+    id               UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    event_type       TEXT        NOT NULL CHECK (event_type IN ('inform', 'session_ended', 'decommission')),
+    domain_id        UUID        NOT NULL REFERENCES domains(id) ON DELETE CASCADE,
+    -- Rhai only: Python scripts run unsandboxed on the controller host.
+    path             TEXT        NOT NULL CHECK (path LIKE '%.rhai'),
+    active_version   INT,
+    previous_version INT,
+    staged_version   INT,
+    staged_group_id  UUID        REFERENCES device_groups(id) ON DELETE SET NULL,
+    created_by       UUID        REFERENCES users(id) ON DELETE SET NULL,
+    created_at       TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at       TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (event_type, domain_id, path)
+);
+
+COMMENT ON TABLE  provisioning_scripts                  IS 'Scripts of the provisioning tree stored in the database; each has a version history.';
+COMMENT ON COLUMN provisioning_scripts.path             IS 'Path below {event_type}/{domain slug}/, e.g. HW1/2.0.0/update.rhai.';
+COMMENT ON COLUMN provisioning_scripts.active_version   IS 'Version run for every device. NULL = inactive: the file on disk, if any, runs.';
+COMMENT ON COLUMN provisioning_scripts.previous_version IS 'Version active before the last change, restored by a rollback.';
+COMMENT ON COLUMN provisioning_scripts.staged_version   IS 'Version run instead of the active one for members of staged_group_id.';
+
+CREATE TABLE provisioning_script_versions (
+    id         UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    script_id  UUID        NOT NULL REFERENCES provisioning_scripts(id) ON DELETE CASCADE,
+    version    INT         NOT NULL CHECK (version > 0),
+    source     TEXT        NOT NULL,
+    comment    TEXT,
+    created_by UUID        REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (script_id, version)
+);
+
+COMMENT ON TABLE  provisioning_script_versions         IS 'Immutable uploads of a provisioning script, numbered from 1.';
+COMMENT ON COLUMN provisioning_script_versions.comment IS 'Change description given on upload.';
@@ -0,0 +1,77 @@
+-- Persistent command queue for devices.
+--
+-- A task is an Action the ACS wants a device to execute, stored until the
+-- device next opens a session. Whatever triggers that session (periodic
+-- Inform, boot, connection request), the controller delivers the device's
+-- pending tasks in priority order.
+--
+-- Lifecycle:
+--
+--   pending ──deliver──► sent ──response──► succeeded
+--      ▲                   │
+--      │                   └──fault──► faulted      (attempts exhausted)
+--      ├─────── fault, attempts < max_attempts ─┤
+--      └─────── session ended, no response ─────┘   (attempt refunded)
+--
+--   pending ──expires_at passed──► expired
+
+DROP TABLE IF EXISTS task_results;
+DROP TABLE IF EXISTS tasks CASCADE;
+
+CREATE TABLE tasks (
+    id           UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_id    UUID        NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    -- nats_common::Action, serialised exactly as the protocol pods expect it.
+    action       JSONB       NOT NULL,
+    status       TEXT        NOT NULL DEFAULT 'pending'
+                             CHECK (status IN ('pending', 'sent', 'succeeded', 'faulted', 'expired')),
+    priority     INTEGER     NOT NULL DEFAULT 100,
+    expires_at   TIMESTAMPTZ,
+    max_attempts INTEGER     NOT NULL DEFAULT 3 CHECK (max_attempts > 0),
+    attempts     INTEGER     NOT NULL DEFAULT 0,
+    -- command_id of the most recent delivery; echoed back in the response.
+    command_id   UUID        UNIQUE,
+    -- Session the latest delivery was published to.
+    session_id   TEXT,
+    last_error   TEXT,
+    created_by   UUID        REFERENCES users(id) ON DELETE SET NULL,
+    -- Async command (POST /device/:uid/commands) this task is one action of.
+    batch_id     UUID,
+    batch_seq    INTEGER,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    sent_at      TIMESTAMPTZ,
+    completed_at TIMESTAMPTZ
+);
+
+-- Delivery scans a single device's pending tasks in priority order.
+CREATE INDEX idx_tasks_device_pending ON tasks(device_id, priority, created_at) WHERE status = 'pending';
+CREATE INDEX idx_tasks_batch_id ON tasks(batch_id) WHERE batch_id IS NOT NULL;
+
+COMMENT ON TABLE  tasks              IS 'Queued device commands, delivered on the device''s next session.';
+COMMENT ON COLUMN tasks.action       IS 'nats_common::Action JSON, e.g. {"Reboot": null} or {"SetParameterValues": {...}}.';
+COMMENT ON COLUMN tasks.status       IS 'pending | sent | succeeded | faulted | expired.';
+COMMENT ON COLUMN tasks.priority     IS 'Lower number = delivered first within a session.';
+COMMENT ON COLUMN tasks.expires_at   IS 'A pending task past this instant is marked expired instead of delivered. NULL = never expires.';
+COMMENT ON COLUMN tasks.max_attempts IS 'Deliveries allowed before a fault becomes final. A fault with attempts remaining returns the task to pending.';
+COMMENT ON COLUMN tasks.command_id   IS 'DeviceCommand.command_id of the latest delivery, used to correlate the device response.';
+COMMENT ON COLUMN tasks.session_id   IS 'Session of the latest delivery. A sent task whose session ends without a response returns to pending.';
+COMMENT ON COLUMN tasks.last_error   IS 'Fault code and string from the most recent failed attempt.';
+COMMENT ON COLUMN tasks.batch_id     IS 'Command id returned by the async command API; groups the actions of one request.';
+COMMENT ON COLUMN tasks.batch_seq    IS 'Position of the action within its batch. Batched actions are delivered in this order in one session.';
+
+CREATE TABLE task_results (
+    id          UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    task_id     UUID        NOT NULL REFERENCES tasks(id) ON DELETE CASCADE,
+    command_id  UUID        NOT NULL,
+    attempt     INTEGER     NOT NULL,
+    -- nats_common::ActionResult JSON.
+    result      JSONB       NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_task_results_task_id ON task_results(task_id);
+
+COMMENT ON TABLE  task_results         IS 'One row per device response to a task delivery; a retried task has several.';
+COMMENT ON COLUMN task_results.attempt IS 'tasks.attempts at the time of the delivery this result answers.';
+COMMENT ON COLUMN task_results.result  IS 'nats_common::ActionResult JSON: {"Success": {...}}, "Done" or {"Fault": {...}}.';
@@ -0,0 +1,70 @@
+-- Outbound webhooks.
+--
+-- A domain subscribes an HTTP endpoint to device lifecycle events
+-- (first_contact, boot, firmware_change, fault, offline). Each event is
+-- written to webhook_deliveries once per matching subscription, and the
+-- controller POSTs it from there, signed with the subscription's secret:
+--
+--   pending ──2xx──► delivered
+--      │ ▲
+--      │ └──other response or no response: retried with exponential backoff
+--      └──max_attempts used up──► dead ──redelivered through the API──► pending
+--
+-- Delivered and dead rows are the delivery history; they are kept for
+-- WEBHOOK_HISTORY_DAYS.
+
+DROP TABLE IF EXISTS webhook_deliveries;
+DROP TABLE IF EXISTS webhook_subscriptions;
+
+CREATE TABLE webhook_subscriptions (
+    id           UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    domain_id    UUID        NOT NULL REFERENCES domains(id) ON DELETE CASCADE,
+    name         TEXT        NOT NULL,
+    url          TEXT        NOT NULL CHECK (url ~ '^https?://'),
+    secret       TEXT        NOT NULL,
+    event_types  TEXT[]      NOT NULL DEFAULT '{}',
+    enabled      BOOLEAN     NOT NULL DEFAULT true,
+    max_attempts INTEGER     NOT NULL DEFAULT 10 CHECK (max_attempts BETWEEN 1 AND 20),
+    created_by   UUID        REFERENCES users(id) ON DELETE SET NULL,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (domain_id, name)
+);
+
+COMMENT ON TABLE  webhook_subscriptions              IS 'HTTP endpoints notified of device lifecycle events in one domain.';
+COMMENT ON COLUMN webhook_subscriptions.secret       IS 'HMAC-SHA256 key signing each payload (X-ACS-Signature). Only returned when set.';
+COMMENT ON COLUMN webhook_subscriptions.event_types  IS 'Event types delivered, e.g. {boot,fault}. Empty = all of them.';
+COMMENT ON COLUMN webhook_subscriptions.enabled      IS 'Disabled subscriptions receive no new events; their pending deliveries wait until re-enabled.';
+COMMENT ON COLUMN webhook_subscriptions.max_attempts IS 'Attempts per delivery before it is dead-lettered.';
+
+CREATE TABLE webhook_deliveries (
+    id              UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    subscription_id UUID        NOT NULL REFERENCES webhook_subscriptions(id) ON DELETE CASCADE,
+    event_id        UUID        NOT NULL,
+    event_type      TEXT        NOT NULL,
+    device_uid      TEXT,
+    payload         JSONB       NOT NULL,
+    status          TEXT        NOT NULL DEFAULT 'pending'
+                                CHECK (status IN ('pending', 'delivered', 'dead')),
+    attempts        INTEGER     NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_attempt_at TIMESTAMPTZ,
+    response_status INTEGER,
+    last_error      TEXT,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at    TIMESTAMPTZ
+);
+
+CREATE INDEX idx_webhook_deliveries_due ON webhook_deliveries(next_attempt_at) WHERE status = 'pending';
+CREATE INDEX idx_webhook_deliveries_subscription ON webhook_deliveries(subscription_id, created_at DESC);
+CREATE INDEX idx_webhook_deliveries_created_at ON webhook_deliveries(created_at);
+
+COMMENT ON TABLE  webhook_deliveries                 IS 'One event for one subscription: the outbox, delivery history and dead letters.';
+COMMENT ON COLUMN webhook_deliveries.event_id        IS 'Identifies the event; the same for every subscription it went to. Sent as X-ACS-Delivery so receivers can drop duplicates.';
+COMMENT ON COLUMN webhook_deliveries.event_type      IS 'first_contact, boot, firmware_change, fault, offline, or ping for test deliveries.';
+COMMENT ON COLUMN webhook_deliveries.device_uid      IS 'Device the event is about. NULL for ping.';
+COMMENT ON COLUMN webhook_deliveries.payload         IS 'JSON body POSTed to the subscription URL.';
+COMMENT ON COLUMN webhook_deliveries.attempts        IS 'Attempts made so far, counted when an attempt starts.';
+COMMENT ON COLUMN webhook_deliveries.next_attempt_at IS 'When a pending delivery is next attempted. Pushed ahead while an attempt is in flight, so a replica that dies mid-attempt leaves it to be retried.';
+COMMENT ON COLUMN webhook_deliveries.response_status IS 'HTTP status of the last attempt. NULL if there was no response.';
+COMMENT ON COLUMN webhook_deliveries.last_error      IS 'Why the last attempt failed.';
@@ -84,9 +84,14 @@ pub enum Action {
         next_level: bool,
     },
 
-    /// Write parameter values. Type defaults to `xsd:string`.
+    /// Write parameter values.
     SetParameterValues {
         parameters: HashMap<String, String>,
+        /// `xsd:` type of each parameter, e.g. `"xsd:unsignedInt"`. Filled
+        /// in by the controller from the data model catalog; parameters
+        /// without one are sent as `xsd:string`.
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        types: HashMap<String, String>,
     },
 
     /// Create a new object instance under a multi-instance object path.
@@ -153,3 +158,25 @@ pub enum SessionLifecycle {
     Started { session_id: String, device_id: String },
     Ended { session_id: String, device_id: String, reason: String },
 }
+
+/// Published by the controller when a device joins or leaves a device group.
+///
+/// Subject: `acs.groups.{group_id}.membership`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupMembershipChange {
+    pub group_id: Uuid,
+    pub group_name: String,
+    pub domain_id: Uuid,
+    /// The device that joined or left (e.g. "AABBCC-1234567").
+    pub device_id: String,
+    pub change: MembershipChange,
+    /// Unix timestamp of the change.
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MembershipChange {
+    Joined,
+    Left,
+}
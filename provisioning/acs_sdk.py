@@ -3,7 +3,8 @@ acs_sdk.py — Shared helper library for ACS provisioning scripts.
 
 Every provisioning script should import this module to:
   - Parse the InformPayload from stdin.
-  - Build well-typed action dicts.
+  - Read the device's inventory context (payload.context).
+  - Build well-typed action and inventory update dicts.
   - Emit actions to stdout.
 
 Usage pattern
@@ -25,6 +26,65 @@ from dataclasses import dataclass, field
 from typing import Any
 
 
+# ── Context ──────────────────────────────────────────────────────────────────
+
+@dataclass
+class DeviceContext:
+    """What the inventory knows about the device (``payload["context"]``).
+
+    Given to scripts when the controller has a database. A device that is
+    not in the inventory yet has an empty context.
+    """
+    version: int = 0
+    domain: dict[str, Any] = field(default_factory=dict)
+    device: dict[str, Any] = field(default_factory=dict)
+    properties: dict[str, Any] = field(default_factory=dict)
+    # [{"id", "name", "config"}], in assignment order.
+    profiles: list[dict[str, Any]] = field(default_factory=list)
+    groups: list[str] = field(default_factory=list)
+    # Parameter values last stored for the device, not those of this event.
+    parameters: dict[str, str] = field(default_factory=dict)
+
+    @classmethod
+    def from_json(cls, raw: dict[str, Any] | None) -> "DeviceContext":
+        if not raw:
+            return cls()
+        return cls(
+            version=raw.get("version", 0),
+            domain=raw.get("domain") or {},
+            device=raw.get("device") or {},
+            properties=raw.get("properties") or {},
+            profiles=raw.get("profiles") or [],
+            groups=raw.get("groups") or [],
+            parameters=raw.get("parameters") or {},
+        )
+
+    def domain_slug(self) -> str | None:
+        return self.domain.get("slug")
+
+    def tags(self) -> list[str]:
+        return self.device.get("tags", [])
+
+    def has_tag(self, tag: str) -> bool:
+        return tag in self.tags()
+
+    def metadata(self, key: str, default: Any = None) -> Any:
+        return self.device.get("metadata", {}).get(key, default)
+
+    def property(self, name: str, default: Any = None) -> Any:
+        return self.properties.get(name, default)
+
+    def in_group(self, name: str) -> bool:
+        return name in self.groups
+
+    def profile(self, name: str) -> dict[str, Any] | None:
+        """Config of the assigned profile called *name*, if any."""
+        return next((p["config"] for p in self.profiles if p.get("name") == name), None)
+
+    def cached_param(self, path: str, default: str | None = None) -> str | None:
+        return self.parameters.get(path, default)
+
+
 # ── Payload ──────────────────────────────────────────────────────────────────
 
 @dataclass
@@ -39,6 +99,9 @@ class InformPayload:
     events: list[str]
     parameter_list: dict[str, str]
     protocol: str = "cwmp"
+    # "add" for the Inform that created the device, "update" afterwards.
+    lifecycle: str = "update"
+    context: DeviceContext = field(default_factory=DeviceContext)
 
     # ── Convenience helpers ──────────────────────────────────────────────────
 
@@ -81,6 +144,66 @@ def load_payload() -> InformPayload:
         events=raw.get("events", []),
         parameter_list=raw.get("parameter_list", {}),
         protocol=raw.get("protocol", "cwmp"),
+        lifecycle=raw.get("lifecycle", "update"),
+        context=DeviceContext.from_json(raw.get("context")),
+    )
+
+
+@dataclass
+class SessionEndedPayload:
+    """Payload of ``session_ended`` scripts."""
+    device_id: str
+    session_id: str
+    reason: str | None
+    hardware_version: str | None
+    software_version: str | None
+    # Session record: protocol, started_at, ended_at, duration_secs,
+    # informs, commands, responses, faults. None if never recorded.
+    session: dict[str, Any] | None = None
+    context: DeviceContext = field(default_factory=DeviceContext)
+
+
+def load_session_ended() -> SessionEndedPayload:
+    """Parse the JSON session_ended payload from stdin."""
+    raw = json.load(sys.stdin)
+    return SessionEndedPayload(
+        device_id=raw["device_id"],
+        session_id=raw["session_id"],
+        reason=raw.get("reason"),
+        hardware_version=raw.get("hardware_version"),
+        software_version=raw.get("software_version"),
+        session=raw.get("session"),
+        context=DeviceContext.from_json(raw.get("context")),
+    )
+
+
+@dataclass
+class DecommissionPayload:
+    """Payload of ``decommission`` scripts, run before a device is deleted."""
+    device_id: str
+    oui: str | None
+    serial_number: str | None
+    manufacturer: str | None
+    product_class: str | None
+    hardware_version: str | None
+    software_version: str | None
+    lifecycle: str = "delete"
+    context: DeviceContext = field(default_factory=DeviceContext)
+
+
+def load_decommission() -> DecommissionPayload:
+    """Parse the JSON decommission payload from stdin."""
+    raw = json.load(sys.stdin)
+    return DecommissionPayload(
+        device_id=raw["device_id"],
+        oui=raw.get("oui"),
+        serial_number=raw.get("serial_number"),
+        manufacturer=raw.get("manufacturer"),
+        product_class=raw.get("product_class"),
+        hardware_version=raw.get("hardware_version"),
+        software_version=raw.get("software_version"),
+        lifecycle=raw.get("lifecycle", "delete"),
+        context=DeviceContext.from_json(raw.get("context")),
     )
 
 
@@ -131,6 +254,30 @@ def download(url: str, file_type: str, file_size: int = 0, target_filename: str
     }}
 
 
+# ── Inventory update builders ─────────────────────────────────────────────────
+# Emitted alongside actions; the controller applies them to the device's
+# inventory record once the scripts have run.
+
+def set_property(name: str, value: Any) -> dict[str, Any]:
+    """Set a device property (source "script", priority 100)."""
+    return {"SetProperty": {"name": name, "value": value}}
+
+
+def delete_property(name: str) -> dict[str, Any]:
+    """Delete a device property, unless set with a higher priority."""
+    return {"DeleteProperty": {"name": name}}
+
+
+def add_tag(tag: str) -> dict[str, Any]:
+    """Tag the device."""
+    return {"AddTag": {"tag": tag}}
+
+
+def remove_tag(tag: str) -> dict[str, Any]:
+    """Remove a tag from the device."""
+    return {"RemoveTag": {"tag": tag}}
+
+
 # ── Output ────────────────────────────────────────────────────────────────────
 
 def emit_actions(actions: list[dict[str, Any]]) -> None: